@@ -1,6 +1,7 @@
 use lyuu_commons::flat_disasm::disasm::flat_disasm;
 
 
+#[allow(clippy::unusual_byte_groupings)]
 fn main() {
   let src = 0b11111111111111111111_00000_0110111_u32.to_le_bytes();
   let (r, _next) = flat_disasm(&src, false).unwrap();
@@ -0,0 +1,361 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::elf::Elf;
+
+use super::function::Function;
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub entry: u64,
+    pub name: Option<String>,
+    pub frame_size: u64,
+    /// (call site, callee), tail calls included
+    pub calls: Vec<(u64, u64)>,
+    /// sites of calls whose target is unknown
+    pub indirect_calls: Vec<u64>,
+}
+
+impl Node {
+    pub fn callees(&self) -> impl Iterator<Item = u64> + '_ {
+        self.calls.iter().map(|(_, c)| *c)
+    }
+
+    fn label(&self) -> String {
+        self.name.clone().unwrap_or_else(|| format!("sub_{:x}", self.entry))
+    }
+}
+
+/// the deepest chain of frames found from a root
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StackPath {
+    pub total: u64,
+    /// function entries, root first
+    pub path: Vec<u64>,
+    /// the call site leaving each entry of `path` but the last
+    pub sites: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallGraph {
+    pub nodes: BTreeMap<u64, Node>,
+}
+
+impl CallGraph {
+    /// calls to addresses that are not detected functions are dropped
+    pub fn new(funcs: &[Function]) -> CallGraph {
+        let entries = funcs.iter().map(|f| f.entry).collect::<BTreeSet<_>>();
+        let nodes = funcs.iter().map(|f| {
+            let mut calls = f.calls.iter()
+                .filter_map(|(site, t)| Some((*site, (*t)?)))
+                .chain(f.tail_calls.iter().copied())
+                .filter(|(_, t)| entries.contains(t))
+                .collect::<Vec<_>>();
+            calls.sort_unstable();
+            calls.dedup();
+            let node = Node {
+                entry: f.entry,
+                name: None,
+                frame_size: f.frame_size,
+                calls,
+                indirect_calls: f.calls.iter().filter(|(_, t)| t.is_none()).map(|(s, _)| *s).collect(),
+            };
+            (f.entry, node)
+        }).collect();
+        CallGraph { nodes }
+    }
+
+    /// name the nodes after the function symbols of `elf`
+    pub fn name_from_elf(&mut self, elf: &Elf) {
+        for sym in elf.symbols.iter().filter(|s| s.is_func()) {
+            if let Some(node) = self.nodes.get_mut(&sym.value) {
+                node.name = Some(sym.name.clone());
+            }
+        }
+    }
+
+    pub fn callers(&self, entry: u64) -> Vec<u64> {
+        self.nodes.values()
+            .filter(|n| n.callees().any(|c| c == entry))
+            .map(|n| n.entry)
+            .collect()
+    }
+
+    /// strongly connected components in reverse topological order (callees
+    /// before callers), tarjan's algorithm
+    pub fn sccs(&self) -> Vec<Vec<u64>> {
+        struct Tarjan<'a> {
+            graph: &'a CallGraph,
+            index: BTreeMap<u64, usize>,
+            low: BTreeMap<u64, usize>,
+            stack: Vec<u64>,
+            on_stack: BTreeSet<u64>,
+            out: Vec<Vec<u64>>,
+        }
+
+        impl Tarjan<'_> {
+            fn visit(&mut self, n: u64) {
+                let i = self.index.len();
+                self.index.insert(n, i);
+                self.low.insert(n, i);
+                self.stack.push(n);
+                self.on_stack.insert(n);
+                for c in self.graph.nodes[&n].callees() {
+                    if !self.index.contains_key(&c) {
+                        self.visit(c);
+                        let low = self.low[&n].min(self.low[&c]);
+                        self.low.insert(n, low);
+                    } else if self.on_stack.contains(&c) {
+                        let low = self.low[&n].min(self.index[&c]);
+                        self.low.insert(n, low);
+                    }
+                }
+                if self.low[&n] == self.index[&n] {
+                    let mut scc = Vec::new();
+                    while let Some(x) = self.stack.pop() {
+                        self.on_stack.remove(&x);
+                        scc.push(x);
+                        if x == n {
+                            break;
+                        }
+                    }
+                    scc.sort_unstable();
+                    self.out.push(scc);
+                }
+            }
+        }
+
+        let mut t = Tarjan {
+            graph: self,
+            index: BTreeMap::new(),
+            low: BTreeMap::new(),
+            stack: Vec::new(),
+            on_stack: BTreeSet::new(),
+            out: Vec::new(),
+        };
+        for n in self.nodes.keys() {
+            if !t.index.contains_key(n) {
+                t.visit(*n);
+            }
+        }
+        t.out
+    }
+
+    /// functions on a call cycle, self recursion included
+    pub fn recursive(&self) -> BTreeSet<u64> {
+        self.sccs().into_iter()
+            .filter(|scc| scc.len() > 1 || self.nodes[&scc[0]].callees().any(|c| c == scc[0]))
+            .flatten()
+            .collect()
+    }
+
+    /// the largest sum of frame sizes over the acyclic call chains from
+    /// `root`, a call back into the current chain is not followed
+    pub fn max_stack(&self, root: u64) -> Option<StackPath> {
+        self.heaviest_path(root, |n, _| n.frame_size)
+    }
+
+    /// the heaviest acyclic call chain from `root`. `weight(node, Some(site))`
+    /// is what `node` adds when the chain goes on through the call at `site`,
+    /// `weight(node, None)` what it adds when the chain ends in it.
+    pub fn heaviest_path(&self, root: u64, weight: impl Fn(&Node, Option<u64>) -> u64) -> Option<StackPath> {
+        if !self.nodes.contains_key(&root) {
+            return None;
+        }
+        let recursive = self.recursive();
+        let mut memo = BTreeMap::new();
+        Some(self.longest(root, &weight, &recursive, &mut BTreeSet::new(), &mut memo))
+    }
+
+    fn longest(
+        &self,
+        n: u64,
+        weight: &dyn Fn(&Node, Option<u64>) -> u64,
+        recursive: &BTreeSet<u64>,
+        chain: &mut BTreeSet<u64>,
+        memo: &mut BTreeMap<u64, StackPath>,
+    ) -> StackPath {
+        // a non recursive node can not reach back into the chain above it,
+        // so its result does not depend on the chain
+        if let Some(r) = memo.get(&n) {
+            return r.clone();
+        }
+        chain.insert(n);
+        let node = &self.nodes[&n];
+        let mut best = StackPath { total: weight(node, None), path: vec![n], sites: Vec::new() };
+        for (site, c) in node.calls.iter().copied() {
+            if chain.contains(&c) {
+                continue;
+            }
+            let r = self.longest(c, weight, recursive, chain, memo);
+            let total = weight(node, Some(site)) + r.total;
+            // on a tie, a longer chain beats ending here
+            if total > best.total || (total == best.total && best.sites.is_empty()) {
+                best = StackPath { total, path: vec![n], sites: vec![site] };
+                best.path.extend(r.path);
+                best.sites.extend(r.sites);
+            }
+        }
+        chain.remove(&n);
+        if !recursive.contains(&n) {
+            memo.insert(n, best.clone());
+        }
+        best
+    }
+
+    /// entries reachable from `root` through known calls, `root` included
+    pub fn reachable(&self, root: u64) -> BTreeSet<u64> {
+        let mut r = BTreeSet::new();
+        let mut work = vec![root];
+        while let Some(n) = work.pop() {
+            if let Some(node) = self.nodes.get(&n) {
+                if r.insert(n) {
+                    work.extend(node.callees());
+                }
+            }
+        }
+        r
+    }
+
+    pub fn to_dot(&self) -> String {
+        let recursive = self.recursive();
+        let mut s = String::new();
+        writeln!(s, "digraph callgraph {{").unwrap();
+        writeln!(s, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for node in self.nodes.values() {
+            let mut attrs = format!("label=\"{}\\n{:#x}\\nframe {}\"", dot_escape(&node.label()), node.entry, node.frame_size);
+            if recursive.contains(&node.entry) {
+                attrs += ", peripheries=2";
+            }
+            if !node.indirect_calls.is_empty() {
+                attrs += ", color=red";
+            }
+            writeln!(s, "    \"{:#x}\" [{}];", node.entry, attrs).unwrap();
+        }
+        for node in self.nodes.values() {
+            for c in node.callees().collect::<BTreeSet<_>>() {
+                writeln!(s, "    \"{:#x}\" -> \"{:#x}\";", node.entry, c).unwrap();
+            }
+            // call sites get their own ids, a site may be some entry too
+            for site in node.indirect_calls.iter() {
+                writeln!(s, "    \"ind_{:#x}\" [shape=plaintext, label=\"?\"];", site).unwrap();
+                writeln!(s, "    \"{:#x}\" -> \"ind_{:#x}\" [style=dashed, label=\"{:#x}\"];", node.entry, site, site).unwrap();
+            }
+        }
+        s += "}\n";
+        s
+    }
+
+    /// `{"nodes": [...], "edges": [...]}`, addresses as hex strings
+    pub fn to_json(&self) -> String {
+        let recursive = self.recursive();
+        let hex = |x: &u64| format!("\"{:#x}\"", x);
+        let nodes = self.nodes.values().map(|n| {
+            let name = n.name.as_deref().map_or("null".to_string(), json_string);
+            let indirect = n.indirect_calls.iter().map(hex).collect::<Vec<_>>().join(", ");
+            format!(
+                "{{\"entry\": {}, \"name\": {}, \"frame_size\": {}, \"recursive\": {}, \"indirect_calls\": [{}]}}",
+                hex(&n.entry), name, n.frame_size, recursive.contains(&n.entry), indirect,
+            )
+        }).collect::<Vec<_>>();
+        let edges = self.nodes.values().flat_map(|n| n.calls.iter().map(move |(site, c)| {
+            format!("{{\"from\": {}, \"to\": {}, \"site\": {}}}", hex(&n.entry), hex(c), hex(site))
+        })).collect::<Vec<_>>();
+        format!("{{\"nodes\": [{}], \"edges\": [{}]}}\n", nodes.join(", "), edges.join(", "))
+    }
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn json_string(s: &str) -> String {
+    let mut r = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => r += "\\\"",
+            '\\' => r += "\\\\",
+            c if (c as u32) < 0x20 => write!(r, "\\u{:04x}", c as u32).unwrap(),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::riscv::regset::RegSet;
+
+    fn func(entry: u64, frame_size: u64, calls: &[(u64, Option<u64>)]) -> Function {
+        Function {
+            entry,
+            origins: BTreeSet::new(),
+            insts: BTreeSet::from([entry]),
+            frame_size,
+            saved: RegSet::EMPTY,
+            returns: BTreeSet::new(),
+            calls: calls.to_vec(),
+            tail_calls: Vec::new(),
+        }
+    }
+
+    // main(32) -> a(16) -> b(64)
+    //          -> r(48) <-> s(8), s -> b
+    //          -> indirect
+    fn graph() -> CallGraph {
+        CallGraph::new(&[
+            func(0x100, 32, &[(0x104, Some(0x200)), (0x108, Some(0x400)), (0x10c, None)]),
+            func(0x200, 16, &[(0x204, Some(0x300))]),
+            func(0x300, 64, &[]),
+            func(0x400, 48, &[(0x404, Some(0x500))]),
+            func(0x500, 8, &[(0x504, Some(0x400)), (0x508, Some(0x300))]),
+        ])
+    }
+
+    #[test]
+    fn test_scc() {
+        let g = graph();
+        assert_eq!(g.recursive(), BTreeSet::from([0x400, 0x500]));
+        let sccs = g.sccs();
+        assert!(sccs.contains(&vec![0x400, 0x500]));
+        // callees come before their callers
+        let pos = |x: u64| sccs.iter().position(|s| s.contains(&x)).unwrap();
+        assert!(pos(0x300) < pos(0x200) && pos(0x200) < pos(0x100));
+        assert_eq!(g.nodes[&0x100].indirect_calls, vec![0x10c]);
+        assert_eq!(g.callers(0x300), vec![0x200, 0x500]);
+    }
+
+    #[test]
+    fn test_max_stack() {
+        let g = graph();
+        // main -> r -> s -> b = 32 + 48 + 8 + 64
+        assert_eq!(g.max_stack(0x100), Some(StackPath { total: 152, path: vec![0x100, 0x400, 0x500, 0x300], sites: vec![0x108, 0x404, 0x508] }));
+        assert_eq!(g.max_stack(0x500).unwrap().total, 8 + 64);
+        assert_eq!(g.max_stack(0x999), None);
+        assert_eq!(g.reachable(0x200), BTreeSet::from([0x200, 0x300]));
+        // only the frame the chain ends in counts, the first of equal chains wins
+        let r = g.heaviest_path(0x100, |n, site| if site.is_some() { 0 } else { n.frame_size }).unwrap();
+        assert_eq!((r.total, r.path, r.sites), (64, vec![0x100, 0x200, 0x300], vec![0x104, 0x204]));
+    }
+
+    #[test]
+    fn test_export() {
+        let mut g = graph();
+        g.nodes.get_mut(&0x100).unwrap().name = Some("main".to_string());
+        let dot = g.to_dot();
+        assert!(dot.contains("\"0x100\" [label=\"main\\n0x100\\nframe 32\", color=red];"));
+        assert!(dot.contains("\"0x400\" [label=\"sub_400\\n0x400\\nframe 48\", peripheries=2];"));
+        assert!(dot.contains("\"0x100\" -> \"ind_0x10c\" [style=dashed, label=\"0x10c\"];"));
+        // r calls s twice, with b in between
+        g.nodes.get_mut(&0x400).unwrap().calls = vec![(0x404, 0x500), (0x408, 0x300), (0x40c, 0x500)];
+        assert_eq!(g.to_dot().matches("\"0x400\" -> \"0x500\";").count(), 1);
+        let json = g.to_json();
+        assert!(json.starts_with("{\"nodes\": [{\"entry\": \"0x100\", \"name\": \"main\", \"frame_size\": 32, \"recursive\": false, \"indirect_calls\": [\"0x10c\"]}"));
+        assert!(json.contains("{\"from\": \"0x500\", \"to\": \"0x400\", \"site\": \"0x504\"}"));
+        assert_eq!(json_string("a\"\n"), "\"a\\\"\\u000a\"");
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Write};
+
+use crate::disassembly::riscv::DisasmConfig;
+use crate::isa::riscv::{CmPopType, EOpType, RiscV};
+use crate::isa::riscv::meta::is_link;
+
+use super::{decode_region, Inst};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum EdgeKind {
+    Fallthrough,
+    Taken,
+    Call,
+    Return,
+    Indirect,
+}
+
+impl Display for EdgeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Taken => "taken",
+            EdgeKind::Call => "call",
+            EdgeKind::Return => "return",
+            EdgeKind::Indirect => "indirect",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// a successor edge, `target` is `None` when it is only known at run time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Edge {
+    pub kind: EdgeKind,
+    pub target: Option<u64>,
+}
+
+impl Edge {
+    pub fn new(kind: EdgeKind, target: Option<u64>) -> Edge {
+        Edge { kind, target }
+    }
+}
+
+/// how an instruction leaves its block, `None` when it simply falls through
+pub fn flow(inst: &Inst) -> Option<Vec<Edge>> {
+    let next = Some(inst.next());
+    let r = match inst.inst {
+        RiscV::Branch(..) => vec![
+            Edge::new(EdgeKind::Taken, inst.target()),
+            Edge::new(EdgeKind::Fallthrough, next),
+        ],
+        RiscV::Jal(rd, _) if is_link(rd) => vec![
+            Edge::new(EdgeKind::Call, inst.target()),
+            Edge::new(EdgeKind::Fallthrough, next),
+        ],
+        RiscV::Jal(..) => vec![Edge::new(EdgeKind::Taken, inst.target())],
+        RiscV::Jalr(rd, rs1, _) if rd.0 == 0 && is_link(rs1) => vec![Edge::new(EdgeKind::Return, None)],
+        RiscV::Jalr(rd, _, _) if is_link(rd) => vec![
+            Edge::new(EdgeKind::Call, None),
+            Edge::new(EdgeKind::Fallthrough, next),
+        ],
+        RiscV::Jalr(..) => vec![Edge::new(EdgeKind::Indirect, None)],
+        // trap returns leave for the interrupted code
+        RiscV::EOp(EOpType::Mret | EOpType::Sret) => vec![Edge::new(EdgeKind::Return, None)],
+        RiscV::CmPop(CmPopType::Pop, ..) => return None,
+        RiscV::CmPop(..) => vec![Edge::new(EdgeKind::Return, None)],
+        RiscV::CmJt(_) => vec![Edge::new(EdgeKind::Indirect, None)],
+        RiscV::CmJalt(_) => vec![
+            Edge::new(EdgeKind::Call, None),
+            Edge::new(EdgeKind::Fallthrough, next),
+        ],
+        _ => return None,
+    };
+    Some(r)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub insts: Vec<Inst>,
+    pub succs: Vec<Edge>,
+}
+
+impl BasicBlock {
+    pub fn start(&self) -> u64 {
+        self.insts[0].addr
+    }
+
+    /// address past the last instruction
+    pub fn end(&self) -> u64 {
+        self.insts.last().unwrap().next()
+    }
+
+    pub fn last(&self) -> &Inst {
+        self.insts.last().unwrap()
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.start() <= addr && addr < self.end()
+    }
+}
+
+/// blocks keyed by their start address
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: BTreeMap<u64, BasicBlock>,
+}
+
+impl Cfg {
+    /// linear sweep over `code` loaded at `base`
+    pub fn build(base: u64, code: &[u8], cfg: &DisasmConfig) -> Cfg {
+        Cfg::from_insts(decode_region(base, code, cfg))
+    }
+
+    /// split `insts` into blocks at branch targets, after terminators and
+    /// at gaps in the address range. `insts` must be sorted by address.
+    pub fn from_insts(insts: Vec<Inst>) -> Cfg {
+        Cfg::from_insts_with(insts, &BTreeMap::new())
+    }
+
+    /// like `from_insts`, `extra` adds successors to the terminator at each
+    /// address, e.g. resolved indirect jumps
+    pub fn from_insts_with(insts: Vec<Inst>, extra: &BTreeMap<u64, Vec<Edge>>) -> Cfg {
+        let addrs = insts.iter().map(|i| i.addr).collect::<BTreeSet<_>>();
+        let mut leaders = BTreeSet::new();
+        let mut prev_next = None;
+        for inst in insts.iter() {
+            if prev_next != Some(inst.addr) {
+                leaders.insert(inst.addr);
+            }
+            prev_next = Some(inst.next());
+            if flow(inst).is_none() && !extra.contains_key(&inst.addr) {
+                continue;
+            }
+            leaders.insert(inst.next());
+            let targets = flow(inst).unwrap_or_default().into_iter()
+                .chain(extra.get(&inst.addr).into_iter().flatten().copied())
+                .filter_map(|e| e.target);
+            leaders.extend(targets.filter(|t| addrs.contains(t)));
+        }
+
+        let mut blocks = BTreeMap::new();
+        let mut cur: Vec<Inst> = Vec::new();
+        for inst in insts {
+            if leaders.contains(&inst.addr) && !cur.is_empty() {
+                let block = Cfg::close(std::mem::take(&mut cur), &addrs, extra);
+                blocks.insert(block.start(), block);
+            }
+            cur.push(inst);
+        }
+        if !cur.is_empty() {
+            let block = Cfg::close(cur, &addrs, extra);
+            blocks.insert(block.start(), block);
+        }
+        Cfg { blocks }
+    }
+
+    fn close(insts: Vec<Inst>, addrs: &BTreeSet<u64>, extra: &BTreeMap<u64, Vec<Edge>>) -> BasicBlock {
+        let last = insts.last().unwrap();
+        let mut succs = match flow(last) {
+            Some(edges) => edges,
+            None if addrs.contains(&last.next()) => vec![Edge::new(EdgeKind::Fallthrough, Some(last.next()))],
+            None => vec![],
+        };
+        if let Some(edges) = extra.get(&last.addr) {
+            // the resolved targets replace the unknown one
+            succs.retain(|e| e.target.is_some());
+            for e in edges {
+                if !succs.contains(e) {
+                    succs.push(*e);
+                }
+            }
+        }
+        BasicBlock { insts, succs }
+    }
+
+    pub fn block(&self, start: u64) -> Option<&BasicBlock> {
+        self.blocks.get(&start)
+    }
+
+    /// the block holding the instruction at `addr`
+    pub fn block_containing(&self, addr: u64) -> Option<&BasicBlock> {
+        self.blocks.range(..=addr).next_back()
+            .map(|(_, b)| b)
+            .filter(|b| b.contains(addr))
+    }
+
+    pub fn insts(&self) -> impl Iterator<Item = &Inst> + '_ {
+        self.blocks.values().flat_map(|b| b.insts.iter())
+    }
+
+    /// predecessors of every block, over the intra-procedural edges
+    /// (fallthrough, taken and resolved indirect jumps)
+    pub fn preds(&self) -> BTreeMap<u64, Vec<u64>> {
+        let mut r: BTreeMap<u64, Vec<u64>> = self.blocks.keys().map(|k| (*k, vec![])).collect();
+        for (start, block) in self.blocks.iter() {
+            for e in block.succs.iter() {
+                let local = matches!(e.kind, EdgeKind::Fallthrough | EdgeKind::Taken | EdgeKind::Indirect);
+                if let (true, Some(t)) = (local, e.target) {
+                    if let Some(p) = r.get_mut(&t) {
+                        p.push(*start);
+                    }
+                }
+            }
+        }
+        r
+    }
+
+    /// graphviz dot of the graph, one box per block
+    pub fn to_dot(&self, name: &str) -> String {
+        let mut s = String::new();
+        writeln!(s, "digraph \"{}\" {{", escape(name)).unwrap();
+        writeln!(s, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        let mut outside = BTreeSet::new();
+        for (start, block) in self.blocks.iter() {
+            let mut label = format!("{:#x}:\\l", start);
+            for inst in block.insts.iter() {
+                label += &format!("{:#x}  {}\\l", inst.addr, escape(&inst.inst.to_string()));
+            }
+            writeln!(s, "    \"{:#x}\" [label=\"{}\"];", start, label).unwrap();
+            for (i, e) in block.succs.iter().enumerate() {
+                let to = match e.target {
+                    Some(t) => {
+                        if !self.blocks.contains_key(&t) {
+                            outside.insert(t);
+                        }
+                        format!("{:#x}", t)
+                    },
+                    None => {
+                        let node = format!("{:#x}_{}", start, i);
+                        writeln!(s, "    \"{}\" [shape=plaintext, label=\"{}\"];", node, e.kind).unwrap();
+                        node
+                    },
+                };
+                let style = match e.kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Taken => ", color=green",
+                    EdgeKind::Call => ", style=dashed",
+                    EdgeKind::Return | EdgeKind::Indirect => ", color=red",
+                };
+                writeln!(s, "    \"{:#x}\" -> \"{}\" [label=\"{}\"{}];", start, to, e.kind, style).unwrap();
+            }
+        }
+        for t in outside {
+            writeln!(s, "    \"{:#x}\" [shape=ellipse];", t).unwrap();
+        }
+        s += "}\n";
+        s
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\t', " ")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0x1000 addi  x10, x0, 0
+    // 0x1004 beq   x11, x0, 0x1010
+    // 0x1008 jal   x1, 0x1100
+    // 0x100c addi  x10, x10, 1
+    // 0x1010 jalr  x0, 0(x1)
+    const FUNC: [u32; 5] = [0x00000513, 0x00058663, 0x0f8000ef, 0x00150513, 0x00008067];
+
+    fn func() -> Cfg {
+        let code = FUNC.iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        Cfg::build(0x1000, &code, &DisasmConfig::default())
+    }
+
+    #[test]
+    fn test_blocks() {
+        let cfg = func();
+        assert_eq!(cfg.blocks.keys().copied().collect::<Vec<_>>(), vec![0x1000, 0x1008, 0x100c, 0x1010]);
+        assert_eq!(cfg.block(0x1000).unwrap().succs, vec![
+            Edge::new(EdgeKind::Taken, Some(0x1010)),
+            Edge::new(EdgeKind::Fallthrough, Some(0x1008)),
+        ]);
+        assert_eq!(cfg.block(0x1008).unwrap().succs, vec![
+            Edge::new(EdgeKind::Call, Some(0x1100)),
+            Edge::new(EdgeKind::Fallthrough, Some(0x100c)),
+        ]);
+        assert_eq!(cfg.block(0x100c).unwrap().succs, vec![Edge::new(EdgeKind::Fallthrough, Some(0x1010))]);
+        assert_eq!(cfg.block(0x1010).unwrap().succs, vec![Edge::new(EdgeKind::Return, None)]);
+        assert_eq!(cfg.block_containing(0x1006).unwrap().start(), 0x1000);
+        assert_eq!(cfg.preds()[&0x1010], vec![0x1000, 0x100c]);
+    }
+
+    #[test]
+    fn test_trap_return() {
+        // csrw mscratch, x10; mret; wfi
+        let code = [0x34051073u32, 0x30200073, 0x10500073].iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        let cfg = Cfg::build(0x1000, &code, &DisasmConfig::default());
+        assert_eq!(cfg.blocks.keys().copied().collect::<Vec<_>>(), vec![0x1000, 0x1008]);
+        assert_eq!(cfg.block(0x1000).unwrap().succs, vec![Edge::new(EdgeKind::Return, None)]);
+        // sret
+        let code = 0x10200073u32.to_le_bytes();
+        assert_eq!(Cfg::build(0x1000, &code, &DisasmConfig::default()).block(0x1000).unwrap().succs, vec![Edge::new(EdgeKind::Return, None)]);
+    }
+
+    #[test]
+    fn test_dot() {
+        let dot = func().to_dot("func");
+        assert!(dot.starts_with("digraph \"func\" {"));
+        assert!(dot.contains("\"0x1000\" -> \"0x1010\" [label=\"taken\", color=green];"));
+        assert!(dot.contains("\"0x1008\" -> \"0x1100\" [label=\"call\", style=dashed];"));
+        assert!(dot.contains("\"0x1100\" [shape=ellipse];"));
+        assert!(dot.contains("\"0x1010_0\" [shape=plaintext, label=\"return\"];"));
+    }
+}
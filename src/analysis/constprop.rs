@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+
+use crate::isa::riscv::{OpType, Reg, RiscV};
+use crate::isa::riscv::bare::memory_layout::region_of;
+use crate::isa::riscv::meta::is_link;
+
+use super::cfg::Cfg;
+use super::ssa::CALLER_SAVED;
+use super::Inst;
+
+
+/// what an instruction was found to compute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolved {
+    /// constant written to rd
+    Value(u64),
+    /// address of a memory access or target of a `jalr`
+    Addr(u64),
+}
+
+impl Resolved {
+    pub fn value(&self) -> u64 {
+        match *self {
+            Resolved::Value(x) | Resolved::Addr(x) => x,
+        }
+    }
+}
+
+impl Display for Resolved {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let x = self.value();
+        write!(f, "# {:#x}", x)?;
+        match region_of(x) {
+            Some((name, 0)) => write!(f, " <{}>", name),
+            Some((name, off)) => write!(f, " <{}+{:#x}>", name, off),
+            None => Ok(()),
+        }
+    }
+}
+
+fn sext32(x: u64) -> u64 {
+    x as i32 as i64 as u64
+}
+
+/// known register constants along straight-line code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstProp {
+    pub is_32bit: bool,
+    regs: [Option<u64>; 32],
+}
+
+impl ConstProp {
+    pub fn new(is_32bit: bool) -> ConstProp {
+        let mut regs = [None; 32];
+        regs[0] = Some(0);
+        ConstProp { is_32bit, regs }
+    }
+
+    pub fn get(&self, reg: Reg) -> Option<u64> {
+        self.regs[reg.0 as usize]
+    }
+
+    /// the constants both `self` and `other` agree on, where two paths join
+    pub fn meet(&self, other: &ConstProp) -> ConstProp {
+        let mut r = self.clone();
+        for (a, b) in r.regs.iter_mut().zip(other.regs) {
+            if *a != b {
+                *a = None;
+            }
+        }
+        r
+    }
+
+    fn set(&mut self, reg: Reg, value: Option<u64>) {
+        if reg.0 != 0 {
+            self.regs[reg.0 as usize] = value.map(|x| self.xlen(x));
+        }
+    }
+
+    /// truncate to the register width, rv32 values are kept zero extended
+    fn xlen(&self, x: u64) -> u64 {
+        if self.is_32bit { x as u32 as u64 } else { x }
+    }
+
+    fn shamt(&self, x: u64) -> u32 {
+        if self.is_32bit { x as u32 & 0x1f } else { x as u32 & 0x3f }
+    }
+
+    fn op(&self, ty: OpType, a: u64, b: u64) -> u64 {
+        let (sa, sb) = if self.is_32bit { (a as i32 as i64, b as i32 as i64) } else { (a as i64, b as i64) };
+        match ty {
+            OpType::Add => a.wrapping_add(b),
+            OpType::Sub => a.wrapping_sub(b),
+            OpType::Sll => a << self.shamt(b),
+            OpType::Slt => (sa < sb) as u64,
+            OpType::Sltu => (self.xlen(a) < self.xlen(b)) as u64,
+            OpType::Xor => a ^ b,
+            OpType::Srl => self.xlen(a) >> self.shamt(b),
+            OpType::Sra => (sa >> self.shamt(b)) as u64,
+            OpType::Or => a | b,
+            OpType::And => a & b,
+        }
+    }
+
+    fn op_w(ty: OpType, a: u64, b: u64) -> Option<u64> {
+        let (a, sh) = (a as u32, b as u32 & 0x1f);
+        let r = match ty {
+            OpType::Add => a.wrapping_add(b as u32),
+            OpType::Sub => a.wrapping_sub(b as u32),
+            OpType::Sll => a << sh,
+            OpType::Srl => a >> sh,
+            OpType::Sra => ((a as i32) >> sh) as u32,
+            _ => return None,
+        };
+        Some(sext32(r as u64))
+    }
+
+    /// the effective address `rs1 + imm`
+    fn addr(&self, rs1: Reg, imm: i16) -> Option<u64> {
+        self.get(rs1).map(|x| self.xlen(x.wrapping_add(imm as i64 as u64)))
+    }
+
+    /// a jump linking through `rd` is a call, the callee may change any
+    /// caller saved register
+    fn call(&mut self, rd: Reg) {
+        if is_link(rd) {
+            for reg in CALLER_SAVED.map(Reg) {
+                self.set(reg, None);
+            }
+        }
+    }
+
+    /// advance over `inst`, returning what it resolved to. values are only
+    /// reported when a known register fed them, `li` of a small immediate
+    /// is not worth a comment.
+    pub fn step(&mut self, inst: &Inst) -> Option<Resolved> {
+        let known = |r: Reg| r.0 != 0 && self.get(r).is_some();
+        let (rd, value, report) = match inst.inst {
+            RiscV::Lui(rd, imm) => (rd, Some(sext32(imm as u64)), true),
+            RiscV::Auipc(rd, imm) => (rd, Some(inst.addr.wrapping_add(sext32(imm as u64))), true),
+            RiscV::OpI(ty, rd, rs1, imm) => (rd, self.get(rs1).map(|a| self.op(ty, a, imm as i64 as u64)), known(rs1)),
+            RiscV::OpIW(ty, rd, rs1, imm) => (rd, self.get(rs1).and_then(|a| ConstProp::op_w(ty, a, imm as i64 as u64)), known(rs1)),
+            RiscV::Op(ty, rd, rs1, rs2) => {
+                let value = self.get(rs1).zip(self.get(rs2)).map(|(a, b)| self.op(ty, a, b));
+                (rd, value, known(rs1) || known(rs2))
+            },
+            RiscV::OpW(ty, rd, rs1, rs2) => {
+                let value = self.get(rs1).zip(self.get(rs2)).and_then(|(a, b)| ConstProp::op_w(ty, a, b));
+                (rd, value, known(rs1) || known(rs2))
+            },
+            RiscV::Load(_, rd, rs1, imm) => {
+                let addr = self.addr(rs1, imm);
+                self.set(rd, None);
+                return addr.map(Resolved::Addr);
+            },
+            RiscV::Store(_, rs1, _, imm) |
+            RiscV::FLoad(_, _, rs1, imm) |
+            RiscV::FStore(_, rs1, _, imm) => return self.addr(rs1, imm).map(Resolved::Addr),
+            RiscV::Jal(rd, _) => {
+                self.call(rd);
+                (rd, Some(inst.next()), false)
+            },
+            RiscV::Jalr(rd, rs1, imm) => {
+                let target = self.addr(rs1, imm).map(|x| x & !1);
+                self.call(rd);
+                self.set(rd, Some(inst.next()));
+                return target.map(Resolved::Addr);
+            },
+            _ => {
+                for reg in inst.inst.writes().x_regs() {
+                    self.set(reg, None);
+                }
+                return None;
+            },
+        };
+        self.set(rd, value);
+        match (rd.0, report) {
+            (0, _) | (_, false) => None,
+            _ => self.get(rd).map(Resolved::Value),
+        }
+    }
+}
+
+/// propagate through `insts` as one straight-line block
+pub fn annotate(insts: &[Inst], is_32bit: bool) -> Vec<Option<Resolved>> {
+    let mut cp = ConstProp::new(is_32bit);
+    insts.iter().map(|i| cp.step(i)).collect()
+}
+
+/// propagate through every block of `cfg`, starting from nothing known at
+/// each block entry
+pub fn annotate_cfg(cfg: &Cfg, is_32bit: bool) -> BTreeMap<u64, Resolved> {
+    let mut r = BTreeMap::new();
+    for block in cfg.blocks.values() {
+        let notes = annotate(&block.insts, is_32bit);
+        r.extend(block.insts.iter().zip(notes).filter_map(|(i, n)| Some((i.addr, n?))));
+    }
+    r
+}
+
+/// one line per instruction, resolved values as trailing comments
+pub fn listing(insts: &[Inst], is_32bit: bool) -> String {
+    let mut s = String::new();
+    for (inst, note) in insts.iter().zip(annotate(insts, is_32bit)) {
+        match note {
+            Some(note) => writeln!(s, "{:#x}:\t{}\t{}", inst.addr, inst.inst, note).unwrap(),
+            None => writeln!(s, "{:#x}:\t{}", inst.addr, inst.inst).unwrap(),
+        }
+    }
+    s
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::decode_region;
+    use crate::disassembly::riscv::DisasmConfig;
+    use super::*;
+
+    fn insts(code: &[u32]) -> Vec<Inst> {
+        let bytes = code.iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        decode_region(0x80000000, &bytes, &DisasmConfig::default())
+    }
+
+    #[test]
+    fn test_uart() {
+        // lui x10, 0x10000; addi x11, x0, 65; sb x11, 5(x10)
+        let insts = insts(&[0x10000537, 0x04100593, 0x00b502a3]);
+        let notes = annotate(&insts, false);
+        assert_eq!(notes, vec![Some(Resolved::Value(0x10000000)), None, Some(Resolved::Addr(0x10000005))]);
+        assert_eq!(notes[0].unwrap().to_string(), "# 0x10000000 <UART0>");
+        assert_eq!(notes[2].unwrap().to_string(), "# 0x10000005 <UART0+0x5>");
+        assert!(listing(&insts, false).starts_with("0x80000000:\tlui\tx10, 268435456\t# 0x10000000 <UART0>\n"));
+    }
+
+    #[test]
+    fn test_ram_bounds() {
+        assert_eq!(Resolved::Value(0x87fffff0).to_string(), "# 0x87fffff0 <KERNBASE+0x7fffff0>");
+        // PHYSTOP is the end of ram, not a device
+        assert_eq!(Resolved::Value(0x88000000).to_string(), "# 0x88000000");
+    }
+
+    #[test]
+    fn test_auipc() {
+        // auipc x5, 0; addi x5, x5, 16; jalr x1, 0(x5)
+        let notes = annotate(&insts(&[0x00000297, 0x01028293, 0x000280e7]), false);
+        assert_eq!(notes, vec![
+            Some(Resolved::Value(0x80000000)),
+            Some(Resolved::Value(0x80000010)),
+            Some(Resolved::Addr(0x80000010)),
+        ]);
+    }
+
+    #[test]
+    fn test_li64() {
+        // li x10, 0x123456789:
+        // lui x10, 0x123; addiw x10, x10, 0x456; slli x10, x10, 12; addi x10, x10, 0x789
+        let insts = insts(&[0x00123537, 0x4565051b, 0x00c51513, 0x78950513]);
+        let notes = annotate(&insts, false);
+        assert_eq!(notes[3], Some(Resolved::Value(0x123456789)));
+        // addiw sign extends, lui 0x80000 is negative on rv64
+        let notes = annotate(&insts_lui_neg(), false);
+        assert_eq!(notes[1], Some(Resolved::Value(0xffffffff80000001)));
+        assert_eq!(annotate(&insts_lui_neg(), true)[1], Some(Resolved::Value(0x80000001)));
+    }
+
+    // lui x10, 0x80000; addiw x10, x10, 1
+    fn insts_lui_neg() -> Vec<Inst> {
+        insts(&[0x80000537, 0x0015051b])
+    }
+
+    #[test]
+    fn test_clobber() {
+        // lui x10, 0x10000; ld x10, 0(x10); sd x0, 0(x10)
+        let notes = annotate(&insts(&[0x10000537, 0x00053503, 0x00053023]), false);
+        assert_eq!(notes[1], Some(Resolved::Addr(0x10000000)));
+        assert_eq!(notes[2], None);
+    }
+
+    #[test]
+    fn test_call() {
+        // lui x10, 0x10000; lui x9, 0x10000; jal x1, 8; sb x0, 0(x10); sb x0, 0(x9)
+        let notes = annotate(&insts(&[0x10000537, 0x100004b7, 0x008000ef, 0x00050023, 0x00048023]), false);
+        // a0 is clobbered by the callee, s1 survives it
+        assert_eq!(notes[3], None);
+        assert_eq!(notes[4], Some(Resolved::Addr(0x10000000)));
+        // lui x5, 0x10000; jalr x1, 0(x5); sb x0, 0(x5)
+        let notes = annotate(&insts(&[0x100002b7, 0x000280e7, 0x00028023]), false);
+        assert_eq!(notes[1], Some(Resolved::Addr(0x10000000)));
+        assert_eq!(notes[2], None);
+        // jal x0 is a plain jump: lui x10, 0x10000; j 8; sb x0, 0(x10)
+        let notes = annotate(&insts(&[0x10000537, 0x0080006f, 0x00050023]), false);
+        assert_eq!(notes[2], Some(Resolved::Addr(0x10000000)));
+    }
+}
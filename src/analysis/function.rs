@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::disassembly::riscv::DisasmConfig;
+use crate::isa::riscv::{OpType, Reg, RiscV};
+use crate::isa::riscv::regset::RegSet;
+
+use super::cfg::{flow, Edge, EdgeKind};
+use super::recursive::Disassembly;
+use super::{decode_region, Inst};
+
+
+const SP: Reg = Reg(2);
+/// instructions scanned for a prologue, from the entry on
+const PROLOGUE_LEN: usize = 24;
+/// how far after `addi sp, sp, -N` the first callee-saved store must follow
+const PROLOGUE_STORE_WINDOW: usize = 4;
+
+/// ra, s0-s11 and fs0-fs11
+fn callee_saved() -> RegSet {
+    let mut r = RegSet::EMPTY;
+    for i in [1, 8, 9].into_iter().chain(18..=27) {
+        r.insert_x(Reg(i));
+    }
+    r.f = (1 << 8) | (1 << 9) | (0x3ff << 18);
+    r
+}
+
+/// how a function start was found, a function may have several
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Origin {
+    /// entry point or symbol given by the caller
+    Entry,
+    CallTarget,
+    Prologue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub entry: u64,
+    pub origins: BTreeSet<Origin>,
+    /// instructions reachable from the entry without leaving the function
+    pub insts: BTreeSet<u64>,
+    /// bytes reserved by the prologue
+    pub frame_size: u64,
+    /// callee-saved registers spilled by the prologue
+    pub saved: RegSet,
+    pub returns: BTreeSet<u64>,
+    /// (call site, target), indirect calls have no target
+    pub calls: Vec<(u64, Option<u64>)>,
+    /// (jump site, target) of jumps into other functions
+    pub tail_calls: Vec<(u64, u64)>,
+}
+
+impl Function {
+    pub fn has_prologue(&self) -> bool {
+        self.frame_size != 0 || !self.saved.is_empty()
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.insts.contains(&addr)
+    }
+
+    /// address past the highest instruction
+    pub fn end(&self, insts: &BTreeMap<u64, Inst>) -> u64 {
+        self.insts.iter().next_back().map_or(self.entry, |a| insts[a].next())
+    }
+}
+
+/// `addi sp, sp, -N`, `c.addi16sp` expands to the same
+fn sp_alloc(inst: &RiscV) -> Option<u64> {
+    match *inst {
+        RiscV::OpI(OpType::Add, Reg(2), Reg(2), imm) if imm < 0 => Some(-(imm as i64) as u64),
+        RiscV::CmPush(_, adj) => Some(adj as u64),
+        _ => None,
+    }
+}
+
+/// a store of a callee-saved register relative to sp
+fn saves(inst: &RiscV, saved: &RegSet) -> RegSet {
+    match *inst {
+        RiscV::Store(_, base, src, _) if base == SP && saved.contains_x(src) => RegSet::EMPTY.with_x(src),
+        RiscV::FStore(_, base, src, _) if base == SP && saved.contains_f(src) => RegSet::EMPTY.with_f(src),
+        RiscV::CmPush(rlist, _) => rlist.regs().fold(RegSet::EMPTY, |r, x| r.with_x(x)),
+        _ => RegSet::EMPTY,
+    }
+}
+
+/// `addi sp, sp, -N` shortly followed by a spill of a callee-saved
+/// register, or a `cm.push`
+fn is_prologue(insts: &BTreeMap<u64, Inst>, addr: u64) -> bool {
+    let inst = insts[&addr];
+    if matches!(inst.inst, RiscV::CmPush(..)) {
+        return true;
+    }
+    if sp_alloc(&inst.inst).is_none() {
+        return false;
+    }
+    let saved = callee_saved();
+    let mut next = inst.next();
+    for _ in 0..PROLOGUE_STORE_WINDOW {
+        let inst = match insts.get(&next) {
+            Some(i) => i,
+            None => return false,
+        };
+        if !saves(&inst.inst, &saved).is_empty() {
+            return true;
+        }
+        if flow(inst).is_some() {
+            return false;
+        }
+        next = inst.next();
+    }
+    false
+}
+
+/// frame size and spills of the straight-line code at `entry`
+fn prologue(insts: &BTreeMap<u64, Inst>, entry: u64) -> (u64, RegSet) {
+    let callee = callee_saved();
+    let (mut frame, mut saved) = (0, RegSet::EMPTY);
+    let mut addr = entry;
+    for _ in 0..PROLOGUE_LEN {
+        let inst = match insts.get(&addr) {
+            Some(i) => i,
+            None => break,
+        };
+        if let Some(n) = sp_alloc(&inst.inst) {
+            frame += n;
+        }
+        saved |= saves(&inst.inst, &callee);
+        if flow(inst).is_some() {
+            break;
+        }
+        addr = inst.next();
+    }
+    (frame, saved)
+}
+
+/// detect functions over already decoded `insts`. `entries` are known
+/// starts, call targets and prologues add to them. `resolved` carries the
+/// targets of indirect jumps found so far.
+pub fn detect(insts: &BTreeMap<u64, Inst>, resolved: &BTreeMap<u64, Vec<Edge>>, entries: &BTreeSet<u64>) -> Vec<Function> {
+    let mut starts: BTreeMap<u64, BTreeSet<Origin>> = BTreeMap::new();
+    for e in entries.iter().filter(|e| insts.contains_key(e)) {
+        starts.entry(*e).or_default().insert(Origin::Entry);
+    }
+    for inst in insts.values() {
+        let calls = flow(inst).into_iter().flatten()
+            .chain(resolved.get(&inst.addr).into_iter().flatten().copied())
+            .filter(|e| e.kind == EdgeKind::Call)
+            .filter_map(|e| e.target);
+        for t in calls.filter(|t| insts.contains_key(t)) {
+            starts.entry(t).or_default().insert(Origin::CallTarget);
+        }
+        if is_prologue(insts, inst.addr) {
+            starts.entry(inst.addr).or_default().insert(Origin::Prologue);
+        }
+    }
+
+    let mut r = Vec::new();
+    for (entry, origins) in starts.iter() {
+        let (frame_size, saved) = prologue(insts, *entry);
+        let mut f = Function {
+            entry: *entry,
+            origins: origins.clone(),
+            insts: BTreeSet::new(),
+            frame_size,
+            saved,
+            returns: BTreeSet::new(),
+            calls: Vec::new(),
+            tail_calls: Vec::new(),
+        };
+        let mut work = vec![*entry];
+        while let Some(addr) = work.pop() {
+            let inst = match insts.get(&addr) {
+                Some(i) if !f.insts.contains(&addr) => i,
+                _ => continue,
+            };
+            f.insts.insert(addr);
+            let mut edges = match flow(inst) {
+                Some(edges) => edges,
+                None => {
+                    work.push(inst.next());
+                    continue;
+                },
+            };
+            if let Some(extra) = resolved.get(&addr) {
+                edges.retain(|e| e.target.is_some());
+                edges.extend(extra.iter().copied());
+            }
+            for e in edges {
+                match (e.kind, e.target) {
+                    (EdgeKind::Return, _) => {
+                        f.returns.insert(addr);
+                    },
+                    (EdgeKind::Call, t) => f.calls.push((addr, t)),
+                    (_, Some(t)) if t != *entry && starts.contains_key(&t) => f.tail_calls.push((addr, t)),
+                    (_, Some(t)) => work.push(t),
+                    (_, None) => (),
+                }
+            }
+        }
+        f.calls.sort_unstable();
+        f.calls.dedup();
+        r.push(f);
+    }
+    r
+}
+
+/// functions of a recursive traversal
+pub fn from_disassembly(dis: &Disassembly) -> Vec<Function> {
+    detect(&dis.insts, &dis.resolved, &dis.functions)
+}
+
+/// functions of a linear sweep over `code` loaded at `base`, for stripped
+/// images without usable entry points
+pub fn from_region(base: u64, code: &[u8], cfg: &DisasmConfig) -> Vec<Function> {
+    let insts = decode_region(base, code, cfg).into_iter().map(|i| (i.addr, i)).collect();
+    detect(&insts, &BTreeMap::new(), &BTreeSet::new())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(code: &[u32]) -> Vec<u8> {
+        code.iter().flat_map(|i| i.to_le_bytes()).collect()
+    }
+
+    // 0x1000 addi  x2, x2, -32
+    // 0x1004 sd    x1, 24(x2)
+    // 0x1008 sd    x8, 16(x2)
+    // 0x100c jal   x1, 0x1024
+    // 0x1010 ld    x1, 24(x2)
+    // 0x1014 ld    x8, 16(x2)
+    // 0x1018 addi  x2, x2, 32
+    // 0x101c jalr  x0, 0(x1)
+    // 0x1020 .word 0
+    // 0x1024 addi  x10, x10, 1
+    // 0x1028 jalr  x0, 0(x1)
+    // 0x102c addi  x2, x2, -16
+    // 0x1030 sd    x9, 8(x2)
+    // 0x1034 addi  x2, x2, 16
+    // 0x1038 jalr  x0, 0(x1)
+    const CODE: [u32; 15] = [
+        0xfe010113, 0x00113c23, 0x00813823, 0x018000ef, 0x01813083, 0x01013403, 0x02010113, 0x00008067,
+        0x00000000, 0x00150513, 0x00008067,
+        0xff010113, 0x00913423, 0x01010113, 0x00008067,
+    ];
+
+    #[test]
+    fn test_region() {
+        let funcs = from_region(0x1000, &words(&CODE), &DisasmConfig::default());
+        assert_eq!(funcs.iter().map(|f| f.entry).collect::<Vec<_>>(), vec![0x1000, 0x1024, 0x102c]);
+
+        let f = &funcs[0];
+        assert_eq!(f.origins, BTreeSet::from([Origin::Prologue]));
+        assert_eq!(f.frame_size, 32);
+        assert_eq!(f.saved.to_string(), "{x1, x8}");
+        assert_eq!(f.calls, vec![(0x100c, Some(0x1024))]);
+        assert_eq!(f.returns, BTreeSet::from([0x101c]));
+        assert_eq!(f.insts.len(), 8);
+
+        let leaf = &funcs[1];
+        assert_eq!(leaf.origins, BTreeSet::from([Origin::CallTarget]));
+        assert!(leaf.is_leaf() && !leaf.has_prologue());
+
+        assert_eq!(funcs[2].frame_size, 16);
+        assert_eq!(funcs[2].saved.to_string(), "{x9}");
+    }
+
+    #[test]
+    fn test_zcmp() {
+        use crate::isa::riscv::RList;
+        let mut insts = BTreeMap::new();
+        for (addr, inst) in [(0x0, RiscV::CmPush(RList(5), 32)), (0x2, RiscV::Jalr(Reg(0), Reg(1), 0))] {
+            insts.insert(addr, Inst { addr, len: 2, inst });
+        }
+        let funcs = detect(&insts, &BTreeMap::new(), &BTreeSet::new());
+        assert_eq!(funcs[0].frame_size, 32);
+        assert_eq!(funcs[0].saved.to_string(), "{x1, x8}");
+    }
+}
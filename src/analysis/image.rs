@@ -0,0 +1,118 @@
+use crate::elf::{Elf, ElfError, EM_RISCV};
+
+
+/// largest segment `Image::from_elf` zero fills
+pub const MAX_SEGMENT: u64 = 1 << 28;
+
+
+/// a contiguous piece of the address space
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub base: u64,
+    pub data: Vec<u8>,
+    pub exec: bool,
+}
+
+impl Region {
+    pub fn end(&self) -> u64 {
+        self.base + self.data.len() as u64
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.base <= addr && addr < self.end()
+    }
+}
+
+/// the memory view the analyses read code and tables from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    pub regions: Vec<Region>,
+}
+
+impl Image {
+    pub fn new() -> Image {
+        Image::default()
+    }
+
+    pub fn add(&mut self, base: u64, data: Vec<u8>, exec: bool) {
+        self.regions.push(Region { base, data, exec });
+        self.regions.sort_by_key(|r| r.base);
+    }
+
+    /// the loadable segments at their virtual addresses, bss zero filled.
+    /// fails for non risc-v images and for segments over `MAX_SEGMENT`.
+    pub fn from_elf(elf: &Elf) -> Result<Image, ElfError> {
+        if elf.machine != EM_RISCV {
+            return Err(ElfError::BadMachine);
+        }
+        let mut r = Image::new();
+        for seg in elf.segments.iter() {
+            let size = seg.mem_size.max(seg.data.len() as u64);
+            if size > MAX_SEGMENT || seg.vaddr.checked_add(size).is_none() {
+                return Err(ElfError::BadSegment);
+            }
+            let mut data = seg.data.clone();
+            data.resize(size as usize, 0);
+            r.add(seg.vaddr, data, seg.is_exec());
+        }
+        Ok(r)
+    }
+
+    pub fn region(&self, addr: u64) -> Option<&Region> {
+        self.regions.iter().find(|r| r.contains(addr))
+    }
+
+    /// `len` bytes at `addr`, `None` unless they are all inside one region
+    pub fn read(&self, addr: u64, len: usize) -> Option<&[u8]> {
+        let region = self.region(addr)?;
+        let offset = (addr - region.base) as usize;
+        region.data.get(offset..offset.checked_add(len)?)
+    }
+
+    /// everything from `addr` to the end of its region
+    pub fn tail(&self, addr: u64) -> Option<&[u8]> {
+        let region = self.region(addr)?;
+        Some(&region.data[(addr - region.base) as usize..])
+    }
+
+    pub fn read_u16(&self, addr: u64) -> Option<u16> {
+        Some(u16::from_le_bytes(self.read(addr, 2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&self, addr: u64) -> Option<u32> {
+        Some(u32::from_le_bytes(self.read(addr, 4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&self, addr: u64) -> Option<u64> {
+        Some(u64::from_le_bytes(self.read(addr, 8)?.try_into().unwrap()))
+    }
+
+    pub fn is_exec(&self, addr: u64) -> bool {
+        self.region(addr).is_some_and(|r| r.exec)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::elf::{build_elf64, parse, ElfError};
+    use super::*;
+
+    /// a one segment image with its p_memsz and e_machine patched
+    fn elf(vaddr: u64, mem_size: u64, machine: u16) -> Elf {
+        let mut raw = build_elf64(vaddr, &[(vaddr, &[0x13, 0, 0, 0])], &[]);
+        raw[0x12..0x14].copy_from_slice(&machine.to_le_bytes());
+        raw[0x40 + 0x28..0x40 + 0x30].copy_from_slice(&mem_size.to_le_bytes());
+        parse(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_from_elf() {
+        let image = Image::from_elf(&elf(0x1000, 0x10, EM_RISCV)).unwrap();
+        assert_eq!(image.regions[0].data.len(), 0x10);
+        assert_eq!(image.read_u32(0x100c), Some(0));
+        assert_eq!(Image::from_elf(&elf(0x1000, 0x10, 62)), Err(ElfError::BadMachine));
+        assert_eq!(Image::from_elf(&elf(0x1000, MAX_SEGMENT + 1, EM_RISCV)), Err(ElfError::BadSegment));
+        assert_eq!(Image::from_elf(&elf(u64::MAX - 2, 4, EM_RISCV)), Err(ElfError::BadSegment));
+    }
+}
@@ -0,0 +1,789 @@
+use std::fmt::Display;
+
+use crate::isa::riscv::meta::is_link;
+use crate::isa::riscv::regset::JVT;
+use crate::isa::riscv::{
+    BrType, CmMvType, CmPopType, Csr, CsrOpType, EOpType, ExtType, IsFenceI, LoadType, MulOpType, OpType, RList, Reg,
+    RiscV, StoreType,
+};
+
+use super::Inst;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Width {
+    /// conditions
+    W1,
+    W8,
+    W16,
+    W32,
+    W64,
+}
+
+impl Width {
+    pub fn bits(&self) -> u32 {
+        match self {
+            Width::W1 => 1,
+            Width::W8 => 8,
+            Width::W16 => 16,
+            Width::W32 => 32,
+            Width::W64 => 64,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        (self.bits() as u64).div_ceil(8)
+    }
+
+    pub fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.bits())
+    }
+}
+
+impl Display for Width {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "i{}", self.bits())
+    }
+}
+
+/// a temporary, each is assigned exactly once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tmp(pub u32);
+
+impl Display for Tmp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "t{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Value {
+    /// takes the width of the operation using it
+    Const(u64),
+    Tmp(Tmp),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Const(x) => write!(f, "{:#x}", x),
+            Value::Tmp(t) => write!(f, "{}", t),
+        }
+    }
+}
+
+/// comparisons give a `W1` result, division follows the riscv rules for a
+/// zero divisor and overflow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    /// high half of signed * signed
+    MulHs,
+    MulHu,
+    /// high half of signed * unsigned
+    MulHsu,
+    Div,
+    DivU,
+    Rem,
+    RemU,
+    And,
+    Or,
+    Xor,
+    /// shift amounts are masked by the lifter
+    Shl,
+    LShr,
+    AShr,
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    LtU,
+    GeU,
+}
+
+impl BinOp {
+    pub fn is_cmp(&self) -> bool {
+        matches!(self, BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Ge | BinOp::LtU | BinOp::GeU)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::MulHs => "mulhs",
+            BinOp::MulHu => "mulhu",
+            BinOp::MulHsu => "mulhsu",
+            BinOp::Div => "div",
+            BinOp::DivU => "divu",
+            BinOp::Rem => "rem",
+            BinOp::RemU => "remu",
+            BinOp::And => "and",
+            BinOp::Or => "or",
+            BinOp::Xor => "xor",
+            BinOp::Shl => "shl",
+            BinOp::LShr => "lshr",
+            BinOp::AShr => "ashr",
+            BinOp::Eq => "eq",
+            BinOp::Ne => "ne",
+            BinOp::Lt => "lt",
+            BinOp::Ge => "ge",
+            BinOp::LtU => "ltu",
+            BinOp::GeU => "geu",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum JumpKind {
+    Jump,
+    Call,
+    Return,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Trap {
+    Ecall,
+    Ebreak,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stmt {
+    /// read an integer register
+    Get { dst: Tmp, reg: Reg },
+    /// write an integer register, never x0
+    Put { reg: Reg, src: Value },
+    /// `width` is that of the operands
+    Bin { dst: Tmp, op: BinOp, width: Width, a: Value, b: Value },
+    /// widen `src` from `from` to `to` bits
+    Ext { dst: Tmp, signed: bool, from: Width, to: Width, src: Value },
+    Trunc { dst: Tmp, from: Width, to: Width, src: Value },
+    Load { dst: Tmp, width: Width, addr: Value },
+    Store { width: Width, addr: Value, src: Value },
+    CsrRead { dst: Tmp, csr: Csr },
+    CsrWrite { csr: Csr, src: Value },
+    Fence { fence_i: bool },
+    /// taken when `cond` is set, otherwise fall through
+    Branch { cond: Value, target: u64 },
+    Jump { kind: JumpKind, target: Value },
+    Trap(Trap),
+    /// an instruction without modeled semantics, floating point and vendor
+    /// instructions. `args` and `defs` are its integer register operands.
+    Opaque { inst: RiscV, args: Vec<Value>, defs: Vec<Tmp> },
+}
+
+impl Stmt {
+    /// the temporaries defined
+    pub fn defs(&self) -> Vec<Tmp> {
+        match self {
+            Stmt::Get { dst, .. } |
+            Stmt::Bin { dst, .. } |
+            Stmt::Ext { dst, .. } |
+            Stmt::Trunc { dst, .. } |
+            Stmt::Load { dst, .. } |
+            Stmt::CsrRead { dst, .. } => vec![*dst],
+            Stmt::Opaque { defs, .. } => defs.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// the operands
+    pub fn uses(&self) -> Vec<Value> {
+        match self {
+            Stmt::Put { src, .. } |
+            Stmt::Ext { src, .. } |
+            Stmt::Trunc { src, .. } |
+            Stmt::CsrWrite { src, .. } => vec![*src],
+            Stmt::Bin { a, b, .. } => vec![*a, *b],
+            Stmt::Load { addr, .. } => vec![*addr],
+            Stmt::Store { addr, src, .. } => vec![*addr, *src],
+            Stmt::Branch { cond, .. } => vec![*cond],
+            Stmt::Jump { target, .. } => vec![*target],
+            Stmt::Opaque { args, .. } => args.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// rewrite every operand through `f`
+    pub fn map_uses(&mut self, mut f: impl FnMut(Value) -> Value) {
+        match self {
+            Stmt::Put { src, .. } |
+            Stmt::Ext { src, .. } |
+            Stmt::Trunc { src, .. } |
+            Stmt::CsrWrite { src, .. } |
+            Stmt::Load { addr: src, .. } |
+            Stmt::Branch { cond: src, .. } |
+            Stmt::Jump { target: src, .. } => *src = f(*src),
+            Stmt::Bin { a, b, .. } |
+            Stmt::Store { addr: a, src: b, .. } => {
+                *a = f(*a);
+                *b = f(*b);
+            },
+            Stmt::Opaque { args, .. } => args.iter_mut().for_each(|x| *x = f(*x)),
+            _ => (),
+        }
+    }
+
+    /// ends a block
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, Stmt::Branch { .. } | Stmt::Jump { .. } | Stmt::Trap(_))
+    }
+}
+
+impl Display for Stmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stmt::Get { dst, reg } => write!(f, "{} = get {}", dst, reg),
+            Stmt::Put { reg, src } => write!(f, "put {}, {}", reg, src),
+            Stmt::Bin { dst, op, width, a, b } => write!(f, "{} = {} {} {}, {}", dst, op.name(), width, a, b),
+            Stmt::Ext { dst, signed, from, to, src } => {
+                write!(f, "{} = {} {} {} to {}", dst, if *signed { "sext" } else { "zext" }, from, src, to)
+            },
+            Stmt::Trunc { dst, from, to, src } => write!(f, "{} = trunc {} {} to {}", dst, from, src, to),
+            Stmt::Load { dst, width, addr } => write!(f, "{} = load {} [{}]", dst, width, addr),
+            Stmt::Store { width, addr, src } => write!(f, "store {} {}, [{}]", width, src, addr),
+            Stmt::CsrRead { dst, csr } => write!(f, "{} = csrr {}", dst, csr),
+            Stmt::CsrWrite { csr, src } => write!(f, "csrw {}, {}", csr, src),
+            Stmt::Fence { fence_i: false } => write!(f, "fence"),
+            Stmt::Fence { fence_i: true } => write!(f, "fence.i"),
+            Stmt::Branch { cond, target } => write!(f, "br {}, {:#x}", cond, target),
+            Stmt::Jump { kind: JumpKind::Jump, target } => write!(f, "jump {}", target),
+            Stmt::Jump { kind: JumpKind::Call, target } => write!(f, "call {}", target),
+            Stmt::Jump { kind: JumpKind::Return, target } => write!(f, "ret {}", target),
+            Stmt::Trap(Trap::Ecall) => write!(f, "trap ecall"),
+            Stmt::Trap(Trap::Ebreak) => write!(f, "trap ebreak"),
+            Stmt::Opaque { inst, args, defs } => {
+                let join = |v: Vec<String>| v.join(", ");
+                if !defs.is_empty() {
+                    write!(f, "{} = ", join(defs.iter().map(|t| t.to_string()).collect()))?;
+                }
+                write!(f, "opaque \"{}\" ({})", inst, join(args.iter().map(|v| v.to_string()).collect()))
+            },
+        }
+    }
+}
+
+fn sext32(x: u64) -> u64 {
+    x as i32 as i64 as u64
+}
+
+/// lifts instructions into statements, temporaries are numbered across
+/// every `lift` call so the statements of several blocks can be combined
+#[derive(Debug, Clone)]
+pub struct Lifter {
+    pub is_32bit: bool,
+    /// (instruction address, statement)
+    pub stmts: Vec<(u64, Stmt)>,
+    /// width of each temporary
+    pub widths: Vec<Width>,
+    addr: u64,
+}
+
+impl Lifter {
+    pub fn new(is_32bit: bool) -> Lifter {
+        Lifter { is_32bit, stmts: Vec::new(), widths: Vec::new(), addr: 0 }
+    }
+
+    pub fn xlen(&self) -> Width {
+        if self.is_32bit { Width::W32 } else { Width::W64 }
+    }
+
+    pub fn fresh(&mut self, width: Width) -> Tmp {
+        self.widths.push(width);
+        Tmp(self.widths.len() as u32 - 1)
+    }
+
+    pub fn width(&self, t: Tmp) -> Width {
+        self.widths[t.0 as usize]
+    }
+
+    fn emit(&mut self, stmt: Stmt) {
+        self.stmts.push((self.addr, stmt));
+    }
+
+    /// a constant truncated to xlen
+    fn imm(&self, x: u64) -> Value {
+        Value::Const(x & self.xlen().mask())
+    }
+
+    fn get(&mut self, reg: Reg) -> Value {
+        if reg.0 == 0 {
+            return Value::Const(0);
+        }
+        let dst = self.fresh(self.xlen());
+        self.emit(Stmt::Get { dst, reg });
+        Value::Tmp(dst)
+    }
+
+    fn put(&mut self, reg: Reg, src: Value) {
+        if reg.0 != 0 {
+            self.emit(Stmt::Put { reg, src });
+        }
+    }
+
+    fn bin(&mut self, op: BinOp, width: Width, a: Value, b: Value) -> Value {
+        let dst = self.fresh(if op.is_cmp() { Width::W1 } else { width });
+        self.emit(Stmt::Bin { dst, op, width, a, b });
+        Value::Tmp(dst)
+    }
+
+    fn ext(&mut self, signed: bool, from: Width, to: Width, src: Value) -> Value {
+        if from == to {
+            return src;
+        }
+        let dst = self.fresh(to);
+        self.emit(Stmt::Ext { dst, signed, from, to, src });
+        Value::Tmp(dst)
+    }
+
+    fn trunc(&mut self, from: Width, to: Width, src: Value) -> Value {
+        if from == to {
+            return src;
+        }
+        let dst = self.fresh(to);
+        self.emit(Stmt::Trunc { dst, from, to, src });
+        Value::Tmp(dst)
+    }
+
+    fn load(&mut self, width: Width, addr: Value) -> Value {
+        let dst = self.fresh(width);
+        self.emit(Stmt::Load { dst, width, addr });
+        Value::Tmp(dst)
+    }
+
+    fn csr_read(&mut self, csr: Csr) -> Value {
+        let dst = self.fresh(self.xlen());
+        self.emit(Stmt::CsrRead { dst, csr });
+        Value::Tmp(dst)
+    }
+
+    /// `rs1 + imm`
+    fn addr(&mut self, rs1: Reg, imm: i16) -> Value {
+        let base = self.get(rs1);
+        let imm = self.imm(imm as i64 as u64);
+        self.bin(BinOp::Add, self.xlen(), base, imm)
+    }
+
+    /// a shift amount masked to the operation width
+    fn shamt(&mut self, width: Width, b: Value) -> Value {
+        let mask = width.bits() as u64 - 1;
+        match b {
+            Value::Const(x) => Value::Const(x & mask),
+            b => self.bin(BinOp::And, width, b, Value::Const(mask)),
+        }
+    }
+
+    /// an alu operation at `width`, comparisons are zero extended back
+    fn alu(&mut self, ty: OpType, width: Width, a: Value, b: Value) -> Value {
+        let op = match ty {
+            OpType::Add => BinOp::Add,
+            OpType::Sub => BinOp::Sub,
+            OpType::Sll => BinOp::Shl,
+            OpType::Slt => BinOp::Lt,
+            OpType::Sltu => BinOp::LtU,
+            OpType::Xor => BinOp::Xor,
+            OpType::Srl => BinOp::LShr,
+            OpType::Sra => BinOp::AShr,
+            OpType::Or => BinOp::Or,
+            OpType::And => BinOp::And,
+        };
+        let b = match op {
+            BinOp::Shl | BinOp::LShr | BinOp::AShr => self.shamt(width, b),
+            _ => b,
+        };
+        let r = self.bin(op, width, a, b);
+        if op.is_cmp() { self.ext(false, Width::W1, width, r) } else { r }
+    }
+
+    /// a 32-bit operation on rv64: truncate the operands, sign extend the
+    /// result
+    fn op_w(&mut self, a: Value, b: Value, f: impl FnOnce(&mut Lifter, Value, Value) -> Value) -> Value {
+        let xlen = self.xlen();
+        let a = self.trunc(xlen, Width::W32, a);
+        let b = match b {
+            Value::Const(x) => Value::Const(x as u32 as u64),
+            b => self.trunc(xlen, Width::W32, b),
+        };
+        let r = f(self, a, b);
+        self.ext(true, Width::W32, xlen, r)
+    }
+
+    fn mul(&mut self, ty: MulOpType, width: Width, a: Value, b: Value) -> Value {
+        let op = match ty {
+            MulOpType::Mul => BinOp::Mul,
+            MulOpType::Mulh => BinOp::MulHs,
+            MulOpType::Mulhsu => BinOp::MulHsu,
+            MulOpType::Mulhu => BinOp::MulHu,
+            MulOpType::Div => BinOp::Div,
+            MulOpType::Divu => BinOp::DivU,
+            MulOpType::Rem => BinOp::Rem,
+            MulOpType::Remu => BinOp::RemU,
+        };
+        self.bin(op, width, a, b)
+    }
+
+    fn jump(&mut self, kind: JumpKind, target: Value) {
+        self.emit(Stmt::Jump { kind, target });
+    }
+
+    /// zcmp register slots, highest numbered register right below `top`
+    fn rlist_slots(&self, rlist: RList) -> Vec<(Reg, u64)> {
+        let bytes = self.xlen().bytes();
+        let regs = rlist.regs().collect::<Vec<_>>();
+        regs.into_iter().rev().enumerate().map(|(i, r)| (r, (i as u64 + 1) * bytes)).collect()
+    }
+
+    fn csr_op(&mut self, ty: CsrOpType, rd: Reg, src: Option<Value>, csr: Csr) {
+        // csrrw with rd = x0 does not read, csrrs/csrrc with a zero source
+        // do not write
+        let old = match (ty, rd.0) {
+            (CsrOpType::Rw, 0) => None,
+            _ => Some(self.csr_read(csr)),
+        };
+        let new = match (ty, src, old) {
+            (CsrOpType::Rw, Some(v), _) => Some(v),
+            (CsrOpType::Rs, Some(v), Some(old)) => Some(self.bin(BinOp::Or, self.xlen(), old, v)),
+            (CsrOpType::Rc, Some(v), Some(old)) => {
+                let not = self.bin(BinOp::Xor, self.xlen(), v, self.imm(u64::MAX));
+                Some(self.bin(BinOp::And, self.xlen(), old, not))
+            },
+            _ => None,
+        };
+        if let Some(v) = new {
+            self.emit(Stmt::CsrWrite { csr, src: v });
+        }
+        if let Some(old) = old {
+            self.put(rd, old);
+        }
+    }
+
+    pub fn lift(&mut self, inst: &Inst) {
+        self.addr = inst.addr;
+        let xlen = self.xlen();
+        let next = self.imm(inst.next());
+        match inst.inst {
+            RiscV::Lui(rd, imm) => {
+                let v = self.imm(sext32(imm as u64));
+                self.put(rd, v);
+            },
+            RiscV::Auipc(rd, imm) => {
+                let v = self.imm(inst.addr.wrapping_add(sext32(imm as u64)));
+                self.put(rd, v);
+            },
+            RiscV::Jal(rd, _) => {
+                let target = self.imm(inst.target().unwrap());
+                self.put(rd, next);
+                self.jump(if is_link(rd) { JumpKind::Call } else { JumpKind::Jump }, target);
+            },
+            RiscV::Jalr(rd, rs1, imm) => {
+                let a = self.addr(rs1, imm);
+                let target = self.bin(BinOp::And, xlen, a, self.imm(!1));
+                self.put(rd, next);
+                let kind = match (rd.0, is_link(rd), is_link(rs1)) {
+                    (0, _, true) => JumpKind::Return,
+                    (_, true, _) => JumpKind::Call,
+                    _ => JumpKind::Jump,
+                };
+                self.jump(kind, target);
+            },
+            RiscV::Branch(ty, rs1, rs2, _) => {
+                let (a, b) = (self.get(rs1), self.get(rs2));
+                let op = match ty {
+                    BrType::Eq => BinOp::Eq,
+                    BrType::Ne => BinOp::Ne,
+                    BrType::Lt => BinOp::Lt,
+                    BrType::Ge => BinOp::Ge,
+                    BrType::Ltu => BinOp::LtU,
+                    BrType::Geu => BinOp::GeU,
+                };
+                let cond = self.bin(op, xlen, a, b);
+                self.emit(Stmt::Branch { cond, target: inst.target().unwrap() });
+            },
+            RiscV::Load(ty, rd, rs1, imm) => {
+                let (width, signed) = match ty {
+                    LoadType::Byte => (Width::W8, true),
+                    LoadType::Half => (Width::W16, true),
+                    LoadType::Word => (Width::W32, true),
+                    LoadType::Double => (Width::W64, true),
+                    LoadType::ByteU => (Width::W8, false),
+                    LoadType::HalfU => (Width::W16, false),
+                    LoadType::WordU => (Width::W32, false),
+                };
+                let addr = self.addr(rs1, imm);
+                let v = self.load(width, addr);
+                let v = self.ext(signed, width, xlen, v);
+                self.put(rd, v);
+            },
+            RiscV::Store(ty, rs1, rs2, imm) => {
+                let width = match ty {
+                    StoreType::Byte => Width::W8,
+                    StoreType::Half => Width::W16,
+                    StoreType::Word => Width::W32,
+                    StoreType::Double => Width::W64,
+                };
+                let addr = self.addr(rs1, imm);
+                let v = self.get(rs2);
+                let src = match v {
+                    Value::Const(x) => Value::Const(x & width.mask()),
+                    v => self.trunc(xlen, width, v),
+                };
+                self.emit(Stmt::Store { width, addr, src });
+            },
+            RiscV::OpI(ty, rd, rs1, imm) => {
+                let a = self.get(rs1);
+                let v = self.alu(ty, xlen, a, self.imm(imm as i64 as u64));
+                self.put(rd, v);
+            },
+            RiscV::Op(ty, rd, rs1, rs2) => {
+                let (a, b) = (self.get(rs1), self.get(rs2));
+                let v = self.alu(ty, xlen, a, b);
+                self.put(rd, v);
+            },
+            RiscV::OpIW(ty, rd, rs1, imm) => {
+                let a = self.get(rs1);
+                let v = self.op_w(a, Value::Const(imm as i64 as u64), |l, a, b| l.alu(ty, Width::W32, a, b));
+                self.put(rd, v);
+            },
+            RiscV::OpW(ty, rd, rs1, rs2) => {
+                let (a, b) = (self.get(rs1), self.get(rs2));
+                let v = self.op_w(a, b, |l, a, b| l.alu(ty, Width::W32, a, b));
+                self.put(rd, v);
+            },
+            RiscV::MulOp(ty, rd, rs1, rs2) => {
+                let (a, b) = (self.get(rs1), self.get(rs2));
+                let v = self.mul(ty, xlen, a, b);
+                self.put(rd, v);
+            },
+            RiscV::MulOpW(ty, rd, rs1, rs2) => {
+                let (a, b) = (self.get(rs1), self.get(rs2));
+                let v = self.op_w(a, b, |l, a, b| l.mul(ty, Width::W32, a, b));
+                self.put(rd, v);
+            },
+            RiscV::Ext(ty, rd, rs1) => {
+                let (width, signed) = match ty {
+                    ExtType::SextB => (Width::W8, true),
+                    ExtType::SextH => (Width::W16, true),
+                    ExtType::ZextH => (Width::W16, false),
+                    ExtType::ZextW => (Width::W32, false),
+                };
+                let a = self.get(rs1);
+                let t = self.trunc(xlen, width, a);
+                let v = self.ext(signed, width, xlen, t);
+                self.put(rd, v);
+            },
+            RiscV::Fence(IsFenceI(fence_i), _, _) => self.emit(Stmt::Fence { fence_i }),
+            RiscV::EOp(EOpType::Call) => self.emit(Stmt::Trap(Trap::Ecall)),
+            RiscV::EOp(EOpType::Break) => self.emit(Stmt::Trap(Trap::Ebreak)),
+            RiscV::EOp(ty @ (EOpType::Mret | EOpType::Sret)) => {
+                let epc = if ty == EOpType::Mret { Csr(0x341) } else { Csr(0x141) };
+                let target = self.csr_read(epc);
+                self.jump(JumpKind::Return, target);
+            },
+            RiscV::CsrOp(ty, rd, rs1, csr) => {
+                let src = match (ty, rs1.0) {
+                    (CsrOpType::Rw, _) => Some(self.get(rs1)),
+                    (_, 0) => None,
+                    _ => Some(self.get(rs1)),
+                };
+                self.csr_op(ty, rd, src, csr);
+            },
+            RiscV::CsrOpI(ty, rd, zimm, csr) => {
+                let src = match (ty, zimm) {
+                    (CsrOpType::Rw, _) => Some(Value::Const(zimm as u64)),
+                    (_, 0) => None,
+                    _ => Some(Value::Const(zimm as u64)),
+                };
+                self.csr_op(ty, rd, src, csr);
+            },
+            RiscV::CmPush(rlist, adj) => {
+                let sp = self.get(Reg(2));
+                for (reg, off) in self.rlist_slots(rlist) {
+                    let addr = self.bin(BinOp::Sub, xlen, sp, Value::Const(off));
+                    let src = self.get(reg);
+                    self.emit(Stmt::Store { width: xlen, addr, src });
+                }
+                let v = self.bin(BinOp::Sub, xlen, sp, Value::Const(adj as u64));
+                self.put(Reg(2), v);
+            },
+            RiscV::CmPop(ty, rlist, adj) => {
+                let sp = self.get(Reg(2));
+                let top = self.bin(BinOp::Add, xlen, sp, Value::Const(adj as u64));
+                let mut ra = None;
+                for (reg, off) in self.rlist_slots(rlist) {
+                    let addr = self.bin(BinOp::Sub, xlen, top, Value::Const(off));
+                    let v = self.load(xlen, addr);
+                    if reg.0 == 1 {
+                        ra = Some(v);
+                    }
+                    self.put(reg, v);
+                }
+                if ty == CmPopType::PopRetZ {
+                    self.put(Reg(10), Value::Const(0));
+                }
+                self.put(Reg(2), top);
+                if ty != CmPopType::Pop {
+                    self.jump(JumpKind::Return, ra.unwrap());
+                }
+            },
+            RiscV::CmMv(CmMvType::Sa01, r1s, r2s) => {
+                let (a0, a1) = (self.get(Reg(10)), self.get(Reg(11)));
+                self.put(r1s, a0);
+                self.put(r2s, a1);
+            },
+            RiscV::CmMv(CmMvType::A01s, r1s, r2s) => {
+                let (a, b) = (self.get(r1s), self.get(r2s));
+                self.put(Reg(10), a);
+                self.put(Reg(11), b);
+            },
+            RiscV::CmJt(index) | RiscV::CmJalt(index) => {
+                let jvt = self.csr_read(JVT);
+                let base = self.bin(BinOp::And, xlen, jvt, self.imm(!0x3f));
+                let addr = self.bin(BinOp::Add, xlen, base, Value::Const(index as u64 * xlen.bytes()));
+                let entry = self.load(xlen, addr);
+                let target = self.bin(BinOp::And, xlen, entry, self.imm(!1));
+                if matches!(inst.inst, RiscV::CmJalt(_)) {
+                    self.put(Reg(1), next);
+                    self.jump(JumpKind::Call, target);
+                } else {
+                    self.jump(JumpKind::Jump, target);
+                }
+            },
+            _ => {
+                let args = inst.inst.reads().x_regs().map(|r| self.get(r)).collect();
+                let regs = inst.inst.writes().x_regs().collect::<Vec<_>>();
+                let defs = regs.iter().map(|_| self.fresh(xlen)).collect::<Vec<_>>();
+                self.emit(Stmt::Opaque { inst: inst.inst, args, defs: defs.clone() });
+                for (reg, t) in regs.into_iter().zip(defs) {
+                    self.put(reg, Value::Tmp(t));
+                }
+            },
+        }
+    }
+}
+
+/// lift straight-line code
+pub fn lift(insts: &[Inst], is_32bit: bool) -> Lifter {
+    let mut l = Lifter::new(is_32bit);
+    for inst in insts {
+        l.lift(inst);
+    }
+    l
+}
+
+/// one statement per line, prefixed by the instruction address
+pub fn listing(stmts: &[(u64, Stmt)]) -> String {
+    stmts.iter().map(|(addr, s)| format!("{:#x}:\t{}\n", addr, s)).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::decode_region;
+    use crate::disassembly::riscv::DisasmConfig;
+    use super::*;
+
+    fn lifted(code: &[u32], is_32bit: bool) -> Vec<String> {
+        let bytes = code.iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        let insts = decode_region(0x1000, &bytes, &DisasmConfig { is_32bit, ..Default::default() });
+        lift(&insts, is_32bit).stmts.iter().map(|(_, s)| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_op_w() {
+        // addw x10, x11, x12
+        assert_eq!(lifted(&[0x00c5853b], false), vec![
+            "t0 = get x11",
+            "t1 = get x12",
+            "t2 = trunc i64 t0 to i32",
+            "t3 = trunc i64 t1 to i32",
+            "t4 = add i32 t2, t3",
+            "t5 = sext i32 t4 to i64",
+            "put x10, t5",
+        ]);
+        // sraiw x10, x10, 3
+        assert_eq!(lifted(&[0x4035551b], false), vec![
+            "t0 = get x10",
+            "t1 = trunc i64 t0 to i32",
+            "t2 = ashr i32 t1, 0x3",
+            "t3 = sext i32 t2 to i64",
+            "put x10, t3",
+        ]);
+    }
+
+    #[test]
+    fn test_shift_mask() {
+        // sll x10, x11, x12
+        assert_eq!(lifted(&[0x00c59533], false)[2], "t2 = and i64 t1, 0x3f");
+        assert_eq!(lifted(&[0x00c59533], true)[2], "t2 = and i32 t1, 0x1f");
+        // sltu x10, x0, x11
+        assert_eq!(lifted(&[0x00b03533], false), vec![
+            "t0 = get x11",
+            "t1 = ltu i64 0x0, t0",
+            "t2 = zext i1 t1 to i64",
+            "put x10, t2",
+        ]);
+    }
+
+    #[test]
+    fn test_memory() {
+        // lbu x10, 5(x11); sh x10, -2(x2)
+        assert_eq!(lifted(&[0x0055c503, 0xfea11f23], false), vec![
+            "t0 = get x11",
+            "t1 = add i64 t0, 0x5",
+            "t2 = load i8 [t1]",
+            "t3 = zext i8 t2 to i64",
+            "put x10, t3",
+            "t4 = get x2",
+            "t5 = add i64 t4, 0xfffffffffffffffe",
+            "t6 = get x10",
+            "t7 = trunc i64 t6 to i16",
+            "store i16 t7, [t5]",
+        ]);
+        // lw on rv32 needs no extension
+        assert_eq!(lifted(&[0x0005a503], true)[2..], ["t2 = load i32 [t1]", "put x10, t2"]);
+    }
+
+    #[test]
+    fn test_control() {
+        // 0x1000 bltu x10, x11, 0x1008
+        // 0x1004 jal  x1, 0x1000
+        // 0x1008 jalr x0, 0(x1)
+        // 0x100c ecall
+        assert_eq!(lifted(&[0x00b56463, 0xffdff0ef, 0x00008067, 0x00000073], false), vec![
+            "t0 = get x10",
+            "t1 = get x11",
+            "t2 = ltu i64 t0, t1",
+            "br t2, 0x1008",
+            "put x1, 0x1008",
+            "call 0x1000",
+            "t3 = get x1",
+            "t4 = add i64 t3, 0x0",
+            "t5 = and i64 t4, 0xfffffffffffffffe",
+            "ret t5",
+            "trap ecall",
+        ]);
+    }
+
+    #[test]
+    fn test_csr() {
+        // csrrs x10, mstatus, x0; csrrc x0, mie, x11; csrrwi x0, mtvec, 4
+        assert_eq!(lifted(&[0x30002573, 0x3045b073, 0x30525073], false), vec![
+            "t0 = csrr mstatus",
+            "put x10, t0",
+            "t1 = get x11",
+            "t2 = csrr mie",
+            "t3 = xor i64 t1, 0xffffffffffffffff",
+            "t4 = and i64 t2, t3",
+            "csrw mie, t4",
+            "csrw mtvec, 0x4",
+        ]);
+    }
+
+    #[test]
+    fn test_opaque() {
+        // fmv.x.w x10, f1
+        assert_eq!(lifted(&[0xe0008553], false), vec!["t0 = opaque \"fmv.x.w\tx10, f1\" ()", "put x10, t0"]);
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::BTreeMap;
+
+use crate::disassembly::riscv::DisasmConfig;
+use crate::isa::riscv::{BrType, LoadType, OpType, Reg, RiscV};
+
+use super::cfg::{BasicBlock, Cfg, Edge, EdgeKind};
+use super::image::Image;
+use super::recursive::Disassembly;
+use super::Inst;
+
+
+/// blocks walked back from the jump, through single predecessors
+const MAX_DEPTH: usize = 4;
+/// larger tables are more likely a wrong bound than a real switch
+const MAX_ENTRIES: u64 = 4096;
+
+/// symbolic register contents, `v` numbers an opaque value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sym {
+    Const(u64),
+    /// v * scale + off
+    Lin { v: u32, scale: u64, off: u64 },
+    /// mem[v * scale + off] + add
+    Load { v: u32, scale: u64, off: u64, ty: LoadType, add: u64 },
+}
+
+fn sext32(x: u64) -> u64 {
+    x as i32 as i64 as u64
+}
+
+/// a forward evaluation of the instructions on the slice
+struct Slicer<'a> {
+    image: &'a Image,
+    regs: [Sym; 32],
+    next: u32,
+    /// (v, off, count): v + off < count
+    bounds: Vec<(u32, u64, u64)>,
+}
+
+impl Slicer<'_> {
+    fn new(image: &Image) -> Slicer<'_> {
+        let mut regs = [Sym::Const(0); 32];
+        for (i, r) in regs.iter_mut().enumerate().skip(1) {
+            *r = Sym::Lin { v: i as u32, scale: 1, off: 0 };
+        }
+        Slicer { image, regs, next: 32, bounds: Vec::new() }
+    }
+
+    fn get(&self, reg: Reg) -> Sym {
+        self.regs[reg.0 as usize]
+    }
+
+    fn set(&mut self, reg: Reg, sym: Sym) {
+        if reg.0 != 0 {
+            self.regs[reg.0 as usize] = sym;
+        }
+    }
+
+    fn fresh(&mut self) -> Sym {
+        self.next += 1;
+        Sym::Lin { v: self.next, scale: 1, off: 0 }
+    }
+
+    fn add(&mut self, a: Sym, b: Sym) -> Sym {
+        match (a, b) {
+            (Sym::Const(a), Sym::Const(b)) => Sym::Const(a.wrapping_add(b)),
+            (Sym::Lin { v, scale, off }, Sym::Const(c)) |
+            (Sym::Const(c), Sym::Lin { v, scale, off }) => Sym::Lin { v, scale, off: off.wrapping_add(c) },
+            (Sym::Load { v, scale, off, ty, add }, Sym::Const(c)) |
+            (Sym::Const(c), Sym::Load { v, scale, off, ty, add }) => Sym::Load { v, scale, off, ty, add: add.wrapping_add(c) },
+            _ => self.fresh(),
+        }
+    }
+
+    fn shl(&mut self, a: Sym, sh: u32) -> Sym {
+        match a {
+            Sym::Const(c) => Sym::Const(c << sh),
+            Sym::Lin { v, scale, off } => Sym::Lin { v, scale: scale << sh, off: off << sh },
+            _ => self.fresh(),
+        }
+    }
+
+    fn load(&mut self, addr: Sym, ty: LoadType) -> Sym {
+        match addr {
+            Sym::Const(a) => read(self.image, a, ty).map_or_else(|| self.fresh(), Sym::Const),
+            Sym::Lin { v, scale, off } => Sym::Load { v, scale, off, ty, add: 0 },
+            _ => self.fresh(),
+        }
+    }
+
+    fn step(&mut self, inst: &Inst) {
+        let r = match inst.inst {
+            RiscV::Lui(rd, imm) => (rd, Sym::Const(sext32(imm as u64))),
+            RiscV::Auipc(rd, imm) => (rd, Sym::Const(inst.addr.wrapping_add(sext32(imm as u64)))),
+            RiscV::OpI(OpType::Add, rd, rs1, imm) => (rd, self.add(self.get(rs1), Sym::Const(imm as i64 as u64))),
+            // the index is assumed to fit in 32 bits
+            RiscV::OpIW(OpType::Add, rd, rs1, imm) => match self.add(self.get(rs1), Sym::Const(imm as i64 as u64)) {
+                Sym::Const(c) => (rd, Sym::Const(sext32(c))),
+                s => (rd, s),
+            },
+            RiscV::OpI(OpType::Sll, rd, rs1, sh) => (rd, self.shl(self.get(rs1), sh as u32)),
+            RiscV::Op(OpType::Add, rd, rs1, rs2) => (rd, self.add(self.get(rs1), self.get(rs2))),
+            RiscV::Load(ty, rd, rs1, imm) => {
+                let addr = self.add(self.get(rs1), Sym::Const(imm as i64 as u64));
+                (rd, self.load(addr, ty))
+            },
+            _ => {
+                for reg in inst.inst.writes().x_regs() {
+                    let s = self.fresh();
+                    self.set(reg, s);
+                }
+                return;
+            },
+        };
+        self.set(r.0, r.1);
+    }
+
+    /// record the index bound implied by leaving `inst` through `taken`
+    fn branch(&mut self, inst: &Inst, taken: bool) {
+        let (ty, a, b) = match inst.inst {
+            RiscV::Branch(ty, rs1, rs2, _) => (ty, self.get(rs1), self.get(rs2)),
+            _ => return,
+        };
+        let less = match (ty, taken) {
+            (BrType::Ltu, true) | (BrType::Geu, false) => true,
+            (BrType::Ltu, false) | (BrType::Geu, true) => false,
+            _ => return,
+        };
+        match (less, a, b) {
+            // v + off < n
+            (true, Sym::Lin { v, scale: 1, off }, Sym::Const(n)) => self.bounds.push((v, off, n)),
+            // n >= v + off
+            (false, Sym::Const(n), Sym::Lin { v, scale: 1, off }) => self.bounds.push((v, off, n.saturating_add(1))),
+            _ => (),
+        }
+    }
+}
+
+fn read(image: &Image, addr: u64, ty: LoadType) -> Option<u64> {
+    let r = match ty {
+        LoadType::Byte => image.read(addr, 1)?[0] as i8 as i64 as u64,
+        LoadType::ByteU => image.read(addr, 1)?[0] as u64,
+        LoadType::Half => image.read_u16(addr)? as i16 as i64 as u64,
+        LoadType::HalfU => image.read_u16(addr)? as u64,
+        LoadType::Word => image.read_u32(addr)? as i32 as i64 as u64,
+        LoadType::WordU => image.read_u32(addr)? as u64,
+        LoadType::Double => image.read_u64(addr)?,
+    };
+    Some(r)
+}
+
+fn load_size(ty: LoadType) -> usize {
+    match ty {
+        LoadType::Byte | LoadType::ByteU => 1,
+        LoadType::Half | LoadType::HalfU => 2,
+        LoadType::Word | LoadType::WordU => 4,
+        LoadType::Double => 8,
+    }
+}
+
+/// targets found for an indirect jump or call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpTable {
+    pub jump: u64,
+    /// table address and entry size, `None` when the target register held
+    /// a constant
+    pub table: Option<(u64, usize)>,
+    /// in table order, duplicates kept
+    pub targets: Vec<u64>,
+}
+
+/// the blocks leading to `jump`, each with the direction it was left in
+fn slice_path<'a>(cfg: &'a Cfg, preds: &BTreeMap<u64, Vec<u64>>, jump: u64) -> Option<Vec<(&'a BasicBlock, Option<bool>)>> {
+    let mut cur = cfg.block_containing(jump)?;
+    let mut path = vec![(cur, None)];
+    while path.len() < MAX_DEPTH {
+        let pred = match preds.get(&cur.start()).map(|p| p.as_slice()) {
+            Some([pred]) => cfg.block(*pred)?,
+            _ => break,
+        };
+        let kinds = pred.succs.iter()
+            .filter(|e| e.target == Some(cur.start()))
+            .map(|e| e.kind)
+            .collect::<Vec<_>>();
+        let taken = match kinds.as_slice() {
+            [EdgeKind::Taken] => Some(true),
+            [EdgeKind::Fallthrough] => Some(false),
+            _ => None,
+        };
+        if pred.succs.iter().any(|e| e.kind == EdgeKind::Call) {
+            break;
+        }
+        path.insert(0, (pred, taken));
+        cur = pred;
+    }
+    Some(path)
+}
+
+/// resolve the indirect jump or call at `jump` through a backward slice
+/// over its block and the single predecessors before it
+pub fn resolve_jump(cfg: &Cfg, preds: &BTreeMap<u64, Vec<u64>>, image: &Image, is_32bit: bool, jump: u64) -> Option<JumpTable> {
+    let path = slice_path(cfg, preds, jump)?;
+    let mut slicer = Slicer::new(image);
+    for (block, taken) in path.iter() {
+        for inst in block.insts.iter().take_while(|i| i.addr != jump) {
+            match taken {
+                Some(taken) if inst.addr == block.last().addr => slicer.branch(inst, *taken),
+                _ => slicer.step(inst),
+            }
+        }
+    }
+    let (rs1, imm) = match cfg.block_containing(jump)?.insts.iter().find(|i| i.addr == jump)?.inst {
+        RiscV::Jalr(_, rs1, imm) => (rs1, imm as i64 as u64),
+        _ => return None,
+    };
+    let mask = if is_32bit { u32::MAX as u64 } else { u64::MAX };
+    let fix = |t: u64| t.wrapping_add(imm) & mask & !1;
+    let r = match slicer.get(rs1) {
+        Sym::Const(c) => JumpTable { jump, table: None, targets: vec![fix(c)] },
+        Sym::Load { v, scale, off, ty, add } => {
+            let &(_, o, count) = slicer.bounds.iter().find(|b| b.0 == v)?;
+            if count == 0 || count > MAX_ENTRIES {
+                return None;
+            }
+            let targets = (0..count)
+                .map(|k| {
+                    let addr = k.wrapping_sub(o).wrapping_mul(scale).wrapping_add(off) & mask;
+                    read(image, addr, ty).map(|e| fix(e.wrapping_add(add)))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            let table = off.wrapping_sub(o.wrapping_mul(scale)) & mask;
+            JumpTable { jump, table: Some((table, load_size(ty))), targets }
+        },
+        _ => return None,
+    };
+    if !r.targets.iter().all(|t| image.is_exec(*t)) {
+        return None;
+    }
+    Some(r)
+}
+
+fn edge_kind(inst: &Inst) -> EdgeKind {
+    if inst.inst.is_call() { EdgeKind::Call } else { EdgeKind::Indirect }
+}
+
+fn edges(inst: &Inst, table: &JumpTable) -> Vec<Edge> {
+    let mut targets = table.targets.clone();
+    targets.sort_unstable();
+    targets.dedup();
+    targets.into_iter().map(|t| Edge::new(edge_kind(inst), Some(t))).collect()
+}
+
+/// resolve the indirect jumps ending the blocks of a linear `cfg`
+pub fn resolve_cfg(cfg: &Cfg, image: &Image, is_32bit: bool) -> (Cfg, Vec<JumpTable>) {
+    let preds = cfg.preds();
+    let mut extra = BTreeMap::new();
+    let mut tables = Vec::new();
+    for block in cfg.blocks.values() {
+        let last = block.last();
+        if !matches!(last.inst, RiscV::Jalr(..)) || last.inst.is_return() {
+            continue;
+        }
+        if let Some(table) = resolve_jump(cfg, &preds, image, is_32bit, last.addr) {
+            extra.insert(last.addr, edges(last, &table));
+            tables.push(table);
+        }
+    }
+    (Cfg::from_insts_with(cfg.insts().copied().collect(), &extra), tables)
+}
+
+impl Disassembly {
+    /// resolve the unresolved indirect jumps and calls, following the new
+    /// targets until nothing more resolves
+    pub fn resolve_indirect(&mut self, image: &Image, cfg: &DisasmConfig) -> Vec<JumpTable> {
+        let mut r = Vec::new();
+        loop {
+            let graph = self.cfg();
+            let preds = graph.preds();
+            let mut found = Vec::new();
+            for jump in self.unresolved.iter() {
+                if let Some(table) = resolve_jump(&graph, &preds, image, cfg.is_32bit, *jump) {
+                    found.push(table);
+                }
+            }
+            if found.is_empty() {
+                return r;
+            }
+            for table in found {
+                let inst = self.insts[&table.jump];
+                let edges = edges(&inst, &table);
+                self.unresolved.remove(&table.jump);
+                let targets = edges.iter().filter_map(|e| e.target).collect::<Vec<_>>();
+                if edge_kind(&inst) == EdgeKind::Call {
+                    self.explore(image, cfg, targets);
+                } else {
+                    self.follow(image, cfg, targets);
+                }
+                self.resolved.insert(table.jump, edges);
+                r.push(table);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::recursive::recursive_disassembly;
+
+    fn words(code: &[u32]) -> Vec<u8> {
+        code.iter().flat_map(|i| i.to_le_bytes()).collect()
+    }
+
+    // 0x1000 addi  x15, x0, 3
+    // 0x1004 bltu  x15, x10, 0x1030
+    // 0x1008 slli  x10, x10, 2
+    // 0x100c lui   x15, 0x2000
+    // 0x1010 addi  x15, x15, 0
+    // 0x1014 add   x10, x10, x15
+    // 0x1018 lw    x10, 0(x10)
+    // 0x101c jalr  x0, 0(x10)
+    // 0x1020 .. 0x1030 ret
+    const ABS: [u32; 13] = [
+        0x00300793, 0x02a7e663, 0x00251513, 0x000027b7, 0x00078793, 0x00f50533, 0x00052503, 0x00050067,
+        0x00008067, 0x00008067, 0x00008067, 0x00008067, 0x00008067,
+    ];
+
+    fn abs_image() -> Image {
+        let mut image = Image::new();
+        image.add(0x1000, words(&ABS), true);
+        image.add(0x2000, words(&[0x1020, 0x1024, 0x1028, 0x102c]), false);
+        image
+    }
+
+    #[test]
+    fn test_absolute() {
+        let image = abs_image();
+        let cfg = Cfg::build(0x1000, &image.regions[0].data, &DisasmConfig::default());
+        let (cfg, tables) = resolve_cfg(&cfg, &image, false);
+        assert_eq!(tables, vec![JumpTable { jump: 0x101c, table: Some((0x2000, 4)), targets: vec![0x1020, 0x1024, 0x1028, 0x102c] }]);
+        let succs = &cfg.block(0x1008).unwrap().succs;
+        assert_eq!(succs.len(), 4);
+        assert!(succs.iter().all(|e| e.kind == EdgeKind::Indirect));
+        assert_eq!(cfg.preds()[&0x1028], vec![0x1008]);
+    }
+
+    #[test]
+    fn test_traversal() {
+        let image = abs_image();
+        let cfg = DisasmConfig::default();
+        let mut dis = recursive_disassembly(&image, &cfg, &[0x1000]);
+        assert!(dis.unresolved.contains(&0x101c));
+        assert!(!dis.is_code(0x1020));
+        let tables = dis.resolve_indirect(&image, &cfg);
+        assert_eq!(tables.len(), 1);
+        assert!(dis.unresolved.is_empty());
+        assert!(dis.is_code(0x1020) && dis.is_code(0x102c));
+        assert_eq!(dis.cfg().block(0x1008).unwrap().succs.len(), 4);
+    }
+
+    // 0x1000 addi  x15, x0, 2
+    // 0x1004 bgeu  x10, x15, 0x1030
+    // 0x1008 auipc x11, 0x1000
+    // 0x100c addi  x11, x11, -8
+    // 0x1010 slli  x10, x10, 2
+    // 0x1014 add   x10, x10, x11
+    // 0x1018 lw    x10, 0(x10)
+    // 0x101c add   x10, x10, x11
+    // 0x1020 jalr  x0, 0(x10)
+    // 0x1024 .. 0x1030 ret
+    const REL: [u32; 13] = [
+        0x00200793, 0x02f57663, 0x00001597, 0xff858593, 0x00251513, 0x00b50533, 0x00052503, 0x00b50533,
+        0x00050067, 0x00008067, 0x00008067, 0x00008067, 0x00008067,
+    ];
+
+    #[test]
+    fn test_relative() {
+        let mut image = Image::new();
+        image.add(0x1000, words(&REL), true);
+        image.add(0x2000, words(&[(0x1024 - 0x2000) as u32, (0x102c - 0x2000) as u32]), false);
+        let cfg = Cfg::build(0x1000, &image.regions[0].data, &DisasmConfig::default());
+        let (_, tables) = resolve_cfg(&cfg, &image, false);
+        assert_eq!(tables[0].table, Some((0x2000, 4)));
+        assert_eq!(tables[0].targets, vec![0x1024, 0x102c]);
+    }
+
+    #[test]
+    fn test_unbounded() {
+        // without the bounds check the table size is unknown
+        let mut code = ABS;
+        code[1] = 0x00000013;
+        let mut image = Image::new();
+        image.add(0x1000, words(&code), true);
+        image.add(0x2000, words(&[0x1020, 0x1024, 0x1028, 0x102c]), false);
+        let cfg = Cfg::build(0x1000, &image.regions[0].data, &DisasmConfig::default());
+        assert!(resolve_cfg(&cfg, &image, false).1.is_empty());
+    }
+}
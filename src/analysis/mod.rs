@@ -0,0 +1,63 @@
+pub mod callgraph;
+pub mod cfg;
+pub mod constprop;
+pub mod function;
+pub mod image;
+pub mod ir;
+pub mod jumptable;
+pub mod recursive;
+pub mod ssa;
+pub mod stack;
+pub mod xref;
+
+use crate::disassembly::riscv::{disassembly_bytes, DisasmConfig};
+use crate::isa::riscv::RiscV;
+
+
+/// a decoded instruction at its address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inst {
+    pub addr: u64,
+    pub len: u8,
+    pub inst: RiscV,
+}
+
+impl Inst {
+    pub fn next(&self) -> u64 {
+        self.addr.wrapping_add(self.len as u64)
+    }
+
+    /// pc relative target of `jal` and branches
+    pub fn target(&self) -> Option<u64> {
+        let offset = match self.inst {
+            RiscV::Jal(_, offset) => offset as i64,
+            RiscV::Branch(_, _, _, offset) => offset as i64,
+            _ => return None,
+        };
+        Some(self.addr.wrapping_add(offset as u64))
+    }
+}
+
+/// decode the instruction at `addr` of the region starting at `base`
+pub fn decode_at(base: u64, code: &[u8], addr: u64, cfg: &DisasmConfig) -> Option<Inst> {
+    let offset = usize::try_from(addr.checked_sub(base)?).ok()?;
+    let (inst, len) = disassembly_bytes(code.get(offset..)?, cfg)?;
+    Some(Inst { addr, len: len as u8, inst })
+}
+
+/// linear sweep over a region, undecodable halfwords are skipped
+pub fn decode_region(base: u64, code: &[u8], cfg: &DisasmConfig) -> Vec<Inst> {
+    let mut r = Vec::new();
+    let mut offset = 0;
+    while offset + 2 <= code.len() {
+        let addr = base + offset as u64;
+        match decode_at(base, code, addr, cfg) {
+            Some(inst) => {
+                offset += inst.len as usize;
+                r.push(inst);
+            },
+            None => offset += 2,
+        }
+    }
+    r
+}
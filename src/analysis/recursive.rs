@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::disassembly::riscv::{disassembly_bytes, DisasmConfig};
+use crate::elf::{Elf, ElfError};
+
+use super::cfg::{flow, Cfg, Edge, EdgeKind};
+use super::image::Image;
+use super::Inst;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteKind {
+    Code,
+    /// never reached from an entry point, data or dead code
+    Unknown,
+}
+
+/// `[start, end)` of an executable region classified as a whole
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: u64,
+    pub end: u64,
+    pub kind: ByteKind,
+}
+
+/// result of a recursive traversal, only instructions reachable from the
+/// entry points are decoded
+#[derive(Debug, Clone, Default)]
+pub struct Disassembly {
+    pub insts: BTreeMap<u64, Inst>,
+    /// entry points and direct call targets
+    pub functions: BTreeSet<u64>,
+    /// indirect jumps and calls the traversal could not follow
+    pub unresolved: BTreeSet<u64>,
+    pub returns: BTreeSet<u64>,
+    /// direct targets outside the image or not decodable
+    pub bad_targets: BTreeSet<u64>,
+    /// successors of the indirect jumps resolved so far
+    pub resolved: BTreeMap<u64, Vec<Edge>>,
+}
+
+impl Disassembly {
+    pub fn new() -> Disassembly {
+        Disassembly::default()
+    }
+
+    /// follow the control flow from `entries`, already decoded addresses
+    /// are not revisited so this can be called again with new entries
+    pub fn explore(&mut self, image: &Image, cfg: &DisasmConfig, entries: impl IntoIterator<Item = u64>) {
+        let entries = entries.into_iter().collect::<Vec<_>>();
+        self.functions.extend(entries.iter().copied());
+        self.follow(image, cfg, entries);
+    }
+
+    /// like `explore` without marking the addresses as functions
+    pub fn follow(&mut self, image: &Image, cfg: &DisasmConfig, addrs: impl IntoIterator<Item = u64>) {
+        let mut work = addrs.into_iter().collect::<Vec<_>>();
+        while let Some(addr) = work.pop() {
+            if self.insts.contains_key(&addr) {
+                continue;
+            }
+            let inst = match image.tail(addr).and_then(|src| disassembly_bytes(src, cfg)) {
+                Some((inst, len)) => Inst { addr, len: len as u8, inst },
+                None => {
+                    self.bad_targets.insert(addr);
+                    continue;
+                },
+            };
+            self.insts.insert(addr, inst);
+            let edges = match flow(&inst) {
+                Some(edges) => edges,
+                None => {
+                    work.push(inst.next());
+                    continue;
+                },
+            };
+            for e in edges {
+                match (e.kind, e.target) {
+                    (EdgeKind::Return, _) => {
+                        self.returns.insert(addr);
+                    },
+                    (EdgeKind::Call, Some(t)) => {
+                        self.functions.insert(t);
+                        work.push(t);
+                    },
+                    (_, Some(t)) => work.push(t),
+                    (_, None) => {
+                        self.unresolved.insert(addr);
+                    },
+                }
+            }
+        }
+    }
+
+    pub fn cfg(&self) -> Cfg {
+        Cfg::from_insts_with(self.insts.values().copied().collect(), &self.resolved)
+    }
+
+    pub fn is_code(&self, addr: u64) -> bool {
+        self.insts.range(..=addr).next_back()
+            .is_some_and(|(_, i)| addr < i.next())
+    }
+
+    /// classify every byte of the executable regions of `image`
+    pub fn coverage(&self, image: &Image) -> Vec<Span> {
+        let mut r: Vec<Span> = Vec::new();
+        for region in image.regions.iter().filter(|r| r.exec) {
+            let mut addr = region.base;
+            let mut code = self.insts.range(region.base..region.end()).map(|(_, i)| i).peekable();
+            while addr < region.end() {
+                let (end, kind) = match code.peek() {
+                    Some(i) if i.addr <= addr => {
+                        let i = code.next().unwrap();
+                        (i.next().min(region.end()), ByteKind::Code)
+                    },
+                    Some(i) => (i.addr, ByteKind::Unknown),
+                    None => (region.end(), ByteKind::Unknown),
+                };
+                if end <= addr {
+                    // overlapping decode, already covered
+                    continue;
+                }
+                match r.last_mut() {
+                    Some(last) if last.end == addr && last.kind == kind => last.end = end,
+                    _ => r.push(Span { start: addr, end, kind }),
+                }
+                addr = end;
+            }
+        }
+        r
+    }
+}
+
+/// recursive traversal of `image` from user given entry points
+pub fn recursive_disassembly(image: &Image, cfg: &DisasmConfig, entries: &[u64]) -> Disassembly {
+    let mut r = Disassembly::new();
+    r.explore(image, cfg, entries.iter().copied());
+    r
+}
+
+/// recursive traversal from the elf entry and its function symbols
+pub fn disassemble_elf(elf: &Elf) -> Result<(Image, Disassembly), ElfError> {
+    let image = Image::from_elf(elf)?;
+    let cfg = DisasmConfig { is_32bit: elf.is_32bit, ..Default::default() };
+    let r = recursive_disassembly(&image, &cfg, &elf.entry_points());
+    Ok((image, r))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::elf::{build_elf64, parse};
+    use super::*;
+
+    // 0x1000 jal   x1, 0x1010
+    // 0x1004 jalr  x0, 0(x1)
+    // 0x1008 .word 0xffffffff, 0
+    // 0x1010 jalr  x0, 0(x10)
+    const CODE: [u32; 5] = [0x010000ef, 0x00008067, 0xffffffff, 0x00000000, 0x00050067];
+
+    fn code() -> Vec<u8> {
+        CODE.iter().flat_map(|i| i.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_traversal() {
+        let mut image = Image::new();
+        image.add(0x1000, code(), true);
+        let r = recursive_disassembly(&image, &DisasmConfig::default(), &[0x1000]);
+        assert_eq!(r.insts.keys().copied().collect::<Vec<_>>(), vec![0x1000, 0x1004, 0x1010]);
+        assert_eq!(r.functions, BTreeSet::from([0x1000, 0x1010]));
+        assert_eq!(r.returns, BTreeSet::from([0x1004]));
+        assert_eq!(r.unresolved, BTreeSet::from([0x1010]));
+        assert!(r.is_code(0x1006) && !r.is_code(0x1008));
+        assert_eq!(r.coverage(&image), vec![
+            Span { start: 0x1000, end: 0x1008, kind: ByteKind::Code },
+            Span { start: 0x1008, end: 0x1010, kind: ByteKind::Unknown },
+            Span { start: 0x1010, end: 0x1014, kind: ByteKind::Code },
+        ]);
+        assert_eq!(r.cfg().blocks.len(), 3);
+    }
+
+    #[test]
+    fn test_elf() {
+        let code = code();
+        let elf = parse(&build_elf64(0x1000, &[(0x1000, &code)], &[("f", 0x1010, 4)])).unwrap();
+        let (image, r) = disassemble_elf(&elf).unwrap();
+        assert_eq!(image.regions.len(), 1);
+        assert_eq!(r.functions, BTreeSet::from([0x1000, 0x1010]));
+        assert_eq!(r.insts.len(), 3);
+    }
+
+    #[test]
+    fn test_bad_target() {
+        let mut image = Image::new();
+        // j 0x2000
+        image.add(0x1000, 0x0000106f_u32.to_le_bytes().to_vec(), true);
+        let r = recursive_disassembly(&image, &DisasmConfig::default(), &[0x1000]);
+        assert_eq!(r.bad_targets, BTreeSet::from([0x2000]));
+    }
+}
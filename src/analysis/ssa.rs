@@ -0,0 +1,386 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+
+use crate::isa::riscv::Reg;
+
+use super::cfg::{Cfg, EdgeKind};
+use super::ir::{JumpKind, Lifter, Stmt, Tmp, Value, Width};
+
+
+/// registers a callee may change: ra, t0-t6 and a0-a7
+pub(super) const CALLER_SAVED: [u8; 16] = [1, 5, 6, 7, 10, 11, 12, 13, 14, 15, 16, 17, 28, 29, 30, 31];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phi {
+    pub dst: Tmp,
+    pub reg: Reg,
+    /// (predecessor block, incoming value)
+    pub args: Vec<(u64, Value)>,
+}
+
+impl Display for Phi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args = self.args.iter().map(|(b, v)| format!("{:#x}: {}", b, v)).collect::<Vec<_>>();
+        write!(f, "{} = phi {} [{}]", self.dst, self.reg, args.join(", "))
+    }
+}
+
+/// a basic block in ssa form, register `Get`s and `Put`s are resolved into
+/// values. the `Get`s left read registers at the entry or after a call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SsaBlock {
+    pub start: u64,
+    pub phis: Vec<Phi>,
+    pub stmts: Vec<(u64, Stmt)>,
+    pub preds: Vec<u64>,
+    pub succs: Vec<u64>,
+    /// value of each register defined in the block at its end
+    pub out: BTreeMap<Reg, Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ssa {
+    pub entry: u64,
+    /// blocks reachable from the entry
+    pub blocks: BTreeMap<u64, SsaBlock>,
+    /// width of each temporary
+    pub widths: Vec<Width>,
+    /// immediate dominators, the entry dominates itself
+    pub idom: BTreeMap<u64, u64>,
+}
+
+impl Ssa {
+    pub fn width(&self, t: Tmp) -> Width {
+        self.widths[t.0 as usize]
+    }
+
+    pub fn dominates(&self, a: u64, mut b: u64) -> bool {
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idom.get(&b) {
+                Some(d) if *d != b => b = *d,
+                _ => return false,
+            }
+        }
+    }
+
+    /// the statement defining `t`, phis are not statements
+    pub fn def(&self, t: Tmp) -> Option<&(u64, Stmt)> {
+        self.blocks.values()
+            .flat_map(|b| b.stmts.iter())
+            .find(|(_, s)| s.defs().contains(&t))
+    }
+}
+
+impl Display for Ssa {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for block in self.blocks.values() {
+            let preds = block.preds.iter().map(|p| format!("{:#x}", p)).collect::<Vec<_>>();
+            writeln!(f, "block {:#x} <- [{}]", block.start, preds.join(", "))?;
+            for phi in block.phis.iter() {
+                writeln!(f, "\t{}", phi)?;
+            }
+            for (addr, stmt) in block.stmts.iter() {
+                writeln!(f, "{:#x}:\t{}", addr, stmt)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// successors inside the function, calls continue at their fallthrough
+fn succs(cfg: &Cfg, start: u64) -> Vec<u64> {
+    let mut r = cfg.blocks[&start].succs.iter()
+        .filter(|e| matches!(e.kind, EdgeKind::Fallthrough | EdgeKind::Taken | EdgeKind::Indirect))
+        .filter_map(|e| e.target)
+        .filter(|t| cfg.blocks.contains_key(t))
+        .collect::<Vec<_>>();
+    r.dedup();
+    r
+}
+
+struct Renamer<'a> {
+    lifter: &'a mut Lifter,
+    blocks: &'a mut BTreeMap<u64, SsaBlock>,
+    children: &'a BTreeMap<u64, Vec<u64>>,
+    stacks: Vec<Vec<Value>>,
+    subst: BTreeMap<Tmp, Value>,
+    /// registers read before any definition, defined at the entry
+    entry_gets: BTreeMap<Reg, Tmp>,
+}
+
+impl Renamer<'_> {
+    fn current(&mut self, reg: Reg) -> Value {
+        if reg.0 == 0 {
+            return Value::Const(0);
+        }
+        if let Some(v) = self.stacks[reg.0 as usize].last() {
+            return *v;
+        }
+        let xlen = self.lifter.xlen();
+        let lifter = &mut *self.lifter;
+        Value::Tmp(*self.entry_gets.entry(reg).or_insert_with(|| lifter.fresh(xlen)))
+    }
+
+    fn define(&mut self, reg: Reg, v: Value, pushed: &mut Vec<Reg>, out: &mut BTreeMap<Reg, Value>) {
+        self.stacks[reg.0 as usize].push(v);
+        pushed.push(reg);
+        out.insert(reg, v);
+    }
+
+    fn rename(&mut self, start: u64) {
+        let mut pushed = Vec::new();
+        let mut out = BTreeMap::new();
+        let block = self.blocks.get_mut(&start).unwrap();
+        let phis = block.phis.iter().map(|p| (p.reg, p.dst)).collect::<Vec<_>>();
+        let stmts = std::mem::take(&mut block.stmts);
+        for (reg, dst) in phis {
+            self.define(reg, Value::Tmp(dst), &mut pushed, &mut out);
+        }
+
+        let mut r = Vec::new();
+        for (addr, mut stmt) in stmts {
+            stmt.map_uses(|v| match v {
+                Value::Tmp(t) => self.subst.get(&t).copied().unwrap_or(v),
+                v => v,
+            });
+            match stmt {
+                Stmt::Get { dst, reg } => {
+                    let v = self.current(reg);
+                    self.subst.insert(dst, v);
+                },
+                Stmt::Put { reg, src } => self.define(reg, src, &mut pushed, &mut out),
+                Stmt::Jump { kind: JumpKind::Call, .. } => {
+                    r.push((addr, stmt));
+                    for reg in CALLER_SAVED.map(Reg) {
+                        let dst = self.lifter.fresh(self.lifter.xlen());
+                        r.push((addr, Stmt::Get { dst, reg }));
+                        self.define(reg, Value::Tmp(dst), &mut pushed, &mut out);
+                    }
+                },
+                stmt => r.push((addr, stmt)),
+            }
+        }
+
+        let block = self.blocks.get_mut(&start).unwrap();
+        block.stmts = r;
+        block.out = out;
+        for succ in block.succs.clone() {
+            let regs = self.blocks[&succ].phis.iter().map(|p| p.reg).collect::<Vec<_>>();
+            let args = regs.into_iter().map(|reg| self.current(reg)).collect::<Vec<_>>();
+            for (phi, v) in self.blocks.get_mut(&succ).unwrap().phis.iter_mut().zip(args) {
+                phi.args.push((start, v));
+            }
+        }
+        for child in self.children.get(&start).into_iter().flatten() {
+            self.rename(*child);
+        }
+        for reg in pushed {
+            self.stacks[reg.0 as usize].pop();
+        }
+    }
+}
+
+/// minimal ssa of the blocks of `cfg` reachable from `entry`, with phis
+/// placed on the iterated dominance frontiers of the register definitions
+pub fn build(cfg: &Cfg, entry: u64, is_32bit: bool) -> Option<Ssa> {
+    cfg.block(entry)?;
+
+    // reverse postorder
+    let mut order = Vec::new();
+    let mut seen = BTreeSet::from([entry]);
+    let mut stack = vec![(entry, succs(cfg, entry), 0)];
+    while let Some((b, s, i)) = stack.last_mut() {
+        match s.get(*i) {
+            Some(t) => {
+                let t = *t;
+                *i += 1;
+                if seen.insert(t) {
+                    stack.push((t, succs(cfg, t), 0));
+                }
+            },
+            None => {
+                order.push(*b);
+                stack.pop();
+            },
+        }
+    }
+    order.reverse();
+    let index = order.iter().enumerate().map(|(i, b)| (*b, i)).collect::<BTreeMap<_, _>>();
+
+    let mut lifter = Lifter::new(is_32bit);
+    let mut blocks = BTreeMap::new();
+    for b in order.iter() {
+        for inst in cfg.blocks[b].insts.iter() {
+            lifter.lift(inst);
+        }
+        let block = SsaBlock {
+            start: *b,
+            phis: Vec::new(),
+            stmts: std::mem::take(&mut lifter.stmts),
+            preds: Vec::new(),
+            succs: succs(cfg, *b),
+            out: BTreeMap::new(),
+        };
+        blocks.insert(*b, block);
+    }
+    for b in order.iter() {
+        for s in blocks[b].succs.clone() {
+            blocks.get_mut(&s).unwrap().preds.push(*b);
+        }
+    }
+
+    // cooper, harvey and kennedy's iterative dominators
+    let mut idom = BTreeMap::from([(entry, entry)]);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in order.iter().skip(1) {
+            let mut new: Option<u64> = None;
+            for p in blocks[b].preds.iter().filter(|p| idom.contains_key(p)) {
+                new = Some(match new {
+                    None => *p,
+                    Some(mut x) => {
+                        let mut y = *p;
+                        while x != y {
+                            while index[&x] > index[&y] {
+                                x = idom[&x];
+                            }
+                            while index[&y] > index[&x] {
+                                y = idom[&y];
+                            }
+                        }
+                        x
+                    },
+                });
+            }
+            if let Some(new) = new {
+                if idom.get(b) != Some(&new) {
+                    idom.insert(*b, new);
+                    changed = true;
+                }
+            }
+        }
+    }
+    let mut children: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    for (b, d) in idom.iter().filter(|(b, d)| b != d) {
+        children.entry(*d).or_default().push(*b);
+    }
+
+    let mut frontier: BTreeMap<u64, BTreeSet<u64>> = BTreeMap::new();
+    for block in blocks.values().filter(|b| b.preds.len() > 1) {
+        for p in block.preds.iter() {
+            let mut runner = *p;
+            while runner != idom[&block.start] {
+                frontier.entry(runner).or_default().insert(block.start);
+                runner = idom[&runner];
+            }
+        }
+    }
+
+    // phi placement
+    let mut defsites: BTreeMap<Reg, BTreeSet<u64>> = BTreeMap::new();
+    for block in blocks.values() {
+        for (_, stmt) in block.stmts.iter() {
+            match stmt {
+                Stmt::Put { reg, .. } => {
+                    defsites.entry(*reg).or_default().insert(block.start);
+                },
+                Stmt::Jump { kind: JumpKind::Call, .. } => {
+                    for reg in CALLER_SAVED.map(Reg) {
+                        defsites.entry(reg).or_default().insert(block.start);
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+    for (reg, sites) in defsites {
+        let mut work = sites.iter().copied().collect::<Vec<_>>();
+        let mut placed = BTreeSet::new();
+        while let Some(b) = work.pop() {
+            for d in frontier.get(&b).into_iter().flatten() {
+                if placed.insert(*d) {
+                    let dst = lifter.fresh(lifter.xlen());
+                    blocks.get_mut(d).unwrap().phis.push(Phi { dst, reg, args: Vec::new() });
+                    if !sites.contains(d) {
+                        work.push(*d);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut renamer = Renamer {
+        lifter: &mut lifter,
+        blocks: &mut blocks,
+        children: &children,
+        stacks: vec![Vec::new(); 32],
+        subst: BTreeMap::new(),
+        entry_gets: BTreeMap::new(),
+    };
+    renamer.rename(entry);
+    let gets = renamer.entry_gets.iter().map(|(reg, dst)| (entry, Stmt::Get { dst: *dst, reg: *reg }));
+    let entry_block = blocks.get_mut(&entry).unwrap();
+    entry_block.stmts.splice(0..0, gets.collect::<Vec<_>>());
+
+    Some(Ssa { entry, blocks, widths: lifter.widths, idom })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::disassembly::riscv::DisasmConfig;
+    use super::*;
+
+    fn ssa(code: &[u32]) -> Ssa {
+        let bytes = code.iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        build(&Cfg::build(0x1000, &bytes, &DisasmConfig::default()), 0x1000, false).unwrap()
+    }
+
+    #[test]
+    fn test_loop() {
+        // 0x1000 addi  x10, x0, 0
+        // 0x1004 addi  x10, x10, 1
+        // 0x1008 bltu  x10, x11, 0x1004
+        // 0x100c jalr  x0, 0(x1)
+        let ssa = ssa(&[0x00000513, 0x00150513, 0xfeb56ee3, 0x00008067]);
+        assert_eq!(ssa.to_string(), "\
+block 0x1000 <- []
+0x1000:\tt11 = get x1
+0x1000:\tt10 = get x11
+0x1000:\tt0 = add i64 0x0, 0x0
+block 0x1004 <- [0x1000, 0x1004]
+\tt9 = phi x10 [0x1000: t0, 0x1004: t2]
+0x1004:\tt2 = add i64 t9, 0x1
+0x1008:\tt5 = ltu i64 t2, t10
+0x1008:\tbr t5, 0x1004
+block 0x100c <- [0x1004]
+0x100c:\tt7 = add i64 t11, 0x0
+0x100c:\tt8 = and i64 t7, 0xfffffffffffffffe
+0x100c:\tret t8
+");
+        assert_eq!(ssa.blocks[&0x1004].out, BTreeMap::from([(Reg(10), Value::Tmp(Tmp(2)))]));
+        assert_eq!(ssa.idom[&0x100c], 0x1004);
+        assert!(ssa.dominates(0x1000, 0x100c) && !ssa.dominates(0x100c, 0x1004));
+    }
+
+    #[test]
+    fn test_diamond_call() {
+        // 0x1000 beq   x10, x0, 0x100c
+        // 0x1004 jal   x1, 0x1020
+        // 0x1008 jal   x0, 0x1010
+        // 0x100c addi  x10, x0, 7
+        // 0x1010 jalr  x0, 0(x5)
+        let ssa = ssa(&[0x00050663, 0x01c000ef, 0x0080006f, 0x00700513, 0x00028067]);
+        let join = &ssa.blocks[&0x1010];
+        assert_eq!(join.preds, vec![0x1008, 0x100c]);
+        let a0 = join.phis.iter().find(|p| p.reg == Reg(10)).unwrap();
+        // the call result on one side, the constant on the other
+        assert!(matches!(a0.args[..], [(0x1008, Value::Tmp(_)), (0x100c, Value::Tmp(_))]));
+        assert!(join.phis.iter().any(|p| p.reg == Reg(5)));
+        assert_eq!(ssa.idom[&0x1010], 0x1000);
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+
+use crate::isa::riscv::{OpType, Reg, RiscV};
+
+use super::callgraph::CallGraph;
+use super::cfg::{flow, Edge, EdgeKind};
+use super::constprop::ConstProp;
+use super::function::{self, Function};
+use super::recursive::Disassembly;
+use super::Inst;
+
+
+const SP: Reg = Reg(2);
+const S0: Reg = Reg(8);
+/// times the sp offset at one instruction may deepen before the function is
+/// considered to grow its stack in a loop
+const MAX_WIDEN: usize = 8;
+
+/// stack use of one function on its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameUsage {
+    pub entry: u64,
+    /// deepest sp reached below the entry sp
+    pub max_local: u64,
+    /// (site, target, bytes in use at the site), tail calls included
+    pub calls: Vec<(u64, Option<u64>, u64)>,
+    /// sites moving sp by an unknown amount or deepening it in a loop
+    pub dynamic: Vec<u64>,
+}
+
+impl FrameUsage {
+    /// bytes in use at the call `site`
+    pub fn depth_at(&self, site: u64) -> u64 {
+        self.calls.iter().find(|(s, _, _)| *s == site).map_or(0, |(_, _, d)| *d)
+    }
+}
+
+/// why a stack bound does not hold
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Unbounded {
+    DynamicSp { func: u64, site: u64 },
+    /// the functions of a recursive cycle
+    Recursion(Vec<u64>),
+    IndirectCall { func: u64, site: u64 },
+    /// call target that is not a detected function
+    UnknownCallee { func: u64, site: u64, target: u64 },
+}
+
+impl Display for Unbounded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unbounded::DynamicSp { func, site } => write!(f, "dynamic sp adjustment at {:#x} in {:#x}", site, func),
+            Unbounded::Recursion(scc) => {
+                let funcs = scc.iter().map(|x| format!("{:#x}", x)).collect::<Vec<_>>();
+                write!(f, "recursion through {}", funcs.join(", "))
+            },
+            Unbounded::IndirectCall { func, site } => write!(f, "indirect call at {:#x} in {:#x}", site, func),
+            Unbounded::UnknownCallee { func, site, target } => write!(f, "call to unknown {:#x} at {:#x} in {:#x}", target, site, func),
+        }
+    }
+}
+
+/// one frame of the worst-case path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    pub func: u64,
+    /// call into the next step, `None` for the last frame
+    pub site: Option<u64>,
+    /// bytes of this frame in use at `site`, or its deepest point
+    pub depth: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackReport {
+    pub root: u64,
+    /// bytes below the root entry sp on the deepest path found
+    pub worst: u64,
+    pub path: Vec<Step>,
+    /// reasons `worst` is only a lower bound, empty if it is exact
+    pub unbounded: Vec<Unbounded>,
+}
+
+impl StackReport {
+    pub fn is_bounded(&self) -> bool {
+        self.unbounded.is_empty()
+    }
+
+    /// the bound is proven and within a stack of `size` bytes
+    pub fn fits(&self, size: u64) -> bool {
+        self.is_bounded() && self.worst <= size
+    }
+}
+
+impl Display for StackReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bound = if self.is_bounded() { "" } else { " (unbounded)" };
+        writeln!(f, "worst case stack from {:#x}: {} bytes{}", self.root, self.worst, bound)?;
+        for step in self.path.iter() {
+            match step.site {
+                Some(site) => writeln!(f, "    {:#x}: {} bytes, call at {:#x}", step.func, step.depth, site)?,
+                None => writeln!(f, "    {:#x}: {} bytes", step.func, step.depth)?,
+            }
+        }
+        for u in self.unbounded.iter() {
+            writeln!(f, "    {}", u)?;
+        }
+        Ok(())
+    }
+}
+
+/// sp offset from the entry after `inst`, `None` for an unknown amount.
+/// `cp` holds the constants before `inst`, register amounts resolve through
+/// it.
+fn sp_delta(inst: &RiscV, d: i64, cp: &ConstProp) -> Option<i64> {
+    let reg = |r: Reg| cp.get(r).map(|x| x as i64);
+    match *inst {
+        RiscV::OpI(OpType::Add, SP, SP, imm) => Some(d + imm as i64),
+        RiscV::CmPush(_, adj) => Some(d - adj as i64),
+        RiscV::CmPop(_, _, adj) => Some(d + adj as i64),
+        RiscV::Op(OpType::Sub, SP, SP, rs) => d.checked_sub(reg(rs)?),
+        RiscV::Op(OpType::Add, SP, SP, rs) | RiscV::Op(OpType::Add, SP, rs, SP) => d.checked_add(reg(rs)?),
+        // a restore from the frame pointer, `addi sp, s0, imm` or `c.mv sp, s0`,
+        // does not deepen the frame
+        RiscV::OpI(OpType::Add, SP, S0, _) | RiscV::Op(OpType::Add, SP, Reg(0), S0) => Some(d),
+        _ if inst.writes().contains_x(SP) => None,
+        _ => Some(d),
+    }
+}
+
+/// track the sp offset over the instructions of `f`, calls and tail calls
+/// are not followed
+pub fn frame_usage(f: &Function, insts: &BTreeMap<u64, Inst>, resolved: &BTreeMap<u64, Vec<Edge>>) -> FrameUsage {
+    let tail_calls = f.tail_calls.iter().copied().collect::<BTreeSet<_>>();
+    let mut r = FrameUsage { entry: f.entry, max_local: 0, calls: Vec::new(), dynamic: Vec::new() };
+    // sp offset and known constants before each instruction. rv64
+    // arithmetic gives the sign extended value of an rv32 `li` as well.
+    let mut state: BTreeMap<u64, (i64, ConstProp)> = BTreeMap::new();
+    let mut widen: BTreeMap<u64, usize> = BTreeMap::new();
+    let mut dynamic = BTreeSet::new();
+    let mut work = vec![(f.entry, 0, ConstProp::new(false))];
+    while let Some((addr, mut d, mut cp)) = work.pop() {
+        let inst = match insts.get(&addr) {
+            Some(i) if f.contains(addr) => i,
+            _ => continue,
+        };
+        if let Some((old, old_cp)) = state.get(&addr) {
+            let joined = old_cp.meet(&cp);
+            if *old <= d && joined == *old_cp {
+                continue;
+            }
+            if d < *old {
+                let n = widen.entry(addr).or_default();
+                *n += 1;
+                if *n > MAX_WIDEN {
+                    dynamic.insert(addr);
+                    continue;
+                }
+            }
+            d = d.min(*old);
+            cp = joined;
+        }
+        state.insert(addr, (d, cp.clone()));
+        let after = sp_delta(&inst.inst, d, &cp).unwrap_or_else(|| {
+            dynamic.insert(addr);
+            d
+        });
+        cp.step(inst);
+        let mut edges = match flow(inst) {
+            Some(edges) => edges,
+            None => {
+                work.push((inst.next(), after, cp));
+                continue;
+            },
+        };
+        if let Some(extra) = resolved.get(&addr) {
+            edges.retain(|e| e.target.is_some());
+            edges.extend(extra.iter().copied());
+        }
+        for e in edges {
+            match (e.kind, e.target) {
+                (EdgeKind::Return, _) | (EdgeKind::Call, _) => (),
+                (_, Some(t)) if tail_calls.contains(&(addr, t)) => (),
+                (_, Some(t)) => work.push((t, after, cp.clone())),
+                (_, None) => (),
+            }
+        }
+    }
+
+    let depth = |addr: &u64| state.get(addr).map_or(0, |(d, _)| (-d).max(0) as u64);
+    r.max_local = state.iter()
+        .map(|(a, (d, cp))| sp_delta(&insts[a].inst, *d, cp).unwrap_or(*d).min(*d))
+        .map(|d| (-d).max(0) as u64)
+        .max()
+        .unwrap_or(0);
+    r.calls = f.calls.iter().map(|(site, t)| (*site, *t, depth(site)))
+        .chain(f.tail_calls.iter().map(|(site, t)| (*site, Some(*t), depth(site))))
+        .collect();
+    r.dynamic = dynamic.into_iter().collect();
+    r
+}
+
+/// per-function frames composed over the call graph
+#[derive(Debug, Clone)]
+pub struct StackAnalysis {
+    pub frames: BTreeMap<u64, FrameUsage>,
+    pub graph: CallGraph,
+    sccs: Vec<Vec<u64>>,
+}
+
+impl StackAnalysis {
+    pub fn new(dis: &Disassembly) -> StackAnalysis {
+        StackAnalysis::from_functions(&function::from_disassembly(dis), &dis.insts, &dis.resolved)
+    }
+
+    pub fn from_functions(funcs: &[Function], insts: &BTreeMap<u64, Inst>, resolved: &BTreeMap<u64, Vec<Edge>>) -> StackAnalysis {
+        let frames = funcs.iter().map(|f| (f.entry, frame_usage(f, insts, resolved))).collect();
+        let graph = CallGraph::new(funcs);
+        let sccs = graph.sccs();
+        StackAnalysis { frames, graph, sccs }
+    }
+
+    /// worst case from `root`, e.g. the kernel entry running on the boot
+    /// stack. the frame usages weigh `CallGraph::heaviest_path`: a frame
+    /// adds its depth at the call taken, or its deepest point at the end.
+    pub fn report(&self, root: u64) -> Option<StackReport> {
+        let depth = |func: u64, site: Option<u64>| {
+            let frame = &self.frames[&func];
+            site.map_or(frame.max_local, |site| frame.depth_at(site))
+        };
+        let best = self.graph.heaviest_path(root, |n, site| depth(n.entry, site))?;
+        let sites = best.sites.iter().map(|s| Some(*s)).chain([None]);
+        let path = best.path.iter().zip(sites)
+            .map(|(func, site)| Step { func: *func, site, depth: depth(*func, site) })
+            .collect();
+        Some(StackReport { root, worst: best.total, path, unbounded: self.unbounded(root) })
+    }
+
+    /// everything reachable from `root` that the bound can not account for
+    fn unbounded(&self, root: u64) -> Vec<Unbounded> {
+        let recursive = self.graph.recursive();
+        let mut r = BTreeSet::new();
+        for func in self.graph.reachable(root) {
+            let frame = &self.frames[&func];
+            r.extend(frame.dynamic.iter().map(|site| Unbounded::DynamicSp { func, site: *site }));
+            for (site, target, _) in frame.calls.iter().copied() {
+                match target {
+                    Some(t) if self.frames.contains_key(&t) => (),
+                    Some(target) => { r.insert(Unbounded::UnknownCallee { func, site, target }); },
+                    None => { r.insert(Unbounded::IndirectCall { func, site }); },
+                }
+            }
+            if recursive.contains(&func) {
+                let scc = self.sccs.iter().find(|s| s.contains(&func)).cloned().unwrap_or_default();
+                r.insert(Unbounded::Recursion(scc));
+            }
+        }
+        r.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::image::Image;
+    use crate::analysis::recursive::recursive_disassembly;
+    use crate::disassembly::riscv::DisasmConfig;
+    use super::*;
+
+    // 0x1000 addi  x2, x2, -16
+    // 0x1004 sd    x1, 8(x2)
+    // 0x1008 jal   x1, 0x1020
+    // 0x100c jalr  x1, 0(x15)
+    // 0x1010 ld    x1, 8(x2)
+    // 0x1014 addi  x2, x2, 16
+    // 0x1018 jalr  x0, 0(x1)
+    // 0x101c nop
+    // 0x1020 addi  x2, x2, -32
+    // 0x1024 sd    x1, 24(x2)
+    // 0x1028 jal   x1, 0x1040
+    // 0x102c jal   x1, 0x1060
+    // 0x1030 ld    x1, 24(x2)
+    // 0x1034 addi  x2, x2, 32
+    // 0x1038 jalr  x0, 0(x1)
+    // 0x103c nop
+    // 0x1040 addi  x2, x2, -48
+    // 0x1044 sd    x8, 40(x2)
+    // 0x1048 sub   x2, x2, x10
+    // 0x104c addi  x2, x8, 0
+    // 0x1050 ld    x8, 40(x2)
+    // 0x1054 addi  x2, x2, 48
+    // 0x1058 jalr  x0, 0(x1)
+    // 0x105c nop
+    // 0x1060 addi  x2, x2, -16
+    // 0x1064 sd    x1, 8(x2)
+    // 0x1068 beq   x10, x0, 0x1074
+    // 0x106c addi  x10, x10, -1
+    // 0x1070 jal   x1, 0x1060
+    // 0x1074 ld    x1, 8(x2)
+    // 0x1078 addi  x2, x2, 16
+    // 0x107c jalr  x0, 0(x1)
+    const CODE: [u32; 32] = [
+        0xff010113, 0x00113423, 0x018000ef, 0x000780e7, 0x00813083, 0x01010113, 0x00008067, 0x00000013,
+        0xfe010113, 0x00113c23, 0x018000ef, 0x034000ef, 0x01813083, 0x02010113, 0x00008067, 0x00000013,
+        0xfd010113, 0x02813423, 0x40a10133, 0x00040113, 0x02813403, 0x03010113, 0x00008067, 0x00000013,
+        0xff010113, 0x00113423, 0x00050663, 0xfff50513, 0xff1ff0ef, 0x00813083, 0x01010113, 0x00008067,
+    ];
+
+    fn analysis() -> StackAnalysis {
+        let mut image = Image::new();
+        image.add(0x1000, CODE.iter().flat_map(|i| i.to_le_bytes()).collect(), true);
+        StackAnalysis::new(&recursive_disassembly(&image, &DisasmConfig::default(), &[0x1000]))
+    }
+
+    #[test]
+    fn test_frames() {
+        let a = analysis();
+        assert_eq!(a.frames[&0x1020].max_local, 32);
+        assert_eq!(a.frames[&0x1020].calls, vec![(0x1028, Some(0x1040), 32), (0x102c, Some(0x1060), 32)]);
+        assert_eq!(a.frames[&0x1040].max_local, 48);
+        assert_eq!(a.frames[&0x1040].dynamic, vec![0x1048]);
+        assert_eq!(a.frames[&0x1060].calls, vec![(0x1070, Some(0x1060), 16)]);
+    }
+
+    #[test]
+    fn test_report() {
+        let a = analysis();
+        let r = a.report(0x1000).unwrap();
+        // main -> f -> g = 16 + 32 + 48
+        assert_eq!(r.worst, 96);
+        assert_eq!(r.path, vec![
+            Step { func: 0x1000, site: Some(0x1008), depth: 16 },
+            Step { func: 0x1020, site: Some(0x1028), depth: 32 },
+            Step { func: 0x1040, site: None, depth: 48 },
+        ]);
+        assert_eq!(r.unbounded, vec![
+            Unbounded::DynamicSp { func: 0x1040, site: 0x1048 },
+            Unbounded::Recursion(vec![0x1060]),
+            Unbounded::IndirectCall { func: 0x1000, site: 0x100c },
+        ]);
+        assert!(!r.fits(4096));
+        assert!(r.to_string().starts_with("worst case stack from 0x1000: 96 bytes (unbounded)\n"));
+
+        // the leaf recursion on its own
+        let r = a.report(0x1060).unwrap();
+        assert_eq!((r.worst, r.unbounded.len()), (16, 1));
+        assert!(a.report(0x1040).unwrap().unbounded.len() == 1);
+        assert!(a.report(0x2000).is_none());
+    }
+
+    #[test]
+    fn test_bounded() {
+        // addi x2, x2, -64; addi x2, x2, 64; jalr x0, 0(x1)
+        let mut image = Image::new();
+        image.add(0x80000000, [0xfc010113u32, 0x04010113, 0x00008067].iter().flat_map(|i| i.to_le_bytes()).collect(), true);
+        let a = StackAnalysis::new(&recursive_disassembly(&image, &DisasmConfig::default(), &[0x80000000]));
+        let r = a.report(0x80000000).unwrap();
+        assert!(r.is_bounded() && r.fits(64) && !r.fits(63));
+    }
+
+    fn frame(code: &[u32]) -> FrameUsage {
+        let mut image = Image::new();
+        image.add(0x80000000, code.iter().flat_map(|i| i.to_le_bytes()).collect(), true);
+        let a = StackAnalysis::new(&recursive_disassembly(&image, &DisasmConfig::default(), &[0x80000000]));
+        a.frames[&0x80000000].clone()
+    }
+
+    #[test]
+    fn test_constant_frame() {
+        // addi x5, x0, 256; sub x2, x2, x5; add x2, x2, x5; jalr x0, 0(x1)
+        let f = frame(&[0x10000293, 0x40510133, 0x00510133, 0x00008067]);
+        assert_eq!((f.max_local, f.dynamic), (256, vec![]));
+        // x5 is only known on one path into the sub:
+        // beq x10, x0, 8; addi x5, x0, 64; sub x2, x2, x5; addi x2, x8, 0; jalr x0, 0(x1)
+        let f = frame(&[0x00050463, 0x04000293, 0x40510133, 0x00040113, 0x00008067]);
+        assert_eq!(f.dynamic, vec![0x80000008]);
+    }
+
+    #[test]
+    fn test_sp_writes() {
+        // andi x2, x2, -64; addi x2, x10, 0; ld x2, 0(x10); add x2, x0, x8; jalr x0, 0(x1)
+        let f = frame(&[0xfc017113, 0x00050113, 0x00053103, 0x00800133, 0x00008067]);
+        // only the frame pointer restore is trusted
+        assert_eq!(f.dynamic, vec![0x80000000, 0x80000004, 0x80000008]);
+    }
+}
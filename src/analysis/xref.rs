@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use crate::isa::riscv::{Csr, RiscV};
+use crate::isa::riscv::bare::memory_layout::region_of;
+
+use super::cfg::EdgeKind;
+use super::constprop::{ConstProp, Resolved};
+use super::image::Image;
+use super::recursive::Disassembly;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum XrefKind {
+    Call,
+    /// branch, jump or tail call into the address
+    Jump,
+    Read,
+    Write,
+    /// the address was materialized into a register, e.g. `la`
+    Ref,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Xref {
+    /// address of the referencing instruction
+    pub from: u64,
+    pub kind: XrefKind,
+}
+
+/// code, data and csr references of a disassembled image
+#[derive(Debug, Clone, Default)]
+pub struct XrefDb {
+    to: BTreeMap<u64, Vec<Xref>>,
+    csrs: BTreeMap<u16, Vec<Xref>>,
+    /// (site, target) of every address reference, for lookups by site
+    from: BTreeMap<u64, Vec<(u64, XrefKind)>>,
+}
+
+impl XrefDb {
+    fn add(&mut self, from: u64, to: u64, kind: XrefKind) {
+        let x = Xref { from, kind };
+        let refs = self.to.entry(to).or_default();
+        if !refs.contains(&x) {
+            refs.push(x);
+            self.from.entry(from).or_default().push((to, kind));
+        }
+    }
+
+    fn add_csr(&mut self, from: u64, csr: Csr, kind: XrefKind) {
+        self.csrs.entry(csr.0).or_default().push(Xref { from, kind });
+    }
+
+    /// data references come from constant propagation inside each block,
+    /// `Ref`s are kept only when they point into `image` or the memory layout
+    pub fn build(dis: &Disassembly, image: &Image, is_32bit: bool) -> XrefDb {
+        let mut r = XrefDb::default();
+        let cfg = dis.cfg();
+        for block in cfg.blocks.values() {
+            let mut cp = ConstProp::new(is_32bit);
+            for inst in block.insts.iter() {
+                let resolved = cp.step(inst);
+                match (inst.inst, resolved) {
+                    (RiscV::Load(..) | RiscV::FLoad(..), Some(Resolved::Addr(a))) => r.add(inst.addr, a, XrefKind::Read),
+                    (RiscV::Store(..) | RiscV::FStore(..), Some(Resolved::Addr(a))) => r.add(inst.addr, a, XrefKind::Write),
+                    (RiscV::Jalr(..), Some(Resolved::Addr(a))) => {
+                        let kind = if inst.inst.is_call() { XrefKind::Call } else { XrefKind::Jump };
+                        r.add(inst.addr, a, kind);
+                    },
+                    (_, Some(Resolved::Value(v))) if image.region(v).is_some() || region_of(v).is_some() => {
+                        r.add(inst.addr, v, XrefKind::Ref);
+                    },
+                    _ => (),
+                }
+                let (reads, writes) = (inst.inst.reads(), inst.inst.writes());
+                if let RiscV::CsrOp(.., csr) | RiscV::CsrOpI(.., csr) = inst.inst {
+                    if reads.contains_csr(csr) {
+                        r.add_csr(inst.addr, csr, XrefKind::Read);
+                    }
+                    if writes.contains_csr(csr) {
+                        r.add_csr(inst.addr, csr, XrefKind::Write);
+                    }
+                }
+            }
+            for e in block.succs.iter() {
+                let kind = match e.kind {
+                    EdgeKind::Call => XrefKind::Call,
+                    EdgeKind::Taken | EdgeKind::Indirect => XrefKind::Jump,
+                    EdgeKind::Fallthrough | EdgeKind::Return => continue,
+                };
+                if let Some(t) = e.target {
+                    r.add(block.last().addr, t, kind);
+                }
+            }
+        }
+        r
+    }
+
+    /// every reference to `addr`
+    pub fn to(&self, addr: u64) -> &[Xref] {
+        self.to.get(&addr).map_or(&[], |r| r.as_slice())
+    }
+
+    /// references to any address in `[start, end)`, e.g. a whole mmio block
+    pub fn to_range(&self, start: u64, end: u64) -> Vec<(u64, Xref)> {
+        self.to.range(start..end)
+            .flat_map(|(a, r)| r.iter().map(|x| (*a, *x)))
+            .collect()
+    }
+
+    /// (target, kind) of the references made by the instruction at `addr`
+    pub fn from(&self, addr: u64) -> &[(u64, XrefKind)] {
+        self.from.get(&addr).map_or(&[], |r| r.as_slice())
+    }
+
+    fn sites(&self, addr: u64, kind: XrefKind) -> Vec<u64> {
+        self.to(addr).iter().filter(|x| x.kind == kind).map(|x| x.from).collect()
+    }
+
+    pub fn callers(&self, addr: u64) -> Vec<u64> {
+        self.sites(addr, XrefKind::Call)
+    }
+
+    pub fn readers(&self, addr: u64) -> Vec<u64> {
+        self.sites(addr, XrefKind::Read)
+    }
+
+    pub fn writers(&self, addr: u64) -> Vec<u64> {
+        self.sites(addr, XrefKind::Write)
+    }
+
+    /// csr accesses, `Read`/`Write` follow the csr side effects of the
+    /// instruction (csrr does not write, csrw does not read)
+    pub fn csr(&self, csr: Csr) -> &[Xref] {
+        self.csrs.get(&csr.0).map_or(&[], |r| r.as_slice())
+    }
+
+    pub fn csr_by_name(&self, name: &str) -> &[Xref] {
+        Csr::from_name(name).map_or(&[], |c| self.csr(c))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::recursive::recursive_disassembly;
+    use crate::disassembly::riscv::DisasmConfig;
+    use crate::isa::riscv::bare::memory_layout::UART0;
+    use super::*;
+
+    // 0x80000000 jal    x1, 0x80000014
+    // 0x80000004 csrrs  x10, mhartid, x0
+    // 0x80000008 csrrw  x0, mtvec, x10
+    // 0x8000000c lui    x10, 0x10000
+    // 0x80000010 sb     x11, 0(x10)
+    // 0x80000014 auipc  x5, 0
+    // 0x80000018 ld     x6, 28(x5)
+    // 0x8000001c jalr   x0, 0(x1)
+    const CODE: [u32; 8] = [0x014000ef, 0xf1402573, 0x30551073, 0x10000537, 0x00b50023, 0x00000297, 0x01c2b303, 0x00008067];
+
+    #[test]
+    fn test_xref() {
+        let mut image = Image::new();
+        image.add(0x80000000, CODE.iter().flat_map(|i| i.to_le_bytes()).collect(), true);
+        let dis = recursive_disassembly(&image, &DisasmConfig::default(), &[0x80000000]);
+        let db = XrefDb::build(&dis, &image, false);
+
+        assert_eq!(db.callers(0x80000014), vec![0x80000000]);
+        assert_eq!(db.writers(UART0), vec![0x80000010]);
+        assert_eq!(db.to(UART0), &[Xref { from: 0x8000000c, kind: XrefKind::Ref }, Xref { from: 0x80000010, kind: XrefKind::Write }]);
+        assert_eq!(db.readers(0x80000030), vec![0x80000018]);
+        assert_eq!(db.from(0x80000018), &[(0x80000030, XrefKind::Read)]);
+        assert_eq!(db.to_range(UART0, UART0 + 0x100).len(), 2);
+
+        assert_eq!(db.csr_by_name("mhartid"), &[Xref { from: 0x80000004, kind: XrefKind::Read }]);
+        assert_eq!(db.csr(Csr(0x305)), &[Xref { from: 0x80000008, kind: XrefKind::Write }]);
+        assert!(db.csr_by_name("nope").is_empty());
+    }
+}
@@ -1 +1,5 @@
-pub mod riscv;
\ No newline at end of file
+pub mod riscv;
+pub mod riscv_c;
+pub mod vendor;
+#[cfg(feature = "xthead")]
+pub mod thead;
\ No newline at end of file
@@ -4,6 +4,9 @@ use crate::isa::riscv::{
     inst_binary::*,
 };
 
+use super::riscv_c::disassembly_compressed;
+use super::vendor::{self, Registry};
+
 
 use crate::{
     utils::{
@@ -39,7 +42,7 @@ fn inst_1101111(inst: &JType) -> RiscV {
 /// jalr
 #[inline]
 fn inst_1100111(inst: &IType) -> RiscV {
-    let imm = inst.sext_offset();
+    let imm = inst.sext_imm();
     let rs1 = inst.rs1();
     let rd = inst.rd();
     RiscV::Jalr(Reg(rd), Reg(rs1), imm)
@@ -110,6 +113,14 @@ fn inst_0010011(inst: &IType) -> Option<RiscV> {
     let rs1 = inst.rs1();
     let rd = inst.rd();
     let sext_imm = inst.sext_imm();
+    if inst.funct3() == 0b001 && inst.imm() >> 5 == 0b0110000 {
+        let ty = match inst.imm() & 0b11111 {
+            0b00100 => ExtType::SextB, // sext.b
+            0b00101 => ExtType::SextH, // sext.h
+            _ => return None,
+        };
+        return Some(RiscV::Ext(ty, Reg(rd), Reg(rs1)));
+    }
     let iopty = match inst.funct3() {
         0b000 => OpType::Add, // addi
         0b010 => OpType::Slt, // slti
@@ -134,7 +145,11 @@ fn inst_0010011(inst: &IType) -> Option<RiscV> {
             return None;
         }
     };
-    Some(RiscV::OpI(iopty, Reg(rd), Reg(rs1), sext_imm))
+    let imm = match iopty {
+        OpType::Sll | OpType::Srl | OpType::Sra => inst.shamt() as Immi16,
+        _ => sext_imm,
+    };
+    Some(RiscV::OpI(iopty, Reg(rd), Reg(rs1), imm))
 }
 
 
@@ -143,29 +158,65 @@ fn inst_0010011(inst: &IType) -> Option<RiscV> {
 fn inst_0011011(inst: &IType) -> Option<RiscV> {
     let rd = inst.rd();
     let rs1 = inst.rs1();
-    let sext_imm = inst.sext_imm();
     let value = match inst.funct3() {
-        0b000 => match field_range_into_u16(inst.imm().into(), 12, 5) {
-            0b0000000 => OpType::Add,// addw
+        0b000 => OpType::Add,// addiw
+        0b001 => match field_range_into_u16(inst.imm().into(), 12, 5) {
+            0b0000000 => OpType::Sll,// slliw
             _ =>  return None,
         },
-        0b001 => OpType::Sll,// sllw
         0b101 => match field_range_into_u16(inst.imm().into(), 12, 5) {
-            0b0000000 => OpType::Srl,    // srlw
-            0b0100000 => OpType::Sra, // sraw
+            0b0000000 => OpType::Srl,    // srliw
+            0b0100000 => OpType::Sra, // sraiw
             _ =>  return None,
         }
         _ => return None,
     };
-    Some(RiscV::OpIW(value, Reg(rd), Reg(rs1), sext_imm))
+    let imm = match value {
+        OpType::Add => inst.sext_imm(),
+        _ => inst.shamt32() as Immi16,
+    };
+    Some(RiscV::OpIW(value, Reg(rd), Reg(rs1), imm))
+}
+
+/// mul, div, rem
+#[inline]
+fn mul_op_type(inst: &RType) -> MulOpType {
+    match inst.funct3() {
+        0b000 => MulOpType::Mul,
+        0b001 => MulOpType::Mulh,
+        0b010 => MulOpType::Mulhsu,
+        0b011 => MulOpType::Mulhu,
+        0b100 => MulOpType::Div,
+        0b101 => MulOpType::Divu,
+        0b110 => MulOpType::Rem,
+        _ => MulOpType::Remu,
+    }
+}
+
+/// zext.h, zext.w. zext.h is in op on rv32 and in op-32 on rv64, the
+/// other form is pack with rs2 = x0
+#[inline]
+fn zext(inst: &RType, is_32bit: bool) -> Option<RiscV> {
+    let word = inst.opcode() == 0b0111011;
+    let ty = match (inst.funct3(), inst.rs2()) {
+        (0b100, 0b00000) if word != is_32bit => ExtType::ZextH,
+        (0b000, 0b00000) if word && !is_32bit => ExtType::ZextW,
+        _ => return None,
+    };
+    Some(RiscV::Ext(ty, Reg(inst.rd()), Reg(inst.rs1())))
 }
 
 /// op
 #[inline]
-fn inst_0110011(inst: &RType) -> Option<RiscV> {
+fn inst_0110011(inst: &RType, is_32bit: bool) -> Option<RiscV> {
     let rs1 = inst.rs1();
     let rs2 = inst.rs2();
     let rd = inst.rd();
+    match inst.funct7() {
+        0b0000001 => return Some(RiscV::MulOp(mul_op_type(inst), Reg(rd), Reg(rs1), Reg(rs2))),
+        0b0000100 => return zext(inst, is_32bit),
+        _ => {},
+    }
     let opty = match inst.funct3() {
         0b000 => match inst.funct7() {
             0b0000000 => OpType::Add,// add
@@ -196,10 +247,19 @@ fn inst_0110011(inst: &RType) -> Option<RiscV> {
 
 /// op word
 #[inline]
-fn inst_0111011(inst: &RType) -> Option<RiscV> {
+fn inst_0111011(inst: &RType, is_32bit: bool) -> Option<RiscV> {
     let rd = inst.rd();
     let rs1 = inst.rs1();
     let rs2 = inst.rs2();
+    match inst.funct7() {
+        0b0000001 => return match mul_op_type(inst) {
+            ty @ (MulOpType::Mul | MulOpType::Div | MulOpType::Divu | MulOpType::Rem | MulOpType::Remu) =>
+                Some(RiscV::MulOpW(ty, Reg(rd), Reg(rs1), Reg(rs2))),
+            _ => None,
+        },
+        0b0000100 => return zext(inst, is_32bit),
+        _ => {},
+    }
     let value = match inst.funct3() {
         0b000 => match inst.funct7() {
             0b0000000 => OpType::Add,// addw
@@ -217,12 +277,41 @@ fn inst_0111011(inst: &RType) -> Option<RiscV> {
     Some(RiscV::OpW(value, Reg(rd), Reg(rs1), Reg(rs2)))
 }
 
+/// amo
+#[inline]
+fn inst_0101111(inst: &RType) -> Option<RiscV> {
+    let rd = Reg(inst.rd());
+    let rs1 = Reg(inst.rs1());
+    let rs2 = Reg(inst.rs2());
+    let width = match inst.funct3() {
+        0b010 => AmoWidth::W,
+        0b011 => AmoWidth::D,
+        _ => return None,
+    };
+    let ord = AqRl(inst.funct7() & 0b11);
+    let ty = match inst.funct7() >> 2 {
+        0b00010 if rs2.0 == 0 => return Some(RiscV::Lr(width, ord, rd, rs1)),  // lr
+        0b00011 => return Some(RiscV::Sc(width, ord, rd, rs1, rs2)),           // sc
+        0b00000 => AmoOpType::Add,  // amoadd
+        0b00001 => AmoOpType::Swap, // amoswap
+        0b00100 => AmoOpType::Xor,  // amoxor
+        0b01000 => AmoOpType::Or,   // amoor
+        0b01100 => AmoOpType::And,  // amoand
+        0b10000 => AmoOpType::Min,  // amomin
+        0b10100 => AmoOpType::Max,  // amomax
+        0b11000 => AmoOpType::Minu, // amominu
+        0b11100 => AmoOpType::Maxu, // amomaxu
+        _ => return None,
+    };
+    Some(RiscV::Amo(ty, width, ord, rd, rs1, rs2))
+}
+
 /// fence
 #[inline(always)]
 fn inst_0001111(inst: &IType) -> Option<RiscV> {
     let r = match inst.funct3() {
         0b000 => RiscV::Fence(IsFenceI(false),
-            Pred(((inst.imm() >> 5) & 0b11111) as u8), Succ((inst.imm() & 0b11111) as u8)),
+            Pred(((inst.imm() >> 4) & 0b1111) as u8), Succ((inst.imm() & 0b1111) as u8)),
         0b001 => RiscV::Fence(IsFenceI(true), Pred(0), Succ(0)),
         _ => return None,
     };
@@ -233,9 +322,15 @@ fn inst_0001111(inst: &IType) -> Option<RiscV> {
 #[inline]
 fn inst_1110011(inst: &IType) -> Option<RiscV> {
     let r = match inst.funct3() {
+        0b000 if inst.rd() == 0 && inst.imm() >> 5 == 0b0001001 =>
+            RiscV::SFenceVma(Reg(inst.rs1()), Reg(inst.imm() as u8 & 0x1f)),
+        0b000 if inst.rd() != 0 || inst.rs1() != 0 => return None,
         0b000 => match inst.imm() {
-            0b0 => RiscV::EOp(EOpType::Call),
-            0b1 => RiscV::EOp(EOpType::Break),
+            0x000 => RiscV::EOp(EOpType::Call),
+            0x001 => RiscV::EOp(EOpType::Break),
+            0x102 => RiscV::EOp(EOpType::Sret),
+            0x105 => RiscV::EOp(EOpType::Wfi),
+            0x302 => RiscV::EOp(EOpType::Mret),
             _ => return None,
         },
         0b001 => RiscV::CsrOp(CsrOpType::Rw, Reg(inst.rd()), Reg(inst.rs1()), Csr(inst.imm()))
@@ -250,7 +345,169 @@ fn inst_1110011(inst: &IType) -> Option<RiscV> {
     Some(r)
 }
 
+/// load fp
+#[inline]
+fn inst_0000111(inst: &IType) -> Option<RiscV> {
+    let prec = FPrec::from_width(inst.funct3())?;
+    Some(RiscV::FLoad(prec, FReg(inst.rd()), Reg(inst.rs1()), inst.sext_imm()))
+}
+
+/// store fp
+#[inline]
+fn inst_0100111(inst: &SType) -> Option<RiscV> {
+    let prec = FPrec::from_width(inst.funct3())?;
+    Some(RiscV::FStore(prec, Reg(inst.rs1()), FReg(inst.rs2()), inst.sext_imm()))
+}
+
+/// fmadd, fmsub, fnmsub, fnmadd
+#[inline]
+fn inst_100xx11(inst: &R4Type) -> Option<RiscV> {
+    let ty = match field_range_into_u8(inst.opcode().into(), 3, 2) {
+        0b00 => FMaType::Madd,
+        0b01 => FMaType::Msub,
+        0b10 => FMaType::Nmsub,
+        _ => FMaType::Nmadd,
+    };
+    let rm = RoundMode::from_bits(inst.funct3())?;
+    Some(RiscV::FMa(ty, FPrec::from_fmt(inst.fmt()),
+        FReg(inst.rd()), FReg(inst.rs1()), FReg(inst.rs2()), FReg(inst.rs3()), rm))
+}
+
+/// op fp
+#[inline]
+fn inst_1010011(inst: &RType) -> Option<RiscV> {
+    let prec = FPrec::from_fmt(inst.funct7());
+    let rd = inst.rd();
+    let rs1 = inst.rs1();
+    let rs2 = inst.rs2();
+    let funct3 = inst.funct3();
+    let r = match inst.funct7() >> 2 {
+        0b00000 => RiscV::FOp(FOpType::Add, prec, FReg(rd), FReg(rs1), FReg(rs2), RoundMode::from_bits(funct3)?),
+        0b00001 => RiscV::FOp(FOpType::Sub, prec, FReg(rd), FReg(rs1), FReg(rs2), RoundMode::from_bits(funct3)?),
+        0b00010 => RiscV::FOp(FOpType::Mul, prec, FReg(rd), FReg(rs1), FReg(rs2), RoundMode::from_bits(funct3)?),
+        0b00011 => RiscV::FOp(FOpType::Div, prec, FReg(rd), FReg(rs1), FReg(rs2), RoundMode::from_bits(funct3)?),
+        0b01011 => match rs2 {
+            0b00000 => RiscV::FSqrt(prec, FReg(rd), FReg(rs1), RoundMode::from_bits(funct3)?),
+            _ => return None,
+        },
+        0b00100 => {
+            let ty = match funct3 {
+                0b000 => FSgnjType::J,  // fsgnj
+                0b001 => FSgnjType::Jn, // fsgnjn
+                0b010 => FSgnjType::Jx, // fsgnjx
+                _ => return None,
+            };
+            RiscV::FSgnj(ty, prec, FReg(rd), FReg(rs1), FReg(rs2))
+        },
+        0b00101 => {
+            let ty = match funct3 {
+                0b000 => FMinMaxType::Min,  // fmin
+                0b001 => FMinMaxType::Max,  // fmax
+                _ => return None,
+            };
+            RiscV::FMinMax(ty, prec, FReg(rd), FReg(rs1), FReg(rs2))
+        },
+        0b01000 => {
+            // fcvt.<fmt>.<rs2 fmt>
+            if rs2 >> 2 != 0 || rs2 == inst.funct7() & 0b11 {
+                return None;
+            }
+            RiscV::FCvt(prec, FPrec::from_fmt(rs2), FReg(rd), FReg(rs1), RoundMode::from_bits(funct3)?)
+        },
+        0b10100 => {
+            let ty = match funct3 {
+                0b010 => FCmpType::Eq,  // feq
+                0b001 => FCmpType::Lt,  // flt
+                0b000 => FCmpType::Le,  // fle
+                _ => return None,
+            };
+            RiscV::FCmp(ty, prec, Reg(rd), FReg(rs1), FReg(rs2))
+        },
+        0b11000 => {
+            let ity = match rs2 {
+                0b00000 => FIntType::W,
+                0b00001 => FIntType::Wu,
+                0b00010 => FIntType::L,
+                0b00011 => FIntType::Lu,
+                _ => return None,
+            };
+            RiscV::FCvtToInt(ity, prec, Reg(rd), FReg(rs1), RoundMode::from_bits(funct3)?)
+        },
+        0b11010 => {
+            let ity = match rs2 {
+                0b00000 => FIntType::W,
+                0b00001 => FIntType::Wu,
+                0b00010 => FIntType::L,
+                0b00011 => FIntType::Lu,
+                _ => return None,
+            };
+            RiscV::FCvtFromInt(ity, prec, FReg(rd), Reg(rs1), RoundMode::from_bits(funct3)?)
+        },
+        0b11100 => match (rs2, funct3) {
+            (0b00000, 0b000) if prec != FPrec::Q => RiscV::FMvToInt(prec, Reg(rd), FReg(rs1)), // fmv.x.<fmt>
+            (0b00000, 0b001) => RiscV::FClass(prec, Reg(rd), FReg(rs1)),                      // fclass
+            _ => return None,
+        },
+        0b11110 => match (rs2, funct3) {
+            (0b00000, 0b000) if prec != FPrec::Q => RiscV::FMvFromInt(prec, FReg(rd), Reg(rs1)), // fmv.<fmt>.x
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(r)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DisasmConfig {
+    pub is_32bit: bool,
+    /// zcb takes encodings that are reserved in plain c
+    pub zcb: bool,
+    /// zcmp and zcmt reuse the c.fsdsp encoding space, enabling either
+    /// of them turns c.fsdsp off
+    pub zcmp: bool,
+    pub zcmt: bool,
+}
+
+/// decode with the default config (rv64gc)
 pub fn disassembly(code: u32) -> Option<(RiscV, usize)> {
+    disassembly_with(code, &DisasmConfig::default())
+}
+
+/// decode `code`, falling back to the registered vendor extensions
+pub fn disassembly_with(code: u32, cfg: &DisasmConfig) -> Option<(RiscV, usize)> {
+    disassembly_std(code, cfg).or_else(|| {
+        let (inst, len) = vendor::decode(code, cfg)?;
+        Some((RiscV::Custom(inst), len))
+    })
+}
+
+/// decode `code`, falling back to the extensions of `registry` instead of
+/// the default one
+pub fn disassembly_in(code: u32, cfg: &DisasmConfig, registry: &Registry) -> Option<(RiscV, usize)> {
+    disassembly_std(code, cfg).or_else(|| {
+        let (inst, len) = registry.decode(code, cfg)?;
+        Some((RiscV::Custom(inst), len))
+    })
+}
+
+/// decode the instruction at the start of `src`, which may be shorter than
+/// 4 bytes at the end of a region
+pub fn disassembly_bytes(src: &[u8], cfg: &DisasmConfig) -> Option<(RiscV, usize)> {
+    let low = u16::from_le_bytes(src.get(0..2)?.try_into().unwrap()) as u32;
+    let code = if low & 0b11 == 0b11 {
+        let high = u16::from_le_bytes(src.get(2..4)?.try_into().unwrap()) as u32;
+        high << 16 | low
+    } else {
+        low
+    };
+    disassembly_with(code, cfg)
+}
+
+fn disassembly_std(code: u32, cfg: &DisasmConfig) -> Option<(RiscV, usize)> {
+    if code & 0b11 != 0b11 {
+        let r = disassembly_compressed(code as u16, cfg)?;
+        return Some((r, 2));
+    }
     let r = match field_range_into_u8(code, 6, 0) {
         0b0110111 => inst_0110111(&UType::from_bytes(code.to_le_bytes())),
         0b0010111 => inst_0010111(&UType::from_bytes(code.to_le_bytes())),
@@ -261,13 +518,145 @@ pub fn disassembly(code: u32) -> Option<(RiscV, usize)> {
         0b0100011 => inst_0100011(&SType::from_bytes(code.to_le_bytes()))?,
         0b0010011 => inst_0010011(&IType::from_bytes(code.to_le_bytes()))?,
         0b0011011 => inst_0011011(&IType::from_bytes(code.to_le_bytes()))?,
-        0b0110011 => inst_0110011(&RType::from_bytes(code.to_le_bytes()))?,
-        0b0111011 => inst_0111011(&RType::from_bytes(code.to_le_bytes()))?,
+        0b0110011 => inst_0110011(&RType::from_bytes(code.to_le_bytes()), cfg.is_32bit)?,
+        0b0111011 => inst_0111011(&RType::from_bytes(code.to_le_bytes()), cfg.is_32bit)?,
+        0b0101111 => inst_0101111(&RType::from_bytes(code.to_le_bytes()))?,
         0b0001111 => inst_0001111(&IType::from_bytes(code.to_le_bytes()))?,
         0b1110011 => inst_1110011(&IType::from_bytes(code.to_le_bytes()))?,
+        0b0000111 => inst_0000111(&IType::from_bytes(code.to_le_bytes()))?,
+        0b0100111 => inst_0100111(&SType::from_bytes(code.to_le_bytes()))?,
+        0b1000011 |
+        0b1000111 |
+        0b1001011 |
+        0b1001111 => inst_100xx11(&R4Type::from_bytes(code.to_le_bytes()))?,
+        0b1010011 => inst_1010011(&RType::from_bytes(code.to_le_bytes()))?,
         _ => {
             return None;
         }
     };
     Some((r, 4))
 }
+
+
+#[cfg(test)]
+mod tests {
+    use crate::isa::riscv::{AmoOpType, AmoWidth, AqRl, EOpType, IsFenceI, OpType, Pred, Reg, RiscV, Succ};
+    use super::{disassembly, disassembly_with, DisasmConfig};
+
+    fn dis(code: u32) -> String {
+        disassembly(code).unwrap().0.to_string()
+    }
+
+    #[test]
+    fn test_fp_precision() {
+        assert_eq!(dis(0x00811087), "flh\tf1, 8(x2)");
+        assert_eq!(dis(0x00a11427), "fsh\tf10, 8(x2)");
+        assert_eq!(dis(0x042090d3), "fadd.h\tf1, f1, f2, rtz");
+        assert_eq!(dis(0x062090d3), "fadd.q\tf1, f1, f2, rtz");
+        assert_eq!(dis(0x0020f0d3), "fadd.s\tf1, f1, f2");
+        assert_eq!(dis(0x4020f0d3), "fcvt.s.h\tf1, f1");
+        assert_eq!(dis(0x4400f0d3), "fcvt.h.s\tf1, f1");
+        assert_eq!(dis(0xe40080d3), "fmv.x.h\tx1, f1");
+        assert_eq!(dis(0x1a20f0c3), "fmadd.d\tf1, f1, f2, f3");
+        assert_eq!(dis(0xa2209553), "flt.d\tx10, f1, f2");
+        assert_eq!(dis(0xc2309553), "fcvt.lu.d\tx10, f1, rtz");
+    }
+
+    #[test]
+    fn test_jalr_imm() {
+        // jalr ra, -4(sp), the i-type immediate is not shifted like a branch offset
+        assert_eq!(disassembly(0xffc100e7).unwrap().0, RiscV::Jalr(Reg(1), Reg(2), -4));
+    }
+
+    #[test]
+    fn test_shift_imm() {
+        // srai a0, a1, 3 carries funct6 in imm[11:6], the operand is the shamt
+        assert_eq!(disassembly(0x4035d513).unwrap().0, RiscV::OpI(OpType::Sra, Reg(10), Reg(11), 3));
+        // slli a0, a1, 33
+        assert_eq!(disassembly(0x02159513).unwrap().0, RiscV::OpI(OpType::Sll, Reg(10), Reg(11), 33));
+    }
+
+    #[test]
+    fn test_op_imm_w() {
+        // addiw a0, a1, -1 has no funct7 to check
+        assert_eq!(disassembly(0xfff5851b).unwrap().0, RiscV::OpIW(OpType::Add, Reg(10), Reg(11), -1));
+        // sraiw a0, a1, 3
+        assert_eq!(disassembly(0x4035d51b).unwrap().0, RiscV::OpIW(OpType::Sra, Reg(10), Reg(11), 3));
+        // slliw with funct7 0100000 does not exist
+        assert!(disassembly(0x4035951b).is_none());
+    }
+
+    #[test]
+    fn test_fence() {
+        // fence rw, w: pred is imm[7:4], succ is imm[3:0]
+        assert_eq!(disassembly(0x0310000f).unwrap().0, RiscV::Fence(IsFenceI(false), Pred(0b0011), Succ(0b0001)));
+    }
+
+    #[test]
+    fn test_store() {
+        // sd ra, 8(sp): the source register comes first, the base in parens
+        assert_eq!(dis(0x00113423), "sd\tx1, 8(x2)");
+        assert_eq!(dis(0xfea42e23), "sw\tx10, -4(x8)");
+    }
+
+    #[test]
+    fn test_m_zbb() {
+        assert_eq!(dis(0x02c58533), "mul\tx10, x11, x12");
+        assert_eq!(dis(0x02c5d53b), "divuw\tx10, x11, x12");
+        assert_eq!(dis(0x60459513), "sext.b\tx10, x11");
+        assert_eq!(dis(0x0805c53b), "zext.h\tx10, x11");
+        // mulhw does not exist
+        assert!(disassembly(0x02c5953b).is_none());
+        // zext.h is op-32 on rv64 and op on rv32, the other form is pack
+        let rv32 = DisasmConfig { is_32bit: true, ..Default::default() };
+        assert!(disassembly(0x0805c533).is_none());
+        assert_eq!(disassembly_with(0x0805c533, &rv32).unwrap().0.to_string(), "zext.h\tx10, x11");
+        assert!(disassembly_with(0x0805c53b, &rv32).is_none());
+        // add.uw rd, rs1, x0 is rv64 only
+        assert_eq!(dis(0x0805853b), "zext.w\tx10, x11");
+        assert!(disassembly_with(0x0805853b, &rv32).is_none());
+        // compressed forms report their length
+        assert_eq!(disassembly(0x8082).unwrap().1, 2);
+    }
+
+    #[test]
+    fn test_system() {
+        assert_eq!(dis(0x00000073), "ecall");
+        assert_eq!(dis(0x30200073), "mret");
+        assert_eq!(dis(0x10200073), "sret");
+        assert_eq!(dis(0x10500073), "wfi");
+        assert_eq!(dis(0x12b50073), "sfence.vma\tx10, x11");
+        // uret is gone, ecall needs zero registers
+        assert!(disassembly(0x00200073).is_none());
+        assert!(disassembly(0x000000f3).is_none());
+        let flat = crate::flat_disasm::disasm::flat_disasm(&0x30200073u32.to_le_bytes(), false).unwrap().0;
+        assert_eq!(RiscV::try_from(flat).ok(), Some(RiscV::EOp(EOpType::Mret)));
+        let flat = crate::flat_disasm::disasm::flat_disasm(&0x12b50073u32.to_le_bytes(), false).unwrap().0;
+        assert_eq!(RiscV::try_from(flat).ok(), Some(RiscV::SFenceVma(Reg(10), Reg(11))));
+    }
+
+    #[test]
+    fn test_atomic() {
+        assert_eq!(dis(0x100422af), "lr.w\tx5, (x8)");
+        assert_eq!(dis(0x1854232f), "sc.w\tx6, x5, (x8)");
+        assert_eq!(dis(0x0c74ae2f), "amoswap.w.aq\tx28, x7, (x9)");
+        assert_eq!(dis(0x0a04a02f), "amoswap.w.rl\tx0, x0, (x9)");
+        // amomaxu.d.aqrl a0, a1, (a2)
+        assert_eq!(disassembly(0xe6b6352f).unwrap().0,
+            RiscV::Amo(AmoOpType::Maxu, AmoWidth::D, AqRl(0b11), Reg(10), Reg(12), Reg(11)));
+        // lr with rs2 set, a reserved funct5 and a byte width
+        assert!(disassembly(0x101422af).is_none());
+        assert!(disassembly(0x3854232f).is_none());
+        assert!(disassembly(0x1854032f).is_none());
+    }
+
+    #[test]
+    fn test_fp_illegal() {
+        // fcvt.s.s
+        assert!(disassembly(0x4000f0d3).is_none());
+        // reserved rounding mode
+        assert!(disassembly(0x0020d0d3).is_none());
+        // fmv.x.q does not exist
+        assert!(disassembly(0xe60080d3).is_none());
+    }
+}
@@ -0,0 +1,430 @@
+use crate::isa::riscv::*;
+use crate::isa::riscv::meta::Format;
+
+use crate::utils::field_range_into_u32;
+
+use super::riscv::DisasmConfig;
+
+
+#[inline]
+fn bits(code: u16, left: usize, right: usize) -> u32 {
+    field_range_into_u32(code.into(), left, right)
+}
+
+/// sign extend the low `width` bits
+#[inline]
+fn sext(value: u32, width: u32) -> i32 {
+    let shift = u32::BITS - width;
+    ((value << shift) as i32) >> shift
+}
+
+/// rd', rs1', rs2': x8..x15
+#[inline]
+fn creg(value: u32) -> Reg {
+    Reg(8 + value as u8)
+}
+
+/// zcmp sreg: s0, s1, s2..s7
+#[inline]
+fn sreg(value: u32) -> Reg {
+    match value {
+        0 | 1 => Reg(8 + value as u8),
+        x => Reg(16 + x as u8),
+    }
+}
+
+/// offset of c.lw/c.sw/c.flw/c.fsw
+#[inline]
+fn cl_word_offset(code: u16) -> Immi16 {
+    (bits(code, 12, 10) << 3 | bits(code, 6, 6) << 2 | bits(code, 5, 5) << 6) as Immi16
+}
+
+/// offset of c.ld/c.sd/c.fld/c.fsd
+#[inline]
+fn cl_double_offset(code: u16) -> Immi16 {
+    (bits(code, 12, 10) << 3 | bits(code, 6, 5) << 6) as Immi16
+}
+
+/// offset of c.lwsp/c.flwsp
+#[inline]
+fn ci_word_sp_offset(code: u16) -> Immi16 {
+    (bits(code, 12, 12) << 5 | bits(code, 6, 4) << 2 | bits(code, 3, 2) << 6) as Immi16
+}
+
+/// offset of c.ldsp/c.fldsp
+#[inline]
+fn ci_double_sp_offset(code: u16) -> Immi16 {
+    (bits(code, 12, 12) << 5 | bits(code, 6, 5) << 3 | bits(code, 4, 2) << 6) as Immi16
+}
+
+/// offset of c.swsp/c.fswsp
+#[inline]
+fn css_word_offset(code: u16) -> Immi16 {
+    (bits(code, 12, 9) << 2 | bits(code, 8, 7) << 6) as Immi16
+}
+
+/// offset of c.sdsp/c.fsdsp
+#[inline]
+fn css_double_offset(code: u16) -> Immi16 {
+    (bits(code, 12, 10) << 3 | bits(code, 9, 7) << 6) as Immi16
+}
+
+/// 6 bit signed immediate of the ci format
+#[inline]
+fn ci_imm(code: u16) -> i32 {
+    sext(bits(code, 12, 12) << 5 | bits(code, 6, 2), 6)
+}
+
+/// 6 bit shift amount of the ci/cb formats
+#[inline]
+fn ci_shamt(code: u16, cfg: &DisasmConfig) -> Option<Immi16> {
+    let shamt = bits(code, 12, 12) << 5 | bits(code, 6, 2);
+    if cfg.is_32bit && shamt >> 5 != 0 {
+        return None;
+    }
+    Some(shamt as Immi16)
+}
+
+/// offset of c.j/c.jal
+#[inline]
+fn cj_offset(code: u16) -> Immi32 {
+    let imm =
+        bits(code, 12, 12) << 11 |
+        bits(code, 11, 11) << 4  |
+        bits(code, 10, 9)  << 8  |
+        bits(code, 8, 8)   << 10 |
+        bits(code, 7, 7)   << 6  |
+        bits(code, 6, 6)   << 7  |
+        bits(code, 5, 3)   << 1  |
+        bits(code, 2, 2)   << 5;
+    sext(imm, 12)
+}
+
+/// offset of c.beqz/c.bnez
+#[inline]
+fn cb_offset(code: u16) -> Immi16 {
+    let imm =
+        bits(code, 12, 12) << 8 |
+        bits(code, 11, 10) << 3 |
+        bits(code, 6, 5)   << 6 |
+        bits(code, 4, 3)   << 1 |
+        bits(code, 2, 2)   << 5;
+    sext(imm, 9) as Immi16
+}
+
+/// quadrant 0
+#[inline]
+fn inst_c00(code: u16, cfg: &DisasmConfig) -> Option<RiscV> {
+    let rd = creg(bits(code, 4, 2));
+    let rs1 = creg(bits(code, 9, 7));
+    let r = match bits(code, 15, 13) {
+        0b000 => {
+            // c.addi4spn
+            let imm = bits(code, 12, 11) << 4 | bits(code, 10, 7) << 6 | bits(code, 6, 6) << 2 | bits(code, 5, 5) << 3;
+            if imm == 0 {
+                return None;
+            }
+            RiscV::OpI(OpType::Add, rd, Reg(2), imm as Immi16)
+        },
+        0b001 => RiscV::FLoad(FPrec::D, FReg(rd.0), rs1, cl_double_offset(code)),   // c.fld
+        0b010 => RiscV::Load(LoadType::Word, rd, rs1, cl_word_offset(code)),       // c.lw
+        0b011 if cfg.is_32bit => RiscV::FLoad(FPrec::S, FReg(rd.0), rs1, cl_word_offset(code)), // c.flw
+        0b011 => RiscV::Load(LoadType::Double, rd, rs1, cl_double_offset(code)),   // c.ld
+        0b100 if cfg.zcb => {
+            // zcb
+            let uimm = (bits(code, 5, 5) << 1 | bits(code, 6, 6)) as Immi16;
+            match bits(code, 12, 10) {
+                0b000 => RiscV::Load(LoadType::ByteU, rd, rs1, uimm),           // c.lbu
+                0b001 if bits(code, 6, 6) == 0 => RiscV::Load(LoadType::HalfU, rd, rs1, uimm),  // c.lhu
+                0b001 => RiscV::Load(LoadType::Half, rd, rs1, uimm & 0b10),     // c.lh
+                0b010 => RiscV::Store(StoreType::Byte, rs1, rd, uimm),          // c.sb
+                0b011 if bits(code, 6, 6) == 0 => RiscV::Store(StoreType::Half, rs1, rd, uimm), // c.sh
+                _ => return None,
+            }
+        },
+        0b101 => RiscV::FStore(FPrec::D, rs1, FReg(rd.0), cl_double_offset(code)),  // c.fsd
+        0b110 => RiscV::Store(StoreType::Word, rs1, rd, cl_word_offset(code)),      // c.sw
+        0b111 if cfg.is_32bit => RiscV::FStore(FPrec::S, rs1, FReg(rd.0), cl_word_offset(code)), // c.fsw
+        0b111 => RiscV::Store(StoreType::Double, rs1, rd, cl_double_offset(code)),  // c.sd
+        _ => return None,
+    };
+    Some(r)
+}
+
+/// quadrant 1
+#[inline]
+fn inst_c01(code: u16, cfg: &DisasmConfig) -> Option<RiscV> {
+    let rd = Reg(bits(code, 11, 7) as u8);
+    let rdc = creg(bits(code, 9, 7));
+    let rs2c = creg(bits(code, 4, 2));
+    let r = match bits(code, 15, 13) {
+        0b000 => RiscV::OpI(OpType::Add, rd, rd, ci_imm(code) as Immi16),  // c.addi, c.nop
+        0b001 if cfg.is_32bit => RiscV::Jal(Reg(1), cj_offset(code)),      // c.jal
+        0b001 if rd.0 != 0 => RiscV::OpIW(OpType::Add, rd, rd, ci_imm(code) as Immi16), // c.addiw
+        0b010 => RiscV::OpI(OpType::Add, rd, Reg(0), ci_imm(code) as Immi16), // c.li
+        0b011 if rd.0 == 2 => {
+            // c.addi16sp
+            let imm =
+                bits(code, 12, 12) << 9 |
+                bits(code, 6, 6)   << 4 |
+                bits(code, 5, 5)   << 6 |
+                bits(code, 4, 3)   << 7 |
+                bits(code, 2, 2)   << 5;
+            if imm == 0 {
+                return None;
+            }
+            RiscV::OpI(OpType::Add, rd, rd, sext(imm, 10) as Immi16)
+        },
+        0b011 => {
+            // c.lui
+            let imm = ci_imm(code);
+            if imm == 0 {
+                return None;
+            }
+            RiscV::Lui(rd, (imm << 12) as Imm32)
+        },
+        0b100 => match bits(code, 11, 10) {
+            0b00 => RiscV::OpI(OpType::Srl, rdc, rdc, ci_shamt(code, cfg)?),    // c.srli
+            0b01 => RiscV::OpI(OpType::Sra, rdc, rdc, ci_shamt(code, cfg)?),    // c.srai
+            0b10 => RiscV::OpI(OpType::And, rdc, rdc, ci_imm(code) as Immi16),  // c.andi
+            _ => match (bits(code, 12, 12), bits(code, 6, 5)) {
+                (0, 0b00) => RiscV::Op(OpType::Sub, rdc, rdc, rs2c),   // c.sub
+                (0, 0b01) => RiscV::Op(OpType::Xor, rdc, rdc, rs2c),   // c.xor
+                (0, 0b10) => RiscV::Op(OpType::Or, rdc, rdc, rs2c),    // c.or
+                (0, 0b11) => RiscV::Op(OpType::And, rdc, rdc, rs2c),   // c.and
+                (1, 0b00) if !cfg.is_32bit => RiscV::OpW(OpType::Sub, rdc, rdc, rs2c), // c.subw
+                (1, 0b01) if !cfg.is_32bit => RiscV::OpW(OpType::Add, rdc, rdc, rs2c), // c.addw
+                (1, 0b10) if cfg.zcb => RiscV::MulOp(MulOpType::Mul, rdc, rdc, rs2c),  // c.mul
+                (1, 0b11) if cfg.zcb => match bits(code, 4, 2) {
+                    0b000 => RiscV::OpI(OpType::And, rdc, rdc, 0xff),           // c.zext.b
+                    0b001 => RiscV::Ext(ExtType::SextB, rdc, rdc),              // c.sext.b
+                    0b010 => RiscV::Ext(ExtType::ZextH, rdc, rdc),              // c.zext.h
+                    0b011 => RiscV::Ext(ExtType::SextH, rdc, rdc),              // c.sext.h
+                    0b100 if !cfg.is_32bit => RiscV::Ext(ExtType::ZextW, rdc, rdc), // c.zext.w
+                    0b101 => RiscV::OpI(OpType::Xor, rdc, rdc, -1),             // c.not
+                    _ => return None,
+                },
+                _ => return None,
+            },
+        },
+        0b101 => RiscV::Jal(Reg(0), cj_offset(code)),                          // c.j
+        0b110 => RiscV::Branch(BrType::Eq, rdc, Reg(0), cb_offset(code)),      // c.beqz
+        0b111 => RiscV::Branch(BrType::Ne, rdc, Reg(0), cb_offset(code)),      // c.bnez
+        _ => return None,
+    };
+    Some(r)
+}
+
+/// zcmp and zcmt, quadrant 2 funct3 101
+#[inline]
+fn inst_c10_101(code: u16, cfg: &DisasmConfig) -> Option<RiscV> {
+    let r = match bits(code, 12, 8) {
+        0b11000 | 0b11010 | 0b11100 | 0b11110 if cfg.zcmp => {
+            let rlist = bits(code, 7, 4) as u8;
+            if rlist < 4 {
+                return None;
+            }
+            let rlist = RList(rlist);
+            let adj = rlist.stack_adj_base(cfg.is_32bit) as Imm32 + bits(code, 3, 2) * 16;
+            match bits(code, 12, 8) {
+                0b11000 => RiscV::CmPush(rlist, adj),
+                0b11010 => RiscV::CmPop(CmPopType::Pop, rlist, adj),
+                0b11100 => RiscV::CmPop(CmPopType::PopRetZ, rlist, adj),
+                _ => RiscV::CmPop(CmPopType::PopRet, rlist, adj),
+            }
+        },
+        x if x >> 2 == 0b011 && cfg.zcmp => {
+            let r1s = bits(code, 9, 7);
+            let r2s = bits(code, 4, 2);
+            match bits(code, 6, 5) {
+                0b01 if r1s != r2s => RiscV::CmMv(CmMvType::Sa01, sreg(r1s), sreg(r2s)),
+                0b11 => RiscV::CmMv(CmMvType::A01s, sreg(r1s), sreg(r2s)),
+                _ => return None,
+            }
+        },
+        x if x >> 2 == 0b000 && cfg.zcmt => {
+            let index = bits(code, 9, 2) as Imm8;
+            if index < 32 {
+                RiscV::CmJt(index)
+            } else {
+                RiscV::CmJalt(index)
+            }
+        },
+        _ => return None,
+    };
+    Some(r)
+}
+
+/// quadrant 2
+#[inline]
+fn inst_c10(code: u16, cfg: &DisasmConfig) -> Option<RiscV> {
+    let rd = Reg(bits(code, 11, 7) as u8);
+    let rs2 = Reg(bits(code, 6, 2) as u8);
+    let r = match bits(code, 15, 13) {
+        0b000 => RiscV::OpI(OpType::Sll, rd, rd, ci_shamt(code, cfg)?),                // c.slli
+        0b001 => RiscV::FLoad(FPrec::D, FReg(rd.0), Reg(2), ci_double_sp_offset(code)), // c.fldsp
+        0b010 if rd.0 != 0 => RiscV::Load(LoadType::Word, rd, Reg(2), ci_word_sp_offset(code)), // c.lwsp
+        0b011 if cfg.is_32bit => RiscV::FLoad(FPrec::S, FReg(rd.0), Reg(2), ci_word_sp_offset(code)), // c.flwsp
+        0b011 if rd.0 != 0 => RiscV::Load(LoadType::Double, rd, Reg(2), ci_double_sp_offset(code)), // c.ldsp
+        0b100 => match (bits(code, 12, 12), rd.0, rs2.0) {
+            (0, 0, _) => return None,
+            (0, _, 0) => RiscV::Jalr(Reg(0), rd, 0),               // c.jr
+            (0, _, _) => RiscV::Op(OpType::Add, rd, Reg(0), rs2),  // c.mv
+            (_, 0, 0) => RiscV::EOp(EOpType::Break),               // c.ebreak
+            (_, _, 0) => RiscV::Jalr(Reg(1), rd, 0),               // c.jalr
+            (_, _, _) => RiscV::Op(OpType::Add, rd, rd, rs2),      // c.add
+        },
+        0b101 if cfg.zcmp || cfg.zcmt => inst_c10_101(code, cfg)?,
+        0b101 => RiscV::FStore(FPrec::D, Reg(2), FReg(rs2.0), css_double_offset(code)),  // c.fsdsp
+        0b110 => RiscV::Store(StoreType::Word, Reg(2), rs2, css_word_offset(code)),      // c.swsp
+        0b111 if cfg.is_32bit => RiscV::FStore(FPrec::S, Reg(2), FReg(rs2.0), css_word_offset(code)), // c.fswsp
+        0b111 => RiscV::Store(StoreType::Double, Reg(2), rs2, css_double_offset(code)),  // c.sdsp
+        _ => return None,
+    };
+    Some(r)
+}
+
+/// decode a 16 bit instruction, base c forms are expanded to their 32 bit
+/// equivalents
+pub fn disassembly_compressed(code: u16, cfg: &DisasmConfig) -> Option<RiscV> {
+    if code == 0 {
+        return None;
+    }
+    match code & 0b11 {
+        0b00 => inst_c00(code, cfg),
+        0b01 => inst_c01(code, cfg),
+        0b10 => inst_c10(code, cfg),
+        _ => None,
+    }
+}
+
+/// encoding format of a 16 bit instruction, the decoded `RiscV` only keeps
+/// the format of the expanded form
+pub fn compressed_format(code: u16, cfg: &DisasmConfig) -> Option<Format> {
+    disassembly_compressed(code, cfg)?;
+    let r = match (code & 0b11, bits(code, 15, 13)) {
+        (0b00, 0b000) => Format::CIW,
+        (0b00, 0b100) => match bits(code, 12, 10) {
+            0b000 => Format::CLB,
+            0b001 => Format::CLH,
+            0b010 => Format::CSB,
+            _ => Format::CSH,
+        },
+        (0b00, x) if x < 0b100 => Format::CL,
+        (0b00, _) => Format::CS,
+        (0b01, 0b001) if cfg.is_32bit => Format::CJ,
+        (0b01, 0b101) => Format::CJ,
+        (0b01, 0b100) => match (bits(code, 11, 10), bits(code, 12, 12), bits(code, 6, 5)) {
+            (0b11, 1, 0b11) => Format::CU,
+            (0b11, _, _) => Format::CA,
+            _ => Format::CB,
+        },
+        (0b01, 0b110 | 0b111) => Format::CB,
+        (0b01, _) => Format::CI,
+        (0b10, 0b100) => Format::CR,
+        (0b10, 0b101) => match disassembly_compressed(code, cfg)? {
+            RiscV::CmPush(..) | RiscV::CmPop(..) => Format::CMPP,
+            RiscV::CmMv(..) => Format::CMMV,
+            RiscV::CmJt(..) | RiscV::CmJalt(..) => Format::CMJT,
+            _ => Format::CSS,
+        },
+        (0b10, 0b110 | 0b111) => Format::CSS,
+        (0b10, _) => Format::CI,
+        _ => return None,
+    };
+    Some(r)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::isa::riscv::*;
+    use crate::isa::riscv::meta::Format;
+    use super::{compressed_format, disassembly_compressed, DisasmConfig};
+
+    const ZC: DisasmConfig = DisasmConfig {
+        is_32bit: true,
+        zcb: true,
+        zcmp: true,
+        zcmt: true,
+    };
+
+    fn dis(code: u16, cfg: &DisasmConfig) -> String {
+        disassembly_compressed(code, cfg).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_zca() {
+        let cfg = DisasmConfig::default();
+        assert_eq!(dis(0x1141, &cfg), "addi\tx2, x2, -16");
+        assert_eq!(dis(0xe406, &cfg), "sd\tx1, 8(x2)");
+        assert_eq!(dis(0x60a2, &cfg), "ld\tx1, 8(x2)");
+        assert_eq!(dis(0x8082, &cfg), "jalr\tx0, 0(x1)");
+        assert_eq!(dis(0x4501, &cfg), "addi\tx10, x0, 0");
+        assert_eq!(dis(0x0800, &cfg), "addi\tx8, x2, 16");
+        assert_eq!(dis(0xc119, &cfg), "beq\tx10, x0, 6");
+        assert_eq!(dis(0xbfed, &cfg), "jal\tx0, -6");
+        assert!(disassembly_compressed(0x0000, &cfg).is_none());
+    }
+
+    #[test]
+    fn test_format() {
+        let cfg = DisasmConfig::default();
+        assert_eq!(compressed_format(0x1141, &cfg), Some(Format::CI));
+        assert_eq!(compressed_format(0xe406, &cfg), Some(Format::CSS));
+        assert_eq!(compressed_format(0x8082, &cfg), Some(Format::CR));
+        assert_eq!(compressed_format(0x0800, &cfg), Some(Format::CIW));
+        assert_eq!(compressed_format(0xc119, &cfg), Some(Format::CB));
+        assert_eq!(compressed_format(0x9c65, &ZC), Some(Format::CU));
+        assert_eq!(compressed_format(0x9c45, &ZC), Some(Format::CA));
+        assert_eq!(compressed_format(0xb8f2, &ZC), Some(Format::CMPP));
+        assert_eq!(compressed_format(0x0000, &cfg), None);
+    }
+
+    #[test]
+    fn test_zcb() {
+        assert_eq!(dis(0x8004, &ZC), "lbu\tx9, 0(x8)");
+        assert_eq!(dis(0x8464, &ZC), "lh\tx9, 2(x8)");
+        assert_eq!(dis(0x8824, &ZC), "sb\tx9, 2(x8)");
+        assert_eq!(dis(0x9c61, &ZC), "andi\tx8, x8, 255");
+        assert_eq!(dis(0x9c65, &ZC), "sext.b\tx8, x8");
+        assert_eq!(dis(0x9c75, &ZC), "xori\tx8, x8, -1");
+        assert_eq!(dis(0x9c45, &ZC), "mul\tx8, x8, x9");
+        // reserved in plain c
+        let cfg = DisasmConfig { zcb: false, ..ZC };
+        for code in [0x8004, 0x8824, 0x9c61, 0x9c45] {
+            assert_eq!(disassembly_compressed(code, &cfg), None);
+        }
+    }
+
+    #[test]
+    fn test_zcmp() {
+        assert_eq!(disassembly_compressed(0xb842, &ZC), Some(RiscV::CmPush(RList(4), 16)));
+        assert_eq!(dis(0xb8f2, &ZC), "cm.push\t{ra, s0-s11}, -64");
+        assert_eq!(dis(0xbe52, &ZC), "cm.popret\t{ra, s0}, 16");
+        assert_eq!(dis(0xae7a, &ZC), "cm.mva01s\tx20, x22");
+        // rlist < 4 is reserved
+        assert!(disassembly_compressed(0xb802, &ZC).is_none());
+
+        let rv64 = DisasmConfig { is_32bit: false, ..ZC };
+        assert_eq!(dis(0xb8f2, &rv64), "cm.push\t{ra, s0-s11}, -112");
+        assert_eq!(dis(0xb8f6, &rv64), "cm.push\t{ra, s0-s11}, -128");
+    }
+
+    #[test]
+    fn test_zcmt() {
+        assert_eq!(disassembly_compressed(0xa00e, &ZC), Some(RiscV::CmJt(3)));
+        assert_eq!(disassembly_compressed(0xa202, &ZC), Some(RiscV::CmJalt(128)));
+
+        let mut table = [0u8; 64];
+        table[12..16].copy_from_slice(&0x80001235_u32.to_le_bytes());
+        let jvt = Jvt(0x1000);
+        assert_eq!(jvt.entry_addr(3, true), 0x100c);
+        assert_eq!(jvt.target(3, true, &table, 0x1000), Some(0x80001234));
+        assert_eq!(Jvt(0x1001).target(3, true, &table, 0x1000), None);
+
+        // c.fsdsp without zcmt
+        let cfg = DisasmConfig { zcmp: false, zcmt: false, ..ZC };
+        assert_eq!(dis(0xa00e, &cfg), "fsd\tf3, 0(x2)");
+    }
+}
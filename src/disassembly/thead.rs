@@ -0,0 +1,337 @@
+use std::fmt::{Formatter, Result as FmtResult};
+
+use once_cell::sync::Lazy;
+
+use crate::isa::riscv::{FReg, Reg};
+use crate::isa::riscv::meta::Flags;
+use crate::isa::riscv::regset::RegSet;
+use crate::utils::{field_range_into_u8, field_range_into_u32};
+
+use super::vendor::*;
+
+
+fn imm2(code: u32) -> u8 {
+    field_range_into_u8(code, 26, 25)
+}
+
+/// rd, rs1, rs2, imm2
+fn fmt_addsl(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "{}, {}, {}, {}", rd(code), rs1(code), rs2(code), imm2(code))
+}
+
+/// rd, rs1, imm6
+fn fmt_imm6(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "{}, {}, {}", rd(code), rs1(code), field_range_into_u8(code, 25, 20))
+}
+
+/// rd, rs1, imm5
+fn fmt_imm5(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "{}, {}, {}", rd(code), rs1(code), field_range_into_u8(code, 24, 20))
+}
+
+/// rd, rs1, msb, lsb
+fn fmt_ext(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    let msb = field_range_into_u8(code, 31, 26);
+    let lsb = field_range_into_u8(code, 25, 20);
+    write!(f, "{}, {}, {}, {}", rd(code), rs1(code), msb, lsb)
+}
+
+/// rs1, rs2
+fn fmt_rs1_rs2(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "{}, {}", rs1(code), rs2(code))
+}
+
+/// rd, rs1, rs2, imm2
+fn fmt_reg_idx(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    fmt_addsl(code, f)
+}
+
+/// fd, rs1, rs2, imm2
+fn fmt_freg_idx(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "{}, {}, {}, {}", FReg(rd(code).0), rs1(code), rs2(code), imm2(code))
+}
+
+/// rd, (rs1), imm5, imm2
+fn fmt_inc(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    let imm5 = ((field_range_into_u32(code, 24, 20) << 27) as i32) >> 27;
+    write!(f, "{}, ({}), {}, {}", rd(code), rs1(code), imm5, imm2(code))
+}
+
+/// rd1, rd2, (rs1), imm2, 4
+fn fmt_pair_d(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "{}, {}, ({}), {}, 4", rd(code), rs2(code), rs1(code), imm2(code))
+}
+
+/// rd1, rd2, (rs1), imm2, 3
+fn fmt_pair_w(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "{}, {}, ({}), {}, 3", rd(code), rs2(code), rs1(code), imm2(code))
+}
+
+fn rs(regs: &[Reg]) -> RegSet {
+    regs.iter().fold(RegSet::EMPTY, |r, x| r.with_x(*x))
+}
+
+/// reads rs1, rs2
+fn use_rs1_rs2(code: u32) -> (RegSet, RegSet) {
+    (rs(&[rs1(code), rs2(code)]), RegSet::EMPTY)
+}
+
+/// rd <- rd, rs1, rs2, for multiply-accumulate and conditional moves
+fn use_acc(code: u32) -> (RegSet, RegSet) {
+    (rs(&[rd(code), rs1(code), rs2(code)]), rs(&[rd(code)]))
+}
+
+/// indexed store of rd
+fn use_sr(code: u32) -> (RegSet, RegSet) {
+    (rs(&[rd(code), rs1(code), rs2(code)]), RegSet::EMPTY)
+}
+
+/// indexed fp load
+fn use_flr(code: u32) -> (RegSet, RegSet) {
+    (rs(&[rs1(code), rs2(code)]), RegSet::EMPTY.with_f(FReg(rd(code).0)))
+}
+
+/// indexed fp store
+fn use_fsr(code: u32) -> (RegSet, RegSet) {
+    (rs(&[rs1(code), rs2(code)]).with_f(FReg(rd(code).0)), RegSet::EMPTY)
+}
+
+/// load with base update
+fn use_lia(code: u32) -> (RegSet, RegSet) {
+    (rs(&[rs1(code)]), rs(&[rd(code), rs1(code)]))
+}
+
+/// store with base update
+fn use_sia(code: u32) -> (RegSet, RegSet) {
+    (rs(&[rd(code), rs1(code)]), rs(&[rs1(code)]))
+}
+
+/// paired load into rd, rs2
+fn use_lpair(code: u32) -> (RegSet, RegSet) {
+    (rs(&[rs1(code)]), rs(&[rd(code), rs2(code)]))
+}
+
+/// paired store of rd, rs2
+fn use_spair(code: u32) -> (RegSet, RegSet) {
+    (rs(&[rd(code), rs1(code), rs2(code)]), RegSet::EMPTY)
+}
+
+fn ba() -> InstTable {
+    let mut t = InstTable::new("xtheadba");
+    t.push(InstDef::new(b"00000_??_?????_?????_001_?????_0001011", "th.addsl", fmt_addsl).regs(use_r));
+    t
+}
+
+fn bb() -> InstTable {
+    let mut t = InstTable::new("xtheadbb");
+    t.push(InstDef::new(b"000100_??????_?????_001_?????_0001011", "th.srri", fmt_imm6).regs(use_i));
+    t.push(InstDef::new(b"0001010_?????_?????_001_?????_0001011", "th.srriw", fmt_imm5).regs(use_i));
+    t.push(InstDef::new(b"??????_??????_?????_010_?????_0001011", "th.ext", fmt_ext).regs(use_i));
+    t.push(InstDef::new(b"??????_??????_?????_011_?????_0001011", "th.extu", fmt_ext).regs(use_i));
+    t.push(InstDef::new(b"1000010_00000_?????_001_?????_0001011", "th.ff0", fmt_rd_rs1).regs(use_i));
+    t.push(InstDef::new(b"1000011_00000_?????_001_?????_0001011", "th.ff1", fmt_rd_rs1).regs(use_i));
+    t.push(InstDef::new(b"1000001_00000_?????_001_?????_0001011", "th.rev", fmt_rd_rs1).regs(use_i));
+    t.push(InstDef::new(b"1001000_00000_?????_001_?????_0001011", "th.revw", fmt_rd_rs1).regs(use_i));
+    t.push(InstDef::new(b"1000000_00000_?????_001_?????_0001011", "th.tstnbz", fmt_rd_rs1).regs(use_i));
+    t
+}
+
+fn bs() -> InstTable {
+    let mut t = InstTable::new("xtheadbs");
+    t.push(InstDef::new(b"100010_??????_?????_001_?????_0001011", "th.tst", fmt_imm6).regs(use_i));
+    t
+}
+
+fn cmo() -> InstTable {
+    let mut t = InstTable::new("xtheadcmo");
+    t.push(InstDef::new(b"0000000_00001_00000_000_00000_0001011", "th.dcache.call", fmt_none).flags(Flags::PRIVILEGED | Flags::MAY_TRAP));
+    t.push(InstDef::new(b"0000000_00011_00000_000_00000_0001011", "th.dcache.ciall", fmt_none).flags(Flags::PRIVILEGED | Flags::MAY_TRAP));
+    t.push(InstDef::new(b"0000000_00010_00000_000_00000_0001011", "th.dcache.iall", fmt_none).flags(Flags::PRIVILEGED | Flags::MAY_TRAP));
+    t.push(InstDef::new(b"0000000_10000_00000_000_00000_0001011", "th.icache.iall", fmt_none).flags(Flags::PRIVILEGED | Flags::MAY_TRAP));
+    t.push(InstDef::new(b"0000000_10001_00000_000_00000_0001011", "th.icache.ialls", fmt_none).flags(Flags::PRIVILEGED | Flags::MAY_TRAP));
+    t.push(InstDef::new(b"0000000_10101_00000_000_00000_0001011", "th.l2cache.call", fmt_none).flags(Flags::PRIVILEGED | Flags::MAY_TRAP));
+    t.push(InstDef::new(b"0000000_10111_00000_000_00000_0001011", "th.l2cache.ciall", fmt_none).flags(Flags::PRIVILEGED | Flags::MAY_TRAP));
+    t.push(InstDef::new(b"0000000_10110_00000_000_00000_0001011", "th.l2cache.iall", fmt_none).flags(Flags::PRIVILEGED | Flags::MAY_TRAP));
+    t.push(InstDef::new(b"0000001_01001_?????_000_00000_0001011", "th.dcache.cpa", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t.push(InstDef::new(b"0000001_01011_?????_000_00000_0001011", "th.dcache.cipa", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t.push(InstDef::new(b"0000001_01010_?????_000_00000_0001011", "th.dcache.ipa", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t.push(InstDef::new(b"0000001_00101_?????_000_00000_0001011", "th.dcache.cva", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t.push(InstDef::new(b"0000001_00111_?????_000_00000_0001011", "th.dcache.civa", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t.push(InstDef::new(b"0000001_00110_?????_000_00000_0001011", "th.dcache.iva", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t.push(InstDef::new(b"0000001_00001_?????_000_00000_0001011", "th.dcache.csw", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t.push(InstDef::new(b"0000001_00011_?????_000_00000_0001011", "th.dcache.cisw", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t.push(InstDef::new(b"0000001_00010_?????_000_00000_0001011", "th.dcache.isw", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t.push(InstDef::new(b"0000001_01000_?????_000_00000_0001011", "th.dcache.cpal1", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t.push(InstDef::new(b"0000001_00100_?????_000_00000_0001011", "th.dcache.cval1", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t.push(InstDef::new(b"0000001_11000_?????_000_00000_0001011", "th.icache.ipa", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t.push(InstDef::new(b"0000001_10000_?????_000_00000_0001011", "th.icache.iva", fmt_rs1).flags(Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1));
+    t
+}
+
+fn condmov() -> InstTable {
+    let mut t = InstTable::new("xtheadcondmov");
+    t.push(InstDef::new(b"0100000_?????_?????_001_?????_0001011", "th.mveqz", fmt_r).regs(use_acc));
+    t.push(InstDef::new(b"0100001_?????_?????_001_?????_0001011", "th.mvnez", fmt_r).regs(use_acc));
+    t
+}
+
+fn fmemidx() -> InstTable {
+    let mut t = InstTable::new("xtheadfmemidx");
+    t.push(InstDef::new(b"01000_??_?????_?????_110_?????_0001011", "th.flrw", fmt_freg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_flr));
+    t.push(InstDef::new(b"01100_??_?????_?????_110_?????_0001011", "th.flrd", fmt_freg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_flr));
+    t.push(InstDef::new(b"01010_??_?????_?????_110_?????_0001011", "th.flurw", fmt_freg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_flr));
+    t.push(InstDef::new(b"01110_??_?????_?????_110_?????_0001011", "th.flurd", fmt_freg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_flr));
+    t.push(InstDef::new(b"01000_??_?????_?????_111_?????_0001011", "th.fsrw", fmt_freg_idx).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_fsr));
+    t.push(InstDef::new(b"01100_??_?????_?????_111_?????_0001011", "th.fsrd", fmt_freg_idx).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_fsr));
+    t.push(InstDef::new(b"01010_??_?????_?????_111_?????_0001011", "th.fsurw", fmt_freg_idx).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_fsr));
+    t.push(InstDef::new(b"01110_??_?????_?????_111_?????_0001011", "th.fsurd", fmt_freg_idx).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_fsr));
+    t
+}
+
+fn mac() -> InstTable {
+    let mut t = InstTable::new("xtheadmac");
+    t.push(InstDef::new(b"0010000_?????_?????_001_?????_0001011", "th.mula", fmt_r).regs(use_acc));
+    t.push(InstDef::new(b"0010001_?????_?????_001_?????_0001011", "th.muls", fmt_r).regs(use_acc));
+    t.push(InstDef::new(b"0010010_?????_?????_001_?????_0001011", "th.mulaw", fmt_r).regs(use_acc));
+    t.push(InstDef::new(b"0010011_?????_?????_001_?????_0001011", "th.mulsw", fmt_r).regs(use_acc));
+    t.push(InstDef::new(b"0010100_?????_?????_001_?????_0001011", "th.mulah", fmt_r).regs(use_acc));
+    t.push(InstDef::new(b"0010101_?????_?????_001_?????_0001011", "th.mulsh", fmt_r).regs(use_acc));
+    t
+}
+
+fn memidx() -> InstTable {
+    let mut t = InstTable::new("xtheadmemidx");
+    t.push(InstDef::new(b"00000_??_?????_?????_100_?????_0001011", "th.lrb", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"10000_??_?????_?????_100_?????_0001011", "th.lrbu", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"00100_??_?????_?????_100_?????_0001011", "th.lrh", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"10100_??_?????_?????_100_?????_0001011", "th.lrhu", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"01000_??_?????_?????_100_?????_0001011", "th.lrw", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"11000_??_?????_?????_100_?????_0001011", "th.lrwu", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"01100_??_?????_?????_100_?????_0001011", "th.lrd", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"00010_??_?????_?????_100_?????_0001011", "th.lurb", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"10010_??_?????_?????_100_?????_0001011", "th.lurbu", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"00110_??_?????_?????_100_?????_0001011", "th.lurh", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"10110_??_?????_?????_100_?????_0001011", "th.lurhu", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"01010_??_?????_?????_100_?????_0001011", "th.lurw", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"11010_??_?????_?????_100_?????_0001011", "th.lurwu", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"01110_??_?????_?????_100_?????_0001011", "th.lurd", fmt_reg_idx).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_r));
+    t.push(InstDef::new(b"00011_??_?????_?????_100_?????_0001011", "th.lbia", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"00001_??_?????_?????_100_?????_0001011", "th.lbib", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"10011_??_?????_?????_100_?????_0001011", "th.lbuia", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"10001_??_?????_?????_100_?????_0001011", "th.lbuib", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"00111_??_?????_?????_100_?????_0001011", "th.lhia", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"00101_??_?????_?????_100_?????_0001011", "th.lhib", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"10111_??_?????_?????_100_?????_0001011", "th.lhuia", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"10101_??_?????_?????_100_?????_0001011", "th.lhuib", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"01011_??_?????_?????_100_?????_0001011", "th.lwia", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"01001_??_?????_?????_100_?????_0001011", "th.lwib", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"11011_??_?????_?????_100_?????_0001011", "th.lwuia", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"11001_??_?????_?????_100_?????_0001011", "th.lwuib", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"01111_??_?????_?????_100_?????_0001011", "th.ldia", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"01101_??_?????_?????_100_?????_0001011", "th.ldib", fmt_inc).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lia));
+    t.push(InstDef::new(b"00000_??_?????_?????_101_?????_0001011", "th.srb", fmt_reg_idx).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sr));
+    t.push(InstDef::new(b"00100_??_?????_?????_101_?????_0001011", "th.srh", fmt_reg_idx).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sr));
+    t.push(InstDef::new(b"01000_??_?????_?????_101_?????_0001011", "th.srw", fmt_reg_idx).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sr));
+    t.push(InstDef::new(b"01100_??_?????_?????_101_?????_0001011", "th.srd", fmt_reg_idx).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sr));
+    t.push(InstDef::new(b"00010_??_?????_?????_101_?????_0001011", "th.surb", fmt_reg_idx).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sr));
+    t.push(InstDef::new(b"00110_??_?????_?????_101_?????_0001011", "th.surh", fmt_reg_idx).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sr));
+    t.push(InstDef::new(b"01010_??_?????_?????_101_?????_0001011", "th.surw", fmt_reg_idx).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sr));
+    t.push(InstDef::new(b"01110_??_?????_?????_101_?????_0001011", "th.surd", fmt_reg_idx).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sr));
+    t.push(InstDef::new(b"00011_??_?????_?????_101_?????_0001011", "th.sbia", fmt_inc).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sia));
+    t.push(InstDef::new(b"00001_??_?????_?????_101_?????_0001011", "th.sbib", fmt_inc).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sia));
+    t.push(InstDef::new(b"00111_??_?????_?????_101_?????_0001011", "th.shia", fmt_inc).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sia));
+    t.push(InstDef::new(b"00101_??_?????_?????_101_?????_0001011", "th.shib", fmt_inc).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sia));
+    t.push(InstDef::new(b"01011_??_?????_?????_101_?????_0001011", "th.swia", fmt_inc).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sia));
+    t.push(InstDef::new(b"01001_??_?????_?????_101_?????_0001011", "th.swib", fmt_inc).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sia));
+    t.push(InstDef::new(b"01111_??_?????_?????_101_?????_0001011", "th.sdia", fmt_inc).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sia));
+    t.push(InstDef::new(b"01101_??_?????_?????_101_?????_0001011", "th.sdib", fmt_inc).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_sia));
+    t
+}
+
+fn mempair() -> InstTable {
+    let mut t = InstTable::new("xtheadmempair");
+    t.push(InstDef::new(b"11111_??_?????_?????_100_?????_0001011", "th.ldd", fmt_pair_d).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lpair));
+    t.push(InstDef::new(b"11100_??_?????_?????_100_?????_0001011", "th.lwd", fmt_pair_w).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lpair));
+    t.push(InstDef::new(b"11110_??_?????_?????_100_?????_0001011", "th.lwud", fmt_pair_w).flags(Flags::LOAD | Flags::MAY_TRAP).regs(use_lpair));
+    t.push(InstDef::new(b"11111_??_?????_?????_101_?????_0001011", "th.sdd", fmt_pair_d).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_spair));
+    t.push(InstDef::new(b"11100_??_?????_?????_101_?????_0001011", "th.swd", fmt_pair_w).flags(Flags::STORE | Flags::MAY_TRAP).regs(use_spair));
+    t
+}
+
+fn sync() -> InstTable {
+    let mut t = InstTable::new("xtheadsync");
+    t.push(InstDef::new(b"0000000_11000_00000_000_00000_0001011", "th.sync", fmt_none).flags(Flags::FENCE));
+    t.push(InstDef::new(b"0000000_11001_00000_000_00000_0001011", "th.sync.s", fmt_none).flags(Flags::FENCE));
+    t.push(InstDef::new(b"0000000_11010_00000_000_00000_0001011", "th.sync.i", fmt_none).flags(Flags::FENCE));
+    t.push(InstDef::new(b"0000000_11011_00000_000_00000_0001011", "th.sync.is", fmt_none).flags(Flags::FENCE));
+    t.push(InstDef::new(b"0000010_?????_?????_000_00000_0001011", "th.sfence.vmas", fmt_rs1_rs2).flags(Flags::FENCE | Flags::PRIVILEGED | Flags::MAY_TRAP).regs(use_rs1_rs2));
+    t
+}
+static IDS: Lazy<Vec<u16>> = Lazy::new(|| {
+    [ba(), bb(), bs(), cmo(), condmov(), fmemidx(), mac(), memidx(), mempair(), sync()]
+        .into_iter()
+        .map(register)
+        .collect()
+});
+
+/// register every xthead* extension once, returns their registry ids
+pub fn register_all() -> &'static [u16] {
+    &IDS
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::disassembly::riscv::disassembly;
+    use crate::disassembly::vendor;
+
+    fn dis(code: u32) -> String {
+        super::register_all();
+        disassembly(code).unwrap().0.to_string()
+    }
+
+    #[test]
+    fn test_register() {
+        let ids = super::register_all();
+        assert_eq!(ids.len(), 10);
+        assert_eq!(super::register_all(), ids);
+        assert_eq!(vendor::find("xtheadmempair"), Some(ids[8]));
+    }
+
+    #[test]
+    fn test_thead() {
+        assert_eq!(dis(0x04c5950b), "th.addsl\tx10, x11, x12, 2");
+        assert_eq!(dis(0x1035950b), "th.srri\tx10, x11, 3");
+        assert_eq!(dis(0x1c35a50b), "th.ext\tx10, x11, 7, 3");
+        assert_eq!(dis(0x8605950b), "th.ff1\tx10, x11");
+        assert_eq!(dis(0x40c5950b), "th.mveqz\tx10, x11, x12");
+        assert_eq!(dis(0x20c5950b), "th.mula\tx10, x11, x12");
+        assert_eq!(dis(0x0295800b), "th.dcache.cpa\tx11");
+        assert_eq!(dis(0x0180000b), "th.sync");
+        assert_eq!(dis(0x42c5c50b), "th.lrw\tx10, x11, x12, 1");
+        assert_eq!(dis(0x5fe5c50b), "th.lwia\tx10, (x11), -2, 3");
+        assert_eq!(dis(0xf8c5c50b), "th.ldd\tx10, x12, (x11), 0, 4");
+        assert_eq!(dis(0x6205e50b), "th.flrd\tf10, x11, x0, 1");
+    }
+
+    #[test]
+    fn test_flags() {
+        super::register_all();
+        assert!(disassembly(0x42c5c50b).unwrap().0.is_load());
+        assert!(disassembly(0xf8c5d50b).unwrap().0.is_store());
+        assert!(disassembly(0x0295800b).unwrap().0.is_privileged());
+        assert!(!disassembly(0x04c5950b).unwrap().0.may_trap());
+    }
+
+    #[test]
+    fn test_regs() {
+        super::register_all();
+        // th.lwia x10, (x11), -2, 3 updates its base
+        let lwia = disassembly(0x5fe5c50b).unwrap().0;
+        assert_eq!(lwia.reads().to_string(), "{x11}");
+        assert_eq!(lwia.writes().to_string(), "{x10, x11}");
+        // th.mula accumulates into rd
+        assert_eq!(disassembly(0x20c5950b).unwrap().0.reads().to_string(), "{x10, x11, x12}");
+        assert_eq!(disassembly(0x6205e50b).unwrap().0.writes().to_string(), "{f10}");
+    }
+}
@@ -0,0 +1,522 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::sync::{Arc, RwLock};
+
+use once_cell::sync::Lazy;
+
+use crate::flat_disasm::{utils::bitpat_mask, FlatRiscV, OpCode};
+use crate::isa::riscv::{CustomInst, Reg, RiscV};
+use crate::isa::riscv::meta::{Extension, Flags, Operand};
+use crate::isa::riscv::regset::RegSet;
+use crate::utils::field_range_into_u8;
+
+use super::riscv::DisasmConfig;
+
+
+/// instructions living outside the standard opcode map, usually in the
+/// custom-0..3 spaces
+pub trait VendorExtension: Send + Sync {
+    /// extension name, e.g. `xtheadba`
+    fn name(&self) -> &str;
+
+    /// decode `code` into an extension local opcode and the instruction length
+    fn decode(&self, code: u32, cfg: &DisasmConfig) -> Option<(u16, usize)>;
+
+    fn mnemonic(&self, op: u16) -> &str;
+
+    /// operands of `inst`, without the mnemonic
+    fn fmt_operands(&self, inst: &CustomInst, f: &mut Formatter<'_>) -> FmtResult;
+
+    fn operands(&self, _inst: &CustomInst) -> Vec<Operand> {
+        Vec::new()
+    }
+
+    /// unknown instructions are assumed to trap
+    fn flags(&self, _inst: &CustomInst) -> Flags {
+        Flags::MAY_TRAP
+    }
+
+    fn reads(&self, _inst: &CustomInst) -> RegSet {
+        RegSet::EMPTY
+    }
+
+    fn writes(&self, _inst: &CustomInst) -> RegSet {
+        RegSet::EMPTY
+    }
+}
+
+
+pub type OperandFormatter = fn(u32, &mut Formatter<'_>) -> FmtResult;
+
+/// registers (read, written) by an encoding
+pub type RegUse = fn(u32) -> (RegSet, RegSet);
+
+/// a single instruction definition in the `bitpat` syntax of `flat_disasm`,
+/// by default without flags and register uses
+#[derive(Clone, Copy)]
+pub struct InstDef {
+    pub pattern: &'static [u8],
+    pub mnemonic: &'static str,
+    pub format: OperandFormatter,
+    pub flags: Flags,
+    pub regs: RegUse,
+}
+
+impl InstDef {
+    pub fn new(pattern: &'static [u8], mnemonic: &'static str, format: OperandFormatter) -> InstDef {
+        InstDef { pattern, mnemonic, format, flags: Flags::NONE, regs: use_none }
+    }
+
+    pub fn flags(self, flags: Flags) -> InstDef {
+        InstDef { flags, ..self }
+    }
+
+    pub fn regs(self, regs: RegUse) -> InstDef {
+        InstDef { regs, ..self }
+    }
+}
+
+/// an extension made of `InstDef`s, matched in insertion order
+pub struct InstTable {
+    pub name: String,
+    defs: Vec<InstDef>,
+    /// (mask, match, bits) of each def, compiled once on push
+    masks: Vec<(u32, u32, usize)>,
+}
+
+impl InstTable {
+    pub fn new(name: &str) -> InstTable {
+        InstTable {
+            name: name.to_string(),
+            defs: Vec::new(),
+            masks: Vec::new(),
+        }
+    }
+
+    pub fn defs(&self) -> &[InstDef] {
+        &self.defs
+    }
+
+    pub fn push(&mut self, def: InstDef) {
+        let (mask, value, len) = bitpat_mask(def.pattern);
+        assert!(len == 16 || len == 32, "{}: pattern must be 16 or 32 bits", def.mnemonic);
+        self.masks.push((mask, value, len));
+        self.defs.push(def);
+    }
+}
+
+impl VendorExtension for InstTable {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn decode(&self, code: u32, _cfg: &DisasmConfig) -> Option<(u16, usize)> {
+        let len = if code & 0b11 == 0b11 { 32 } else { 16 };
+        self.masks.iter()
+            .position(|&(mask, value, bits)| bits == len && code & mask == value)
+            .map(|op| (op as u16, len / 8))
+    }
+
+    fn mnemonic(&self, op: u16) -> &str {
+        self.defs[op as usize].mnemonic
+    }
+
+    fn fmt_operands(&self, inst: &CustomInst, f: &mut Formatter<'_>) -> FmtResult {
+        (self.defs[inst.op as usize].format)(inst.raw, f)
+    }
+
+    fn flags(&self, inst: &CustomInst) -> Flags {
+        self.defs[inst.op as usize].flags
+    }
+
+    fn reads(&self, inst: &CustomInst) -> RegSet {
+        (self.defs[inst.op as usize].regs)(inst.raw).0
+    }
+
+    fn writes(&self, inst: &CustomInst) -> RegSet {
+        (self.defs[inst.op as usize].regs)(inst.raw).1
+    }
+}
+
+
+// operand formatters for the standard field positions
+
+pub fn rd(code: u32) -> Reg {
+    Reg(field_range_into_u8(code, 11, 7))
+}
+
+pub fn rs1(code: u32) -> Reg {
+    Reg(field_range_into_u8(code, 19, 15))
+}
+
+pub fn rs2(code: u32) -> Reg {
+    Reg(field_range_into_u8(code, 24, 20))
+}
+
+/// sign extended imm[11:0] of the i format
+pub fn iimm(code: u32) -> i32 {
+    (code as i32) >> 20
+}
+
+pub fn fmt_none(_code: u32, _f: &mut Formatter<'_>) -> FmtResult {
+    Ok(())
+}
+
+/// rd, rs1, rs2
+pub fn fmt_r(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "{}, {}, {}", rd(code), rs1(code), rs2(code))
+}
+
+/// rd, rs1, imm
+pub fn fmt_i(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "{}, {}, {}", rd(code), rs1(code), iimm(code))
+}
+
+/// rd, rs1
+pub fn fmt_rd_rs1(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "{}, {}", rd(code), rs1(code))
+}
+
+/// rs1
+pub fn fmt_rs1(code: u32, f: &mut Formatter<'_>) -> FmtResult {
+    write!(f, "{}", rs1(code))
+}
+
+// register uses for the standard field positions
+
+pub fn use_none(_code: u32) -> (RegSet, RegSet) {
+    (RegSet::EMPTY, RegSet::EMPTY)
+}
+
+/// rd <- rs1, rs2
+pub fn use_r(code: u32) -> (RegSet, RegSet) {
+    (RegSet::EMPTY.with_x(rs1(code)).with_x(rs2(code)), RegSet::EMPTY.with_x(rd(code)))
+}
+
+/// rd <- rs1
+pub fn use_i(code: u32) -> (RegSet, RegSet) {
+    (RegSet::EMPTY.with_x(rs1(code)), RegSet::EMPTY.with_x(rd(code)))
+}
+
+/// reads rs1 only
+pub fn use_rs1(code: u32) -> (RegSet, RegSet) {
+    (RegSet::EMPTY.with_x(rs1(code)), RegSet::EMPTY)
+}
+
+
+/// `FlatRiscV::ext_op` of a custom instruction packs the extension id above
+/// the local opcode
+const FLAT_OP_BITS: u32 = 10;
+const MAX_EXTENSIONS: usize = 1 << (u16::BITS - FLAT_OP_BITS);
+
+/// a set of extensions, consulted in registration order after the
+/// standard decoder gives up. ids index into it and stay valid across
+/// `unregister`.
+#[derive(Default)]
+pub struct Registry {
+    exts: Vec<Option<Arc<dyn VendorExtension>>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// add `ext`, returns its id
+    pub fn register(&mut self, ext: impl VendorExtension + 'static) -> u16 {
+        assert!(self.exts.len() < MAX_EXTENSIONS, "too many vendor extensions");
+        self.exts.push(Some(Arc::new(ext)));
+        (self.exts.len() - 1) as u16
+    }
+
+    /// remove extension `id`, its id is not reused
+    pub fn unregister(&mut self, id: u16) -> Option<Arc<dyn VendorExtension>> {
+        self.exts.get_mut(id as usize)?.take()
+    }
+
+    pub fn extension(&self, id: u16) -> Option<Arc<dyn VendorExtension>> {
+        self.exts.get(id as usize)?.clone()
+    }
+
+    /// id of the first extension called `name`
+    pub fn find(&self, name: &str) -> Option<u16> {
+        self.exts.iter()
+            .position(|ext| ext.as_ref().is_some_and(|e| e.name() == name))
+            .map(|id| id as u16)
+    }
+
+    pub fn decode(&self, code: u32, cfg: &DisasmConfig) -> Option<(CustomInst, usize)> {
+        self.exts.iter().enumerate().find_map(|(ext, e)| {
+            let (op, len) = e.as_ref()?.decode(code, cfg)?;
+            let raw = if len == 2 { code & 0xffff } else { code };
+            Some((CustomInst { ext: ext as u16, op, raw }, len))
+        })
+    }
+
+    /// `flat_disasm` counterpart of `decode`, the length is in bits
+    pub fn decode_flat(&self, src: &[u8], is_32bit: bool) -> Option<(FlatRiscV, usize)> {
+        let code = match src.get(0..4) {
+            Some(r) => u32::from_le_bytes(r.try_into().unwrap()),
+            None => u16::from_le_bytes(src.get(0..2)?.try_into().unwrap()) as u32,
+        };
+        let cfg = DisasmConfig { is_32bit, ..Default::default() };
+        let (inst, len) = self.decode(code, &cfg)?;
+        if inst.op >> FLAT_OP_BITS != 0 {
+            return None;
+        }
+        let r = FlatRiscV {
+            opcode: OpCode::custom,
+            ext_op: inst.ext << FLAT_OP_BITS | inst.op,
+            rd: rd(inst.raw).0,
+            rs1: rs1(inst.raw).0,
+            rs2: rs2(inst.raw).0,
+            imm: inst.raw,
+        };
+        Some((r, len * 8))
+    }
+}
+
+/// the default registry, used by `disassembly` and to name, format and
+/// describe `RiscV::Custom` instructions
+pub static REGISTRY: Lazy<RwLock<Registry>> = Lazy::new(|| RwLock::new(Registry::new()));
+
+/// register an extension in the default registry, returns its id
+pub fn register(ext: impl VendorExtension + 'static) -> u16 {
+    REGISTRY.write().unwrap().register(ext)
+}
+
+/// remove an extension from the default registry
+pub fn unregister(id: u16) -> Option<Arc<dyn VendorExtension>> {
+    REGISTRY.write().unwrap().unregister(id)
+}
+
+pub fn extension(id: u16) -> Option<Arc<dyn VendorExtension>> {
+    REGISTRY.read().unwrap().extension(id)
+}
+
+pub fn find(name: &str) -> Option<u16> {
+    REGISTRY.read().unwrap().find(name)
+}
+
+pub fn decode(code: u32, cfg: &DisasmConfig) -> Option<(CustomInst, usize)> {
+    REGISTRY.read().unwrap().decode(code, cfg)
+}
+
+/// `flat_disasm` counterpart of `decode`, the length is in bits
+pub fn decode_flat(src: &[u8], is_32bit: bool) -> Option<(FlatRiscV, usize)> {
+    REGISTRY.read().unwrap().decode_flat(src, is_32bit)
+}
+
+impl FlatRiscV {
+    pub fn custom(&self) -> Option<CustomInst> {
+        if self.opcode != OpCode::custom {
+            return None;
+        }
+        Some(CustomInst {
+            ext: self.ext_op >> FLAT_OP_BITS,
+            op: self.ext_op & ((1 << FLAT_OP_BITS) - 1),
+            raw: self.imm,
+        })
+    }
+}
+
+impl CustomInst {
+    pub fn len(&self) -> usize {
+        if self.raw & 0b11 == 0b11 { 4 } else { 2 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    pub fn mnemonic(&self) -> String {
+        extension(self.ext).map_or_else(|| "unknown".to_string(), |e| e.mnemonic(self.op).to_string())
+    }
+
+    /// `mnemonic`, with `ext` referring to `registry` rather than the default one
+    pub fn mnemonic_in(&self, registry: &Registry) -> String {
+        registry.extension(self.ext).map_or_else(|| "unknown".to_string(), |e| e.mnemonic(self.op).to_string())
+    }
+
+    pub fn display_in<'a>(&'a self, registry: &'a Registry) -> InRegistry<'a, CustomInst> {
+        InRegistry(self, registry)
+    }
+
+    fn fmt_with(&self, ext: Option<Arc<dyn VendorExtension>>, f: &mut Formatter<'_>) -> FmtResult {
+        match ext {
+            Some(e) => {
+                let operands = Operands(e.as_ref(), self).to_string();
+                if operands.is_empty() {
+                    write!(f, "{}", e.mnemonic(self.op))
+                } else {
+                    write!(f, "{}\t{}", e.mnemonic(self.op), operands)
+                }
+            },
+            None => write!(f, ".insn\t{:#x}", self.raw),
+        }
+    }
+}
+
+impl RiscV {
+    /// display with vendor extension ids referring to `registry`, for
+    /// instructions from `disassembly_in`
+    pub fn display_in<'a>(&'a self, registry: &'a Registry) -> InRegistry<'a, RiscV> {
+        InRegistry(self, registry)
+    }
+}
+
+impl Extension {
+    pub fn display_in<'a>(&'a self, registry: &'a Registry) -> InRegistry<'a, Extension> {
+        InRegistry(self, registry)
+    }
+}
+
+/// a value displayed against a registry other than the default one, see
+/// `RiscV::display_in`
+pub struct InRegistry<'a, T>(&'a T, &'a Registry);
+
+impl Display for InRegistry<'_, CustomInst> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        self.0.fmt_with(self.1.extension(self.0.ext), f)
+    }
+}
+
+impl Display for InRegistry<'_, RiscV> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self.0 {
+            RiscV::Custom(inst) => inst.display_in(self.1).fmt(f),
+            inst => inst.fmt(f),
+        }
+    }
+}
+
+impl Display for InRegistry<'_, Extension> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self.0 {
+            Extension::Vendor(id) => match self.1.extension(*id) {
+                Some(e) => write!(f, "{}", e.name()),
+                None => write!(f, "vendor{}", id),
+            },
+            x => x.fmt(f),
+        }
+    }
+}
+
+struct Operands<'a>(&'a dyn VendorExtension, &'a CustomInst);
+
+impl Display for Operands<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        self.0.fmt_operands(self.1, f)
+    }
+}
+
+impl Display for CustomInst {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        self.fmt_with(extension(self.ext), f)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::{Formatter, Result as FmtResult};
+
+    use crate::disassembly::riscv::{disassembly_in, DisasmConfig};
+    use crate::flat_disasm::OpCode;
+    use crate::isa::riscv::{CustomInst, RiscV};
+    use super::*;
+
+    struct Halt;
+
+    impl VendorExtension for Halt {
+        fn name(&self) -> &str {
+            "xtesthalt"
+        }
+
+        fn decode(&self, code: u32, _cfg: &DisasmConfig) -> Option<(u16, usize)> {
+            (code == 0x0000_707b).then_some((0, 4))
+        }
+
+        fn mnemonic(&self, _op: u16) -> &str {
+            "test.halt"
+        }
+
+        fn fmt_operands(&self, _inst: &CustomInst, _f: &mut Formatter<'_>) -> FmtResult {
+            Ok(())
+        }
+    }
+
+    fn table() -> InstTable {
+        // custom-1
+        let mut table = InstTable::new("xtestmac");
+        table.push(InstDef::new(b"0000000??????????000_?????_0101011", "test.mac", fmt_r).regs(use_r));
+        table.push(InstDef::new(b"?????????????????001_?????_0101011", "test.addi", fmt_i));
+        table
+    }
+
+    #[test]
+    fn test_inst_table() {
+        let mut registry = Registry::new();
+        let ext = registry.register(table());
+        assert_eq!(registry.find("xtestmac"), Some(ext));
+        let cfg = DisasmConfig::default();
+
+        let (inst, len) = registry.decode(0x00c5852b, &cfg).unwrap();
+        assert_eq!(len, 4);
+        assert_eq!(inst, CustomInst { ext, op: 0, raw: 0x00c5852b });
+        assert_eq!(inst.display_in(&registry).to_string(), "test.mac\tx10, x11, x12");
+        assert_eq!(inst.mnemonic_in(&registry), "test.mac");
+        let (inst, _) = registry.decode(0xffc5952b, &cfg).unwrap();
+        assert_eq!(inst.display_in(&registry).to_string(), "test.addi\tx10, x11, -4");
+        assert!(registry.decode(0x00c5a52b, &cfg).is_none());
+        // the standard decoder still wins
+        assert!(matches!(disassembly_in(0x00c58533, &cfg, &registry), Some((RiscV::Op(..), 4))));
+        assert!(matches!(disassembly_in(0x00c5852b, &cfg, &registry), Some((RiscV::Custom(_), 4))));
+    }
+
+    #[test]
+    fn test_unregister() {
+        let mut registry = Registry::new();
+        let halt = registry.register(Halt);
+        let mac = registry.register(table());
+        assert!(registry.decode(0x0000_707b, &DisasmConfig::default()).is_some());
+        assert!(registry.unregister(halt).is_some());
+        assert!(registry.unregister(halt).is_none());
+        assert!(registry.decode(0x0000_707b, &DisasmConfig::default()).is_none());
+        assert_eq!(registry.find("xtesthalt"), None);
+        // ids of the remaining extensions are unchanged
+        assert_eq!(registry.find("xtestmac"), Some(mac));
+        assert_eq!(registry.decode(0x00c5852b, &DisasmConfig::default()).unwrap().0.ext, mac);
+    }
+
+    #[test]
+    fn test_private_registry() {
+        // ids of a private registry mean nothing to the default one
+        let mut registry = Registry::new();
+        registry.register(Halt);
+        let ext = registry.register(table());
+        let cfg = DisasmConfig::default();
+        let (r, _) = disassembly_in(0x00c5852b, &cfg, &registry).unwrap();
+        assert_eq!(r.display_in(&registry).to_string(), "test.mac\tx10, x11, x12");
+        assert_eq!(r.mnemonic_in(&registry), "test.mac");
+        assert_eq!(r.reads_in(&registry).to_string(), "{x11, x12}");
+        assert_eq!(r.writes_in(&registry).to_string(), "{x10}");
+        assert_eq!(r.flags_in(&registry), Flags::NONE);
+        let info = r.info_in(&registry);
+        assert_eq!(info.extension, Extension::Vendor(ext));
+        assert_eq!(info.extension.display_in(&registry).to_string(), "xtestmac");
+        // standard instructions display as usual
+        let (r, _) = disassembly_in(0x00c58533, &cfg, &registry).unwrap();
+        assert_eq!(r.display_in(&registry).to_string(), r.to_string());
+
+        let (r, len) = registry.decode_flat(&0x00c5852b_u32.to_le_bytes(), false).unwrap();
+        assert_eq!((r.opcode, len), (OpCode::custom, 32));
+        assert_eq!(r.custom(), Some(CustomInst { ext, op: 0, raw: 0x00c5852b }));
+
+        assert!(registry.unregister(ext).is_some());
+        assert!(disassembly_in(0x00c5852b, &cfg, &registry).is_none());
+        let inst = CustomInst { ext, op: 0, raw: 0x00c5852b };
+        assert_eq!(RiscV::Custom(inst).display_in(&registry).to_string(), ".insn\t0xc5852b");
+        assert_eq!(RiscV::Custom(inst).flags_in(&registry), Flags::MAY_TRAP);
+        assert_eq!(Extension::Vendor(ext).display_in(&registry).to_string(), "vendor1");
+    }
+}
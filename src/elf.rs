@@ -0,0 +1,412 @@
+use std::fmt::Display;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    Truncated,
+    BadMagic,
+    /// only little endian images are supported
+    BadEncoding,
+    BadClass,
+    /// the image is not for risc-v
+    BadMachine,
+    /// a segment is too large to be loaded or wraps the address space
+    BadSegment,
+}
+
+impl Display for ElfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ElfError::Truncated => "truncated elf",
+            ElfError::BadMagic => "not an elf",
+            ElfError::BadEncoding => "big endian elf",
+            ElfError::BadClass => "unknown elf class",
+            ElfError::BadMachine => "not a risc-v elf",
+            ElfError::BadSegment => "oversized elf segment",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl std::error::Error for ElfError {}
+
+pub const EM_RISCV: u16 = 243;
+
+pub const PT_LOAD: u32 = 1;
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHF_EXECINSTR: u64 = 4;
+
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+
+/// a `PT_LOAD` program header with its file contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub mem_size: u64,
+    pub flags: u32,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    pub fn is_exec(&self) -> bool {
+        self.flags & PF_X != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl Section {
+    pub fn is_exec(&self) -> bool {
+        self.flags & SHF_EXECINSTR != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+    /// `STT_*`
+    pub kind: u8,
+}
+
+impl Symbol {
+    pub fn is_func(&self) -> bool {
+        self.kind == STT_FUNC
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Elf {
+    pub is_32bit: bool,
+    pub machine: u16,
+    pub flags: u32,
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+}
+
+/// little endian field reader over the two elf classes
+struct Reader<'a> {
+    data: &'a [u8],
+    is_32bit: bool,
+}
+
+impl Reader<'_> {
+    fn bytes(&self, offset: u64, len: u64) -> Result<&[u8], ElfError> {
+        let start = usize::try_from(offset).map_err(|_| ElfError::Truncated)?;
+        let len = usize::try_from(len).map_err(|_| ElfError::Truncated)?;
+        self.data.get(start..start.checked_add(len).ok_or(ElfError::Truncated)?).ok_or(ElfError::Truncated)
+    }
+
+    fn u8(&self, offset: u64) -> Result<u8, ElfError> {
+        Ok(self.bytes(offset, 1)?[0])
+    }
+
+    fn u16(&self, offset: u64) -> Result<u16, ElfError> {
+        Ok(u16::from_le_bytes(self.bytes(offset, 2)?.try_into().unwrap()))
+    }
+
+    fn u32(&self, offset: u64) -> Result<u32, ElfError> {
+        Ok(u32::from_le_bytes(self.bytes(offset, 4)?.try_into().unwrap()))
+    }
+
+    fn u64(&self, offset: u64) -> Result<u64, ElfError> {
+        Ok(u64::from_le_bytes(self.bytes(offset, 8)?.try_into().unwrap()))
+    }
+
+    /// an address sized field
+    fn addr(&self, offset: u64) -> Result<u64, ElfError> {
+        if self.is_32bit {
+            self.u32(offset).map(|x| x as u64)
+        } else {
+            self.u64(offset)
+        }
+    }
+
+    fn str(&self, offset: u64) -> Result<String, ElfError> {
+        let start = usize::try_from(offset).map_err(|_| ElfError::Truncated)?;
+        let s = self.data.get(start..).ok_or(ElfError::Truncated)?;
+        let end = s.iter().position(|c| *c == 0).ok_or(ElfError::Truncated)?;
+        Ok(String::from_utf8_lossy(&s[..end]).into_owned())
+    }
+}
+
+struct Shdr {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    entsize: u64,
+}
+
+pub fn parse(data: &[u8]) -> Result<Elf, ElfError> {
+    if data.len() < 0x34 {
+        return Err(ElfError::Truncated);
+    }
+    if data[0..4] != *b"\x7fELF" {
+        return Err(ElfError::BadMagic);
+    }
+    let is_32bit = match data[4] {
+        1 => true,
+        2 => false,
+        _ => return Err(ElfError::BadClass),
+    };
+    if data[5] != 1 {
+        return Err(ElfError::BadEncoding);
+    }
+    let r = Reader { data, is_32bit };
+    // offsets come from the file, any sum of them may wrap
+    let at = |base: u64, offset: u64| base.checked_add(offset).ok_or(ElfError::Truncated);
+    let (phoff, shoff, flags, ehsize) = if is_32bit { (0x1c, 0x20, 0x24, 0x28) } else { (0x20, 0x28, 0x30, 0x34) };
+    // e_ehsize is followed by e_phentsize, e_phnum, e_shentsize, e_shnum and e_shstrndx
+    let half = |n: u64| r.u16(ehsize + 2 * n).map(|x| x as u64);
+    let machine = r.u16(0x12)?;
+    let entry = r.addr(0x18)?;
+    let phoff = r.addr(phoff)?;
+    let shoff = r.addr(shoff)?;
+    let flags = r.u32(flags)?;
+    let (phentsize, phnum, shentsize, shnum, shstrndx) = (half(1)?, half(2)?, half(3)?, half(4)?, half(5)?);
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let ph = at(phoff, i * phentsize)?;
+        if r.u32(ph)? != PT_LOAD {
+            continue;
+        }
+        let (flags, offset, vaddr, paddr, filesz, memsz) = if is_32bit {
+            (r.u32(ph + 0x18)?, r.addr(ph + 0x4)?, r.addr(ph + 0x8)?, r.addr(ph + 0xc)?, r.addr(ph + 0x10)?, r.addr(ph + 0x14)?)
+        } else {
+            (r.u32(ph + 0x4)?, r.addr(ph + 0x8)?, r.addr(ph + 0x10)?, r.addr(ph + 0x18)?, r.addr(ph + 0x20)?, r.addr(ph + 0x28)?)
+        };
+        segments.push(Segment {
+            vaddr,
+            paddr,
+            mem_size: memsz,
+            flags,
+            data: r.bytes(offset, filesz)?.to_vec(),
+        });
+    }
+
+    let shdr = |i: u64| -> Result<Shdr, ElfError> {
+        let sh = at(shoff, i * shentsize)?;
+        // sh_flags and everything after it are address sized
+        let w = if is_32bit { 4 } else { 8 };
+        Ok(Shdr {
+            name: r.u32(sh)?,
+            kind: r.u32(sh + 0x4)?,
+            flags: r.addr(sh + 0x8)?,
+            addr: r.addr(sh + 0x8 + w)?,
+            offset: r.addr(sh + 0x8 + 2 * w)?,
+            size: r.addr(sh + 0x8 + 3 * w)?,
+            link: r.u32(sh + 0x8 + 4 * w)?,
+            entsize: r.addr(sh + 0x10 + 5 * w)?,
+        })
+    };
+    let mut sections = Vec::new();
+    let mut symbols = Vec::new();
+    if shoff != 0 && shnum != 0 {
+        let shstr = shdr(shstrndx)?.offset;
+        for i in 0..shnum {
+            let Shdr { name, kind, flags, addr, offset, size, link, entsize } = shdr(i)?;
+            sections.push(Section {
+                name: r.str(at(shstr, name as u64)?)?,
+                kind,
+                flags,
+                addr,
+                offset,
+                size,
+            });
+            if kind != SHT_SYMTAB || entsize == 0 {
+                continue;
+            }
+            let strtab = shdr(link as u64)?.offset;
+            for j in 0..size / entsize {
+                let sym = at(offset, j * entsize)?;
+                let (name, value, size, info) = if is_32bit {
+                    (r.u32(sym)?, r.addr(sym + 0x4)?, r.addr(sym + 0x8)?, r.u8(sym + 0xc)?)
+                } else {
+                    (r.u32(sym)?, r.u64(sym + 0x8)?, r.u64(sym + 0x10)?, r.u8(sym + 0x4)?)
+                };
+                if name == 0 {
+                    continue;
+                }
+                symbols.push(Symbol { name: r.str(at(strtab, name as u64)?)?, value, size, kind: info & 0xf });
+            }
+        }
+    }
+
+    Ok(Elf { is_32bit, machine, flags, entry, segments, sections, symbols })
+}
+
+impl Elf {
+    /// the entry point and every function symbol
+    pub fn entry_points(&self) -> Vec<u64> {
+        let mut r = vec![self.entry];
+        r.extend(self.symbols.iter().filter(|s| s.is_func() && s.value != 0).map(|s| s.value));
+        r.sort_unstable();
+        r.dedup();
+        r
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    /// the function or object symbol covering `addr`
+    pub fn symbolize(&self, addr: u64) -> Option<&Symbol> {
+        self.symbols.iter()
+            .filter(|s| matches!(s.kind, STT_FUNC | STT_OBJECT))
+            .find(|s| s.value <= addr && addr < s.value.saturating_add(s.size.max(1)))
+    }
+}
+
+
+/// a minimal elf64 writer for tests: one rwx segment per `(vaddr, data)`,
+/// a `.symtab` with the given function symbols
+#[cfg(test)]
+pub(crate) fn build_elf64(entry: u64, segments: &[(u64, &[u8])], funcs: &[(&str, u64, u64)]) -> Vec<u8> {
+    fn put(v: &mut [u8], off: usize, bytes: &[u8]) {
+        v[off..off + bytes.len()].copy_from_slice(bytes);
+    }
+    let phoff = 0x40;
+    let mut data_off = phoff + 0x38 * segments.len();
+    let mut out = vec![0u8; data_off];
+    put(&mut out, 0, b"\x7fELF\x02\x01\x01");
+    put(&mut out, 0x10, &2u16.to_le_bytes());
+    put(&mut out, 0x12, &EM_RISCV.to_le_bytes());
+    put(&mut out, 0x14, &1u32.to_le_bytes());
+    put(&mut out, 0x18, &entry.to_le_bytes());
+    put(&mut out, 0x20, &(phoff as u64).to_le_bytes());
+    put(&mut out, 0x34, &0x40u16.to_le_bytes());
+    put(&mut out, 0x36, &0x38u16.to_le_bytes());
+    put(&mut out, 0x38, &(segments.len() as u16).to_le_bytes());
+    put(&mut out, 0x3a, &0x40u16.to_le_bytes());
+    for (i, (vaddr, bytes)) in segments.iter().enumerate() {
+        let ph = phoff + 0x38 * i;
+        put(&mut out, ph, &PT_LOAD.to_le_bytes());
+        put(&mut out, ph + 4, &(PF_R | PF_W | PF_X).to_le_bytes());
+        put(&mut out, ph + 8, &(data_off as u64).to_le_bytes());
+        put(&mut out, ph + 0x10, &vaddr.to_le_bytes());
+        put(&mut out, ph + 0x18, &vaddr.to_le_bytes());
+        put(&mut out, ph + 0x20, &(bytes.len() as u64).to_le_bytes());
+        put(&mut out, ph + 0x28, &(bytes.len() as u64).to_le_bytes());
+        out.extend_from_slice(bytes);
+        data_off += bytes.len();
+    }
+
+    // null, .symtab, .strtab, .shstrtab
+    let shstrtab = b"\0.symtab\0.strtab\0.shstrtab\0";
+    let mut strtab = vec![0u8];
+    let mut symtab = vec![0u8; 24];
+    for (name, value, size) in funcs {
+        let mut sym = [0u8; 24];
+        sym[0..4].copy_from_slice(&(strtab.len() as u32).to_le_bytes());
+        sym[4] = STT_FUNC | 1 << 4;
+        sym[6..8].copy_from_slice(&1u16.to_le_bytes());
+        sym[8..16].copy_from_slice(&value.to_le_bytes());
+        sym[16..24].copy_from_slice(&size.to_le_bytes());
+        symtab.extend_from_slice(&sym);
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+    }
+    let symtab_off = out.len();
+    out.extend_from_slice(&symtab);
+    let strtab_off = out.len();
+    out.extend_from_slice(&strtab);
+    let shstrtab_off = out.len();
+    out.extend_from_slice(shstrtab);
+    let shoff = out.len();
+    let sections: [(u32, u32, usize, usize, u32, u64); 4] = [
+        (0, 0, 0, 0, 0, 0),
+        (1, SHT_SYMTAB, symtab_off, symtab.len(), 2, 24),
+        (9, 3, strtab_off, strtab.len(), 0, 0),
+        (17, 3, shstrtab_off, shstrtab.len(), 0, 0),
+    ];
+    for (name, kind, offset, size, link, entsize) in sections {
+        let mut sh = [0u8; 0x40];
+        sh[0..4].copy_from_slice(&name.to_le_bytes());
+        sh[4..8].copy_from_slice(&kind.to_le_bytes());
+        sh[0x18..0x20].copy_from_slice(&(offset as u64).to_le_bytes());
+        sh[0x20..0x28].copy_from_slice(&(size as u64).to_le_bytes());
+        sh[0x28..0x2c].copy_from_slice(&link.to_le_bytes());
+        sh[0x38..0x40].copy_from_slice(&entsize.to_le_bytes());
+        out.extend_from_slice(&sh);
+    }
+    put(&mut out, 0x28, &(shoff as u64).to_le_bytes());
+    put(&mut out, 0x3c, &4u16.to_le_bytes());
+    put(&mut out, 0x3e, &3u16.to_le_bytes());
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let code = 0x00008067u32.to_le_bytes();
+        let elf = parse(&build_elf64(0x80000000, &[(0x80000000, &code)], &[("_entry", 0x80000000, 4), ("main", 0x80000004, 8)])).unwrap();
+        assert!(!elf.is_32bit);
+        assert_eq!(elf.machine, EM_RISCV);
+        assert_eq!(elf.entry, 0x80000000);
+        assert_eq!(elf.segments.len(), 1);
+        assert_eq!(elf.segments[0].data, code);
+        assert!(elf.segments[0].is_exec());
+        assert_eq!(elf.sections.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), vec!["", ".symtab", ".strtab", ".shstrtab"]);
+        assert_eq!(elf.symbol("main").unwrap().value, 0x80000004);
+        assert_eq!(elf.symbolize(0x80000008).unwrap().name, "main");
+        assert_eq!(elf.entry_points(), vec![0x80000000, 0x80000004]);
+    }
+
+    #[test]
+    fn test_bad() {
+        assert_eq!(parse(&[0; 8]), Err(ElfError::Truncated));
+        assert_eq!(parse(&[0; 0x40]), Err(ElfError::BadMagic));
+
+        // a single section header, its own string table, whose name offset
+        // wraps past the end of the address space
+        let mut elf = vec![0u8; 0x80];
+        elf[0..7].copy_from_slice(b"\x7fELF\x02\x01\x01");
+        elf[0x28..0x30].copy_from_slice(&0x40u64.to_le_bytes());
+        elf[0x34..0x36].copy_from_slice(&0x40u16.to_le_bytes());
+        elf[0x3a..0x3c].copy_from_slice(&0x40u16.to_le_bytes());
+        elf[0x3c..0x3e].copy_from_slice(&1u16.to_le_bytes());
+        elf[0x40..0x44].copy_from_slice(&0x20u32.to_le_bytes());
+        elf[0x58..0x60].copy_from_slice(&(u64::MAX - 0xf).to_le_bytes());
+        assert_eq!(parse(&elf), Err(ElfError::Truncated));
+        // the section headers themselves wrap
+        elf[0x28..0x30].copy_from_slice(&(u64::MAX - 0x3f).to_le_bytes());
+        elf[0x3c..0x3e].copy_from_slice(&2u16.to_le_bytes());
+        elf[0x3e..0x40].copy_from_slice(&1u16.to_le_bytes());
+        assert_eq!(parse(&elf), Err(ElfError::Truncated));
+    }
+
+    #[test]
+    fn test_symbolize_top() {
+        // a symbol running past the end of the address space
+        let code = 0x00008067u32.to_le_bytes();
+        let elf = parse(&build_elf64(0x1000, &[(0x1000, &code)], &[("top", u64::MAX - 4, 16)])).unwrap();
+        assert_eq!(elf.symbolize(u64::MAX - 1).unwrap().name, "top");
+        assert!(elf.symbolize(0x1000).is_none());
+    }
+}
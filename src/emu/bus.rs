@@ -0,0 +1,378 @@
+use std::any::Any;
+
+use crate::isa::riscv::bare::memory_layout::{KERNBASE, PHYSTOP};
+
+use super::memory::{Memory, Ram};
+use super::trap::Exception;
+
+
+/// something mapped on the bus. offsets are relative to the mapping base
+/// and an access the device rejects is an access fault.
+pub trait Device: Any {
+    fn read(&mut self, offset: u64, size: u8) -> Option<u64>;
+    fn write(&mut self, offset: u64, size: u8, value: u64) -> bool;
+
+    /// backing bytes of memory-like devices, for loaders and inspection
+    fn bytes_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    /// checked after every store to the device
+    fn wants_dma(&self) -> bool {
+        false
+    }
+
+    /// access the rest of the bus, the device itself is unmapped meanwhile
+    fn dma(&mut self, _bus: &mut Bus) {}
+}
+
+impl Device for Ram {
+    fn read(&mut self, offset: u64, size: u8) -> Option<u64> {
+        self.load(self.base + offset, size).ok()
+    }
+
+    fn write(&mut self, offset: u64, size: u8, value: u64) -> bool {
+        self.store(self.base + offset, size, value).is_ok()
+    }
+
+    fn bytes_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.data)
+    }
+}
+
+/// read-only memory, stores fault
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom {
+    pub data: Vec<u8>,
+}
+
+impl Device for Rom {
+    fn read(&mut self, offset: u64, size: u8) -> Option<u64> {
+        let offset = offset as usize;
+        let src = self.data.get(offset..offset + size as usize)?;
+        let mut bytes = [0; 8];
+        bytes[..size as usize].copy_from_slice(src);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    fn write(&mut self, _offset: u64, _size: u8, _value: u64) -> bool {
+        false
+    }
+
+    fn bytes_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.data)
+    }
+}
+
+/// a device made of two callbacks
+pub struct Mmio<R, W> {
+    pub read: R,
+    pub write: W,
+}
+
+impl<R, W> Device for Mmio<R, W>
+where
+    R: FnMut(u64, u8) -> Option<u64> + 'static,
+    W: FnMut(u64, u8, u64) -> bool + 'static,
+{
+    fn read(&mut self, offset: u64, size: u8) -> Option<u64> {
+        (self.read)(offset, size)
+    }
+
+    fn write(&mut self, offset: u64, size: u8, value: u64) -> bool {
+        (self.write)(offset, size, value)
+    }
+}
+
+pub struct Mapping {
+    pub name: String,
+    pub base: u64,
+    pub size: u64,
+    pub device: Box<dyn Device>,
+}
+
+impl Mapping {
+    pub fn contains(&self, addr: u64) -> bool {
+        self.base <= addr && addr - self.base < self.size
+    }
+}
+
+/// address ranges mapped to devices
+#[derive(Default)]
+pub struct Bus {
+    maps: Vec<Mapping>,
+    /// misaligned accesses are split into bytes instead of raising a
+    /// misaligned exception
+    pub misaligned: bool,
+    /// physical bytes written by device dma since last taken, as one
+    /// range, so that a machine can break the reservations of its harts
+    pub stored: Option<(u64, u64)>,
+    in_dma: bool,
+}
+
+impl Bus {
+    pub fn new() -> Bus {
+        Bus::default()
+    }
+
+    /// the `memory_layout` machine: ram from `KERNBASE` up to `PHYSTOP`,
+    /// devices are mapped by their own modules
+    pub fn with_layout() -> Bus {
+        let mut bus = Bus::new();
+        bus.add_ram("ram", KERNBASE, PHYSTOP - KERNBASE);
+        bus
+    }
+
+    /// false if `[base, base + size)` overlaps an existing mapping or wraps
+    /// around the address space
+    pub fn map(&mut self, name: &str, base: u64, size: u64, device: impl Device) -> bool {
+        let end = match base.checked_add(size) {
+            Some(end) if size != 0 => end,
+            _ => return false,
+        };
+        // mapped ranges never wrap, their ends can not overflow
+        if self.maps.iter().any(|m| base < m.base + m.size && m.base < end) {
+            return false;
+        }
+        self.maps.push(Mapping { name: name.to_string(), base, size, device: Box::new(device) });
+        self.maps.sort_by_key(|m| m.base);
+        true
+    }
+
+    pub fn add_ram(&mut self, name: &str, base: u64, size: u64) -> bool {
+        self.map(name, base, size, Ram::new(base, size as usize))
+    }
+
+    pub fn add_rom(&mut self, name: &str, base: u64, data: Vec<u8>) -> bool {
+        let size = data.len() as u64;
+        self.map(name, base, size, Rom { data })
+    }
+
+    pub fn unmap(&mut self, name: &str) -> Option<Mapping> {
+        let i = self.maps.iter().position(|m| m.name == name)?;
+        Some(self.maps.remove(i))
+    }
+
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.maps
+    }
+
+    pub fn find(&self, addr: u64) -> Option<&Mapping> {
+        self.maps.iter().find(|m| m.contains(addr))
+    }
+
+    fn find_mut(&mut self, addr: u64) -> Option<&mut Mapping> {
+        self.maps.iter_mut().find(|m| m.contains(addr))
+    }
+
+    /// the device mapped as `name`, if it is a `T`
+    pub fn device<T: Device>(&mut self, name: &str) -> Option<&mut T> {
+        let m = self.maps.iter_mut().find(|m| m.name == name)?;
+        let any: &mut dyn Any = m.device.as_mut();
+        any.downcast_mut::<T>()
+    }
+
+    /// copy `src` into memory-like devices, read-only ones included
+    pub fn write_bytes(&mut self, addr: u64, src: &[u8]) -> bool {
+        if self.in_dma && !src.is_empty() {
+            let end = addr.saturating_add(src.len() as u64);
+            let (lo, hi) = self.stored.unwrap_or((addr, end));
+            self.stored = Some((lo.min(addr), hi.max(end)));
+        }
+        let mut done = 0;
+        while done < src.len() {
+            let addr = addr + done as u64;
+            let m = match self.find_mut(addr) {
+                Some(m) => m,
+                None => return false,
+            };
+            let offset = (addr - m.base) as usize;
+            let bytes = match m.device.bytes_mut() {
+                Some(b) => b,
+                None => return false,
+            };
+            let n = (bytes.len() - offset).min(src.len() - done);
+            bytes[offset..offset + n].copy_from_slice(&src[done..done + n]);
+            done += n;
+        }
+        true
+    }
+
+    /// read from memory-like devices without side effects
+    pub fn read_bytes(&mut self, addr: u64, len: usize) -> Option<Vec<u8>> {
+        let mut r = Vec::with_capacity(len);
+        while r.len() < len {
+            let addr = addr + r.len() as u64;
+            let m = self.find_mut(addr)?;
+            let offset = (addr - m.base) as usize;
+            let bytes = m.device.bytes_mut()?;
+            let n = (bytes.len() - offset).min(len - r.len());
+            r.extend_from_slice(&bytes[offset..offset + n]);
+        }
+        Some(r)
+    }
+
+    /// let the device mapped as `name` do its memory accesses
+    pub fn dma(&mut self, name: &str) -> bool {
+        match self.maps.iter().position(|m| m.name == name) {
+            Some(i) => {
+                self.run_dma(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn run_dma(&mut self, i: usize) {
+        let mut device = std::mem::replace(&mut self.maps[i].device, Box::new(Rom { data: vec![] }));
+        let outer = std::mem::replace(&mut self.in_dma, true);
+        device.dma(self);
+        self.in_dma = outer;
+        self.maps[i].device = device;
+    }
+
+    /// the index of the mapping holding all of `[addr, addr + size)`
+    fn access(&self, addr: u64, size: u8) -> Option<(usize, u64)> {
+        let i = self.maps.iter().position(|m| m.contains(addr))?;
+        let offset = addr - self.maps[i].base;
+        (offset + size as u64 <= self.maps[i].size).then_some((i, offset))
+    }
+}
+
+impl Memory for Bus {
+    fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+        if !addr.is_multiple_of(size as u64) {
+            if !self.misaligned {
+                return Err(Exception::LoadMisaligned(addr));
+            }
+            let mut v = 0;
+            for i in 0..size as u64 {
+                v |= self.load(addr.wrapping_add(i), 1)? << (i * 8);
+            }
+            return Ok(v);
+        }
+        let (i, offset) = self.access(addr, size).ok_or(Exception::LoadAccessFault(addr))?;
+        self.maps[i].device.read(offset, size).ok_or(Exception::LoadAccessFault(addr))
+    }
+
+    fn store(&mut self, addr: u64, size: u8, value: u64) -> Result<(), Exception> {
+        if !addr.is_multiple_of(size as u64) {
+            if !self.misaligned {
+                return Err(Exception::StoreMisaligned(addr));
+            }
+            for i in 0..size as u64 {
+                self.store(addr.wrapping_add(i), 1, value >> (i * 8) & 0xff)?;
+            }
+            return Ok(());
+        }
+        let (i, offset) = self.access(addr, size).ok_or(Exception::StoreAccessFault(addr))?;
+        let device = &mut self.maps[i].device;
+        if !device.write(offset, size, value) {
+            return Err(Exception::StoreAccessFault(addr));
+        }
+        if device.wants_dma() {
+            self.run_dma(i);
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crate::emu::exec::execute;
+    use crate::emu::hart::Hart;
+    use crate::isa::riscv::bare::memory_layout::UART0;
+    use crate::isa::riscv::{LoadType, Reg, RiscV, StoreType};
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let mut bus = Bus::with_layout();
+        assert_eq!(bus.find(KERNBASE).unwrap().name, "ram");
+        assert!(bus.find(PHYSTOP).is_none());
+        bus.store(PHYSTOP - 8, 8, 0x1122334455667788).unwrap();
+        assert_eq!(bus.load(PHYSTOP - 4, 4), Ok(0x11223344));
+        assert_eq!(bus.load(PHYSTOP, 1), Err(Exception::LoadAccessFault(PHYSTOP)));
+        assert_eq!(bus.store(0, 1, 0), Err(Exception::StoreAccessFault(0)));
+        assert_eq!(bus.device::<Ram>("ram").unwrap().data.len() as u64, PHYSTOP - KERNBASE);
+        assert!(bus.device::<Rom>("ram").is_none());
+    }
+
+    #[test]
+    fn test_rom() {
+        let mut bus = Bus::new();
+        assert!(bus.add_rom("rom", 0x1000, vec![1, 2, 3, 4]));
+        assert!(!bus.add_ram("overlap", 0xff0, 0x20));
+        assert_eq!(bus.load(0x1000, 4), Ok(0x04030201));
+        assert_eq!(bus.store(0x1000, 4, 0), Err(Exception::StoreAccessFault(0x1000)));
+        // an access running past the end of a mapping
+        assert_eq!(bus.load(0x1002, 4), Err(Exception::LoadMisaligned(0x1002)));
+        bus.misaligned = true;
+        assert_eq!(bus.load(0x1002, 4), Err(Exception::LoadAccessFault(0x1004)));
+        assert_eq!(bus.load(0x1001, 2), Ok(0x0302));
+        // loaders may still fill it
+        assert!(bus.write_bytes(0x1000, &[9]));
+        assert_eq!(bus.read_bytes(0x1000, 2), Some(vec![9, 2]));
+    }
+
+    #[test]
+    fn test_wrap() {
+        let mut bus = Bus::new();
+        assert!(!bus.add_ram("wrap", u64::MAX - 0xf, 0x20));
+        assert!(!bus.add_ram("top", u64::MAX - 0xf, 0x10));
+        assert!(bus.add_ram("high", u64::MAX - 0x1f, 0x10));
+        assert!(!bus.add_ram("overlap", u64::MAX - 0x27, 0x10));
+        assert_eq!(bus.load(u64::MAX - 7, 8), Err(Exception::LoadAccessFault(u64::MAX - 7)));
+        // ram at 0 asked for the top of the address space
+        let mut ram = Ram::new(0, 0x100);
+        assert_eq!(ram.load(u64::MAX - 3, 8), Err(Exception::LoadAccessFault(u64::MAX - 3)));
+        assert_eq!(ram.store(u64::MAX, 1, 0), Err(Exception::StoreAccessFault(u64::MAX)));
+        assert!(!ram.write_bytes(u64::MAX - 1, &[0; 4]));
+        assert!(ram.write_bytes(0xfc, &[1; 4]));
+    }
+
+    #[test]
+    fn test_alignment() {
+        let mut bus = Bus::new();
+        bus.add_ram("ram", 0x0, 0x100);
+        assert_eq!(bus.load(0x2, 4), Err(Exception::LoadMisaligned(0x2)));
+        assert_eq!(bus.store(0x1, 2, 0), Err(Exception::StoreMisaligned(0x1)));
+        bus.misaligned = true;
+        bus.store(0x1, 4, 0xaabbccdd).unwrap();
+        assert_eq!(bus.load(0x0, 8), Ok(0xaabbccdd00));
+        assert_eq!(bus.load(0x3, 2), Ok(0xaabb));
+    }
+
+    #[test]
+    fn test_mmio() {
+        let last = Rc::new(Cell::new(0u64));
+        let seen = last.clone();
+        let mut bus = Bus::with_layout();
+        let dev = Mmio {
+            read: |offset, _| (offset == 5).then_some(0x60),
+            write: move |offset, size, value| {
+                seen.set(offset << 16 | (size as u64) << 8 | value);
+                true
+            },
+        };
+        assert!(bus.map("uart", UART0, 0x100, dev));
+
+        // a hart storing through the bus
+        let mut hart = Hart::new(false);
+        hart.set_reg(Reg(10), UART0);
+        hart.set_reg(Reg(11), 0x41);
+        execute(&mut hart, &mut bus, RiscV::Store(StoreType::Byte, Reg(10), Reg(11), 0)).unwrap();
+        assert_eq!(last.get(), 0x141);
+        execute(&mut hart, &mut bus, RiscV::Load(LoadType::ByteU, Reg(12), Reg(10), 5)).unwrap();
+        assert_eq!(hart.reg(Reg(12)), 0x60);
+        let r = execute(&mut hart, &mut bus, RiscV::Load(LoadType::ByteU, Reg(12), Reg(10), 4));
+        assert_eq!(r, Err(Exception::LoadAccessFault(UART0 + 4)));
+
+        assert!(bus.unmap("uart").is_some());
+        assert_eq!(bus.load(UART0, 1), Err(Exception::LoadAccessFault(UART0)));
+    }
+}
@@ -0,0 +1,245 @@
+use std::time::Instant;
+
+use crate::isa::riscv::bare::memory_layout::{clint_mtimecmp, CLINT, CLINT_MTINE};
+
+use super::bus::Device;
+use super::hart::Hart;
+use super::trap::Interrupt;
+
+
+const MSIP: u64 = 0;
+const MTIMECMP: u64 = clint_mtimecmp(0) - CLINT;
+const MTIME: u64 = CLINT_MTINE - CLINT;
+
+/// what drives `mtime`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// one tick every n retired instructions, deterministic
+    Instructions(u64),
+    /// host time at `hz` ticks a second
+    Wall { start: Instant, hz: u64 },
+}
+
+impl Clock {
+    pub fn wall(hz: u64) -> Clock {
+        Clock::Wall { start: Instant::now(), hz }
+    }
+}
+
+/// `size` bytes at byte `offset` of a 64-bit register
+fn read_part(reg: u64, offset: u64, size: u8) -> u64 {
+    let v = reg >> (offset * 8);
+    if size == 8 { v } else { v & ((1 << (size as u64 * 8)) - 1) }
+}
+
+fn write_part(reg: u64, offset: u64, size: u8, value: u64) -> u64 {
+    let mask = if size == 8 { u64::MAX } else { ((1 << (size as u64 * 8)) - 1) << (offset * 8) };
+    reg & !mask | (value << (offset * 8)) & mask
+}
+
+/// the core local interruptor: `msip` and `mtimecmp` per hart and a
+/// shared `mtime`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clint {
+    pub clock: Clock,
+    instret: u64,
+    /// added to the clock, so that writes to `mtime` stick
+    offset: u64,
+    msip: Vec<bool>,
+    mtimecmp: Vec<u64>,
+}
+
+impl Clint {
+    pub const SIZE: u64 = 0x10000;
+
+    pub fn new(harts: usize, clock: Clock) -> Clint {
+        Clint {
+            clock,
+            instret: 0,
+            offset: 0,
+            msip: vec![false; harts],
+            mtimecmp: vec![u64::MAX; harts],
+        }
+    }
+
+    pub fn harts(&self) -> usize {
+        self.msip.len()
+    }
+
+    /// advance an instruction clock
+    pub fn tick(&mut self, instructions: u64) {
+        self.instret = self.instret.wrapping_add(instructions);
+    }
+
+    pub fn mtime(&self) -> u64 {
+        let raw = match self.clock {
+            Clock::Instructions(n) => self.instret / n.max(1),
+            Clock::Wall { start, hz } => (start.elapsed().as_nanos() * hz as u128 / 1_000_000_000) as u64,
+        };
+        raw.wrapping_add(self.offset)
+    }
+
+    pub fn set_mtime(&mut self, value: u64) {
+        self.offset = self.offset.wrapping_add(value.wrapping_sub(self.mtime()));
+    }
+
+    pub fn msip(&self, hart: usize) -> bool {
+        self.msip[hart]
+    }
+
+    pub fn set_msip(&mut self, hart: usize, value: bool) {
+        self.msip[hart] = value;
+    }
+
+    pub fn mtimecmp(&self, hart: usize) -> u64 {
+        self.mtimecmp[hart]
+    }
+
+    /// the `mip` bits this device drives for `hart`
+    pub fn pending(&self, hart: usize) -> u64 {
+        let mut mip = 0;
+        if self.msip[hart] {
+            mip |= Interrupt::MachineSoftware.bit();
+        }
+        if self.mtime() >= self.mtimecmp[hart] {
+            mip |= Interrupt::MachineTimer.bit();
+        }
+        mip
+    }
+
+    /// copy `MSIP` and `MTIP` into the `mip` of hart `id`
+    pub fn update(&self, id: usize, hart: &mut Hart) {
+        let mask = Interrupt::MachineSoftware.bit() | Interrupt::MachineTimer.bit();
+        hart.mip = hart.mip & !mask | self.pending(id);
+    }
+}
+
+impl Device for Clint {
+    fn read(&mut self, offset: u64, size: u8) -> Option<u64> {
+        let harts = self.harts() as u64;
+        if (MSIP..MSIP + 4 * harts).contains(&offset) {
+            let hart = (offset - MSIP) / 4;
+            return Some(read_part(self.msip[hart as usize] as u64, (offset - MSIP) % 4, size));
+        }
+        if (MTIMECMP..MTIMECMP + 8 * harts).contains(&offset) {
+            let hart = (offset - MTIMECMP) / 8;
+            return Some(read_part(self.mtimecmp[hart as usize], (offset - MTIMECMP) % 8, size));
+        }
+        if (MTIME..MTIME + 8).contains(&offset) {
+            return Some(read_part(self.mtime(), offset - MTIME, size));
+        }
+        None
+    }
+
+    fn write(&mut self, offset: u64, size: u8, value: u64) -> bool {
+        let harts = self.harts() as u64;
+        if (MSIP..MSIP + 4 * harts).contains(&offset) {
+            let hart = (offset - MSIP) / 4;
+            let msip = write_part(self.msip[hart as usize] as u64, (offset - MSIP) % 4, size, value);
+            self.msip[hart as usize] = msip & 1 != 0;
+            return true;
+        }
+        if (MTIMECMP..MTIMECMP + 8 * harts).contains(&offset) {
+            let hart = (offset - MTIMECMP) as usize / 8;
+            self.mtimecmp[hart] = write_part(self.mtimecmp[hart], (offset - MTIMECMP) % 8, size, value);
+            return true;
+        }
+        if (MTIME..MTIME + 8).contains(&offset) {
+            let mtime = write_part(self.mtime(), offset - MTIME, size, value);
+            self.set_mtime(mtime);
+            return true;
+        }
+        false
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::emu::bus::Bus;
+    use crate::emu::memory::Memory;
+    use super::*;
+
+    fn clint_bus(harts: usize) -> Bus {
+        let mut bus = Bus::new();
+        assert!(bus.map("clint", CLINT, Clint::SIZE, Clint::new(harts, Clock::Instructions(1))));
+        bus
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut bus = clint_bus(2);
+        let mut hart = Hart::new(false);
+        bus.device::<Clint>("clint").unwrap().tick(100);
+        assert_eq!(bus.load(CLINT_MTINE, 8), Ok(100));
+
+        // xv6's timerinit: mtimecmp = mtime + interval
+        let interval = 1000;
+        let now = bus.load(CLINT_MTINE, 8).unwrap();
+        bus.store(clint_mtimecmp(1), 8, now + interval).unwrap();
+        let clint = bus.device::<Clint>("clint").unwrap();
+        clint.update(1, &mut hart);
+        assert_eq!(hart.mip, 0);
+        clint.tick(999);
+        clint.update(1, &mut hart);
+        assert_eq!(hart.mip, 0);
+        clint.tick(1);
+        clint.update(1, &mut hart);
+        assert_eq!(hart.mip, Interrupt::MachineTimer.bit());
+        // hart 0 never set its compare value
+        assert_eq!(clint.pending(0), 0);
+
+        // rewriting mtimecmp clears it, as a handler would
+        bus.store(clint_mtimecmp(1), 8, 1100 + interval).unwrap();
+        bus.device::<Clint>("clint").unwrap().update(1, &mut hart);
+        assert_eq!(hart.mip, 0);
+    }
+
+    #[test]
+    fn test_halves() {
+        let mut bus = clint_bus(1);
+        let clint = bus.device::<Clint>("clint").unwrap();
+        clint.clock = Clock::Instructions(10);
+        clint.tick(25);
+        assert_eq!(clint.mtime(), 2);
+
+        // rv32 writes mtimecmp a word at a time
+        bus.store(clint_mtimecmp(0) + 4, 4, 0x1).unwrap();
+        bus.store(clint_mtimecmp(0), 4, 0x10).unwrap();
+        assert_eq!(bus.load(clint_mtimecmp(0), 8), Ok(0x1_0000_0010));
+        assert_eq!(bus.load(clint_mtimecmp(0) + 4, 4), Ok(0x1));
+
+        bus.store(CLINT_MTINE + 4, 4, 0x1).unwrap();
+        assert_eq!(bus.load(CLINT_MTINE, 8), Ok(0x1_0000_0002));
+        bus.device::<Clint>("clint").unwrap().tick(150);
+        assert_eq!(bus.load(CLINT_MTINE, 8), Ok(0x1_0000_0011));
+        assert_eq!(bus.device::<Clint>("clint").unwrap().pending(0), Interrupt::MachineTimer.bit());
+        assert!(bus.load(CLINT + 4, 4).is_err());
+    }
+
+    #[test]
+    fn test_software() {
+        let mut bus = clint_bus(2);
+        let mut harts = [Hart::new(false), Hart::new(false)];
+        bus.store(CLINT + 4, 4, 1).unwrap();
+        let clint = bus.device::<Clint>("clint").unwrap();
+        for (i, hart) in harts.iter_mut().enumerate() {
+            clint.update(i, hart);
+        }
+        assert_eq!(harts[0].mip, 0);
+        assert_eq!(harts[1].mip, Interrupt::MachineSoftware.bit());
+        assert_eq!(bus.load(CLINT + 4, 4), Ok(1));
+
+        bus.store(CLINT + 4, 4, 0).unwrap();
+        bus.device::<Clint>("clint").unwrap().update(1, &mut harts[1]);
+        assert_eq!(harts[1].mip, 0);
+    }
+
+    #[test]
+    fn test_wall_clock() {
+        let clint = Clint::new(1, Clock::wall(10_000_000));
+        let a = clint.mtime();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(clint.mtime() > a);
+    }
+}
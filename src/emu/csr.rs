@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+
+use crate::isa::riscv::reg::CSR_MAP;
+use crate::isa::riscv::Csr;
+
+use super::hart::Hart;
+use super::mmu::Satp;
+use super::trap::{Exception, Privilege};
+
+
+pub const SSTATUS: u16 = 0x100;
+pub const SIE: u16 = 0x104;
+pub const STVEC: u16 = 0x105;
+pub const SCOUNTEREN: u16 = 0x106;
+pub const SENVCFG: u16 = 0x10a;
+pub const SSCRATCH: u16 = 0x140;
+pub const SEPC: u16 = 0x141;
+pub const SCAUSE: u16 = 0x142;
+pub const STVAL: u16 = 0x143;
+pub const SIP: u16 = 0x144;
+pub const STIMECMP: u16 = 0x14d;
+pub const STIMECMPH: u16 = 0x15d;
+pub const SATP: u16 = 0x180;
+pub const MSTATUS: u16 = 0x300;
+pub const MISA: u16 = 0x301;
+pub const MEDELEG: u16 = 0x302;
+pub const MIDELEG: u16 = 0x303;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MCOUNTEREN: u16 = 0x306;
+pub const MENVCFG: u16 = 0x30a;
+pub const MSTATUSH: u16 = 0x310;
+pub const MENVCFGH: u16 = 0x31a;
+pub const MCOUNTINHIBIT: u16 = 0x320;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+pub const MCYCLE: u16 = 0xb00;
+pub const MINSTRET: u16 = 0xb02;
+pub const CYCLE: u16 = 0xc00;
+pub const TIME: u16 = 0xc01;
+pub const INSTRET: u16 = 0xc02;
+pub const MHARTID: u16 = 0xf14;
+
+pub const MSTATUS_SIE: u64 = 1 << 1;
+pub const MSTATUS_MIE: u64 = 1 << 3;
+pub const MSTATUS_SPIE: u64 = 1 << 5;
+pub const MSTATUS_MPIE: u64 = 1 << 7;
+pub const MSTATUS_SPP: u64 = 1 << 8;
+pub const MSTATUS_MPP: u64 = 0b11 << 11;
+pub const MSTATUS_FS: u64 = 0b11 << 13;
+pub const MSTATUS_MPRV: u64 = 1 << 17;
+pub const MSTATUS_SUM: u64 = 1 << 18;
+pub const MSTATUS_MXR: u64 = 1 << 19;
+pub const MSTATUS_TVM: u64 = 1 << 20;
+pub const MSTATUS_TW: u64 = 1 << 21;
+pub const MSTATUS_TSR: u64 = 1 << 22;
+pub const MSTATUS_UXL: u64 = 0b11 << 32;
+pub const MSTATUS_SXL: u64 = 0b11 << 34;
+pub const MSTATUS_SD: u64 = 1 << 63;
+
+pub const ENVCFG_FIOM: u64 = 1 << 0;
+/// svadu, the hardware updates `A` and `D` instead of raising page faults
+pub const MENVCFG_ADUE: u64 = 1 << 61;
+/// sstc, `stimecmp` drives the supervisor timer interrupt
+pub const MENVCFG_STCE: u64 = 1 << 63;
+
+const MSTATUS_WRITE: u64 = MSTATUS_SIE | MSTATUS_MIE | MSTATUS_SPIE | MSTATUS_MPIE | MSTATUS_SPP |
+    MSTATUS_MPP | MSTATUS_MPRV | MSTATUS_SUM | MSTATUS_MXR | MSTATUS_TVM | MSTATUS_TW | MSTATUS_TSR;
+const SSTATUS_READ: u64 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_FS | MSTATUS_SUM |
+    MSTATUS_MXR | MSTATUS_UXL | MSTATUS_SD;
+const SSTATUS_WRITE: u64 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_SUM | MSTATUS_MXR;
+
+/// ssip, stip and seip
+const S_INTERRUPTS: u64 = 0x222;
+const STIP: u64 = 0x20;
+/// every standard interrupt
+const INTERRUPTS: u64 = 0xaaa;
+/// all exceptions but ecall from m-mode and the reserved codes
+const MEDELEG_WRITE: u64 = 0xb3ff;
+
+/// csrs of extensions the interpreter does not have: hypervisor, debug,
+/// floating point, user interrupts and the old base and bound scheme
+fn unimplemented(name: &str) -> bool {
+    (name.starts_with('h') && !name.starts_with("hpmcounter")) ||
+        ["vs", "u", "d", "f", "mbase", "mbound", "mibase", "mibound", "mdbase", "mdbound"]
+            .iter().any(|p| name.starts_with(p)) ||
+        matches!(name, "sedeleg" | "sideleg" | "mtinst" | "mtval2")
+}
+
+fn set_low(x: u64, v: u64) -> u64 {
+    x & !0xffff_ffff | v & 0xffff_ffff
+}
+
+fn set_high(x: u64, v: u64) -> u64 {
+    x & 0xffff_ffff | v << 32
+}
+
+/// storage of the csrs in `CSR_MAP` the interpreter implements. values
+/// here are raw, the architectural views are `Hart::read_csr` and
+/// `Hart::write_csr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrFile {
+    values: HashMap<u16, u64>,
+}
+
+impl CsrFile {
+    pub fn new(hartid: u64, is_32bit: bool) -> CsrFile {
+        let mut values: HashMap<u16, u64> = CSR_MAP.iter()
+            .filter(|(_, name)| !unimplemented(name))
+            .map(|(&addr, _)| (addr as u16, 0))
+            .collect();
+        if !is_32bit {
+            // the rv32 high halves
+            values.retain(|&addr, _| {
+                !(0xb80..0xba0).contains(&addr) && !(0xc80..0xca0).contains(&addr) && ![MSTATUSH, MENVCFGH, STIMECMPH].contains(&addr)
+            });
+            values.insert(MSTATUS, 2 << 32 | 2 << 34);
+        }
+        // a/d updates are on at reset, as on qemu
+        values.insert(MENVCFG, MENVCFG_ADUE);
+        values.insert(MHARTID, hartid);
+        CsrFile { values }
+    }
+
+    pub fn contains(&self, csr: u16) -> bool {
+        self.values.contains_key(&csr)
+    }
+
+    /// the raw value, 0 for csrs that are not implemented
+    pub fn get(&self, csr: u16) -> u64 {
+        self.values.get(&csr).copied().unwrap_or(0)
+    }
+
+    /// set the raw value, ignored for csrs that are not implemented
+    pub fn set(&mut self, csr: u16, value: u64) {
+        if let Some(v) = self.values.get_mut(&csr) {
+            *v = value;
+        }
+    }
+
+    /// count a retired instruction in `mcycle` and `minstret`
+    pub fn retire(&mut self) {
+        let inhibit = self.get(MCOUNTINHIBIT);
+        if inhibit & 0b001 == 0 {
+            self.set(MCYCLE, self.get(MCYCLE).wrapping_add(1));
+        }
+        if inhibit & 0b100 == 0 {
+            self.set(MINSTRET, self.get(MINSTRET).wrapping_add(1));
+        }
+    }
+}
+
+impl Hart {
+    pub fn hartid(&self) -> u64 {
+        self.csr.get(MHARTID)
+    }
+
+    pub fn misa(&self) -> u64 {
+        let ext = |c: u8| 1 << (c - b'A');
+        let mxl = if self.is_32bit { 1 << 30 } else { 2 << 62 };
+        let c = if self.compressed { ext(b'C') } else { 0 };
+        mxl | ext(b'A') | ext(b'I') | ext(b'M') | ext(b'S') | ext(b'U') | c
+    }
+
+    /// the `mip` value, device wires and software set bits. with sstc
+    /// `stip` is the `stimecmp` comparison instead.
+    pub fn pending(&self) -> u64 {
+        let mip = self.mip | self.csr.get(MIP);
+        if self.csr.get(MENVCFG) & MENVCFG_STCE == 0 {
+            return mip;
+        }
+        let stip = if self.csr.get(TIME) >= self.csr.get(STIMECMP) { STIP } else { 0 };
+        mip & !STIP | stip
+    }
+
+    /// whether the current mode may access `csr`, an illegal instruction
+    /// if not
+    pub fn check_csr(&self, csr: Csr, write: bool) -> Result<(), Exception> {
+        let illegal = Err(Exception::IllegalInstruction(0));
+        let addr = csr.0;
+        if !self.csr.contains(addr) || (self.privilege as u8) < csr.privilege() || (write && csr.is_read_only()) {
+            return illegal;
+        }
+        if addr == SATP && self.privilege == Privilege::Supervisor && self.csr.get(MSTATUS) & MSTATUS_TVM != 0 {
+            return illegal;
+        }
+        let sstc = self.csr.get(MENVCFG) & MENVCFG_STCE != 0 && self.csr.get(MCOUNTEREN) & 0b10 != 0;
+        if (addr == STIMECMP || addr == STIMECMPH) && self.privilege < Privilege::Machine && !sstc {
+            return illegal;
+        }
+        // user counters are gated by the counter enables
+        if (0xc00..0xca0).contains(&addr) {
+            let bit = 1 << (addr & 0x1f);
+            if self.privilege < Privilege::Machine && self.csr.get(MCOUNTEREN) & bit == 0 {
+                return illegal;
+            }
+            if self.privilege == Privilege::User && self.csr.get(SCOUNTEREN) & bit == 0 {
+                return illegal;
+            }
+        }
+        Ok(())
+    }
+
+    /// read `csr` as a `csrr*` in the current mode would
+    pub fn read_csr(&self, csr: Csr) -> Result<u64, Exception> {
+        self.check_csr(csr, false)?;
+        let addr = csr.0;
+        let epc_mask = if self.compressed { !1 } else { !3 };
+        let v = match addr {
+            SSTATUS => self.csr.get(MSTATUS) & SSTATUS_READ,
+            MSTATUSH => 0,
+            MISA => self.misa(),
+            SIE => self.csr.get(MIE) & self.csr.get(MIDELEG),
+            SIP => self.pending() & self.csr.get(MIDELEG),
+            MIP => self.pending(),
+            SEPC | MEPC => self.csr.get(addr) & epc_mask,
+            TIME => self.csr.get(TIME),
+            // unprivileged counters shadow the machine ones
+            0xc00..=0xc1f => self.csr.get(addr - 0x100),
+            MENVCFGH => self.csr.get(MENVCFG) >> 32,
+            STIMECMPH => self.csr.get(STIMECMP) >> 32,
+            0xc81 => self.csr.get(TIME) >> 32,
+            0xc80..=0xc9f => self.csr.get(addr - 0x180) >> 32,
+            0xb80..=0xb9f => self.csr.get(addr - 0x80) >> 32,
+            _ => self.csr.get(addr),
+        };
+        Ok(self.trunc(v))
+    }
+
+    /// write `csr` as a `csrr*` in the current mode would, fields are
+    /// legalized as warl
+    pub fn write_csr(&mut self, csr: Csr, value: u64) -> Result<(), Exception> {
+        self.check_csr(csr, true)?;
+        let addr = csr.0;
+        let v = self.trunc(value);
+        let old = self.csr.get(addr);
+        let new = match addr {
+            MSTATUS => {
+                let mut new = old & !MSTATUS_WRITE | v & MSTATUS_WRITE;
+                // mpp = 2 is reserved
+                if new & MSTATUS_MPP == 2 << 11 {
+                    new = new & !MSTATUS_MPP | old & MSTATUS_MPP;
+                }
+                new
+            }
+            SSTATUS => {
+                let mstatus = self.csr.get(MSTATUS);
+                self.csr.set(MSTATUS, mstatus & !SSTATUS_WRITE | v & SSTATUS_WRITE);
+                return Ok(());
+            }
+            MISA | MSTATUSH => return Ok(()),
+            MEDELEG => v & MEDELEG_WRITE,
+            MIDELEG => v & S_INTERRUPTS,
+            MIE => v & INTERRUPTS,
+            MIP if self.csr.get(MENVCFG) & MENVCFG_STCE != 0 => v & S_INTERRUPTS & !STIP,
+            MIP => v & S_INTERRUPTS,
+            SIE | SIP => {
+                let mask = self.csr.get(MIDELEG) & if addr == SIE { S_INTERRUPTS } else { 0x2 };
+                let target = if addr == SIE { MIE } else { MIP };
+                let cur = self.csr.get(target);
+                self.csr.set(target, cur & !mask | v & mask);
+                return Ok(());
+            }
+            // vectored or direct, reserved modes leave the register alone
+            MTVEC | STVEC if v & 0b11 >= 2 => return Ok(()),
+            MEPC | SEPC => v & !1,
+            // unsupported translation modes leave the register alone
+            SATP if Satp::decode(v, self.is_32bit).is_none() => return Ok(()),
+            MENVCFG if self.is_32bit => set_low(old, v & ENVCFG_FIOM),
+            MENVCFG => v & (ENVCFG_FIOM | MENVCFG_ADUE | MENVCFG_STCE),
+            MENVCFGH => {
+                let cur = self.csr.get(MENVCFG);
+                self.csr.set(MENVCFG, set_high(cur, v & ((MENVCFG_ADUE | MENVCFG_STCE) >> 32)));
+                return Ok(());
+            }
+            SENVCFG => v & ENVCFG_FIOM,
+            STIMECMP if self.is_32bit => set_low(old, v),
+            STIMECMPH => {
+                let cur = self.csr.get(STIMECMP);
+                self.csr.set(STIMECMP, set_high(cur, v));
+                return Ok(());
+            }
+            MCOUNTEREN | SCOUNTEREN => v & 0xffff_ffff,
+            MCOUNTINHIBIT => v & 0xffff_fffd,
+            MCYCLE | MINSTRET if self.is_32bit => set_low(old, v),
+            0xb80..=0xb9f => {
+                let low = addr - 0x80;
+                let cur = self.csr.get(low);
+                self.csr.set(low, set_high(cur, v));
+                return Ok(());
+            }
+            _ => v,
+        };
+        self.csr.set(addr, new);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csr(h: &Hart, addr: u16) -> u64 {
+        h.read_csr(Csr(addr)).unwrap()
+    }
+
+    #[test]
+    fn test_mstatus() {
+        let mut h = Hart::new(false);
+        assert_eq!(csr(&h, MSTATUS), 2 << 32 | 2 << 34);
+        h.write_csr(Csr(MSTATUS), u64::MAX).unwrap();
+        let xl = 2 << 32 | 2 << 34;
+        assert_eq!(csr(&h, MSTATUS), MSTATUS_WRITE | xl);
+        assert_eq!(csr(&h, SSTATUS), SSTATUS_WRITE | 2 << 32);
+
+        // a reserved mpp keeps the old one
+        h.write_csr(Csr(MSTATUS), 2 << 11).unwrap();
+        assert_eq!(csr(&h, MSTATUS) & MSTATUS_MPP, MSTATUS_MPP);
+        h.write_csr(Csr(MSTATUS), 1 << 11).unwrap();
+        assert_eq!(csr(&h, MSTATUS), 1 << 11 | xl);
+        h.write_csr(Csr(SSTATUS), MSTATUS_SIE | MSTATUS_MIE).unwrap();
+        assert_eq!(csr(&h, MSTATUS), MSTATUS_SIE | 1 << 11 | xl);
+
+        assert_eq!(csr(&h, MISA), 2 << 62 | 0x141105);
+        h.write_csr(Csr(MISA), 0).unwrap();
+        assert_eq!(csr(&h, MISA), 2 << 62 | 0x141105);
+        let h32 = Hart::new(true);
+        assert_eq!(csr(&h32, MISA), 1 << 30 | 0x141105);
+        assert_eq!(csr(&h32, MSTATUS), 0);
+    }
+
+    #[test]
+    fn test_warl() {
+        let mut h = Hart::new(false);
+        h.write_csr(Csr(MEDELEG), u64::MAX).unwrap();
+        assert_eq!(csr(&h, MEDELEG), 0xb3ff);
+        h.write_csr(Csr(MIDELEG), u64::MAX).unwrap();
+        assert_eq!(csr(&h, MIDELEG), 0x222);
+        h.write_csr(Csr(MIE), u64::MAX).unwrap();
+        assert_eq!(csr(&h, MIE), 0xaaa);
+        assert_eq!(csr(&h, SIE), 0x222);
+        h.write_csr(Csr(SIE), 0).unwrap();
+        assert_eq!(csr(&h, MIE), 0x888);
+
+        // device wires are read only, supervisor bits can be set
+        h.mip = 0x880;
+        h.write_csr(Csr(MIP), u64::MAX).unwrap();
+        assert_eq!(csr(&h, MIP), 0xaa2);
+        assert_eq!(csr(&h, SIP), 0x222);
+        h.write_csr(Csr(SIP), 0).unwrap();
+        assert_eq!(csr(&h, MIP), 0xaa0);
+
+        h.write_csr(Csr(MTVEC), 0x80001001).unwrap();
+        h.write_csr(Csr(MTVEC), 0x80002002).unwrap();
+        assert_eq!(csr(&h, MTVEC), 0x80001001);
+        h.write_csr(Csr(MEPC), 0x80000003).unwrap();
+        assert_eq!(csr(&h, MEPC), 0x80000002);
+        h.compressed = false;
+        assert_eq!(csr(&h, MEPC), 0x80000000);
+
+        h.write_csr(Csr(SATP), 8 << 60 | 0x1234).unwrap();
+        h.write_csr(Csr(SATP), 11 << 60 | 0x5678).unwrap();
+        assert_eq!(csr(&h, SATP), 8 << 60 | 0x1234);
+        h.write_csr(Csr(MENVCFG), u64::MAX).unwrap();
+        assert_eq!(csr(&h, MENVCFG), MENVCFG_STCE | MENVCFG_ADUE | ENVCFG_FIOM);
+        let mut h32 = Hart::new(true);
+        h32.write_csr(Csr(MENVCFGH), 0).unwrap();
+        assert_eq!(h32.csr.get(MENVCFG), 0);
+        h32.write_csr(Csr(SATP), 1 << 31 | 0x1234).unwrap();
+        assert_eq!(csr(&h32, SATP), 1 << 31 | 0x1234);
+        h.write_csr(Csr(MSCRATCH), u64::MAX).unwrap();
+        assert_eq!(csr(&h, MSCRATCH), u64::MAX);
+    }
+
+    #[test]
+    fn test_access() {
+        let illegal = Err(Exception::IllegalInstruction(0));
+        let mut h = Hart::new(false);
+        h.csr.set(MHARTID, 3);
+        assert_eq!(csr(&h, MHARTID), 3);
+        assert_eq!(h.write_csr(Csr(MHARTID), 0), Err(Exception::IllegalInstruction(0)));
+        // fcsr, ustatus and hstatus are not implemented
+        for addr in [0x003, 0x000, 0x600, 0xc80] {
+            assert_eq!(h.read_csr(Csr(addr)), illegal);
+        }
+
+        h.privilege = Privilege::Supervisor;
+        assert_eq!(h.read_csr(Csr(MSTATUS)), illegal);
+        assert_eq!(csr(&h, SSTATUS), 2 << 32);
+        h.csr.set(MSTATUS, MSTATUS_TVM);
+        assert_eq!(h.read_csr(Csr(SATP)), illegal);
+
+        // counters
+        h.csr.set(MINSTRET, 41);
+        h.csr.retire();
+        assert_eq!(h.read_csr(Csr(INSTRET)), illegal);
+        h.csr.set(MCOUNTEREN, 0b111);
+        assert_eq!(csr(&h, INSTRET), 42);
+        assert_eq!(csr(&h, CYCLE), 1);
+        h.privilege = Privilege::User;
+        assert_eq!(h.read_csr(Csr(TIME)), illegal);
+        assert_eq!(h.read_csr(Csr(SSTATUS)), illegal);
+        h.csr.set(SCOUNTEREN, 0b010);
+        h.csr.set(TIME, 7);
+        assert_eq!(csr(&h, TIME), 7);
+
+        let mut h32 = Hart::new(true);
+        h32.write_csr(Csr(0xb82), 1).unwrap();
+        h32.write_csr(Csr(MINSTRET), 0xffff_ffff).unwrap();
+        h32.csr.retire();
+        assert_eq!((csr(&h32, MINSTRET), csr(&h32, 0xb82)), (0, 2));
+    }
+
+    #[test]
+    fn test_sstc() {
+        let illegal = Err(Exception::IllegalInstruction(0));
+        let mut h = Hart::new(false);
+        h.csr.set(TIME, 100);
+        h.write_csr(Csr(STIMECMP), 150).unwrap();
+        h.write_csr(Csr(MIP), STIP).unwrap();
+        assert_eq!(csr(&h, MIP), STIP);
+
+        // stimecmp takes over stip
+        h.write_csr(Csr(MENVCFG), MENVCFG_STCE).unwrap();
+        assert_eq!(csr(&h, MIP), 0);
+        h.csr.set(TIME, 150);
+        assert_eq!(csr(&h, MIP), STIP);
+        h.write_csr(Csr(MIP), 0).unwrap();
+        assert_eq!(csr(&h, SIP), 0);
+        h.write_csr(Csr(MIDELEG), STIP).unwrap();
+        assert_eq!(csr(&h, SIP), STIP);
+
+        // s-mode needs mcounteren.tm as well
+        h.privilege = Privilege::Supervisor;
+        assert_eq!(h.read_csr(Csr(STIMECMP)), illegal);
+        h.csr.set(MCOUNTEREN, 0b10);
+        h.write_csr(Csr(STIMECMP), 200).unwrap();
+        assert_eq!(csr(&h, SIP), 0);
+
+        let mut h32 = Hart::new(true);
+        h32.write_csr(Csr(STIMECMPH), 1).unwrap();
+        h32.write_csr(Csr(STIMECMP), 2).unwrap();
+        assert_eq!(h32.csr.get(STIMECMP), 1 << 32 | 2);
+        assert_eq!(csr(&h32, STIMECMPH), 1);
+    }
+}
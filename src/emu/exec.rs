@@ -0,0 +1,760 @@
+use crate::disassembly::riscv::{disassembly_with, DisasmConfig};
+use crate::flat_disasm::FlatRiscV;
+use crate::isa::riscv::{AmoOpType, AmoWidth, BrType, Csr, CsrOpType, EOpType, LoadType, MulOpType, OpType, Rd, RiscV, Rs1, StoreType};
+
+use super::csr::{MIE, MSTATUS, MSTATUS_TVM, MSTATUS_TW};
+use super::hart::{sext, zext, Hart};
+use super::memory::Memory;
+use super::mmu::Access;
+use super::trap::{Exception, Interrupt, Privilege};
+
+
+/// an alu operation on the low `bits` of the operands, the result is not
+/// truncated
+fn alu(ty: OpType, a: u64, b: u64, bits: u32) -> u64 {
+    let sh = (b & (bits as u64 - 1)) as u32;
+    match ty {
+        OpType::Add => a.wrapping_add(b),
+        OpType::Sub => a.wrapping_sub(b),
+        OpType::Sll => a << sh,
+        OpType::Slt => (sext(a, bits) < sext(b, bits)) as u64,
+        OpType::Sltu => (zext(a, bits) < zext(b, bits)) as u64,
+        OpType::Xor => a ^ b,
+        OpType::Srl => zext(a, bits) >> sh,
+        OpType::Sra => (sext(a, bits) >> sh) as u64,
+        OpType::Or => a | b,
+        OpType::And => a & b,
+    }
+}
+
+/// `M` operations on the low `bits` of the operands, with the riscv
+/// results for division by zero and overflow
+fn mul(ty: MulOpType, a: u64, b: u64, bits: u32) -> u64 {
+    let (sa, sb, ua, ub) = (sext(a, bits), sext(b, bits), zext(a, bits), zext(b, bits));
+    match ty {
+        MulOpType::Mul => a.wrapping_mul(b),
+        MulOpType::Mulh => ((sa as i128 * sb as i128) >> bits) as u64,
+        MulOpType::Mulhsu => ((sa as i128 * ub as i128) >> bits) as u64,
+        MulOpType::Mulhu => ((ua as u128 * ub as u128) >> bits) as u64,
+        MulOpType::Div if ub == 0 => u64::MAX,
+        MulOpType::Div => sa.wrapping_div(sb) as u64,
+        MulOpType::Divu if ub == 0 => u64::MAX,
+        MulOpType::Divu => ua / ub,
+        MulOpType::Rem if ub == 0 => a,
+        MulOpType::Rem => sa.wrapping_rem(sb) as u64,
+        MulOpType::Remu if ub == 0 => a,
+        MulOpType::Remu => ua % ub,
+    }
+}
+
+/// the value an amo stores, from the old value in memory and rs2
+fn amo(ty: AmoOpType, old: u64, src: u64, bits: u32) -> u64 {
+    match ty {
+        AmoOpType::Add => old.wrapping_add(src),
+        AmoOpType::Swap => src,
+        AmoOpType::Xor => old ^ src,
+        AmoOpType::Or => old | src,
+        AmoOpType::And => old & src,
+        AmoOpType::Min => if sext(old, bits) <= sext(src, bits) { old } else { src },
+        AmoOpType::Max => if sext(old, bits) >= sext(src, bits) { old } else { src },
+        AmoOpType::Minu => if zext(old, bits) <= zext(src, bits) { old } else { src },
+        AmoOpType::Maxu => if zext(old, bits) >= zext(src, bits) { old } else { src },
+    }
+}
+
+/// the address of an lr, sc or amo, which must be naturally aligned
+fn amo_addr(hart: &Hart, rs1: Rs1, width: AmoWidth, store: bool) -> Result<u64, Exception> {
+    let addr = hart.reg(rs1);
+    match addr.is_multiple_of(width.bytes() as u64) {
+        true => Ok(addr),
+        false if store => Err(Exception::StoreMisaligned(addr)),
+        false => Err(Exception::LoadMisaligned(addr)),
+    }
+}
+
+fn load_size(ty: LoadType) -> (u8, bool) {
+    match ty {
+        LoadType::Byte => (1, true),
+        LoadType::Half => (2, true),
+        LoadType::Word => (4, true),
+        LoadType::Double => (8, true),
+        LoadType::ByteU => (1, false),
+        LoadType::HalfU => (2, false),
+        LoadType::WordU => (4, false),
+    }
+}
+
+fn store_size(ty: StoreType) -> u8 {
+    match ty {
+        StoreType::Byte => 1,
+        StoreType::Half => 2,
+        StoreType::Word => 4,
+        StoreType::Double => 8,
+    }
+}
+
+/// a jump target, checked against the pc alignment
+fn jump_target(hart: &Hart, target: u64) -> Result<u64, Exception> {
+    let target = hart.trunc(target);
+    if !target.is_multiple_of(hart.ialign()) {
+        return Err(Exception::InstructionMisaligned(target));
+    }
+    Ok(target)
+}
+
+/// `csrrw`, `csrrs` and `csrrc`. `csrrw` with rd = x0 does not read and
+/// the others do not write when the source is zero.
+fn csr_op(hart: &mut Hart, ty: CsrOpType, rd: Rd, csr: Csr, src: u64, zero: bool) -> Result<(), Exception> {
+    let read = !(ty == CsrOpType::Rw && rd.0 == 0);
+    let write = ty == CsrOpType::Rw || !zero;
+    hart.check_csr(csr, write)?;
+    let old = if read { hart.read_csr(csr)? } else { 0 };
+    if write {
+        let new = match ty {
+            CsrOpType::Rw => src,
+            CsrOpType::Rs => old | src,
+            CsrOpType::Rc => old & !src,
+        };
+        hart.write_csr(csr, new)?;
+    }
+    if read {
+        hart.set_reg(rd, old);
+    }
+    Ok(())
+}
+
+/// execute a 4-byte `inst` at `hart.pc`
+pub fn execute(hart: &mut Hart, mem: &mut impl Memory, inst: RiscV) -> Result<(), Exception> {
+    execute_sized(hart, mem, inst, 4)
+}
+
+/// execute `inst` of `len` bytes at `hart.pc`. on an exception neither the
+/// registers nor the pc are changed.
+pub fn execute_sized(hart: &mut Hart, mem: &mut impl Memory, inst: RiscV, len: u8) -> Result<(), Exception> {
+    let illegal = Exception::IllegalInstruction(0);
+    let pc = hart.pc;
+    let next = hart.trunc(pc.wrapping_add(len as u64));
+    let xlen = hart.xlen();
+    let mut new_pc = next;
+    match inst {
+        RiscV::Lui(rd, imm) => hart.set_reg(rd, sext(imm as u64, 32) as u64),
+        RiscV::Auipc(rd, imm) => hart.set_reg(rd, pc.wrapping_add(sext(imm as u64, 32) as u64)),
+        RiscV::Jal(rd, offset) => {
+            new_pc = jump_target(hart, pc.wrapping_add(offset as i64 as u64))?;
+            hart.set_reg(rd, next);
+        },
+        RiscV::Jalr(rd, rs1, offset) => {
+            new_pc = jump_target(hart, hart.reg(rs1).wrapping_add(offset as i64 as u64) & !1)?;
+            hart.set_reg(rd, next);
+        },
+        RiscV::Branch(ty, rs1, rs2, offset) => {
+            let (a, b) = (hart.reg(rs1), hart.reg(rs2));
+            let (sa, sb) = (hart.signed(a), hart.signed(b));
+            let taken = match ty {
+                BrType::Eq => a == b,
+                BrType::Ne => a != b,
+                BrType::Lt => sa < sb,
+                BrType::Ge => sa >= sb,
+                BrType::Ltu => a < b,
+                BrType::Geu => a >= b,
+            };
+            if taken {
+                new_pc = jump_target(hart, pc.wrapping_add(offset as i64 as u64))?;
+            }
+        },
+        RiscV::Load(ty, rd, rs1, offset) => {
+            let (size, signed) = load_size(ty);
+            if hart.is_32bit && matches!(ty, LoadType::Double | LoadType::WordU) {
+                return Err(illegal);
+            }
+            let addr = hart.trunc(hart.reg(rs1).wrapping_add(offset as i64 as u64));
+            let v = hart.load(mem, addr, size)?;
+            hart.set_reg(rd, if signed { sext(v, size as u32 * 8) as u64 } else { v });
+        },
+        RiscV::Store(ty, rs1, rs2, offset) => {
+            let size = store_size(ty);
+            if hart.is_32bit && ty == StoreType::Double {
+                return Err(illegal);
+            }
+            let addr = hart.trunc(hart.reg(rs1).wrapping_add(offset as i64 as u64));
+            let v = zext(hart.reg(rs2), size as u32 * 8);
+            hart.store(mem, addr, size, v)?;
+        },
+        RiscV::OpI(ty, rd, rs1, imm) => {
+            if matches!(ty, OpType::Sll | OpType::Srl | OpType::Sra) && imm as u32 >= xlen {
+                return Err(illegal);
+            }
+            hart.set_reg(rd, alu(ty, hart.reg(rs1), imm as i64 as u64, xlen));
+        },
+        RiscV::Op(ty, rd, rs1, rs2) => hart.set_reg(rd, alu(ty, hart.reg(rs1), hart.reg(rs2), xlen)),
+        RiscV::OpIW(ty, rd, rs1, imm) if !hart.is_32bit => {
+            hart.set_reg(rd, sext(alu(ty, hart.reg(rs1), imm as i64 as u64, 32), 32) as u64);
+        },
+        RiscV::OpW(ty, rd, rs1, rs2) if !hart.is_32bit => {
+            hart.set_reg(rd, sext(alu(ty, hart.reg(rs1), hart.reg(rs2), 32), 32) as u64);
+        },
+        RiscV::MulOp(ty, rd, rs1, rs2) => hart.set_reg(rd, mul(ty, hart.reg(rs1), hart.reg(rs2), xlen)),
+        RiscV::MulOpW(ty, rd, rs1, rs2) if !hart.is_32bit => {
+            hart.set_reg(rd, sext(mul(ty, hart.reg(rs1), hart.reg(rs2), 32), 32) as u64);
+        },
+        RiscV::Lr(width, _, _, _) | RiscV::Sc(width, ..) | RiscV::Amo(_, width, ..)
+            if hart.is_32bit && width == AmoWidth::D => return Err(illegal),
+        RiscV::Lr(width, _, rd, rs1) => {
+            let (size, bits) = (width.bytes(), width.bytes() as u32 * 8);
+            let addr = amo_addr(hart, rs1, width, false)?;
+            let paddr = hart.translate(mem, addr, Access::Load)?;
+            let v = hart.load(mem, addr, size)?;
+            hart.reservation = Some(paddr);
+            hart.set_reg(rd, sext(v, bits) as u64);
+        },
+        RiscV::Sc(width, _, rd, rs1, rs2) => {
+            let size = width.bytes();
+            let addr = amo_addr(hart, rs1, width, true)?;
+            let paddr = hart.translate(mem, addr, Access::Store)?;
+            // the reservation is gone either way
+            let failed = hart.reservation.take() != Some(paddr);
+            if !failed {
+                hart.store(mem, addr, size, zext(hart.reg(rs2), size as u32 * 8))?;
+            }
+            hart.set_reg(rd, failed as u64);
+        },
+        RiscV::Amo(ty, width, _, rd, rs1, rs2) => {
+            let (size, bits) = (width.bytes(), width.bytes() as u32 * 8);
+            let addr = amo_addr(hart, rs1, width, true)?;
+            // faults are reported as store/amo faults, writability first
+            let as_store = |e| match e {
+                Exception::LoadAccessFault(a) => Exception::StoreAccessFault(a),
+                Exception::LoadPageFault(a) => Exception::StorePageFault(a),
+                e => e,
+            };
+            hart.translate(mem, addr, Access::Store)?;
+            let old = hart.load(mem, addr, size).map_err(as_store)?;
+            hart.store(mem, addr, size, zext(amo(ty, old, hart.reg(rs2), bits), bits))?;
+            hart.set_reg(rd, sext(old, bits) as u64);
+        },
+        RiscV::Fence(..) => (),
+        RiscV::EOp(EOpType::Call) => return Err(Exception::Ecall(hart.privilege)),
+        RiscV::EOp(EOpType::Break) => return Err(Exception::Breakpoint(pc)),
+        RiscV::EOp(EOpType::Mret) => new_pc = hart.mret()?,
+        RiscV::EOp(EOpType::Sret) => new_pc = hart.sret()?,
+        RiscV::EOp(EOpType::Wfi) => {
+            let tw = hart.csr.get(MSTATUS) & MSTATUS_TW != 0;
+            match hart.privilege {
+                Privilege::User => return Err(illegal),
+                Privilege::Supervisor if tw => return Err(illegal),
+                _ => hart.wfi = true,
+            }
+        },
+        RiscV::SFenceVma(rs1, rs2) => {
+            let tvm = hart.csr.get(MSTATUS) & MSTATUS_TVM != 0;
+            match hart.privilege {
+                Privilege::User => return Err(illegal),
+                Privilege::Supervisor if tvm => return Err(illegal),
+                _ => {
+                    let asid_mask = if hart.is_32bit { 0x1ff } else { 0xffff };
+                    let vaddr = (rs1.0 != 0).then(|| hart.reg(rs1));
+                    let asid = (rs2.0 != 0).then(|| (hart.reg(rs2) & asid_mask) as u16);
+                    hart.tlb.flush(vaddr, asid);
+                }
+            }
+        },
+        RiscV::CsrOp(ty, rd, rs1, csr) => csr_op(hart, ty, rd, csr, hart.reg(rs1), rs1.0 == 0)?,
+        RiscV::CsrOpI(ty, rd, zimm, csr) => csr_op(hart, ty, rd, csr, zimm as u64, zimm == 0)?,
+        _ => return Err(illegal),
+    }
+    hart.pc = new_pc;
+    hart.csr.retire();
+    Ok(())
+}
+
+/// execute the flat form of an instruction, which is always 4 bytes
+pub fn execute_flat(hart: &mut Hart, mem: &mut impl Memory, inst: FlatRiscV) -> Result<(), Exception> {
+    let inst = RiscV::try_from(inst).map_err(|_| Exception::IllegalInstruction(0))?;
+    execute(hart, mem, inst)
+}
+
+/// fetch, decode and execute one instruction
+pub fn step(hart: &mut Hart, mem: &mut impl Memory) -> Result<RiscV, Exception> {
+    let low = hart.fetch(mem, hart.pc, 2)?;
+    let code = if low & 0b11 == 0b11 {
+        let high = hart.trunc(hart.pc.wrapping_add(2));
+        hart.fetch(mem, high, 2)? << 16 | low
+    } else {
+        low
+    };
+    let cfg = DisasmConfig { is_32bit: hart.is_32bit, ..Default::default() };
+    let (inst, len) = match disassembly_with(code as u32, &cfg) {
+        Some((_, 2)) if !hart.compressed => return Err(Exception::IllegalInstruction(code)),
+        Some(r) => r,
+        None => return Err(Exception::IllegalInstruction(code)),
+    };
+    execute_sized(hart, mem, inst, len as u8).map_err(|e| match e {
+        Exception::IllegalInstruction(0) => Exception::IllegalInstruction(code),
+        e => e,
+    })?;
+    Ok(inst)
+}
+
+/// what a hart did in one `run_step`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Retired(RiscV),
+    /// the trap handler was entered
+    Exception(Exception),
+    Interrupt(Interrupt),
+    /// in `wfi` with nothing pending
+    Waiting,
+}
+
+/// take a pending interrupt or step, entering the trap handler on an
+/// exception
+pub fn run_step(hart: &mut Hart, mem: &mut impl Memory) -> Event {
+    if let Some(i) = hart.pending_interrupt() {
+        hart.take_interrupt(i);
+        return Event::Interrupt(i);
+    }
+    if hart.wfi {
+        // resumes on any locally enabled interrupt, even a masked one
+        if hart.pending() & hart.csr.get(MIE) == 0 {
+            return Event::Waiting;
+        }
+        hart.wfi = false;
+    }
+    match step(hart, mem) {
+        Ok(inst) => Event::Retired(inst),
+        Err(e) => {
+            hart.take_exception(e);
+            Event::Exception(e)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::flat_disasm::disasm::flat_disasm;
+    use crate::isa::riscv::{AqRl, CsrOpType, Csr, IsFenceI, Pred, Reg, Succ};
+    use crate::emu::csr::*;
+    use crate::emu::memory::Ram;
+    use super::*;
+
+    const BASE: u64 = 0x80000000;
+
+    fn hart(is_32bit: bool) -> (Hart, Ram) {
+        let mut h = Hart::new(is_32bit);
+        h.pc = BASE;
+        (h, Ram::new(BASE, 0x1000))
+    }
+
+    fn x(i: u8) -> Reg {
+        Reg(i)
+    }
+
+    /// run `inst` with x1 = a, x2 = b, returning x3
+    fn op(inst: RiscV, a: u64, b: u64, is_32bit: bool) -> u64 {
+        let (mut h, mut m) = hart(is_32bit);
+        h.set_reg(x(1), a);
+        h.set_reg(x(2), b);
+        execute(&mut h, &mut m, inst).unwrap();
+        assert_eq!(h.pc, BASE + 4);
+        h.reg(x(3))
+    }
+
+    fn op64(ty: OpType, a: u64, b: u64) -> u64 {
+        op(RiscV::Op(ty, x(3), x(1), x(2)), a, b, false)
+    }
+
+    fn opw(ty: OpType, a: u64, b: u64) -> u64 {
+        op(RiscV::OpW(ty, x(3), x(1), x(2)), a, b, false)
+    }
+
+    fn op32(ty: OpType, a: u64, b: u64) -> u64 {
+        op(RiscV::Op(ty, x(3), x(1), x(2)), a, b, true)
+    }
+
+    fn opi(ty: OpType, a: u64, imm: i16, is_32bit: bool) -> u64 {
+        op(RiscV::OpI(ty, x(3), x(1), imm), a, 0, is_32bit)
+    }
+
+    #[test]
+    fn test_x0() {
+        let (mut h, mut m) = hart(false);
+        execute(&mut h, &mut m, RiscV::OpI(OpType::Add, x(0), x(0), 5)).unwrap();
+        assert_eq!(h.reg(x(0)), 0);
+        h.set_reg(x(0), 1);
+        assert_eq!(h.reg(x(0)), 0);
+    }
+
+    #[test]
+    fn test_lui_auipc() {
+        let (mut h, mut m) = hart(false);
+        execute(&mut h, &mut m, RiscV::Lui(x(1), 0x80000000)).unwrap();
+        assert_eq!(h.reg(x(1)), 0xffffffff80000000);
+        execute(&mut h, &mut m, RiscV::Auipc(x(2), 0xfffff000)).unwrap();
+        assert_eq!(h.reg(x(2)), BASE + 4 - 0x1000);
+
+        let (mut h, mut m) = hart(true);
+        execute(&mut h, &mut m, RiscV::Lui(x(1), 0x80000000)).unwrap();
+        assert_eq!(h.reg(x(1)), 0x80000000);
+        execute(&mut h, &mut m, RiscV::Auipc(x(2), 0x80000000)).unwrap();
+        assert_eq!(h.reg(x(2)), 4);
+    }
+
+    #[test]
+    fn test_jal_jalr() {
+        let (mut h, mut m) = hart(false);
+        execute(&mut h, &mut m, RiscV::Jal(x(1), -8)).unwrap();
+        assert_eq!((h.pc, h.reg(x(1))), (BASE - 8, BASE + 4));
+
+        // rd == rs1 reads before writing, bit 0 of the target is cleared
+        h.set_reg(x(5), BASE + 0x101);
+        execute(&mut h, &mut m, RiscV::Jalr(x(5), x(5), 0x10)).unwrap();
+        assert_eq!((h.pc, h.reg(x(5))), (BASE + 0x110, BASE - 4));
+
+        h.compressed = false;
+        let r = execute(&mut h, &mut m, RiscV::Jal(x(1), 2));
+        assert_eq!(r, Err(Exception::InstructionMisaligned(BASE + 0x112)));
+        assert_eq!((h.pc, h.reg(x(1))), (BASE + 0x110, BASE + 4));
+    }
+
+    #[test]
+    fn test_branch() {
+        let cases = [
+            (BrType::Eq, 5, 5, true),
+            (BrType::Ne, 5, 5, false),
+            (BrType::Lt, -1i64 as u64, 0, true),
+            (BrType::Ltu, -1i64 as u64, 0, false),
+            (BrType::Ge, 0, -1i64 as u64, true),
+            (BrType::Geu, 0, -1i64 as u64, false),
+            (BrType::Ge, 3, 3, true),
+        ];
+        for (ty, a, b, taken) in cases {
+            let (mut h, mut m) = hart(false);
+            h.set_reg(x(1), a);
+            h.set_reg(x(2), b);
+            execute(&mut h, &mut m, RiscV::Branch(ty, x(1), x(2), 0x40)).unwrap();
+            assert_eq!(h.pc, if taken { BASE + 0x40 } else { BASE + 4 }, "{:?}", ty);
+        }
+        // rv32 compares the 32-bit values
+        let (mut h, mut m) = hart(true);
+        h.set_reg(x(1), 0x80000000);
+        execute(&mut h, &mut m, RiscV::Branch(BrType::Lt, x(1), x(0), 0x40)).unwrap();
+        assert_eq!(h.pc, BASE + 0x40);
+    }
+
+    #[test]
+    fn test_load_store() {
+        let (mut h, mut m) = hart(false);
+        h.set_reg(x(1), BASE + 0x100);
+        h.set_reg(x(2), 0x8081828384858687);
+        execute(&mut h, &mut m, RiscV::Store(StoreType::Double, x(1), x(2), 8)).unwrap();
+        execute(&mut h, &mut m, RiscV::Store(StoreType::Byte, x(1), x(2), 0)).unwrap();
+        assert_eq!(m.load(BASE + 0x108, 8), Ok(0x8081828384858687));
+        assert_eq!(m.load(BASE + 0x100, 2), Ok(0x87));
+
+        let cases = [
+            (LoadType::Byte, 0xffffffffffffff87),
+            (LoadType::ByteU, 0x87),
+            (LoadType::Half, 0xffffffffffff8687),
+            (LoadType::HalfU, 0x8687),
+            (LoadType::Word, 0xffffffff84858687),
+            (LoadType::WordU, 0x84858687),
+            (LoadType::Double, 0x8081828384858687),
+        ];
+        for (ty, v) in cases {
+            execute(&mut h, &mut m, RiscV::Load(ty, x(3), x(1), 8)).unwrap();
+            assert_eq!(h.reg(x(3)), v, "{:?}", ty);
+        }
+
+        let r = execute(&mut h, &mut m, RiscV::Load(LoadType::Word, x(3), x(0), 0));
+        assert_eq!(r, Err(Exception::LoadAccessFault(0)));
+        let r = execute(&mut h, &mut m, RiscV::Store(StoreType::Word, x(0), x(3), -4));
+        assert_eq!(r, Err(Exception::StoreAccessFault(u64::MAX - 3)));
+
+        // rv32 has no ld/lwu/sd, stores take the low word
+        let (mut h, mut m) = hart(true);
+        let r = execute(&mut h, &mut m, RiscV::Load(LoadType::Double, x(3), x(1), 0));
+        assert_eq!(r, Err(Exception::IllegalInstruction(0)));
+        h.set_reg(x(1), BASE + 0x10);
+        h.set_reg(x(2), 0x1_8000_0000);
+        execute(&mut h, &mut m, RiscV::Store(StoreType::Word, x(1), x(2), 0)).unwrap();
+        execute(&mut h, &mut m, RiscV::Load(LoadType::Word, x(3), x(1), 0)).unwrap();
+        assert_eq!(h.reg(x(3)), 0x80000000);
+    }
+
+    #[test]
+    fn test_op_imm() {
+        assert_eq!(opi(OpType::Add, 1, -2, false), u64::MAX);
+        assert_eq!(opi(OpType::Add, 1, -2, true), 0xffffffff);
+        assert_eq!(opi(OpType::Slt, -5i64 as u64, -4, false), 1);
+        // sltiu sign extends the immediate before the unsigned compare
+        assert_eq!(opi(OpType::Sltu, 5, -1, false), 1);
+        assert_eq!(opi(OpType::Sltu, u64::MAX, -1, false), 0);
+        assert_eq!(opi(OpType::Xor, 0x0f, -1, false), !0x0f);
+        assert_eq!(opi(OpType::Or, 0x0f, 0x30, false), 0x3f);
+        assert_eq!(opi(OpType::And, 0xff, 0x70f, false), 0x0f);
+        assert_eq!(opi(OpType::Sll, 1, 63, false), 1 << 63);
+        assert_eq!(opi(OpType::Srl, 1 << 63, 63, false), 1);
+        assert_eq!(opi(OpType::Sra, 1 << 63, 63, false), u64::MAX);
+        assert_eq!(opi(OpType::Sra, 0x80000000, 31, true), 0xffffffff);
+        assert_eq!(opi(OpType::Srl, 0x80000000, 31, true), 1);
+
+        let (mut h, mut m) = hart(true);
+        let r = execute(&mut h, &mut m, RiscV::OpI(OpType::Sll, x(1), x(1), 32));
+        assert_eq!(r, Err(Exception::IllegalInstruction(0)));
+    }
+
+    #[test]
+    fn test_op() {
+        assert_eq!(op64(OpType::Add, u64::MAX, 2), 1);
+        assert_eq!(op64(OpType::Sub, 0, 1), u64::MAX);
+        // shift amounts are masked to 6 bits
+        assert_eq!(op64(OpType::Sll, 1, 65), 2);
+        assert_eq!(op64(OpType::Srl, 1 << 63, 127), 1);
+        assert_eq!(op64(OpType::Sra, 1 << 63, 127), u64::MAX);
+        assert_eq!(op64(OpType::Slt, u64::MAX, 0), 1);
+        assert_eq!(op64(OpType::Sltu, u64::MAX, 0), 0);
+        assert_eq!(op64(OpType::Xor, 0b1100, 0b1010), 0b0110);
+        assert_eq!(op64(OpType::Or, 0b1100, 0b1010), 0b1110);
+        assert_eq!(op64(OpType::And, 0b1100, 0b1010), 0b1000);
+    }
+
+    #[test]
+    fn test_op_rv32() {
+        assert_eq!(op32(OpType::Add, 0xffffffff, 2), 1);
+        assert_eq!(op32(OpType::Sub, 0, 1), 0xffffffff);
+        // shift amounts are masked to 5 bits
+        assert_eq!(op32(OpType::Sll, 1, 33), 2);
+        assert_eq!(op32(OpType::Sll, 0x80000000, 1), 0);
+        assert_eq!(op32(OpType::Srl, 0x80000000, 63), 1);
+        assert_eq!(op32(OpType::Sra, 0x80000000, 31), 0xffffffff);
+        assert_eq!(op32(OpType::Slt, 0x80000000, 0), 1);
+        assert_eq!(op32(OpType::Sltu, 0x80000000, 0), 0);
+    }
+
+    #[test]
+    fn test_op_w() {
+        // results are sign extended from bit 31
+        assert_eq!(opw(OpType::Add, 0x7fffffff, 1), 0xffffffff80000000);
+        assert_eq!(opw(OpType::Add, 0x1_0000_0001, 0x1_0000_0001), 2);
+        assert_eq!(opw(OpType::Sub, 0, 1), u64::MAX);
+        // shift amounts are masked to 5 bits
+        assert_eq!(opw(OpType::Sll, 1, 63), 0xffffffff80000000);
+        assert_eq!(opw(OpType::Sll, 1, 32), 1);
+        // srlw shifts the zero extended low word
+        assert_eq!(opw(OpType::Srl, 0xffffffff_80000000, 4), 0x08000000);
+        assert_eq!(opw(OpType::Srl, 0x80000000, 0), 0xffffffff80000000);
+        assert_eq!(opw(OpType::Sra, 0x80000000, 4), 0xfffffffff8000000);
+        assert_eq!(op(RiscV::OpIW(OpType::Add, x(3), x(1), -1), 0, 0, false), u64::MAX);
+        assert_eq!(op(RiscV::OpIW(OpType::Add, x(3), x(1), 0), 0xdeadbeef, 0, false), 0xffffffffdeadbeef);
+        assert_eq!(op(RiscV::OpIW(OpType::Srl, x(3), x(1), 31), 0x80000000, 0, false), 1);
+        assert_eq!(op(RiscV::OpIW(OpType::Sra, x(3), x(1), 31), 0x80000000, 0, false), u64::MAX);
+
+        let (mut h, mut m) = hart(true);
+        let r = execute(&mut h, &mut m, RiscV::OpW(OpType::Add, x(3), x(1), x(2)));
+        assert_eq!(r, Err(Exception::IllegalInstruction(0)));
+    }
+
+    #[test]
+    fn test_mul() {
+        let m = |ty, a: i64, b: i64| op(RiscV::MulOp(ty, x(3), x(1), x(2)), a as u64, b as u64, false);
+        let mw = |ty, a: i64, b: i64| op(RiscV::MulOpW(ty, x(3), x(1), x(2)), a as u64, b as u64, false);
+        let m32 = |ty, a: i64, b: i64| op(RiscV::MulOp(ty, x(3), x(1), x(2)), a as u64, b as u64, true);
+        assert_eq!(m(MulOpType::Mul, -3, 5), -15i64 as u64);
+        assert_eq!(m(MulOpType::Mulh, -1, -1), 0);
+        assert_eq!(m(MulOpType::Mulh, i64::MIN, 2), u64::MAX);
+        assert_eq!(m(MulOpType::Mulhu, -1, -1), u64::MAX - 1);
+        assert_eq!(m(MulOpType::Mulhsu, -1, -1), u64::MAX);
+        assert_eq!(m(MulOpType::Div, -7, 2), -3i64 as u64);
+        assert_eq!(m(MulOpType::Div, 7, 0), u64::MAX);
+        assert_eq!(m(MulOpType::Div, i64::MIN, -1), i64::MIN as u64);
+        assert_eq!(m(MulOpType::Divu, 7, 0), u64::MAX);
+        assert_eq!(m(MulOpType::Rem, -7, 2), -1i64 as u64);
+        assert_eq!(m(MulOpType::Rem, 7, 0), 7);
+        assert_eq!(m(MulOpType::Rem, i64::MIN, -1), 0);
+        assert_eq!(m(MulOpType::Remu, -7, 0), -7i64 as u64);
+        assert_eq!(mw(MulOpType::Mul, 0x10000, 0x10000), 0);
+        assert_eq!(mw(MulOpType::Div, i32::MIN as i64, -1), i32::MIN as i64 as u64);
+        assert_eq!(mw(MulOpType::Divu, 0x1_0000_0010, 0), u64::MAX);
+        assert_eq!(mw(MulOpType::Remu, 0x1_8000_0000, 0), 0xffffffff80000000);
+        assert_eq!(m32(MulOpType::Mulh, -1, 1), 0xffffffff);
+        assert_eq!(m32(MulOpType::Mulhu, -1, -1), 0xfffffffe);
+        assert_eq!(m32(MulOpType::Div, i32::MIN as i64, -1), 0x80000000);
+    }
+
+    #[test]
+    fn test_atomic() {
+        let (mut h, mut m) = hart(false);
+        let a = BASE + 0x800;
+        m.store(a, 4, 0xfffffff0).unwrap();
+        h.set_reg(x(1), a);
+        h.set_reg(x(2), 5);
+        let amo = |ty| RiscV::Amo(ty, AmoWidth::W, AqRl(0), x(3), x(1), x(2));
+        let lr = RiscV::Lr(AmoWidth::W, AqRl(0b10), x(4), x(1));
+        let sc = RiscV::Sc(AmoWidth::W, AqRl(0b01), x(5), x(1), x(2));
+
+        // the old value is sign extended, w ops compare 32 bits
+        execute(&mut h, &mut m, amo(AmoOpType::Add)).unwrap();
+        assert_eq!((h.reg(x(3)), m.load(a, 4).unwrap()), (-16i64 as u64, 0xfffffff5));
+        execute(&mut h, &mut m, amo(AmoOpType::Min)).unwrap();
+        execute(&mut h, &mut m, amo(AmoOpType::Maxu)).unwrap();
+        assert_eq!(m.load(a, 4).unwrap(), 0xfffffff5);
+        execute(&mut h, &mut m, amo(AmoOpType::Swap)).unwrap();
+        assert_eq!(m.load(a, 4).unwrap(), 5);
+
+        h.set_reg(x(2), 7);
+        execute(&mut h, &mut m, lr).unwrap();
+        assert_eq!((h.reg(x(4)), h.reservation), (5, Some(a)));
+        execute(&mut h, &mut m, sc).unwrap();
+        assert_eq!((h.reg(x(5)), m.load(a, 4).unwrap(), h.reservation), (0, 7, None));
+        // without a reservation sc fails and does not store
+        h.set_reg(x(2), 9);
+        execute(&mut h, &mut m, sc).unwrap();
+        assert_eq!((h.reg(x(5)), m.load(a, 4).unwrap()), (1, 7));
+        // a store to the doubleword from elsewhere breaks the reservation
+        execute(&mut h, &mut m, lr).unwrap();
+        h.snoop((a + 4, a + 5));
+        execute(&mut h, &mut m, sc).unwrap();
+        assert_eq!(h.reg(x(5)), 1);
+        execute(&mut h, &mut m, lr).unwrap();
+        h.snoop((a + 8, a + 16));
+        execute(&mut h, &mut m, sc).unwrap();
+        assert_eq!((h.reg(x(5)), m.load(a, 4).unwrap()), (0, 9));
+        assert_eq!(h.stored.take(), Some((a, a + 4)));
+        // and so does a trap
+        execute(&mut h, &mut m, lr).unwrap();
+        h.take_exception(Exception::Breakpoint(0));
+        assert_eq!(h.reservation, None);
+
+        h.set_reg(x(1), a + 2);
+        assert_eq!(execute(&mut h, &mut m, lr), Err(Exception::LoadMisaligned(a + 2)));
+        assert_eq!(execute(&mut h, &mut m, amo(AmoOpType::Add)), Err(Exception::StoreMisaligned(a + 2)));
+        let (mut h, mut m) = hart(true);
+        let lr_d = RiscV::Lr(AmoWidth::D, AqRl(0), x(4), x(1));
+        assert_eq!(execute(&mut h, &mut m, lr_d), Err(Exception::IllegalInstruction(0)));
+    }
+
+    #[test]
+    fn test_system() {
+        let (mut h, mut m) = hart(false);
+        execute(&mut h, &mut m, RiscV::Fence(IsFenceI(false), Pred(0xf), Succ(0xf))).unwrap();
+        assert_eq!(execute(&mut h, &mut m, RiscV::EOp(EOpType::Call)), Err(Exception::Ecall(Privilege::Machine)));
+        assert_eq!(execute(&mut h, &mut m, RiscV::EOp(EOpType::Break)), Err(Exception::Breakpoint(BASE + 4)));
+        assert_eq!(h.pc, BASE + 4);
+
+        // csrrs x1, mstatus, x2; csrrci x0, mstatus, 8
+        h.set_reg(x(2), MSTATUS_MIE);
+        execute(&mut h, &mut m, RiscV::CsrOp(CsrOpType::Rs, x(1), x(2), Csr(MSTATUS))).unwrap();
+        assert_eq!(h.reg(x(1)), 2 << 32 | 2 << 34);
+        assert_eq!(h.csr.get(MSTATUS) & MSTATUS_MIE, MSTATUS_MIE);
+        execute(&mut h, &mut m, RiscV::CsrOpI(CsrOpType::Rc, x(0), 8, Csr(MSTATUS))).unwrap();
+        assert_eq!(h.csr.get(MSTATUS) & MSTATUS_MIE, 0);
+        // reading a read-only csr is fine, writing it is not
+        execute(&mut h, &mut m, RiscV::CsrOp(CsrOpType::Rs, x(1), x(0), Csr(MHARTID))).unwrap();
+        let r = execute(&mut h, &mut m, RiscV::CsrOp(CsrOpType::Rw, x(0), x(1), Csr(MHARTID)));
+        assert_eq!(r, Err(Exception::IllegalInstruction(0)));
+        assert_eq!(h.csr.get(MINSTRET), 4);
+
+        h.privilege = Privilege::User;
+        let r = execute(&mut h, &mut m, RiscV::CsrOp(CsrOpType::Rs, x(1), x(0), Csr(MSTATUS)));
+        assert_eq!(r, Err(Exception::IllegalInstruction(0)));
+        assert_eq!(execute(&mut h, &mut m, RiscV::EOp(EOpType::Wfi)), Err(Exception::IllegalInstruction(0)));
+        assert_eq!(execute(&mut h, &mut m, RiscV::EOp(EOpType::Sret)), Err(Exception::IllegalInstruction(0)));
+        h.privilege = Privilege::Supervisor;
+        assert_eq!(execute(&mut h, &mut m, RiscV::EOp(EOpType::Mret)), Err(Exception::IllegalInstruction(0)));
+    }
+
+    #[test]
+    fn test_trap_delegation() {
+        let (mut h, mut m) = hart(false);
+        h.write_csr(Csr(MTVEC), BASE + 0x100).unwrap();
+        // vectored
+        h.write_csr(Csr(STVEC), (BASE + 0x200) | 1).unwrap();
+        h.write_csr(Csr(MEDELEG), 1 << 8).unwrap();
+        h.write_csr(Csr(MIDELEG), 0x222).unwrap();
+        h.write_csr(Csr(MEPC), BASE + 0x400).unwrap();
+        execute(&mut h, &mut m, RiscV::EOp(EOpType::Mret)).unwrap();
+        assert_eq!((h.pc, h.privilege), (BASE + 0x400, Privilege::User));
+        assert_eq!(h.csr.get(MSTATUS) & (MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP), MSTATUS_MPIE);
+
+        // ecall from u is delegated, from s it is not
+        let e = execute(&mut h, &mut m, RiscV::EOp(EOpType::Call)).unwrap_err();
+        h.take_exception(e);
+        assert_eq!((h.pc, h.privilege), (BASE + 0x200, Privilege::Supervisor));
+        assert_eq!((h.csr.get(SCAUSE), h.csr.get(SEPC)), (8, BASE + 0x400));
+        assert_eq!(h.csr.get(MSTATUS) & MSTATUS_SPP, 0);
+        let e = execute(&mut h, &mut m, RiscV::EOp(EOpType::Call)).unwrap_err();
+        h.take_exception(e);
+        assert_eq!((h.pc, h.privilege), (BASE + 0x100, Privilege::Machine));
+        assert_eq!((h.csr.get(MCAUSE), h.csr.get(MEPC)), (9, BASE + 0x200));
+        assert_eq!(h.csr.get(MSTATUS) & MSTATUS_MPP, 1 << 11);
+        execute(&mut h, &mut m, RiscV::EOp(EOpType::Mret)).unwrap();
+        assert_eq!((h.pc, h.privilege), (BASE + 0x200, Privilege::Supervisor));
+        execute(&mut h, &mut m, RiscV::EOp(EOpType::Sret)).unwrap();
+        assert_eq!((h.pc, h.privilege), (BASE + 0x400, Privilege::User));
+
+        // a delegated timer interrupt uses the vector
+        h.csr.set(MIE, Interrupt::SupervisorTimer.bit() | Interrupt::MachineTimer.bit());
+        h.csr.set(MIP, Interrupt::SupervisorTimer.bit());
+        assert_eq!(h.pending_interrupt(), Some(Interrupt::SupervisorTimer));
+        h.take_interrupt(Interrupt::SupervisorTimer);
+        assert_eq!((h.pc, h.privilege), (BASE + 0x200 + 4 * 5, Privilege::Supervisor));
+        assert_eq!(h.csr.get(SCAUSE), 1 << 63 | 5);
+        // masked by sstatus.sie in s-mode, m-mode ones are not
+        assert_eq!(h.pending_interrupt(), None);
+        h.mip = Interrupt::MachineTimer.bit();
+        assert_eq!(h.pending_interrupt(), Some(Interrupt::MachineTimer));
+        h.take_interrupt(Interrupt::MachineTimer);
+        assert_eq!((h.pc, h.csr.get(MCAUSE)), (BASE + 0x100, 1 << 63 | 7));
+        assert_eq!(h.pending_interrupt(), None);
+    }
+
+    #[test]
+    fn test_run_step() {
+        let (mut h, mut m) = hart(false);
+        // csrr x1, mhartid
+        m.write_bytes(BASE + 0x400, &0xf14020f3u32.to_le_bytes());
+        // wfi; mret
+        m.write_bytes(BASE + 0x100, &[0x10500073u32.to_le_bytes(), 0x30200073u32.to_le_bytes()].concat());
+        h.write_csr(Csr(MTVEC), BASE + 0x100).unwrap();
+        h.privilege = Privilege::User;
+        h.pc = BASE + 0x400;
+
+        assert_eq!(run_step(&mut h, &mut m), Event::Exception(Exception::IllegalInstruction(0xf14020f3)));
+        assert_eq!((h.pc, h.csr.get(MTVAL), h.csr.get(MEPC)), (BASE + 0x100, 0xf14020f3, BASE + 0x400));
+        assert_eq!(run_step(&mut h, &mut m), Event::Retired(RiscV::EOp(EOpType::Wfi)));
+        assert_eq!(run_step(&mut h, &mut m), Event::Waiting);
+        // wakes on an enabled interrupt even with mstatus.mie clear
+        h.csr.set(MIE, Interrupt::MachineTimer.bit());
+        h.mip = Interrupt::MachineTimer.bit();
+        assert_eq!(run_step(&mut h, &mut m), Event::Retired(RiscV::EOp(EOpType::Mret)));
+        assert_eq!((h.pc, h.privilege), (BASE + 0x400, Privilege::User));
+        assert_eq!(run_step(&mut h, &mut m), Event::Interrupt(Interrupt::MachineTimer));
+        assert_eq!(h.pc, BASE + 0x100);
+    }
+
+    #[test]
+    fn test_step() {
+        // addi x1, x0, 5; c.addi x1, 3; slli x1, x1, 60; .word 0
+        let (mut h, mut m) = hart(false);
+        let code = [0x00500093u32.to_le_bytes().to_vec(), vec![0x8d, 0x00], 0x03c09093u32.to_le_bytes().to_vec()].concat();
+        m.write_bytes(BASE, &code);
+        for _ in 0..3 {
+            step(&mut h, &mut m).unwrap();
+        }
+        assert_eq!((h.pc, h.reg(x(1))), (BASE + 10, 8 << 60));
+        assert_eq!(step(&mut h, &mut m), Err(Exception::IllegalInstruction(0)));
+        h.pc = BASE + 0x1000;
+        assert_eq!(step(&mut h, &mut m), Err(Exception::InstructionAccessFault(BASE + 0x1000)));
+    }
+
+    #[test]
+    fn test_flat() {
+        let (mut h, mut m) = hart(false);
+        h.set_reg(x(11), 0x7fffffff);
+        // addiw x10, x11, 1
+        let (inst, _) = flat_disasm(&0x0015851bu32.to_le_bytes(), false).unwrap();
+        execute_flat(&mut h, &mut m, inst).unwrap();
+        assert_eq!(h.reg(x(10)), 0xffffffff80000000);
+    }
+}
@@ -0,0 +1,167 @@
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+
+/// header, then an empty memory reservation map
+const HEADER_SIZE: usize = 40;
+const RSVMAP_SIZE: usize = 16;
+
+/// a flattened device tree writer, nodes are written depth first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fdt {
+    structs: Vec<u8>,
+    strings: Vec<u8>,
+    depth: usize,
+    pub boot_cpuid: u32,
+}
+
+impl Fdt {
+    pub fn new() -> Fdt {
+        Fdt::default()
+    }
+
+    fn token(&mut self, token: u32) {
+        self.structs.extend_from_slice(&token.to_be_bytes());
+    }
+
+    fn pad(&mut self) {
+        while !self.structs.len().is_multiple_of(4) {
+            self.structs.push(0);
+        }
+    }
+
+    /// the root node is named ""
+    pub fn begin_node(&mut self, name: &str) {
+        self.token(FDT_BEGIN_NODE);
+        self.structs.extend_from_slice(name.as_bytes());
+        self.structs.push(0);
+        self.pad();
+        self.depth += 1;
+    }
+
+    pub fn end_node(&mut self) {
+        assert!(self.depth > 0, "no open node");
+        self.token(FDT_END_NODE);
+        self.depth -= 1;
+    }
+
+    fn name_offset(&mut self, name: &str) -> u32 {
+        let mut key = name.as_bytes().to_vec();
+        key.push(0);
+        // reuse a name already in the table
+        let found = self.strings.windows(key.len())
+            .enumerate()
+            .find(|(i, w)| *w == key && (*i == 0 || self.strings[i - 1] == 0))
+            .map(|(i, _)| i);
+        let offset = found.unwrap_or_else(|| {
+            self.strings.extend_from_slice(&key);
+            self.strings.len() - key.len()
+        });
+        offset as u32
+    }
+
+    pub fn prop(&mut self, name: &str, value: &[u8]) {
+        let offset = self.name_offset(name);
+        self.token(FDT_PROP);
+        self.token(value.len() as u32);
+        self.token(offset);
+        self.structs.extend_from_slice(value);
+        self.pad();
+    }
+
+    pub fn prop_empty(&mut self, name: &str) {
+        self.prop(name, &[]);
+    }
+
+    pub fn prop_u32(&mut self, name: &str, value: u32) {
+        self.prop_cells(name, &[value]);
+    }
+
+    pub fn prop_cells(&mut self, name: &str, cells: &[u32]) {
+        let bytes: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+        self.prop(name, &bytes);
+    }
+
+    /// 64-bit values as two cells each, for `reg` with two address and
+    /// size cells
+    pub fn prop_u64s(&mut self, name: &str, values: &[u64]) {
+        let cells: Vec<u32> = values.iter().flat_map(|v| [(v >> 32) as u32, *v as u32]).collect();
+        self.prop_cells(name, &cells);
+    }
+
+    pub fn prop_str(&mut self, name: &str, value: &str) {
+        self.prop_strs(name, &[value]);
+    }
+
+    pub fn prop_strs(&mut self, name: &str, values: &[&str]) {
+        let bytes: Vec<u8> = values.iter().flat_map(|s| s.bytes().chain([0])).collect();
+        self.prop(name, &bytes);
+    }
+
+    /// the blob, every node must be closed
+    pub fn finish(mut self) -> Vec<u8> {
+        assert_eq!(self.depth, 0, "unclosed node");
+        self.token(FDT_END);
+        let off_struct = HEADER_SIZE + RSVMAP_SIZE;
+        let off_strings = off_struct + self.structs.len();
+        let total = off_strings + self.strings.len();
+        let header = [
+            FDT_MAGIC,
+            total as u32,
+            off_struct as u32,
+            off_strings as u32,
+            HEADER_SIZE as u32,
+            // version 17, compatible back to 16
+            17,
+            16,
+            self.boot_cpuid,
+            self.strings.len() as u32,
+            self.structs.len() as u32,
+        ];
+        let mut out: Vec<u8> = header.iter().flat_map(|x| x.to_be_bytes()).collect();
+        out.extend_from_slice(&[0; RSVMAP_SIZE]);
+        out.extend_from_slice(&self.structs);
+        out.extend_from_slice(&self.strings);
+        out
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn be32(b: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(b[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_fdt() {
+        let mut fdt = Fdt::new();
+        fdt.begin_node("");
+        fdt.prop_u32("#address-cells", 2);
+        fdt.begin_node("memory@80000000");
+        fdt.prop_str("device_type", "memory");
+        fdt.prop_u64s("reg", &[0x80000000, 0x8000000]);
+        fdt.end_node();
+        fdt.begin_node("chosen");
+        fdt.prop_str("device_type", "x");
+        fdt.end_node();
+        fdt.end_node();
+        let b = fdt.finish();
+
+        assert_eq!(be32(&b, 0), FDT_MAGIC);
+        assert_eq!(be32(&b, 4) as usize, b.len());
+        let (structs, strings) = (be32(&b, 8) as usize, be32(&b, 12) as usize);
+        assert_eq!(&b[strings..], b"#address-cells\0device_type\0reg\0");
+        // the root, then its property
+        assert_eq!(&b[structs..structs + 12], [0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 3]);
+        assert_eq!(be32(&b, structs + 12), 4);
+        assert_eq!(be32(&b, structs + 20), 2);
+        // memory@80000000 is padded to 16 bytes
+        assert_eq!(&b[structs + 28..structs + 44], b"memory@80000000\0");
+        assert_eq!(be32(&b, strings - 4), FDT_END);
+    }
+}
@@ -0,0 +1,51 @@
+use super::bus::Device;
+
+
+pub const FINISHER_FAIL: u32 = 0x3333;
+pub const FINISHER_PASS: u32 = 0x5555;
+pub const FINISHER_RESET: u32 = 0x7777;
+
+/// how the guest ended the simulation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Finish {
+    /// poweroff
+    Pass,
+    /// the exit code in the high half of the written word
+    Fail(u32),
+    Reset,
+}
+
+/// qemu's `sifive,test0`: a word written at offset 0 powers off or resets
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Finisher {
+    pub status: Option<Finish>,
+}
+
+impl Finisher {
+    pub const SIZE: u64 = 0x1000;
+
+    pub fn new() -> Finisher {
+        Finisher { status: None }
+    }
+}
+
+impl Device for Finisher {
+    fn read(&mut self, _offset: u64, _size: u8) -> Option<u64> {
+        Some(0)
+    }
+
+    fn write(&mut self, offset: u64, size: u8, value: u64) -> bool {
+        if offset != 0 || size != 4 {
+            return false;
+        }
+        let value = value as u32;
+        self.status = match value & 0xffff {
+            FINISHER_PASS => Some(Finish::Pass),
+            FINISHER_FAIL => Some(Finish::Fail(value >> 16)),
+            FINISHER_RESET => Some(Finish::Reset),
+            // unknown commands are ignored
+            _ => return true,
+        };
+        true
+    }
+}
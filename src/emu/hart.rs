@@ -0,0 +1,92 @@
+use crate::isa::riscv::Reg;
+
+use super::csr::CsrFile;
+use super::mmu::Tlb;
+use super::trap::Privilege;
+
+
+/// sign extend the low `bits` of `x`
+pub fn sext(x: u64, bits: u32) -> i64 {
+    ((x << (64 - bits)) as i64) >> (64 - bits)
+}
+
+/// zero extend the low `bits` of `x`
+pub fn zext(x: u64, bits: u32) -> u64 {
+    x & (u64::MAX >> (64 - bits))
+}
+
+/// architectural state of one hart. on rv32 registers hold zero extended
+/// 32-bit values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hart {
+    x: [u64; 32],
+    pub pc: u64,
+    pub is_32bit: bool,
+    /// the `C` extension, jump targets only need 2-byte alignment
+    pub compressed: bool,
+    pub privilege: Privilege,
+    /// interrupt wires driven by devices, `Interrupt::bit`s as in `mip`
+    pub mip: u64,
+    pub csr: CsrFile,
+    /// stalled in `wfi` until an interrupt is pending
+    pub wfi: bool,
+    pub tlb: Tlb,
+    /// the reservation of `lr`, the aligned doubleword around a physical
+    /// address
+    pub reservation: Option<u64>,
+    /// physical bytes stored to since last taken, as one range, so that a
+    /// machine can break the reservations of other harts
+    pub stored: Option<(u64, u64)>,
+}
+
+impl Hart {
+    pub fn new(is_32bit: bool) -> Hart {
+        Hart {
+            x: [0; 32],
+            pc: 0,
+            is_32bit,
+            compressed: true,
+            privilege: Privilege::Machine,
+            mip: 0,
+            csr: CsrFile::new(0, is_32bit),
+            wfi: false,
+            tlb: Tlb::new(),
+            reservation: None,
+            stored: None,
+        }
+    }
+
+    pub fn xlen(&self) -> u32 {
+        if self.is_32bit { 32 } else { 64 }
+    }
+
+    pub fn reg(&self, reg: Reg) -> u64 {
+        self.x[reg.0 as usize]
+    }
+
+    /// writes to x0 are dropped
+    pub fn set_reg(&mut self, reg: Reg, value: u64) {
+        if reg.0 != 0 {
+            self.x[reg.0 as usize] = self.trunc(value);
+        }
+    }
+
+    pub fn regs(&self) -> &[u64; 32] {
+        &self.x
+    }
+
+    /// truncate to xlen
+    pub fn trunc(&self, x: u64) -> u64 {
+        zext(x, self.xlen())
+    }
+
+    /// the signed value of an xlen value
+    pub fn signed(&self, x: u64) -> i64 {
+        sext(x, self.xlen())
+    }
+
+    /// required alignment of the pc
+    pub fn ialign(&self) -> u64 {
+        if self.compressed { 2 } else { 4 }
+    }
+}
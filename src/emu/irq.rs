@@ -0,0 +1,30 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+
+/// a level-triggered interrupt wire. a device drives one end and an
+/// interrupt controller samples the other, clones share the level.
+#[derive(Debug, Clone, Default)]
+pub struct IrqLine(Rc<Cell<bool>>);
+
+impl IrqLine {
+    pub fn new() -> IrqLine {
+        IrqLine::default()
+    }
+
+    pub fn set(&self, level: bool) {
+        self.0.set(level)
+    }
+
+    pub fn raise(&self) {
+        self.set(true)
+    }
+
+    pub fn lower(&self) {
+        self.set(false)
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.0.get()
+    }
+}
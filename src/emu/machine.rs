@@ -0,0 +1,737 @@
+use std::fmt::Display;
+
+use crate::elf::{self, ElfError, EM_RISCV};
+use crate::isa::riscv::bare::memory_layout::*;
+use crate::isa::riscv::Reg;
+
+use super::bus::Bus;
+use super::clint::{Clint, Clock};
+use super::csr::{MHARTID, TIME};
+use super::exec::{run_step, Event};
+use super::fdt::Fdt;
+use super::finisher::{Finish, Finisher, FINISHER_PASS, FINISHER_RESET};
+use super::hart::Hart;
+use super::plic::Plic;
+use super::uart::{Sink, Uart};
+use super::virtio::{Disk, VirtioBlk};
+
+
+/// `timebase-frequency` in the device tree, the one qemu reports
+pub const TIMEBASE: u32 = 10_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    Elf(ElfError),
+    /// not a risc-v image of the machine's xlen
+    Machine,
+    /// a segment does not fit in ram, by physical address
+    Placement(u64),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Elf(e) => write!(f, "{}", e),
+            LoadError::Machine => write!(f, "not a kernel for this machine"),
+            LoadError::Placement(addr) => write!(f, "segment at {:#x} is outside of ram", addr),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<ElfError> for LoadError {
+    fn from(e: ElfError) -> Self {
+        LoadError::Elf(e)
+    }
+}
+
+/// how harts take turns. a schedule always gives the same interleaving,
+/// so races reproduce from run to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    /// instructions a hart runs before the next one's turn
+    pub quantum: u64,
+    /// with a seed, every turn is 1 to `quantum` instructions long
+    pub seed: Option<u64>,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule { quantum: 1, seed: None }
+    }
+}
+
+/// splitmix64, fine with any seed
+fn splitmix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// round robin over the harts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Scheduler {
+    schedule: Schedule,
+    hart: usize,
+    /// instructions left in the current turn
+    left: u64,
+    rng: u64,
+}
+
+impl Scheduler {
+    fn new(schedule: Schedule) -> Scheduler {
+        let mut s = Scheduler { schedule, hart: 0, left: 0, rng: schedule.seed.unwrap_or(0) };
+        s.left = s.turn();
+        s
+    }
+
+    fn turn(&mut self) -> u64 {
+        let quantum = self.schedule.quantum.max(1);
+        match self.schedule.seed {
+            Some(_) => 1 + splitmix(&mut self.rng) % quantum,
+            None => quantum,
+        }
+    }
+
+    /// the hart to run next
+    fn next(&mut self, harts: usize) -> usize {
+        if self.left == 0 {
+            self.hart = (self.hart + 1) % harts;
+            self.left = self.turn();
+        }
+        self.left -= 1;
+        self.hart
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtConfig {
+    pub harts: usize,
+    pub is_32bit: bool,
+    pub clock: Clock,
+    pub schedule: Schedule,
+}
+
+impl Default for VirtConfig {
+    fn default() -> Self {
+        VirtConfig { harts: 1, is_32bit: false, clock: Clock::Instructions(1), schedule: Schedule::default() }
+    }
+}
+
+/// qemu's `virt` board as laid out in `memory_layout`: ram, uart0, clint,
+/// plic, the test finisher and an optional virtio disk
+pub struct VirtMachine {
+    pub config: VirtConfig,
+    pub bus: Bus,
+    pub harts: Vec<Hart>,
+    /// address of the device tree, set by `load_kernel`
+    pub dtb: u64,
+    scheduler: Scheduler,
+}
+
+impl VirtMachine {
+    pub fn new(config: VirtConfig) -> VirtMachine {
+        let mut bus = Bus::with_layout();
+        let uart = Uart::new(Sink::Buffer(vec![]));
+        let mut plic = Plic::new(config.harts);
+        plic.connect(UART0_IRQ as usize, uart.irq.clone());
+        bus.map("test", VIRT_TEST, Finisher::SIZE, Finisher::new());
+        bus.map("clint", CLINT, Clint::SIZE, Clint::new(config.harts, config.clock));
+        bus.map("plic", PLIC, Plic::SIZE, plic);
+        bus.map("uart0", UART0, Uart::SIZE, uart);
+        let scheduler = Scheduler::new(config.schedule);
+        let mut m = VirtMachine { config, bus, harts: vec![], dtb: 0, scheduler };
+        m.reset(KERNBASE);
+        m
+    }
+
+    pub fn uart(&mut self) -> &mut Uart {
+        self.bus.device("uart0").unwrap()
+    }
+
+    /// put a virtio block device at `VIRTIO0`, false if there is one
+    pub fn attach_disk(&mut self, disk: Disk, legacy: bool) -> bool {
+        let blk = VirtioBlk::new(disk, legacy);
+        let line = blk.irq.clone();
+        self.bus.map("virtio0", VIRTIO0, VirtioBlk::SIZE, blk) &&
+            self.bus.device::<Plic>("plic").unwrap().connect(VIRTIO0_IRQ as usize, line)
+    }
+
+    /// every hart in m-mode at `entry`, with a0 = hartid and a1 = the
+    /// device tree. the schedule starts over.
+    pub fn reset(&mut self, entry: u64) {
+        self.scheduler = Scheduler::new(self.config.schedule);
+        self.harts = (0..self.config.harts).map(|id| {
+            let mut hart = Hart::new(self.config.is_32bit);
+            hart.csr.set(MHARTID, id as u64);
+            hart.pc = entry;
+            hart.set_reg(Reg(10), id as u64);
+            hart.set_reg(Reg(11), self.dtb);
+            hart
+        }).collect();
+    }
+
+    /// copy a kernel to ram, an elf to its physical addresses and a flat
+    /// image to `KERNBASE`, place the device tree at the end of ram as
+    /// qemu does and reset to the entry
+    pub fn load_kernel(&mut self, image: &[u8]) -> Result<u64, LoadError> {
+        let entry = if image.starts_with(b"\x7fELF") {
+            let elf = elf::parse(image)?;
+            if elf.machine != EM_RISCV || elf.is_32bit != self.config.is_32bit {
+                return Err(LoadError::Machine);
+            }
+            for seg in &elf.segments {
+                if seg.mem_size > PHYSTOP - KERNBASE {
+                    return Err(LoadError::Placement(seg.paddr));
+                }
+                let mut data = seg.data.clone();
+                data.resize(data.len().max(seg.mem_size as usize), 0);
+                if !self.bus.write_bytes(seg.paddr, &data) {
+                    return Err(LoadError::Placement(seg.paddr));
+                }
+            }
+            elf.entry
+        } else {
+            if !self.bus.write_bytes(KERNBASE, image) {
+                return Err(LoadError::Placement(KERNBASE));
+            }
+            KERNBASE
+        };
+        let dtb = self.device_tree();
+        self.dtb = (PHYSTOP - dtb.len() as u64) & !0x1fffff;
+        self.bus.write_bytes(self.dtb, &dtb);
+        self.reset(entry);
+        Ok(entry)
+    }
+
+    /// `riscv,isa` from `misa`
+    fn isa(&self) -> String {
+        let hart = &self.harts[0];
+        let misa = hart.misa();
+        let base: String = "imafdqc".chars().filter(|c| misa >> (*c as u8 - b'a') & 1 != 0).collect();
+        format!("rv{}{}_zicsr_zifencei_sstc_svadu", hart.xlen(), base)
+    }
+
+    /// the flattened device tree of the board, as qemu would generate it
+    pub fn device_tree(&self) -> Vec<u8> {
+        let harts = self.config.harts as u32;
+        let intc = |hart: u32| hart + 1;
+        let (plic, test) = (harts + 1, harts + 2);
+        let mut fdt = Fdt::new();
+        fdt.begin_node("");
+        fdt.prop_u32("#address-cells", 2);
+        fdt.prop_u32("#size-cells", 2);
+        fdt.prop_str("compatible", "riscv-virtio");
+        fdt.prop_str("model", "riscv-virtio,qemu");
+
+        fdt.begin_node("chosen");
+        fdt.prop_str("stdout-path", &format!("/soc/serial@{:x}", UART0));
+        fdt.end_node();
+
+        fdt.begin_node(&format!("memory@{:x}", KERNBASE));
+        fdt.prop_str("device_type", "memory");
+        fdt.prop_u64s("reg", &[KERNBASE, PHYSTOP - KERNBASE]);
+        fdt.end_node();
+
+        fdt.begin_node("cpus");
+        fdt.prop_u32("#address-cells", 1);
+        fdt.prop_u32("#size-cells", 0);
+        fdt.prop_u32("timebase-frequency", TIMEBASE);
+        for hart in 0..harts {
+            fdt.begin_node(&format!("cpu@{}", hart));
+            fdt.prop_str("device_type", "cpu");
+            fdt.prop_u32("reg", hart);
+            fdt.prop_str("status", "okay");
+            fdt.prop_str("compatible", "riscv");
+            fdt.prop_str("riscv,isa", &self.isa());
+            fdt.prop_str("mmu-type", if self.config.is_32bit { "riscv,sv32" } else { "riscv,sv57" });
+            fdt.begin_node("interrupt-controller");
+            fdt.prop_u32("#interrupt-cells", 1);
+            fdt.prop_empty("interrupt-controller");
+            fdt.prop_str("compatible", "riscv,cpu-intc");
+            fdt.prop_u32("phandle", intc(hart));
+            fdt.end_node();
+            fdt.end_node();
+        }
+        fdt.end_node();
+
+        fdt.begin_node("soc");
+        fdt.prop_u32("#address-cells", 2);
+        fdt.prop_u32("#size-cells", 2);
+        fdt.prop_str("compatible", "simple-bus");
+        fdt.prop_empty("ranges");
+
+        fdt.begin_node(&format!("test@{:x}", VIRT_TEST));
+        fdt.prop_strs("compatible", &["sifive,test1", "sifive,test0", "syscon"]);
+        fdt.prop_u64s("reg", &[VIRT_TEST, Finisher::SIZE]);
+        fdt.prop_u32("phandle", test);
+        fdt.end_node();
+        for (name, compatible, value) in [("poweroff", "syscon-poweroff", FINISHER_PASS), ("reboot", "syscon-reboot", FINISHER_RESET)] {
+            fdt.begin_node(name);
+            fdt.prop_str("compatible", compatible);
+            fdt.prop_u32("regmap", test);
+            fdt.prop_u32("offset", 0);
+            fdt.prop_u32("value", value);
+            fdt.end_node();
+        }
+
+        fdt.begin_node(&format!("serial@{:x}", UART0));
+        fdt.prop_str("compatible", "ns16550a");
+        fdt.prop_u64s("reg", &[UART0, Uart::SIZE]);
+        fdt.prop_u32("clock-frequency", 0x384000);
+        fdt.prop_u32("interrupts", UART0_IRQ as u32);
+        fdt.prop_u32("interrupt-parent", plic);
+        fdt.end_node();
+
+        if self.bus.find(VIRTIO0).is_some() {
+            fdt.begin_node(&format!("virtio_mmio@{:x}", VIRTIO0));
+            fdt.prop_str("compatible", "virtio,mmio");
+            fdt.prop_u64s("reg", &[VIRTIO0, VirtioBlk::SIZE]);
+            fdt.prop_u32("interrupts", VIRTIO0_IRQ as u32);
+            fdt.prop_u32("interrupt-parent", plic);
+            fdt.end_node();
+        }
+
+        // machine and supervisor external interrupts of every hart
+        fdt.begin_node(&format!("plic@{:x}", PLIC));
+        fdt.prop_strs("compatible", &["sifive,plic-1.0.0", "riscv,plic0"]);
+        fdt.prop_u64s("reg", &[PLIC, Plic::SIZE]);
+        fdt.prop_u32("#interrupt-cells", 1);
+        fdt.prop_u32("#address-cells", 0);
+        fdt.prop_empty("interrupt-controller");
+        fdt.prop_u32("riscv,ndev", Plic::SOURCES as u32 - 1);
+        fdt.prop_cells("interrupts-extended", &(0..harts).flat_map(|h| [intc(h), 11, intc(h), 9]).collect::<Vec<_>>());
+        fdt.prop_u32("phandle", plic);
+        fdt.end_node();
+
+        fdt.begin_node(&format!("clint@{:x}", CLINT));
+        fdt.prop_strs("compatible", &["sifive,clint0", "riscv,clint0"]);
+        fdt.prop_u64s("reg", &[CLINT, Clint::SIZE]);
+        fdt.prop_cells("interrupts-extended", &(0..harts).flat_map(|h| [intc(h), 3, intc(h), 7]).collect::<Vec<_>>());
+        fdt.end_node();
+
+        fdt.end_node();
+        fdt.end_node();
+        fdt.finish()
+    }
+
+    /// one instruction on the hart whose turn it is, then a clock tick.
+    /// the finisher's status once the guest wrote it.
+    pub fn step(&mut self) -> Option<Finish> {
+        let id = self.scheduler.next(self.harts.len());
+        let hart = &mut self.harts[id];
+        let clint = self.bus.device::<Clint>("clint").unwrap();
+        clint.update(id, hart);
+        hart.csr.set(TIME, clint.mtime());
+        self.bus.device::<Plic>("plic").unwrap().update(id, hart);
+        if run_step(hart, &mut self.bus) == Event::Waiting {
+            // a waiting hart gives up the rest of its turn
+            self.scheduler.left = 0;
+        }
+        if let Some(stored) = hart.stored.take() {
+            for (i, other) in self.harts.iter_mut().enumerate() {
+                if i != id {
+                    other.snoop(stored);
+                }
+            }
+        }
+        // device dma the step set off, e.g. a virtio notify, breaks every
+        // reservation it overlaps, the stepping hart's included
+        if let Some(stored) = self.bus.stored.take() {
+            for hart in self.harts.iter_mut() {
+                hart.snoop(stored);
+            }
+        }
+        self.bus.device::<Clint>("clint").unwrap().tick(1);
+        self.bus.device::<Finisher>("test").unwrap().status.take()
+    }
+
+    /// step until the guest powers off or resets, `None` after `limit`
+    /// steps without
+    pub fn run(&mut self, limit: u64) -> Option<Finish> {
+        (0..limit).find_map(|_| self.step())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// instructions to bytes, a halfword for every compressed one
+    fn code(insts: &[u32]) -> Vec<u8> {
+        insts.iter().flat_map(|i| {
+            let bytes = i.to_le_bytes();
+            bytes[..if i & 3 == 3 { 4 } else { 2 }].to_vec()
+        }).collect()
+    }
+
+    #[test]
+    fn test_flat_boot() {
+        let mut image = code(&[
+            // _entry:
+            0x04051c63, // bnez a0, park
+            0x0005e283, // lwu t0, 0(a1)
+            0x000ee337, // lui t1, 238
+            0xfe13031b, // addiw t1, t1, -31
+            0x00c31313, // slli t1, t1, 12
+            0xdd030313, // addi t1, t1, -560
+            0x04629463, // bne t0, t1, fail
+            0x00000397, // auipc t2, 0
+            0x05838393, // addi t2, t2, 88
+            0x10000e37, // lui t3, 65536
+            // print:
+            0x0003ce83, // lbu t4, 0(t2)
+            0x000e8e63, // beqz t4, done
+            // wait:
+            0x005e4f03, // lbu t5, 5(t3)
+            0x020f7f13, // andi t5, t5, 32
+            0xfe0f0ce3, // beqz t5, wait
+            0x01de0023, // sb t4, 0(t3)
+            0x00138393, // addi t2, t2, 1
+            0xfe5ff06f, // j print
+            // done:
+            0x001002b7, // lui t0, 256
+            0x00005337, // lui t1, 5
+            0x5553031b, // addiw t1, t1, 1365
+            0x0062a023, // sw t1, 0(t0)
+            // park:
+            0x10500073, // wfi
+            0xffdff06f, // j park
+            // fail:
+            0x001002b7, // lui t0, 256
+            0x00013337, // lui t1, 19
+            0x3333031b, // addiw t1, t1, 819
+            0x0062a023, // sw t1, 0(t0)
+            0xfe9ff06f, // j park
+        ]);
+        image.extend_from_slice(b"hello\n\0");
+        let mut m = VirtMachine::new(VirtConfig { harts: 2, ..Default::default() });
+        assert_eq!(m.load_kernel(&image), Ok(KERNBASE));
+        assert_eq!(m.dtb, 0x87e00000);
+        assert_eq!(m.harts[1].reg(Reg(10)), 1);
+        assert_eq!(m.harts[1].reg(Reg(11)), m.dtb);
+        assert_eq!(m.run(10000), Some(Finish::Pass));
+        assert_eq!(m.uart().output(), b"hello\n");
+        // parked on wfi
+        assert_eq!(m.run(100), None);
+    }
+
+    #[test]
+    fn test_elf_boot() {
+        // start.c: m-mode setup, sstc timer, then an s-mode main on an
+        // identity mapped sv39 that powers off after three ticks
+        let text = code(&[
+            // _entry:
+            0x08001137, // lui sp, 32769
+            0x0112, // slli sp, sp, 4
+            0x6285, // lui t0, 1
+            0x00150313, // addi t1, a0, 1
+            0x026282b3, // mul t0, t0, t1
+            0x9116, // add sp, sp, t0
+            0x300022f3, // csrr t0, mstatus
+            0x7379, // lui t1, 1048574
+            0x7ff3031b, // addiw t1, t1, 2047
+            0x0062f2b3, // and t0, t0, t1
+            0x6305, // lui t1, 1
+            0x8003031b, // addiw t1, t1, -2048
+            0x0062e2b3, // or t0, t0, t1
+            0x30029073, // csrw mstatus, t0
+            0x00000297, // auipc t0, 0
+            0x05e28293, // addi t0, t0, 94
+            0x34129073, // csrw mepc, t0
+            0x18001073, // csrw satp, zero
+            0x62c1, // lui t0, 16
+            0x32fd, // addiw t0, t0, -1
+            0x30229073, // csrw medeleg, t0
+            0x30329073, // csrw mideleg, t0
+            0x104022f3, // csrr t0, sie
+            0x2222e293, // ori t0, t0, 546
+            0x10429073, // csrw sie, t0
+            0x304022f3, // csrr t0, mie
+            0x0202e293, // ori t0, t0, 32
+            0x30429073, // csrw mie, t0
+            0x4285, // li t0, 1
+            0x12fe, // slli t0, t0, 63
+            0x30a2a073, // csrs menvcfg, t0
+            0x306022f3, // csrr t0, mcounteren
+            0x0022e293, // ori t0, t0, 2
+            0x30629073, // csrw mcounteren, t0
+            0xc01022f3, // rdtime t0
+            0x3e828293, // addi t0, t0, 1000
+            0x14d29073, // csrw stimecmp, t0
+            0xf1402573, // csrr a0, mhartid
+            0x822a, // mv tp, a0
+            0x30200073, // mret
+            // main:
+            0x000802b7, // lui t0, 128
+            0x2285, // addiw t0, t0, 1
+            0x4321, // li t1, 8
+            0x1372, // slli t1, t1, 60
+            0x0062e2b3, // or t0, t0, t1
+            0x12000073, // sfence.vma
+            0x18029073, // csrw satp, t0
+            0x12000073, // sfence.vma
+            0x00000297, // auipc t0, 0
+            0x02a28293, // addi t0, t0, 42
+            0x10529073, // csrw stvec, t0
+            0x10016073, // csrsi sstatus, 2
+            0x448d, // li s1, 3
+            // spin:
+            0x10500073, // wfi
+            0xfe944ee3, // blt s0, s1, spin
+            0x00100937, // lui s2, 256
+            0x6995, // lui s3, 5
+            0x5559899b, // addiw s3, s3, 1365
+            0x01392023, // sw s3, 0(s2)
+            0xb7ed, // j spin
+            // kernelvec:
+            0x142022f3, // csrr t0, scause
+            0x537d, // li t1, -1
+            0x137e, // slli t1, t1, 63
+            0x0315, // addi t1, t1, 5
+            0x02629163, // bne t0, t1, bad
+            0x0405, // addi s0, s0, 1
+            0x100002b7, // lui t0, 65536
+            0x02e00313, // li t1, 46
+            0x00628023, // sb t1, 0(t0)
+            0xc01022f3, // rdtime t0
+            0x3e828293, // addi t0, t0, 1000
+            0x14d29073, // csrw stimecmp, t0
+            0x10200073, // sret
+            // bad:
+            0x001002b7, // lui t0, 256
+            0x00023337, // lui t1, 35
+            0x3333031b, // addiw t1, t1, 819
+            0x0062a023, // sw t1, 0(t0)
+            0xbfc5, // j bad
+        ]);
+        // gigapages, the devices and the ram
+        let pagetable = [0xc7u64, 0, 0x200000cf].iter().flat_map(|p| p.to_le_bytes()).collect::<Vec<_>>();
+        let image = elf::build_elf64(KERNBASE, &[(KERNBASE, &text), (KERNBASE + 0x1000, &pagetable)], &[("_entry", KERNBASE, 0)]);
+        let mut m = VirtMachine::new(VirtConfig::default());
+        assert_eq!(m.load_kernel(&image), Ok(KERNBASE));
+        assert_eq!(m.run(100000), Some(Finish::Pass));
+        assert_eq!(m.uart().output(), b"...");
+        assert_eq!(m.harts[0].reg(Reg(8)), 3);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let image = elf::build_elf64(KERNBASE, &[(KERNBASE, &[0; 4])], &[]);
+        let mut m = VirtMachine::new(VirtConfig { is_32bit: true, ..Default::default() });
+        assert_eq!(m.load_kernel(&image), Err(LoadError::Machine));
+        let mut m = VirtMachine::new(VirtConfig::default());
+        let image = elf::build_elf64(0x1000, &[(0x1000, &[0; 4])], &[]);
+        assert_eq!(m.load_kernel(&image), Err(LoadError::Placement(0x1000)));
+        assert_eq!(m.load_kernel(b"\x7fELF"), Err(LoadError::Elf(ElfError::Truncated)));
+        assert_eq!(m.load_kernel(&vec![0; (PHYSTOP - KERNBASE) as usize + 1]), Err(LoadError::Placement(KERNBASE)));
+        // the device tree is there without a kernel too
+        let dtb = m.device_tree();
+        assert_eq!(dtb[..4], [0xd0, 0x0d, 0xfe, 0xed]);
+        assert!(dtb.windows(8).any(|w| w == b"rv64imac"));
+    }
+
+    /// four harts bump a counter with lr/sc and another under an amoswap
+    /// lock, a hundred times each. once all are done hart 0 checks both and
+    /// wakes the others with an ipi, each acks and parks.
+    fn smp() -> Vec<u8> {
+        code(&[
+            // _entry:
+            0x00080437, // lui s0, 128
+            0x2405, // addiw s0, s0, 1
+            0x0432, // slli s0, s0, 12
+            0x000804b7, // lui s1, 128
+            0x2485, // addiw s1, s1, 1
+            0x04b2, // slli s1, s1, 12
+            0x04a1, // addi s1, s1, 8
+            0x00080937, // lui s2, 128
+            0x2905, // addiw s2, s2, 1
+            0x0932, // slli s2, s2, 12
+            0x0941, // addi s2, s2, 16
+            0x00080a37, // lui s4, 128
+            0x2a05, // addiw s4, s4, 1
+            0x0a32, // slli s4, s4, 12
+            0x0a61, // addi s4, s4, 24
+            0x06400993, // li s3, 100
+            0x00080ab7, // lui s5, 128
+            0x2a85, // addiw s5, s5, 1
+            0x0ab2, // slli s5, s5, 12
+            0x020a8a93, // addi s5, s5, 32
+            // loop:
+            0x100422af, // lr.w t0, (s0)
+            0x0285, // addi t0, t0, 1
+            0x1854232f, // sc.w t1, t0, (s0)
+            0xfe031be3, // bnez t1, loop
+            0x4385, // li t2, 1
+            // acquire:
+            0x0c74ae2f, // amoswap.w.aq t3, t2, (s1)
+            0xfe0e1ee3, // bnez t3, acquire
+            0x00092e83, // lw t4, 0(s2)
+            0x0e85, // addi t4, t4, 1
+            0x01d92023, // sw t4, 0(s2)
+            0x0a04a02f, // amoswap.w.rl zero, zero, (s1)
+            0x19fd, // addi s3, s3, -1
+            0xfc099ce3, // bnez s3, loop
+            0x4305, // li t1, 1
+            0x006aa02f, // amoadd.w zero, t1, (s5)
+            0xe531, // bnez a0, ipi
+            0x4311, // li t1, 4
+            // done:
+            0x000aa283, // lw t0, 0(s5)
+            0xfe629ee3, // bne t0, t1, done
+            0x00042283, // lw t0, 0(s0)
+            0x19000313, // li t1, 400
+            0x06629363, // bne t0, t1, fail
+            0x00092283, // lw t0, 0(s2)
+            0x04629f63, // bne t0, t1, fail
+            0x020002b7, // lui t0, 8192
+            0x4305, // li t1, 1
+            0x0062a223, // sw t1, 4(t0)
+            0x0062a423, // sw t1, 8(t0)
+            0x0062a623, // sw t1, 12(t0)
+            0x430d, // li t1, 3
+            // acks:
+            0x000a2283, // lw t0, 0(s4)
+            0xfe629ee3, // bne t0, t1, acks
+            0x001002b7, // lui t0, 256
+            0x6315, // lui t1, 5
+            0x5553031b, // addiw t1, t1, 1365
+            0x0062a023, // sw t1, 0(t0)
+            0xa02d, // j park
+            // ipi:
+            0x30446073, // csrsi mie, 8
+            // wait:
+            0x10500073, // wfi
+            0x344022f3, // csrr t0, mip
+            0x0082f293, // andi t0, t0, 8
+            0xfe028ae3, // beqz t0, wait
+            0x020002b7, // lui t0, 8192
+            0x00251313, // slli t1, a0, 2
+            0x929a, // add t0, t0, t1
+            0x0002a023, // sw zero, 0(t0)
+            0x4305, // li t1, 1
+            0x006a202f, // amoadd.w zero, t1, (s4)
+            // park:
+            0x10500073, // wfi
+            0xbff5, // j park
+            // fail:
+            0x001002b7, // lui t0, 256
+            0x634d, // lui t1, 19
+            0x3333031b, // addiw t1, t1, 819
+            0x0062a023, // sw t1, 0(t0)
+            0xb7f5, // j park
+        ])
+    }
+
+    /// how and after how many steps the guest finished under `schedule`
+    fn run_smp(schedule: Schedule) -> (Finish, u64) {
+        let mut m = VirtMachine::new(VirtConfig { harts: 4, schedule, ..Default::default() });
+        m.load_kernel(&smp()).unwrap();
+        let r = (1..=200000).find_map(|n| m.step().map(|f| (f, n))).unwrap();
+        assert_eq!(m.bus.read_bytes(0x80001018, 4), Some(vec![3, 0, 0, 0]));
+        r
+    }
+
+    #[test]
+    fn test_smp() {
+        let (finish, steps) = run_smp(Schedule::default());
+        assert_eq!(finish, Finish::Pass);
+        for quantum in [7, 64] {
+            assert_eq!(run_smp(Schedule { quantum, seed: None }).0, Finish::Pass);
+        }
+        // a seed replays exactly, another one interleaves differently
+        let seeded = run_smp(Schedule { quantum: 16, seed: Some(1) });
+        assert_eq!(seeded.0, Finish::Pass);
+        assert_eq!(run_smp(Schedule { quantum: 16, seed: Some(1) }), seeded);
+        assert_ne!(run_smp(Schedule { quantum: 16, seed: Some(2) }).1, seeded.1);
+        assert_ne!(steps, seeded.1);
+    }
+
+    /// an lr on `reserved`, a virtio read of sector 1 into `buf` set off by
+    /// the notify store, then an sc. the sc result.
+    fn sc_around_dma(reserved: u64, buf: u64) -> u64 {
+        use crate::emu::memory::Memory;
+        use crate::emu::virtio::*;
+
+        let mut m = VirtMachine::new(VirtConfig::default());
+        let disk = (0..2 * SECTOR).map(|i| (i / SECTOR) as u8 + 1).collect();
+        assert!(m.attach_disk(Disk::Memory(disk), false));
+        let code = code(&[
+            0x100632af, // lr.d t0, (a2)
+            0x0406a823, // sw zero, 80(a3)
+            0x1856332f, // sc.d t1, t0, (a2)
+            0x0000006f, // j .
+        ]);
+        assert!(m.bus.write_bytes(KERNBASE, &code));
+
+        // a modern queue at desc, avail and used, one request published
+        let (desc, avail, used, header, status) = (KERNBASE + 0x1000, KERNBASE + 0x2000, KERNBASE + 0x3000, KERNBASE + 0x4000, KERNBASE + 0x4100);
+        let reg = |m: &mut VirtMachine, r: u64, v: u64| m.bus.store(VIRTIO0 + r, 4, v).unwrap();
+        reg(&mut m, STATUS, (STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK) as u64);
+        reg(&mut m, QUEUE_NUM, 8);
+        reg(&mut m, QUEUE_DESC_LOW, desc);
+        reg(&mut m, QUEUE_DRIVER_LOW, avail);
+        reg(&mut m, QUEUE_DEVICE_LOW, used);
+        reg(&mut m, QUEUE_READY, 1);
+        reg(&mut m, STATUS, (STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK | STATUS_DRIVER_OK) as u64);
+        m.bus.store(header + 8, 8, 1).unwrap();
+        for (i, (addr, len, flags)) in [(header, 16, DESC_NEXT), (buf, SECTOR, DESC_WRITE | DESC_NEXT), (status, 1, DESC_WRITE)].into_iter().enumerate() {
+            let d = desc + 16 * i as u64;
+            m.bus.store(d, 8, addr).unwrap();
+            m.bus.store(d + 8, 4, len).unwrap();
+            m.bus.store(d + 12, 2, flags as u64).unwrap();
+            m.bus.store(d + 14, 2, i as u64 + 1).unwrap();
+        }
+        m.bus.store(avail + 2, 2, 1).unwrap();
+
+        m.harts[0].set_reg(Reg(12), reserved);
+        m.harts[0].set_reg(Reg(13), VIRTIO0);
+        for _ in 0..3 {
+            assert_eq!(m.step(), None);
+        }
+        assert_eq!(m.bus.load(used + 2, 2), Ok(1));
+        assert_eq!(m.bus.load(buf, 8), Ok(0x0202020202020202));
+        m.harts[0].reg(Reg(6))
+    }
+
+    #[test]
+    fn test_dma_breaks_reservation() {
+        let buf = KERNBASE + 0x8000;
+        // the disk overwrote the reserved doubleword, the sc fails
+        assert_eq!(sc_around_dma(buf + 0x100, buf), 1);
+        // dma elsewhere leaves the reservation alone
+        assert_eq!(sc_around_dma(buf + crate::emu::virtio::SECTOR, buf), 0);
+    }
+
+    /// boots an xv6-riscv build from XV6_KERNEL and XV6_FS, its
+    /// kernel/kernel and fs.img, up to the first shell prompt. run with
+    /// `cargo test -- --ignored`. XV6_LEGACY selects the legacy virtio
+    /// interface older xv6 drivers expect.
+    #[test]
+    #[ignore = "needs XV6_KERNEL/XV6_FS"]
+    fn test_xv6() {
+        const CHUNK: u64 = 1_000_000;
+        const LIMIT: u64 = 4_000_000_000;
+        let kernel = std::env::var("XV6_KERNEL").expect("XV6_KERNEL not set");
+        let fs = std::env::var("XV6_FS").expect("XV6_FS not set");
+        let contains = |hay: &[u8], needle: &[u8]| hay.windows(needle.len()).any(|w| w == needle);
+        let mut m = VirtMachine::new(VirtConfig { harts: 3, ..Default::default() });
+        // a copy, the fixture stays as it is
+        let legacy = std::env::var_os("XV6_LEGACY").is_some();
+        assert!(m.attach_disk(Disk::Memory(std::fs::read(fs).unwrap()), legacy));
+        m.load_kernel(&std::fs::read(kernel).unwrap()).unwrap();
+
+        let mut steps = 0;
+        while !contains(m.uart().output(), b"init: starting sh\n$ ") {
+            assert!(steps < LIMIT, "no shell prompt:\n{}", String::from_utf8_lossy(m.uart().output()));
+            let finish = m.run(CHUNK);
+            assert_eq!(finish, None, "stopped early:\n{}", String::from_utf8_lossy(m.uart().output()));
+            steps += CHUNK;
+        }
+        assert!(contains(m.uart().output(), b"xv6 kernel is booting"));
+    }
+}
@@ -0,0 +1,67 @@
+use super::trap::Exception;
+
+
+/// physical memory as seen by a hart. `size` is 1, 2, 4 or 8 bytes and
+/// values are little endian, zero extended.
+pub trait Memory {
+    fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception>;
+    fn store(&mut self, addr: u64, size: u8, value: u64) -> Result<(), Exception>;
+
+    /// an instruction parcel, load faults are reported as fetch faults
+    fn fetch(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+        self.load(addr, size).map_err(|e| match e {
+            Exception::LoadMisaligned(x) => Exception::InstructionMisaligned(x),
+            Exception::LoadAccessFault(x) => Exception::InstructionAccessFault(x),
+            Exception::LoadPageFault(x) => Exception::InstructionPageFault(x),
+            e => e,
+        })
+    }
+}
+
+/// a plain byte array mapped at `base`, misaligned accesses are allowed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ram {
+    pub base: u64,
+    pub data: Vec<u8>,
+}
+
+impl Ram {
+    pub fn new(base: u64, size: usize) -> Ram {
+        Ram { base, data: vec![0; size] }
+    }
+
+    pub fn end(&self) -> u64 {
+        self.base + self.data.len() as u64
+    }
+
+    /// the offset of `[addr, addr + size)` if it is inside
+    pub fn offset(&self, addr: u64, size: u8) -> Option<usize> {
+        let offset = addr.checked_sub(self.base)?;
+        (offset.checked_add(size as u64)? <= self.data.len() as u64).then_some(offset as usize)
+    }
+
+    /// copy `src` in at `addr`, false if it does not fit
+    pub fn write_bytes(&mut self, addr: u64, src: &[u8]) -> bool {
+        let offset = match addr.checked_sub(self.base) {
+            Some(o) if o.checked_add(src.len() as u64).is_some_and(|end| end <= self.data.len() as u64) => o as usize,
+            _ => return false,
+        };
+        self.data[offset..offset + src.len()].copy_from_slice(src);
+        true
+    }
+}
+
+impl Memory for Ram {
+    fn load(&mut self, addr: u64, size: u8) -> Result<u64, Exception> {
+        let offset = self.offset(addr, size).ok_or(Exception::LoadAccessFault(addr))?;
+        let mut bytes = [0; 8];
+        bytes[..size as usize].copy_from_slice(&self.data[offset..offset + size as usize]);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn store(&mut self, addr: u64, size: u8, value: u64) -> Result<(), Exception> {
+        let offset = self.offset(addr, size).ok_or(Exception::StoreAccessFault(addr))?;
+        self.data[offset..offset + size as usize].copy_from_slice(&value.to_le_bytes()[..size as usize]);
+        Ok(())
+    }
+}
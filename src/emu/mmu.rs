@@ -0,0 +1,714 @@
+use super::csr::{MENVCFG, MENVCFG_ADUE, MSTATUS, MSTATUS_MPRV, MSTATUS_MXR, MSTATUS_SUM, SATP};
+use super::hart::{sext, Hart};
+use super::memory::Memory;
+use super::trap::{Exception, Privilege};
+
+
+pub const PAGE_SIZE: u64 = 4096;
+
+pub const PTE_V: u64 = 1 << 0;
+pub const PTE_R: u64 = 1 << 1;
+pub const PTE_W: u64 = 1 << 2;
+pub const PTE_X: u64 = 1 << 3;
+pub const PTE_U: u64 = 1 << 4;
+pub const PTE_G: u64 = 1 << 5;
+pub const PTE_A: u64 = 1 << 6;
+pub const PTE_D: u64 = 1 << 7;
+
+/// reserved, pbmt and napot bits of sv39 and wider ptes, none of which
+/// are implemented
+const PTE_HIGH: u64 = 0x3ff << 54;
+
+/// translation schemes, as selected by `satp.MODE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Bare,
+    Sv32,
+    Sv39,
+    Sv48,
+    Sv57,
+}
+
+impl Mode {
+    /// page table levels
+    pub fn levels(self) -> u32 {
+        match self {
+            Mode::Bare => 0,
+            Mode::Sv32 => 2,
+            Mode::Sv39 => 3,
+            Mode::Sv48 => 4,
+            Mode::Sv57 => 5,
+        }
+    }
+
+    /// significant bits of a virtual address
+    pub fn va_bits(self) -> u32 {
+        12 + self.levels() * self.vpn_bits()
+    }
+
+    fn vpn_bits(self) -> u32 {
+        if self == Mode::Sv32 { 10 } else { 9 }
+    }
+
+    fn pte_size(self) -> u8 {
+        if self == Mode::Sv32 { 4 } else { 8 }
+    }
+}
+
+/// what a translation is for, it picks the permission and the fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Fetch,
+    Load,
+    Store,
+}
+
+impl Access {
+    pub fn page_fault(self, addr: u64) -> Exception {
+        match self {
+            Access::Fetch => Exception::InstructionPageFault(addr),
+            Access::Load => Exception::LoadPageFault(addr),
+            Access::Store => Exception::StorePageFault(addr),
+        }
+    }
+
+    pub fn access_fault(self, addr: u64) -> Exception {
+        match self {
+            Access::Fetch => Exception::InstructionAccessFault(addr),
+            Access::Load => Exception::LoadAccessFault(addr),
+            Access::Store => Exception::StoreAccessFault(addr),
+        }
+    }
+}
+
+/// a mapping found by a page table walk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Leaf {
+    /// first virtual address of the page
+    pub vbase: u64,
+    pub pbase: u64,
+    /// in bytes, larger than `PAGE_SIZE` for superpages
+    pub size: u64,
+    pub pte: u64,
+    /// physical address of the pte
+    pub addr: u64,
+    /// a `G` bit was set on the way down
+    pub global: bool,
+}
+
+impl Leaf {
+    pub fn contains(&self, vaddr: u64) -> bool {
+        vaddr & !(self.size - 1) == self.vbase
+    }
+
+    pub fn translate(&self, vaddr: u64) -> u64 {
+        self.pbase | vaddr & (self.size - 1)
+    }
+}
+
+/// a decoded `satp`. the walker only needs memory, so it also works on
+/// memory dumps outside of a hart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Satp {
+    pub mode: Mode,
+    pub asid: u16,
+    /// physical address of the root table
+    pub root: u64,
+}
+
+impl Satp {
+    /// `None` for modes that are not supported at this xlen
+    pub fn decode(satp: u64, is_32bit: bool) -> Option<Satp> {
+        let (mode, asid, ppn) = if is_32bit {
+            let mode = if satp >> 31 & 1 != 0 { Mode::Sv32 } else { Mode::Bare };
+            (mode, satp >> 22 & 0x1ff, satp & 0x3f_ffff)
+        } else {
+            let mode = match satp >> 60 {
+                0 => Mode::Bare,
+                8 => Mode::Sv39,
+                9 => Mode::Sv48,
+                10 => Mode::Sv57,
+                _ => return None,
+            };
+            (mode, satp >> 44 & 0xffff, satp & ((1 << 44) - 1))
+        };
+        Some(Satp { mode, asid: asid as u16, root: ppn << 12 })
+    }
+
+    fn ppn(&self, pte: u64) -> u64 {
+        if self.mode == Mode::Sv32 { pte >> 10 } else { pte >> 10 & ((1 << 44) - 1) }
+    }
+
+    /// sign extend a virtual address from `va_bits`
+    fn canonical(&self, vaddr: u64) -> u64 {
+        if self.mode == Mode::Sv32 { vaddr } else { sext(vaddr, self.mode.va_bits()) as u64 }
+    }
+
+    /// whether `pte` is malformed, a leaf or not
+    fn invalid(&self, pte: u64) -> bool {
+        pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) ||
+            (self.mode != Mode::Sv32 && pte & PTE_HIGH != 0)
+    }
+
+    /// walk the table for `vaddr`, without permission or `A`/`D` checks.
+    /// faults are the ones `access` would take: a page fault for invalid,
+    /// reserved or misaligned entries and non canonical addresses, an
+    /// access fault when a table is not in memory. a bare satp maps every
+    /// page to itself.
+    pub fn walk(&self, mem: &mut impl Memory, vaddr: u64, access: Access) -> Result<Leaf, Exception> {
+        if self.mode == Mode::Bare {
+            let vbase = vaddr & !(PAGE_SIZE - 1);
+            let pte = PTE_V | PTE_R | PTE_W | PTE_X | PTE_A | PTE_D;
+            return Ok(Leaf { vbase, pbase: vbase, size: PAGE_SIZE, pte, addr: 0, global: true });
+        }
+        if self.canonical(vaddr) != vaddr {
+            return Err(access.page_fault(vaddr));
+        }
+        let (bits, size) = (self.mode.vpn_bits(), self.mode.pte_size());
+        let mut table = self.root;
+        let mut global = false;
+        for level in (0..self.mode.levels()).rev() {
+            let shift = 12 + level * bits;
+            let addr = table + (vaddr >> shift & ((1 << bits) - 1)) * size as u64;
+            let pte = mem.load(addr, size).map_err(|_| access.access_fault(vaddr))?;
+            if self.invalid(pte) {
+                break;
+            }
+            global |= pte & PTE_G != 0;
+            let base = self.ppn(pte) << 12;
+            if pte & (PTE_R | PTE_X) == 0 {
+                table = base;
+                continue;
+            }
+            let size = 1 << shift;
+            if base & (size - 1) != 0 {
+                break;
+            }
+            return Ok(Leaf { vbase: vaddr & !(size - 1), pbase: base, size, pte, addr, global });
+        }
+        Err(access.page_fault(vaddr))
+    }
+
+    /// the physical address of `vaddr`, if it is mapped
+    pub fn translate(&self, mem: &mut impl Memory, vaddr: u64) -> Option<u64> {
+        self.walk(mem, vaddr, Access::Load).ok().map(|leaf| leaf.translate(vaddr))
+    }
+
+    /// every leaf in the table by virtual address. malformed entries and
+    /// tables that are not in memory are skipped.
+    pub fn leaves(&self, mem: &mut impl Memory) -> Vec<Leaf> {
+        let mut r = vec![];
+        if self.mode != Mode::Bare {
+            self.collect(mem, self.root, self.mode.levels() - 1, 0, false, &mut r);
+        }
+        r.sort_by_key(|leaf| leaf.vbase);
+        r
+    }
+
+    fn collect(&self, mem: &mut impl Memory, table: u64, level: u32, vbase: u64, global: bool, out: &mut Vec<Leaf>) {
+        let (bits, size) = (self.mode.vpn_bits(), self.mode.pte_size());
+        let shift = 12 + level * bits;
+        for i in 0..1 << bits {
+            let addr = table + i * size as u64;
+            let Ok(pte) = mem.load(addr, size) else { return };
+            if self.invalid(pte) {
+                continue;
+            }
+            let vbase = vbase | i << shift;
+            let global = global || pte & PTE_G != 0;
+            let base = self.ppn(pte) << 12;
+            if pte & (PTE_R | PTE_X) == 0 {
+                if level > 0 {
+                    self.collect(mem, base, level - 1, vbase, global, out);
+                }
+            } else if base & ((1 << shift) - 1) == 0 {
+                out.push(Leaf { vbase: self.canonical(vbase), pbase: base, size: 1 << shift, pte, addr, global });
+            }
+        }
+    }
+}
+
+/// a fully associative tlb with round robin replacement. entries only go
+/// away on `flush`, so stale mappings stay visible until `sfence.vma` as
+/// on hardware.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tlb {
+    entries: Vec<(u16, Leaf)>,
+    next: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl Default for Tlb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tlb {
+    pub const ENTRIES: usize = 64;
+
+    pub fn new() -> Tlb {
+        Tlb { entries: Vec::with_capacity(Tlb::ENTRIES), next: 0, hits: 0, misses: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn lookup(&mut self, vaddr: u64, asid: u16) -> Option<Leaf> {
+        let r = self.entries.iter()
+            .find(|(a, leaf)| (leaf.global || *a == asid) && leaf.contains(vaddr))
+            .map(|(_, leaf)| *leaf);
+        if r.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        r
+    }
+
+    pub fn insert(&mut self, asid: u16, leaf: Leaf) {
+        if let Some(e) = self.entries.iter_mut().find(|(a, l)| *a == asid && l.vbase == leaf.vbase) {
+            *e = (asid, leaf);
+        } else if self.entries.len() < Tlb::ENTRIES {
+            self.entries.push((asid, leaf));
+        } else {
+            self.entries[self.next] = (asid, leaf);
+            self.next = (self.next + 1) % Tlb::ENTRIES;
+        }
+    }
+
+    /// `sfence.vma`, `None` is every address or every address space. a
+    /// flush of one address space keeps the global mappings.
+    pub fn flush(&mut self, vaddr: Option<u64>, asid: Option<u16>) {
+        self.entries.retain(|(a, leaf)| {
+            let addr = vaddr.is_none_or(|v| leaf.contains(v));
+            let space = asid.is_none_or(|x| x == *a && !leaf.global);
+            !(addr && space)
+        });
+    }
+}
+
+/// `e` with the physical address of a memory fault replaced by `vaddr`
+fn at(e: Exception, vaddr: u64) -> Exception {
+    match e {
+        Exception::InstructionMisaligned(_) => Exception::InstructionMisaligned(vaddr),
+        Exception::InstructionAccessFault(_) => Exception::InstructionAccessFault(vaddr),
+        Exception::LoadMisaligned(_) => Exception::LoadMisaligned(vaddr),
+        Exception::LoadAccessFault(_) => Exception::LoadAccessFault(vaddr),
+        Exception::StoreMisaligned(_) => Exception::StoreMisaligned(vaddr),
+        Exception::StoreAccessFault(_) => Exception::StoreAccessFault(vaddr),
+        e => e,
+    }
+}
+
+/// whether a leaf allows `access` from `privilege`
+fn allowed(pte: u64, status: u64, privilege: Privilege, access: Access) -> bool {
+    let user = pte & PTE_U != 0;
+    let mode = match privilege {
+        Privilege::User => user,
+        _ => !user || (access != Access::Fetch && status & MSTATUS_SUM != 0),
+    };
+    mode && match access {
+        Access::Fetch => pte & PTE_X != 0,
+        Access::Load => pte & PTE_R != 0 || (status & MSTATUS_MXR != 0 && pte & PTE_X != 0),
+        Access::Store => pte & PTE_W != 0,
+    }
+}
+
+impl Hart {
+    /// the mode loads and stores are checked in, `mprv` borrows `mpp`
+    pub fn data_privilege(&self) -> Privilege {
+        let status = self.csr.get(MSTATUS);
+        if self.privilege == Privilege::Machine && status & MSTATUS_MPRV != 0 {
+            Privilege::from_bits(status >> 11)
+        } else {
+            self.privilege
+        }
+    }
+
+    /// the physical address of `vaddr`, walking the table on a tlb miss.
+    /// missing `A` or `D` bits are set in memory under svadu and are page
+    /// faults otherwise.
+    pub fn translate(&mut self, mem: &mut impl Memory, vaddr: u64, access: Access) -> Result<u64, Exception> {
+        let privilege = if access == Access::Fetch { self.privilege } else { self.data_privilege() };
+        let satp = match Satp::decode(self.csr.get(SATP), self.is_32bit) {
+            Some(satp) if satp.mode != Mode::Bare && privilege != Privilege::Machine => satp,
+            _ => return Ok(vaddr),
+        };
+        let status = self.csr.get(MSTATUS);
+        let mut leaf = match self.tlb.lookup(vaddr, satp.asid) {
+            Some(leaf) => leaf,
+            None => {
+                let leaf = satp.walk(mem, vaddr, access)?;
+                self.tlb.insert(satp.asid, leaf);
+                leaf
+            }
+        };
+        let need = PTE_A | if access == Access::Store { PTE_D } else { 0 };
+        if leaf.pte & need != need && self.csr.get(MENVCFG) & MENVCFG_ADUE != 0 {
+            // update the pte in memory, not a possibly stale cached one
+            leaf = satp.walk(mem, vaddr, access)?;
+            if allowed(leaf.pte, status, privilege, access) {
+                leaf.pte |= need;
+                mem.store(leaf.addr, satp.mode.pte_size(), leaf.pte).map_err(|_| access.access_fault(vaddr))?;
+            }
+            self.tlb.insert(satp.asid, leaf);
+        }
+        if !allowed(leaf.pte, status, privilege, access) || leaf.pte & need != need {
+            return Err(access.page_fault(vaddr));
+        }
+        Ok(leaf.translate(vaddr))
+    }
+
+    /// physical addresses of an access: where it starts and, when it
+    /// crosses into a page that is not physically next, where that page is
+    fn span(&mut self, mem: &mut impl Memory, vaddr: u64, size: u8, access: Access) -> Result<(u64, Option<u64>), Exception> {
+        let first = self.translate(mem, vaddr, access)?;
+        let last = self.trunc(vaddr.wrapping_add(size as u64 - 1));
+        if last & !(PAGE_SIZE - 1) == vaddr & !(PAGE_SIZE - 1) {
+            return Ok((first, None));
+        }
+        let next = self.translate(mem, last & !(PAGE_SIZE - 1), access)?;
+        let len = PAGE_SIZE - vaddr % PAGE_SIZE;
+        Ok((first, (next != first + len).then_some(next)))
+    }
+
+    /// load through the mmu. an access split over two discontiguous pages
+    /// is done a byte at a time.
+    pub fn load(&mut self, mem: &mut impl Memory, vaddr: u64, size: u8) -> Result<u64, Exception> {
+        let (first, next) = self.span(mem, vaddr, size, Access::Load)?;
+        let Some(next) = next else {
+            return mem.load(first, size).map_err(|e| at(e, vaddr));
+        };
+        let len = PAGE_SIZE - vaddr % PAGE_SIZE;
+        let mut v = 0;
+        for i in 0..size as u64 {
+            let addr = if i < len { first + i } else { next + i - len };
+            v |= mem.load(addr, 1).map_err(|e| at(e, vaddr))? << (8 * i);
+        }
+        Ok(v)
+    }
+
+    /// store through the mmu, both pages are checked before anything is
+    /// written
+    pub fn store(&mut self, mem: &mut impl Memory, vaddr: u64, size: u8, value: u64) -> Result<(), Exception> {
+        let (first, next) = self.span(mem, vaddr, size, Access::Store)?;
+        let Some(next) = next else {
+            mem.store(first, size, value).map_err(|e| at(e, vaddr))?;
+            self.note_store(first, size as u64);
+            return Ok(());
+        };
+        let len = PAGE_SIZE - vaddr % PAGE_SIZE;
+        for i in 0..size as u64 {
+            let addr = if i < len { first + i } else { next + i - len };
+            mem.store(addr, 1, value >> (8 * i) & 0xff).map_err(|e| at(e, vaddr))?;
+            self.note_store(addr, 1);
+        }
+        Ok(())
+    }
+
+    fn note_store(&mut self, addr: u64, len: u64) {
+        let (lo, hi) = self.stored.unwrap_or((addr, addr + len));
+        self.stored = Some((lo.min(addr), hi.max(addr + len)));
+    }
+
+    /// drop the reservation if another hart stored to `[lo, hi)` inside it
+    pub fn snoop(&mut self, (lo, hi): (u64, u64)) {
+        if let Some(r) = self.reservation {
+            let granule = r & !7;
+            if lo < granule + 8 && granule < hi {
+                self.reservation = None;
+            }
+        }
+    }
+
+    /// an instruction parcel through the mmu, parcels never cross a page
+    pub fn fetch(&mut self, mem: &mut impl Memory, vaddr: u64, size: u8) -> Result<u64, Exception> {
+        let addr = self.translate(mem, vaddr, Access::Fetch)?;
+        mem.fetch(addr, size).map_err(|e| at(e, vaddr))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::emu::csr::*;
+    use crate::emu::exec::execute;
+    use crate::emu::memory::Ram;
+    use crate::isa::riscv::{Csr, Reg, RiscV};
+    use super::*;
+
+    const BASE: u64 = 0x80000000;
+    const ROOT: u64 = BASE + 0x100000;
+
+    /// page tables from a bump allocator after `ROOT`
+    struct Tables {
+        mode: Mode,
+        next: u64,
+    }
+
+    impl Tables {
+        fn new(mode: Mode) -> Tables {
+            Tables { mode, next: ROOT + PAGE_SIZE }
+        }
+
+        fn satp(&self, asid: u64) -> u64 {
+            let mode = match self.mode {
+                Mode::Sv32 => return 1 << 31 | asid << 22 | ROOT >> 12,
+                Mode::Bare => return 0,
+                Mode::Sv39 => 8,
+                Mode::Sv48 => 9,
+                Mode::Sv57 => 10,
+            };
+            mode << 60 | asid << 44 | ROOT >> 12
+        }
+
+        fn map(&mut self, ram: &mut Ram, vaddr: u64, paddr: u64, level: u32, flags: u64) {
+            let (bits, size) = (self.mode.vpn_bits(), self.mode.pte_size());
+            let mut table = ROOT;
+            for l in (level..self.mode.levels()).rev() {
+                let addr = table + (vaddr >> (12 + l * bits) & ((1 << bits) - 1)) * size as u64;
+                if l == level {
+                    ram.store(addr, size, paddr >> 12 << 10 | flags | PTE_V).unwrap();
+                    return;
+                }
+                let pte = ram.load(addr, size).unwrap();
+                table = if pte & PTE_V != 0 {
+                    pte >> 10 << 12
+                } else {
+                    let t = self.next;
+                    self.next += PAGE_SIZE;
+                    ram.store(addr, size, t >> 12 << 10 | PTE_V).unwrap();
+                    t
+                };
+            }
+        }
+    }
+
+    const RW: u64 = PTE_R | PTE_W | PTE_A | PTE_D;
+
+    /// an s-mode hart with sv39 translation on
+    fn sv39() -> (Hart, Ram, Tables) {
+        let mut h = Hart::new(false);
+        h.privilege = Privilege::Supervisor;
+        let t = Tables::new(Mode::Sv39);
+        h.csr.set(SATP, t.satp(0));
+        (h, Ram::new(BASE, 0x400000), t)
+    }
+
+    #[test]
+    fn test_walk() {
+        let (_, mut m, mut t) = sv39();
+        t.map(&mut m, 0x1000, BASE + 0x10000, 0, RW);
+        t.map(&mut m, 0x4020_0000, BASE + 0x200000, 1, PTE_R | PTE_X | PTE_G);
+        t.map(&mut m, 0xffff_ffff_c000_0000, BASE, 2, PTE_R);
+        let satp = Satp::decode(t.satp(0), false).unwrap();
+        assert_eq!(satp.mode, Mode::Sv39);
+        assert_eq!(satp.translate(&mut m, 0x1234), Some(BASE + 0x10234));
+        assert_eq!(satp.translate(&mut m, 0x4020_1234), Some(BASE + 0x201234));
+        assert_eq!(satp.translate(&mut m, 0xffff_ffff_c012_3456), Some(BASE + 0x123456));
+        assert_eq!(satp.translate(&mut m, 0x2000), None);
+        assert_eq!(satp.walk(&mut m, 0x2000, Access::Store), Err(Exception::StorePageFault(0x2000)));
+        // not sign extended from bit 38
+        assert_eq!(satp.walk(&mut m, 1 << 39, Access::Fetch), Err(Exception::InstructionPageFault(1 << 39)));
+
+        let leaves = satp.leaves(&mut m);
+        let pages: Vec<_> = leaves.iter().map(|l| (l.vbase, l.pbase, l.size, l.global)).collect();
+        assert_eq!(pages, [
+            (0x1000, BASE + 0x10000, PAGE_SIZE, false),
+            (0x4020_0000, BASE + 0x200000, 0x200000, true),
+            (0xffff_ffff_c000_0000, BASE, 0x4000_0000, false),
+        ]);
+
+        // a misaligned megapage and a w without r
+        t.map(&mut m, 0x8000_0000, BASE + 0x1000, 1, PTE_R);
+        t.map(&mut m, 0x3000, BASE, 0, PTE_W);
+        assert_eq!(satp.walk(&mut m, 0x8000_0000, Access::Load), Err(Exception::LoadPageFault(0x8000_0000)));
+        assert_eq!(satp.walk(&mut m, 0x3000, Access::Load), Err(Exception::LoadPageFault(0x3000)));
+        assert_eq!(satp.leaves(&mut m).len(), 3);
+        // the root is not in memory
+        let satp = Satp::decode(8 << 60, false).unwrap();
+        assert_eq!(satp.walk(&mut m, 0x1000, Access::Load), Err(Exception::LoadAccessFault(0x1000)));
+    }
+
+    #[test]
+    fn test_permissions() {
+        let (mut h, mut m, mut t) = sv39();
+        t.map(&mut m, 0x1000, BASE + 0x10000, 0, RW | PTE_X | PTE_U);
+        t.map(&mut m, 0x2000, BASE + 0x11000, 0, PTE_X | PTE_A);
+        t.map(&mut m, 0x3000, BASE + 0x12000, 0, PTE_R | PTE_A);
+        m.store(BASE + 0x10008, 8, 42).unwrap();
+
+        // user pages from s-mode need sum, and can never be executed
+        assert_eq!(h.load(&mut m, 0x1008, 8), Err(Exception::LoadPageFault(0x1008)));
+        h.csr.set(MSTATUS, MSTATUS_SUM);
+        assert_eq!(h.load(&mut m, 0x1008, 8), Ok(42));
+        assert_eq!(h.fetch(&mut m, 0x1000, 2), Err(Exception::InstructionPageFault(0x1000)));
+        h.privilege = Privilege::User;
+        assert_eq!(h.fetch(&mut m, 0x1000, 2), Ok(0));
+        assert_eq!(h.load(&mut m, 0x3000, 4), Err(Exception::LoadPageFault(0x3000)));
+
+        // execute only pages are readable with mxr
+        h.privilege = Privilege::Supervisor;
+        assert_eq!(h.load(&mut m, 0x2000, 4), Err(Exception::LoadPageFault(0x2000)));
+        h.csr.set(MSTATUS, MSTATUS_MXR);
+        assert_eq!(h.load(&mut m, 0x2000, 4), Ok(0));
+        assert_eq!(h.store(&mut m, 0x3000, 4, 1), Err(Exception::StorePageFault(0x3000)));
+
+        // m-mode is untranslated unless mprv borrows s-mode
+        h.privilege = Privilege::Machine;
+        assert_eq!(h.load(&mut m, BASE + 0x10008, 8), Ok(42));
+        h.csr.set(MSTATUS, MSTATUS_MPRV | 1 << 11);
+        assert_eq!(h.load(&mut m, BASE + 0x10008, 8), Err(Exception::LoadPageFault(BASE + 0x10008)));
+        assert_eq!(h.translate(&mut m, 0x3004, Access::Load), Ok(BASE + 0x12004));
+        assert_eq!(h.fetch(&mut m, BASE, 2), Ok(0));
+
+        // the fault lands in stval with the virtual address
+        h.privilege = Privilege::Supervisor;
+        h.csr.set(MEDELEG, 1 << Exception::LoadPageFault(0).code());
+        let e = execute(&mut h, &mut m, RiscV::Load(crate::isa::riscv::LoadType::Word, Reg(1), Reg(0), 0x7f0)).unwrap_err();
+        assert_eq!(e, Exception::LoadPageFault(0x7f0));
+        h.take_exception(e);
+        assert_eq!((h.csr.get(SCAUSE), h.csr.get(STVAL)), (13, 0x7f0));
+    }
+
+    #[test]
+    fn test_accessed_dirty() {
+        let (mut h, mut m, mut t) = sv39();
+        t.map(&mut m, 0x1000, BASE + 0x10000, 0, PTE_R | PTE_W);
+        let satp = Satp::decode(t.satp(0), false).unwrap();
+        let pte = |m: &mut Ram| satp.walk(m, 0x1000, Access::Load).unwrap().pte;
+
+        // svade faults
+        h.csr.set(MENVCFG, 0);
+        assert_eq!(h.load(&mut m, 0x1000, 4), Err(Exception::LoadPageFault(0x1000)));
+        assert_eq!(pte(&mut m) & (PTE_A | PTE_D), 0);
+        // svadu sets the bits, even with a cached pte
+        h.csr.set(MENVCFG, MENVCFG_ADUE);
+        assert_eq!(h.load(&mut m, 0x1000, 4), Ok(0));
+        assert_eq!(pte(&mut m) & (PTE_A | PTE_D), PTE_A);
+        h.store(&mut m, 0x1000, 4, 1).unwrap();
+        assert_eq!(pte(&mut m) & (PTE_A | PTE_D), PTE_A | PTE_D);
+        assert_eq!(h.load(&mut m, 0x1000, 4), Ok(1));
+
+        // a store across two pages that are not physically next
+        t.map(&mut m, 0x2000, BASE + 0x20000, 0, RW);
+        h.store(&mut m, 0x1ffc, 8, 0x0807060504030201).unwrap();
+        assert_eq!(m.load(BASE + 0x10ffc, 4), Ok(0x04030201));
+        assert_eq!(m.load(BASE + 0x20000, 4), Ok(0x08070605));
+        assert_eq!(h.load(&mut m, 0x1ffe, 4), Ok(0x06050403));
+        // nothing is written when the second page faults
+        assert_eq!(h.store(&mut m, 0x2ffc, 8, u64::MAX), Err(Exception::StorePageFault(0x3000)));
+        assert_eq!(m.load(BASE + 0x20ffc, 4), Ok(0));
+    }
+
+    #[test]
+    fn test_tlb() {
+        let (mut h, mut m, mut t) = sv39();
+        t.map(&mut m, 0x1000, BASE + 0x10000, 0, RW);
+        t.map(&mut m, 0x2000, BASE + 0x11000, 0, RW | PTE_G);
+        assert_eq!(h.translate(&mut m, 0x1000, Access::Load), Ok(BASE + 0x10000));
+        assert_eq!(h.translate(&mut m, 0x2000, Access::Load), Ok(BASE + 0x11000));
+        assert_eq!((h.tlb.hits, h.tlb.misses), (0, 2));
+
+        // stale until flushed
+        t.map(&mut m, 0x1000, BASE + 0x30000, 0, RW);
+        assert_eq!(h.translate(&mut m, 0x1000, Access::Load), Ok(BASE + 0x10000));
+        h.tlb.flush(Some(0x2000), None);
+        assert_eq!(h.translate(&mut m, 0x1000, Access::Load), Ok(BASE + 0x10000));
+        h.tlb.flush(Some(0x1fff), None);
+        assert_eq!(h.translate(&mut m, 0x1000, Access::Load), Ok(BASE + 0x30000));
+
+        // another address space misses, an asid flush keeps globals
+        h.csr.set(SATP, t.satp(1));
+        assert_eq!(h.translate(&mut m, 0x2000, Access::Load), Ok(BASE + 0x11000));
+        assert_eq!(h.tlb.len(), 2);
+        assert_eq!(h.translate(&mut m, 0x1000, Access::Load), Ok(BASE + 0x30000));
+        assert_eq!(h.tlb.len(), 3);
+        h.tlb.flush(None, Some(0));
+        assert_eq!(h.tlb.len(), 2);
+
+        // sfence.vma is privileged and trapped by tvm
+        let sfence = RiscV::SFenceVma(Reg(0), Reg(0));
+        h.privilege = Privilege::User;
+        assert_eq!(execute(&mut h, &mut m, sfence), Err(Exception::IllegalInstruction(0)));
+        h.privilege = Privilege::Supervisor;
+        h.csr.set(MSTATUS, MSTATUS_TVM);
+        assert_eq!(execute(&mut h, &mut m, sfence), Err(Exception::IllegalInstruction(0)));
+        h.csr.set(MSTATUS, 0);
+        h.set_reg(Reg(5), 1);
+        execute(&mut h, &mut m, RiscV::SFenceVma(Reg(0), Reg(5))).unwrap();
+        assert_eq!(h.tlb.len(), 1);
+        execute(&mut h, &mut m, sfence).unwrap();
+        assert!(h.tlb.is_empty());
+    }
+
+    #[test]
+    fn test_sv48_sv57() {
+        // (mode, a va beyond sv39, the first non canonical va, top level
+        // leaf and its size: 512 GiB for sv48, 256 TiB for sv57)
+        let cases = [
+            (Mode::Sv48, 1 << 40, 1 << 47, 0xffff_ff80_0000_0000, 1 << 39),
+            (Mode::Sv57, 1 << 50, 1 << 56, 0xff00_0000_0000_0000, 1 << 48),
+        ];
+        for (mode, wide, bad, top, size) in cases {
+            let mut h = Hart::new(false);
+            h.privilege = Privilege::Supervisor;
+            let mut m = Ram::new(BASE, 0x400000);
+            let mut t = Tables::new(mode);
+            t.map(&mut m, 0x1000, BASE + 0x10000, 0, RW);
+            t.map(&mut m, wide, BASE + 0x11000, 0, RW);
+            t.map(&mut m, top, 0, mode.levels() - 1, RW | PTE_G);
+            h.write_csr(Csr(SATP), t.satp(5)).unwrap();
+            let satp = Satp::decode(h.csr.get(SATP), false).unwrap();
+            assert_eq!((satp.mode, satp.asid, satp.root), (mode, 5, ROOT));
+
+            assert_eq!(satp.translate(&mut m, 0x1234), Some(BASE + 0x10234));
+            assert_eq!(satp.translate(&mut m, wide | 0x10), Some(BASE + 0x11010));
+            assert_eq!(satp.translate(&mut m, top + BASE + 0x10008), Some(BASE + 0x10008));
+            assert_eq!(satp.translate(&mut m, top | (size - 1)), Some(size - 1));
+            assert_eq!(satp.walk(&mut m, bad, Access::Load), Err(Exception::LoadPageFault(bad)));
+            assert_eq!(satp.walk(&mut m, wide | bad, Access::Load), Err(Exception::LoadPageFault(wide | bad)));
+            // the canonical neighbour of the top leaf is not mapped
+            assert_eq!(satp.walk(&mut m, top - 1, Access::Load), Err(Exception::LoadPageFault(top - 1)));
+
+            let leaves = satp.leaves(&mut m);
+            let pages: Vec<_> = leaves.iter().map(|l| (l.vbase, l.pbase, l.size, l.global)).collect();
+            assert_eq!(pages, [
+                (0x1000, BASE + 0x10000, PAGE_SIZE, false),
+                (wide, BASE + 0x11000, PAGE_SIZE, false),
+                (top, 0, size, true),
+            ]);
+
+            // through the hart and its tlb
+            h.store(&mut m, 0x1008, 8, 42).unwrap();
+            assert_eq!(h.load(&mut m, top + BASE + 0x10008, 8), Ok(42));
+            assert_eq!(h.load(&mut m, bad, 8), Err(Exception::LoadPageFault(bad)));
+        }
+    }
+
+    #[test]
+    fn test_sv32() {
+        let mut h = Hart::new(true);
+        h.privilege = Privilege::Supervisor;
+        let mut m = Ram::new(BASE, 0x400000);
+        let mut t = Tables::new(Mode::Sv32);
+        t.map(&mut m, 0x1000, BASE + 0x2000, 0, RW);
+        t.map(&mut m, 0x4000_0000, BASE, 1, RW);
+        h.write_csr(Csr(SATP), t.satp(3)).unwrap();
+        assert_eq!(Satp::decode(h.csr.get(SATP), true).unwrap().asid, 3);
+        h.store(&mut m, 0x1004, 4, 0xdead).unwrap();
+        assert_eq!(m.load(BASE + 0x2004, 4), Ok(0xdead));
+        assert_eq!(h.load(&mut m, 0x4000_2004, 4), Ok(0xdead));
+        assert_eq!(h.load(&mut m, 0x8000_0000, 4), Err(Exception::LoadPageFault(0x8000_0000)));
+        // a 4 MiB megapage
+        let satp = Satp::decode(h.csr.get(SATP), true).unwrap();
+        assert_eq!(satp.leaves(&mut m).iter().map(|l| l.size).collect::<Vec<_>>(), [PAGE_SIZE, 0x400000]);
+    }
+}
@@ -0,0 +1,15 @@
+pub mod bus;
+pub mod clint;
+pub mod csr;
+pub mod exec;
+pub mod fdt;
+pub mod finisher;
+pub mod hart;
+pub mod irq;
+pub mod machine;
+pub mod memory;
+pub mod mmu;
+pub mod plic;
+pub mod trap;
+pub mod uart;
+pub mod virtio;
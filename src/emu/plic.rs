@@ -0,0 +1,294 @@
+use super::bus::Device;
+use super::hart::Hart;
+use super::irq::IrqLine;
+use super::trap::Interrupt;
+
+
+const PRIORITY: u64 = 0;
+const PENDING: u64 = 0x1000;
+const ENABLE: u64 = 0x2000;
+const CONTEXT: u64 = 0x200000;
+
+/// per hart and mode state, contexts `2 * hart` and `2 * hart + 1` are
+/// the machine and supervisor ones as on qemu virt
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Context {
+    enable: Vec<u32>,
+    threshold: u32,
+}
+
+/// the platform level interrupt controller. sources are level triggered
+/// wires sampled on every access, a claimed source is not pending again
+/// until it is completed.
+#[derive(Debug, Clone)]
+pub struct Plic {
+    priority: Vec<u32>,
+    pending: Vec<bool>,
+    claimed: Vec<bool>,
+    lines: Vec<Option<IrqLine>>,
+    contexts: Vec<Context>,
+}
+
+impl Plic {
+    pub const SIZE: u64 = 0x4000000;
+    /// number of sources including the reserved source 0
+    pub const SOURCES: usize = 96;
+    /// priorities are 3 bits wide
+    pub const MAX_PRIORITY: u32 = 7;
+
+    pub fn new(harts: usize) -> Plic {
+        let words = Plic::SOURCES.div_ceil(32);
+        Plic {
+            priority: vec![0; Plic::SOURCES],
+            pending: vec![false; Plic::SOURCES],
+            claimed: vec![false; Plic::SOURCES],
+            lines: vec![None; Plic::SOURCES],
+            contexts: vec![Context { enable: vec![0; words], threshold: 0 }; harts * 2],
+        }
+    }
+
+    /// wire a device interrupt to `source`, false if there is no such source
+    pub fn connect(&mut self, source: usize, line: IrqLine) -> bool {
+        if source == 0 || source >= Plic::SOURCES {
+            return false;
+        }
+        self.lines[source] = Some(line);
+        true
+    }
+
+    /// pend `source` by hand, for sources without a wire
+    pub fn set_pending(&mut self, source: usize, pending: bool) {
+        if source != 0 && source < Plic::SOURCES {
+            self.pending[source] = pending;
+        }
+    }
+
+    pub fn is_pending(&mut self, source: usize) -> bool {
+        self.sample();
+        self.pending.get(source).copied().unwrap_or(false)
+    }
+
+    fn sample(&mut self) {
+        for (i, line) in self.lines.iter().enumerate() {
+            if let Some(line) = line {
+                if line.is_high() && !self.claimed[i] {
+                    self.pending[i] = true;
+                } else if !line.is_high() {
+                    self.pending[i] = false;
+                }
+            }
+        }
+    }
+
+    fn enabled(&self, context: usize, source: usize) -> bool {
+        self.contexts[context].enable[source / 32] >> (source % 32) & 1 != 0
+    }
+
+    /// the source `context` would claim: the highest priority enabled
+    /// pending one above the threshold, the lowest id on ties
+    fn best(&self, context: usize) -> Option<usize> {
+        let threshold = self.contexts[context].threshold;
+        (1..Plic::SOURCES)
+            .filter(|&s| self.pending[s] && self.enabled(context, s) && self.priority[s] > threshold)
+            .min_by_key(|&s| (std::cmp::Reverse(self.priority[s]), s))
+    }
+
+    pub fn claim(&mut self, context: usize) -> u32 {
+        self.sample();
+        match self.best(context) {
+            Some(s) => {
+                self.pending[s] = false;
+                self.claimed[s] = true;
+                s as u32
+            }
+            None => 0,
+        }
+    }
+
+    pub fn complete(&mut self, context: usize, source: u32) {
+        let source = source as usize;
+        if source < Plic::SOURCES && self.enabled(context, source) {
+            self.claimed[source] = false;
+        }
+        self.sample();
+    }
+
+    /// whether `context` has an interrupt to take
+    pub fn interrupting(&mut self, context: usize) -> bool {
+        self.sample();
+        self.best(context).is_some()
+    }
+
+    /// drive `MEIP` and `SEIP` of hart `id`
+    pub fn update(&mut self, id: usize, hart: &mut Hart) {
+        for (context, irq) in [(2 * id, Interrupt::MachineExternal), (2 * id + 1, Interrupt::SupervisorExternal)] {
+            if self.interrupting(context) {
+                hart.mip |= irq.bit();
+            } else {
+                hart.mip &= !irq.bit();
+            }
+        }
+    }
+}
+
+impl Device for Plic {
+    fn read(&mut self, offset: u64, size: u8) -> Option<u64> {
+        if size != 4 {
+            return None;
+        }
+        self.sample();
+        let contexts = self.contexts.len() as u64;
+        let v = match offset {
+            o if o < PENDING => self.priority.get(((o - PRIORITY) / 4) as usize).copied().unwrap_or(0),
+            o if o < ENABLE => {
+                let word = ((o - PENDING) / 4) as usize * 32;
+                (0..32).filter(|i| self.pending.get(word + i).copied().unwrap_or(false))
+                    .fold(0, |v, i| v | 1 << i)
+            }
+            o if o < ENABLE + contexts * 0x80 => {
+                let context = &self.contexts[((o - ENABLE) / 0x80) as usize];
+                context.enable.get(((o - ENABLE) % 0x80 / 4) as usize).copied().unwrap_or(0)
+            }
+            o if (CONTEXT..CONTEXT + contexts * 0x1000).contains(&o) => {
+                let context = ((o - CONTEXT) / 0x1000) as usize;
+                match (o - CONTEXT) % 0x1000 {
+                    0 => self.contexts[context].threshold,
+                    4 => self.claim(context),
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        };
+        Some(v as u64)
+    }
+
+    fn write(&mut self, offset: u64, size: u8, value: u64) -> bool {
+        if size != 4 {
+            return false;
+        }
+        let value = value as u32;
+        let contexts = self.contexts.len() as u64;
+        match offset {
+            o if o < PENDING => {
+                let source = ((o - PRIORITY) / 4) as usize;
+                if source != 0 && source < Plic::SOURCES {
+                    self.priority[source] = value.min(Plic::MAX_PRIORITY);
+                }
+            }
+            // pending bits are read only
+            o if o < ENABLE => {}
+            o if o < ENABLE + contexts * 0x80 => {
+                let context = &mut self.contexts[((o - ENABLE) / 0x80) as usize];
+                let word = ((o - ENABLE) % 0x80 / 4) as usize;
+                if let Some(w) = context.enable.get_mut(word) {
+                    // source 0 does not exist
+                    *w = if word == 0 { value & !1 } else { value };
+                }
+            }
+            o if (CONTEXT..CONTEXT + contexts * 0x1000).contains(&o) => {
+                let context = ((o - CONTEXT) / 0x1000) as usize;
+                match (o - CONTEXT) % 0x1000 {
+                    0 => self.contexts[context].threshold = value.min(Plic::MAX_PRIORITY),
+                    4 => self.complete(context, value),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::emu::bus::Bus;
+    use crate::emu::memory::Memory;
+    use crate::emu::uart::{Sink, Uart, IER, IER_RX, LSR, LSR_DR, RBR};
+    use crate::isa::riscv::bare::memory_layout::*;
+    use super::*;
+
+    fn plic_bus(harts: usize) -> Bus {
+        let mut bus = Bus::new();
+        assert!(bus.map("plic", PLIC, Plic::SIZE, Plic::new(harts)));
+        bus
+    }
+
+    /// xv6's plicinit and plicinithart
+    fn xv6_init(bus: &mut Bus, hart: u64) {
+        bus.store(PLIC + UART0_IRQ * 4, 4, 1).unwrap();
+        bus.store(PLIC + VIRTIO0_IRQ * 4, 4, 1).unwrap();
+        bus.store(plic_senable(hart), 4, (1 << UART0_IRQ) | (1 << VIRTIO0_IRQ)).unwrap();
+        bus.store(plic_spriority(hart), 4, 0).unwrap();
+    }
+
+    #[test]
+    fn test_xv6_uart() {
+        let mut bus = plic_bus(2);
+        assert!(bus.map("uart0", UART0, Uart::SIZE, Uart::new(Sink::Buffer(vec![]))));
+        let line = bus.device::<Uart>("uart0").unwrap().irq.clone();
+        assert!(bus.device::<Plic>("plic").unwrap().connect(UART0_IRQ as usize, line));
+        xv6_init(&mut bus, 1);
+        bus.store(UART0 + IER, 1, IER_RX as u64).unwrap();
+
+        let mut hart = Hart::new(false);
+        bus.device::<Plic>("plic").unwrap().update(1, &mut hart);
+        assert_eq!(hart.mip, 0);
+        bus.device::<Uart>("uart0").unwrap().push_input(b"x");
+        bus.device::<Plic>("plic").unwrap().update(1, &mut hart);
+        assert_eq!(hart.mip, Interrupt::SupervisorExternal.bit());
+        assert_eq!(bus.load(PLIC_PENDING, 4), Ok(1 << UART0_IRQ));
+
+        // devintr: claim, service, complete
+        assert_eq!(bus.load(plic_sclaim(1), 4), Ok(UART0_IRQ));
+        assert_eq!(bus.load(PLIC_PENDING, 4), Ok(0));
+        bus.device::<Plic>("plic").unwrap().update(1, &mut hart);
+        assert_eq!(hart.mip, 0);
+        while bus.load(UART0 + LSR, 1).unwrap() as u8 & LSR_DR != 0 {
+            bus.load(UART0 + RBR, 1).unwrap();
+        }
+        bus.store(plic_sclaim(1), 4, UART0_IRQ).unwrap();
+        assert_eq!(bus.load(plic_sclaim(1), 4), Ok(0));
+
+        // still high at completion pends again
+        bus.device::<Uart>("uart0").unwrap().push_input(b"y");
+        assert_eq!(bus.load(plic_sclaim(1), 4), Ok(UART0_IRQ));
+        bus.store(plic_sclaim(1), 4, UART0_IRQ).unwrap();
+        bus.device::<Plic>("plic").unwrap().update(1, &mut hart);
+        assert_eq!(hart.mip, Interrupt::SupervisorExternal.bit());
+        // hart 0 did not enable anything
+        let mut other = Hart::new(false);
+        bus.device::<Plic>("plic").unwrap().update(0, &mut other);
+        assert_eq!(other.mip, 0);
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut bus = plic_bus(1);
+        xv6_init(&mut bus, 0);
+        bus.store(PLIC + UART0_IRQ * 4, 4, 3).unwrap();
+        bus.store(PLIC + 4 * 4, 4, 9).unwrap();
+        assert_eq!(bus.load(PLIC + 4 * 4, 4), Ok(7));
+        bus.store(plic_menable(0), 4, 1 << 4 | 1 << VIRTIO0_IRQ | 1).unwrap();
+        assert_eq!(bus.load(plic_menable(0), 4), Ok(1 << 4 | 1 << VIRTIO0_IRQ));
+
+        let plic = bus.device::<Plic>("plic").unwrap();
+        for s in [VIRTIO0_IRQ, UART0_IRQ, 4] {
+            plic.set_pending(s as usize, true);
+        }
+        let mut hart = Hart::new(false);
+        plic.update(0, &mut hart);
+        assert_eq!(hart.mip, Interrupt::MachineExternal.bit() | Interrupt::SupervisorExternal.bit());
+
+        // the supervisor context takes uart over virtio, machine takes 4
+        assert_eq!(bus.load(plic_sclaim(0), 4), Ok(UART0_IRQ));
+        assert_eq!(bus.load(plic_mclaim(0), 4), Ok(4));
+        bus.store(plic_mpriority(0), 4, 1).unwrap();
+        assert_eq!(bus.load(plic_mclaim(0), 4), Ok(0));
+        bus.store(plic_spriority(0), 4, 0).unwrap();
+        assert_eq!(bus.load(plic_sclaim(0), 4), Ok(VIRTIO0_IRQ));
+        assert_eq!(bus.load(plic_sclaim(0), 4), Ok(0));
+        // registers are 32 bits wide
+        assert!(bus.load(PLIC_PENDING, 2).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use std::fmt::Display;
+
+use super::csr::*;
+use super::hart::Hart;
+
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Privilege {
+    User       = 0b00,
+    Supervisor = 0b01,
+    Machine    = 0b11,
+}
+
+impl Privilege {
+    /// from an `mpp`/`spp` field, the reserved value 2 is taken as user
+    pub fn from_bits(bits: u64) -> Privilege {
+        match bits & 0b11 {
+            0b01 => Privilege::Supervisor,
+            0b11 => Privilege::Machine,
+            _ => Privilege::User,
+        }
+    }
+}
+
+impl Display for Privilege {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Privilege::User => write!(f, "U"),
+            Privilege::Supervisor => write!(f, "S"),
+            Privilege::Machine => write!(f, "M"),
+        }
+    }
+}
+
+/// synchronous exceptions, the payload is the `xtval` value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Exception {
+    InstructionMisaligned(u64),
+    InstructionAccessFault(u64),
+    /// the instruction bits when known, otherwise 0
+    IllegalInstruction(u64),
+    Breakpoint(u64),
+    LoadMisaligned(u64),
+    LoadAccessFault(u64),
+    StoreMisaligned(u64),
+    StoreAccessFault(u64),
+    /// from the privilege mode the `ecall` executed in
+    Ecall(Privilege),
+    InstructionPageFault(u64),
+    LoadPageFault(u64),
+    StorePageFault(u64),
+}
+
+impl Exception {
+    /// the `xcause` exception code
+    pub fn code(&self) -> u64 {
+        match self {
+            Exception::InstructionMisaligned(_) => 0,
+            Exception::InstructionAccessFault(_) => 1,
+            Exception::IllegalInstruction(_) => 2,
+            Exception::Breakpoint(_) => 3,
+            Exception::LoadMisaligned(_) => 4,
+            Exception::LoadAccessFault(_) => 5,
+            Exception::StoreMisaligned(_) => 6,
+            Exception::StoreAccessFault(_) => 7,
+            Exception::Ecall(Privilege::User) => 8,
+            Exception::Ecall(Privilege::Supervisor) => 9,
+            Exception::Ecall(Privilege::Machine) => 11,
+            Exception::InstructionPageFault(_) => 12,
+            Exception::LoadPageFault(_) => 13,
+            Exception::StorePageFault(_) => 15,
+        }
+    }
+
+    pub fn tval(&self) -> u64 {
+        match *self {
+            Exception::Ecall(_) => 0,
+            Exception::InstructionMisaligned(x) |
+            Exception::InstructionAccessFault(x) |
+            Exception::IllegalInstruction(x) |
+            Exception::Breakpoint(x) |
+            Exception::LoadMisaligned(x) |
+            Exception::LoadAccessFault(x) |
+            Exception::StoreMisaligned(x) |
+            Exception::StoreAccessFault(x) |
+            Exception::InstructionPageFault(x) |
+            Exception::LoadPageFault(x) |
+            Exception::StorePageFault(x) => x,
+        }
+    }
+}
+
+impl Display for Exception {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Exception::InstructionMisaligned(_) => "instruction address misaligned",
+            Exception::InstructionAccessFault(_) => "instruction access fault",
+            Exception::IllegalInstruction(_) => "illegal instruction",
+            Exception::Breakpoint(_) => "breakpoint",
+            Exception::LoadMisaligned(_) => "load address misaligned",
+            Exception::LoadAccessFault(_) => "load access fault",
+            Exception::StoreMisaligned(_) => "store address misaligned",
+            Exception::StoreAccessFault(_) => "store access fault",
+            Exception::Ecall(p) => return write!(f, "environment call from {}-mode", p),
+            Exception::InstructionPageFault(_) => "instruction page fault",
+            Exception::LoadPageFault(_) => "load page fault",
+            Exception::StorePageFault(_) => "store page fault",
+        };
+        write!(f, "{} ({:#x})", name, self.tval())
+    }
+}
+
+impl std::error::Error for Exception {}
+
+/// asynchronous interrupts, as bits of `mip`/`mie`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Interrupt {
+    SupervisorSoftware,
+    MachineSoftware,
+    SupervisorTimer,
+    MachineTimer,
+    SupervisorExternal,
+    MachineExternal,
+}
+
+impl Interrupt {
+    /// in the order they are taken when several are pending
+    pub const PRIORITY: [Interrupt; 6] = [
+        Interrupt::MachineExternal,
+        Interrupt::MachineSoftware,
+        Interrupt::MachineTimer,
+        Interrupt::SupervisorExternal,
+        Interrupt::SupervisorSoftware,
+        Interrupt::SupervisorTimer,
+    ];
+
+    /// the `xcause` interrupt code
+    pub fn code(&self) -> u64 {
+        match self {
+            Interrupt::SupervisorSoftware => 1,
+            Interrupt::MachineSoftware => 3,
+            Interrupt::SupervisorTimer => 5,
+            Interrupt::MachineTimer => 7,
+            Interrupt::SupervisorExternal => 9,
+            Interrupt::MachineExternal => 11,
+        }
+    }
+
+    pub fn bit(&self) -> u64 {
+        1 << self.code()
+    }
+}
+
+impl Display for Interrupt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Interrupt::SupervisorSoftware => "supervisor software interrupt",
+            Interrupt::MachineSoftware => "machine software interrupt",
+            Interrupt::SupervisorTimer => "supervisor timer interrupt",
+            Interrupt::MachineTimer => "machine timer interrupt",
+            Interrupt::SupervisorExternal => "supervisor external interrupt",
+            Interrupt::MachineExternal => "machine external interrupt",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Hart {
+    /// the interrupt to take now, if any. delegated interrupts are taken
+    /// in s-mode and never preempt m-mode.
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        let pending = self.pending() & self.csr.get(MIE);
+        if pending == 0 {
+            return None;
+        }
+        let status = self.csr.get(MSTATUS);
+        let mideleg = self.csr.get(MIDELEG);
+        let m = self.privilege < Privilege::Machine || status & MSTATUS_MIE != 0;
+        let s = self.privilege < Privilege::Supervisor ||
+            (self.privilege == Privilege::Supervisor && status & MSTATUS_SIE != 0);
+        Interrupt::PRIORITY.into_iter().find(|i| {
+            pending & i.bit() != 0 && if mideleg & i.bit() != 0 { s } else { m }
+        })
+    }
+
+    pub fn take_exception(&mut self, e: Exception) {
+        self.enter_trap(false, e.code(), e.tval());
+    }
+
+    pub fn take_interrupt(&mut self, i: Interrupt) {
+        self.enter_trap(true, i.code(), 0);
+    }
+
+    fn enter_trap(&mut self, interrupt: bool, code: u64, tval: u64) {
+        // a trap ends any lr/sc sequence
+        self.reservation = None;
+        let deleg = self.csr.get(if interrupt { MIDELEG } else { MEDELEG });
+        let to_s = self.privilege < Privilege::Machine && deleg >> code & 1 != 0;
+        let cause = (interrupt as u64) << (self.xlen() - 1) | code;
+        let status = self.csr.get(MSTATUS);
+        let (epc, xcause, xtval, xtvec) = if to_s { (SEPC, SCAUSE, STVAL, STVEC) } else { (MEPC, MCAUSE, MTVAL, MTVEC) };
+        self.csr.set(epc, self.pc);
+        self.csr.set(xcause, cause);
+        self.csr.set(xtval, tval);
+        let status = if to_s {
+            let spie = if status & MSTATUS_SIE != 0 { MSTATUS_SPIE } else { 0 };
+            let spp = if self.privilege == Privilege::Supervisor { MSTATUS_SPP } else { 0 };
+            status & !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP) | spie | spp
+        } else {
+            let mpie = if status & MSTATUS_MIE != 0 { MSTATUS_MPIE } else { 0 };
+            let mpp = (self.privilege as u64) << 11;
+            status & !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP) | mpie | mpp
+        };
+        self.csr.set(MSTATUS, status);
+        self.privilege = if to_s { Privilege::Supervisor } else { Privilege::Machine };
+
+        let tvec = self.csr.get(xtvec);
+        let offset = if interrupt && tvec & 0b11 == 1 { 4 * code } else { 0 };
+        self.pc = self.trunc((tvec & !0b11).wrapping_add(offset));
+        self.wfi = false;
+    }
+
+    /// leave an m-mode trap handler, the pc to return to
+    pub fn mret(&mut self) -> Result<u64, Exception> {
+        if self.privilege != Privilege::Machine {
+            return Err(Exception::IllegalInstruction(0));
+        }
+        let status = self.csr.get(MSTATUS);
+        let mpp = Privilege::from_bits(status >> 11);
+        let mie = if status & MSTATUS_MPIE != 0 { MSTATUS_MIE } else { 0 };
+        let mprv = if mpp == Privilege::Machine { status & MSTATUS_MPRV } else { 0 };
+        let status = status & !(MSTATUS_MIE | MSTATUS_MPP | MSTATUS_MPRV) | mie | MSTATUS_MPIE | mprv;
+        self.csr.set(MSTATUS, status);
+        self.privilege = mpp;
+        Ok(self.epc(MEPC))
+    }
+
+    /// leave an s-mode trap handler, the pc to return to
+    pub fn sret(&mut self) -> Result<u64, Exception> {
+        let status = self.csr.get(MSTATUS);
+        if self.privilege == Privilege::User ||
+            (self.privilege == Privilege::Supervisor && status & MSTATUS_TSR != 0) {
+            return Err(Exception::IllegalInstruction(0));
+        }
+        let spp = if status & MSTATUS_SPP != 0 { Privilege::Supervisor } else { Privilege::User };
+        let sie = if status & MSTATUS_SPIE != 0 { MSTATUS_SIE } else { 0 };
+        let status = status & !(MSTATUS_SIE | MSTATUS_SPP | MSTATUS_MPRV) | sie | MSTATUS_SPIE;
+        self.csr.set(MSTATUS, status);
+        self.privilege = spp;
+        Ok(self.epc(SEPC))
+    }
+
+    fn epc(&self, epc: u16) -> u64 {
+        let mask = if self.compressed { !1 } else { !3 };
+        self.trunc(self.csr.get(epc) & mask)
+    }
+}
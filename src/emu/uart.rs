@@ -0,0 +1,288 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::Path;
+
+use super::bus::Device;
+use super::irq::IrqLine;
+
+
+pub const RBR: u64 = 0;
+pub const THR: u64 = 0;
+pub const IER: u64 = 1;
+pub const IIR: u64 = 2;
+pub const FCR: u64 = 2;
+pub const LCR: u64 = 3;
+pub const MCR: u64 = 4;
+pub const LSR: u64 = 5;
+pub const MSR: u64 = 6;
+pub const SCR: u64 = 7;
+
+pub const IER_RX: u8 = 1 << 0;
+pub const IER_TX: u8 = 1 << 1;
+pub const LCR_DLAB: u8 = 1 << 7;
+pub const MCR_LOOP: u8 = 1 << 4;
+pub const LSR_DR: u8 = 1 << 0;
+pub const LSR_THRE: u8 = 1 << 5;
+pub const LSR_TEMT: u8 = 1 << 6;
+
+/// `IIR` values, by priority
+const IIR_NONE: u8 = 0x01;
+const IIR_RX: u8 = 0x04;
+const IIR_TX: u8 = 0x02;
+
+/// where transmitted bytes go
+pub enum Sink {
+    Buffer(Vec<u8>),
+    Writer(Box<dyn Write>),
+}
+
+impl Sink {
+    pub fn stdout() -> Sink {
+        Sink::Writer(Box::new(std::io::stdout()))
+    }
+
+    pub fn file(path: impl AsRef<Path>) -> std::io::Result<Sink> {
+        Ok(Sink::Writer(Box::new(std::fs::File::create(path)?)))
+    }
+
+    fn put(&mut self, byte: u8) {
+        match self {
+            Sink::Buffer(b) => b.push(byte),
+            Sink::Writer(w) => {
+                // the guest has no way to see host errors
+                let _ = w.write_all(&[byte]).and_then(|_| w.flush());
+            }
+        }
+    }
+}
+
+/// an ns16550a. transmission is instant and received bytes are queued
+/// without a fifo limit.
+pub struct Uart {
+    pub sink: Sink,
+    pub irq: IrqLine,
+    rx: VecDeque<u8>,
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+    /// the transmitter went empty and `IIR` has not reported it yet
+    thre: bool,
+}
+
+impl Uart {
+    /// size of the register window on the bus
+    pub const SIZE: u64 = 0x100;
+
+    pub fn new(sink: Sink) -> Uart {
+        Uart {
+            sink,
+            irq: IrqLine::new(),
+            rx: VecDeque::new(),
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            divisor: 0,
+            thre: false,
+        }
+    }
+
+    /// bytes transmitted so far, for a buffer sink
+    pub fn output(&self) -> &[u8] {
+        match &self.sink {
+            Sink::Buffer(b) => b,
+            Sink::Writer(_) => &[],
+        }
+    }
+
+    /// queue bytes as if they arrived on the line
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.rx.extend(bytes);
+        self.update();
+    }
+
+    pub fn divisor(&self) -> u16 {
+        self.divisor
+    }
+
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+
+    fn iir(&self) -> u8 {
+        let id = if self.ier & IER_RX != 0 && !self.rx.is_empty() {
+            IIR_RX
+        } else if self.ier & IER_TX != 0 && self.thre {
+            IIR_TX
+        } else {
+            IIR_NONE
+        };
+        // fifos enabled
+        if self.fcr & 1 != 0 { id | 0xc0 } else { id }
+    }
+
+    fn update(&self) {
+        self.irq.set(self.iir() & IIR_NONE == 0);
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        if self.mcr & MCR_LOOP != 0 {
+            self.rx.push_back(byte);
+        } else {
+            self.sink.put(byte);
+        }
+        self.thre = true;
+    }
+}
+
+impl Device for Uart {
+    fn read(&mut self, offset: u64, _size: u8) -> Option<u64> {
+        let v = match offset {
+            RBR if self.dlab() => self.divisor as u8,
+            RBR => self.rx.pop_front().unwrap_or(0),
+            IER if self.dlab() => (self.divisor >> 8) as u8,
+            IER => self.ier,
+            IIR => {
+                let iir = self.iir();
+                // reading the `THRE` identification clears it
+                if iir & 0x0f == IIR_TX {
+                    self.thre = false;
+                }
+                iir
+            }
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                let dr = if self.rx.is_empty() { 0 } else { LSR_DR };
+                dr | LSR_THRE | LSR_TEMT
+            }
+            // cts, dsr and dcd asserted
+            MSR => 0xb0,
+            SCR => self.scr,
+            _ => return None,
+        };
+        self.update();
+        Some(v as u64)
+    }
+
+    fn write(&mut self, offset: u64, _size: u8, value: u64) -> bool {
+        let v = value as u8;
+        match offset {
+            THR if self.dlab() => self.divisor = self.divisor & 0xff00 | v as u16,
+            THR => self.transmit(v),
+            IER if self.dlab() => self.divisor = self.divisor & 0x00ff | (v as u16) << 8,
+            IER => {
+                // enabling the interrupt with an empty transmitter raises it
+                if v & IER_TX != 0 && self.ier & IER_TX == 0 {
+                    self.thre = true;
+                }
+                self.ier = v & 0x0f;
+            }
+            FCR => {
+                if v & 0b10 != 0 {
+                    self.rx.clear();
+                }
+                self.fcr = v & 0xc9;
+            }
+            LCR => self.lcr = v,
+            MCR => self.mcr = v & 0x1f,
+            // read only
+            LSR | MSR => {}
+            SCR => self.scr = v,
+            _ => return false,
+        }
+        self.update();
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::emu::bus::Bus;
+    use crate::emu::memory::Memory;
+    use crate::isa::riscv::bare::memory_layout::UART0;
+    use super::*;
+
+    /// the register setup of xv6's `uartinit`
+    fn init(bus: &mut Bus) {
+        bus.store(UART0 + IER, 1, 0).unwrap();
+        bus.store(UART0 + LCR, 1, LCR_DLAB as u64).unwrap();
+        bus.store(UART0 + THR, 1, 0x03).unwrap();
+        bus.store(UART0 + IER, 1, 0x00).unwrap();
+        bus.store(UART0 + LCR, 1, 0x03).unwrap();
+        bus.store(UART0 + FCR, 1, 0x07).unwrap();
+        bus.store(UART0 + IER, 1, (IER_RX | IER_TX) as u64).unwrap();
+    }
+
+    fn uart_bus() -> Bus {
+        let mut bus = Bus::new();
+        assert!(bus.map("uart0", UART0, Uart::SIZE, Uart::new(Sink::Buffer(vec![]))));
+        bus
+    }
+
+    #[test]
+    fn test_transmit() {
+        let mut bus = uart_bus();
+        init(&mut bus);
+        let uart = bus.device::<Uart>("uart0").unwrap();
+        assert_eq!(uart.divisor(), 3);
+        let irq = uart.irq.clone();
+        // enabling the tx interrupt with nothing to send
+        assert!(irq.is_high());
+        assert_eq!(bus.load(UART0 + IIR, 1), Ok(0xc2));
+        assert!(!irq.is_high());
+        assert_eq!(bus.load(UART0 + IIR, 1), Ok(0xc1));
+
+        for &c in b"hi\n" {
+            assert_ne!(bus.load(UART0 + LSR, 1).unwrap() as u8 & LSR_THRE, 0);
+            bus.store(UART0 + THR, 1, c as u64).unwrap();
+        }
+        assert!(irq.is_high());
+        assert_eq!(bus.device::<Uart>("uart0").unwrap().output(), b"hi\n");
+        // the divisor latch hides the data registers
+        bus.store(UART0 + LCR, 1, 0x83).unwrap();
+        assert_eq!(bus.load(UART0 + RBR, 1), Ok(3));
+        bus.store(UART0 + THR, 1, 0x01).unwrap();
+        assert_eq!(bus.device::<Uart>("uart0").unwrap().divisor(), 1);
+        assert_eq!(bus.device::<Uart>("uart0").unwrap().output(), b"hi\n");
+    }
+
+    #[test]
+    fn test_receive() {
+        let mut bus = uart_bus();
+        init(&mut bus);
+        bus.load(UART0 + IIR, 1).unwrap();
+        let uart = bus.device::<Uart>("uart0").unwrap();
+        let irq = uart.irq.clone();
+        assert!(!irq.is_high());
+        uart.push_input(b"ab");
+        assert!(irq.is_high());
+        assert_eq!(bus.load(UART0 + IIR, 1), Ok(0xc4));
+
+        // xv6's uartintr: drain while data ready
+        let mut got = vec![];
+        while bus.load(UART0 + LSR, 1).unwrap() as u8 & LSR_DR != 0 {
+            got.push(bus.load(UART0 + RBR, 1).unwrap() as u8);
+        }
+        assert_eq!(got, b"ab");
+        assert!(!irq.is_high());
+
+        // loopback and fifo reset
+        bus.store(UART0 + MCR, 1, MCR_LOOP as u64).unwrap();
+        bus.store(UART0 + THR, 1, b'x' as u64).unwrap();
+        assert_eq!(bus.load(UART0 + RBR, 1), Ok(b'x' as u64));
+        bus.store(UART0 + THR, 1, b'y' as u64).unwrap();
+        bus.store(UART0 + FCR, 1, 0x03).unwrap();
+        assert_eq!(bus.load(UART0 + LSR, 1), Ok((LSR_THRE | LSR_TEMT) as u64));
+        assert_eq!(bus.device::<Uart>("uart0").unwrap().output(), b"");
+
+        bus.store(UART0 + SCR, 1, 0x5a).unwrap();
+        assert_eq!(bus.load(UART0 + SCR, 1), Ok(0x5a));
+        assert_eq!(bus.load(UART0 + 8, 1), Err(crate::emu::trap::Exception::LoadAccessFault(UART0 + 8)));
+    }
+}
@@ -0,0 +1,629 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::bus::{Bus, Device};
+use super::irq::IrqLine;
+
+
+pub const MAGIC: u32 = 0x74726976;
+pub const VENDOR: u32 = 0x554d4551;
+pub const DEVICE_BLK: u32 = 2;
+
+// virtio-mmio registers
+pub const MAGIC_VALUE: u64 = 0x000;
+pub const VERSION: u64 = 0x004;
+pub const DEVICE_ID: u64 = 0x008;
+pub const VENDOR_ID: u64 = 0x00c;
+pub const DEVICE_FEATURES: u64 = 0x010;
+pub const DEVICE_FEATURES_SEL: u64 = 0x014;
+pub const DRIVER_FEATURES: u64 = 0x020;
+pub const DRIVER_FEATURES_SEL: u64 = 0x024;
+/// legacy only
+pub const GUEST_PAGE_SIZE: u64 = 0x028;
+pub const QUEUE_SEL: u64 = 0x030;
+pub const QUEUE_NUM_MAX: u64 = 0x034;
+pub const QUEUE_NUM: u64 = 0x038;
+/// legacy only
+pub const QUEUE_ALIGN: u64 = 0x03c;
+/// legacy only
+pub const QUEUE_PFN: u64 = 0x040;
+pub const QUEUE_READY: u64 = 0x044;
+pub const QUEUE_NOTIFY: u64 = 0x050;
+pub const INTERRUPT_STATUS: u64 = 0x060;
+pub const INTERRUPT_ACK: u64 = 0x064;
+pub const STATUS: u64 = 0x070;
+pub const QUEUE_DESC_LOW: u64 = 0x080;
+pub const QUEUE_DESC_HIGH: u64 = 0x084;
+pub const QUEUE_DRIVER_LOW: u64 = 0x090;
+pub const QUEUE_DRIVER_HIGH: u64 = 0x094;
+pub const QUEUE_DEVICE_LOW: u64 = 0x0a0;
+pub const QUEUE_DEVICE_HIGH: u64 = 0x0a4;
+pub const CONFIG_GENERATION: u64 = 0x0fc;
+pub const CONFIG: u64 = 0x100;
+
+pub const STATUS_ACKNOWLEDGE: u32 = 1;
+pub const STATUS_DRIVER: u32 = 2;
+pub const STATUS_DRIVER_OK: u32 = 4;
+pub const STATUS_FEATURES_OK: u32 = 8;
+pub const STATUS_NEEDS_RESET: u32 = 0x40;
+
+pub const F_BLK_FLUSH: u64 = 1 << 9;
+pub const F_VERSION_1: u64 = 1 << 32;
+
+pub const DESC_NEXT: u16 = 1;
+pub const DESC_WRITE: u16 = 2;
+
+pub const BLK_T_IN: u32 = 0;
+pub const BLK_T_OUT: u32 = 1;
+pub const BLK_T_FLUSH: u32 = 4;
+pub const BLK_T_GET_ID: u32 = 8;
+
+pub const BLK_S_OK: u8 = 0;
+pub const BLK_S_IOERR: u8 = 1;
+pub const BLK_S_UNSUPP: u8 = 2;
+
+pub const SECTOR: u64 = 512;
+
+/// the only queue of the block device
+const QUEUE_MAX: u32 = 1024;
+
+const ID: &[u8] = b"lyuu-virtio-blk";
+
+/// backing store of a block device
+pub enum Disk {
+    Memory(Vec<u8>),
+    File(File),
+}
+
+impl Disk {
+    /// an image file, opened for reading and writing
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Disk> {
+        Ok(Disk::File(OpenOptions::new().read(true).write(true).open(path)?))
+    }
+
+    pub fn len(&self) -> u64 {
+        match self {
+            Disk::Memory(v) => v.len() as u64,
+            Disk::File(f) => f.metadata().map(|m| m.len()).unwrap_or(0),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        match self {
+            Disk::Memory(v) => {
+                let src = v.get(offset as usize..offset as usize + buf.len())
+                    .ok_or(std::io::ErrorKind::UnexpectedEof)?;
+                buf.copy_from_slice(src);
+                Ok(())
+            }
+            Disk::File(f) => {
+                f.seek(SeekFrom::Start(offset))?;
+                f.read_exact(buf)
+            }
+        }
+    }
+
+    pub fn write_at(&mut self, offset: u64, buf: &[u8]) -> std::io::Result<()> {
+        match self {
+            Disk::Memory(v) => {
+                let dst = v.get_mut(offset as usize..offset as usize + buf.len())
+                    .ok_or(std::io::ErrorKind::WriteZero)?;
+                dst.copy_from_slice(buf);
+                Ok(())
+            }
+            Disk::File(f) => {
+                f.seek(SeekFrom::Start(offset))?;
+                f.write_all(buf)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Disk::Memory(_) => Ok(()),
+            Disk::File(f) => f.sync_data(),
+        }
+    }
+}
+
+fn read_u16(bus: &mut Bus, addr: u64) -> Option<u16> {
+    Some(u16::from_le_bytes(bus.read_bytes(addr, 2)?.try_into().ok()?))
+}
+
+fn read_u32(bus: &mut Bus, addr: u64) -> Option<u32> {
+    Some(u32::from_le_bytes(bus.read_bytes(addr, 4)?.try_into().ok()?))
+}
+
+fn read_u64(bus: &mut Bus, addr: u64) -> Option<u64> {
+    Some(u64::from_le_bytes(bus.read_bytes(addr, 8)?.try_into().ok()?))
+}
+
+fn write_u16(bus: &mut Bus, addr: u64, v: u16) -> Option<()> {
+    bus.write_bytes(addr, &v.to_le_bytes()).then_some(())
+}
+
+fn write_u32(bus: &mut Bus, addr: u64, v: u32) -> Option<()> {
+    bus.write_bytes(addr, &v.to_le_bytes()).then_some(())
+}
+
+fn set_low(x: u64, v: u32) -> u64 {
+    x & !0xffff_ffff | v as u64
+}
+
+fn set_high(x: u64, v: u32) -> u64 {
+    x & 0xffff_ffff | (v as u64) << 32
+}
+
+/// driver written state, cleared by a reset
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Regs {
+    status: u32,
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    page_size: u32,
+    queue_sel: u32,
+    interrupt_status: u32,
+    num: u32,
+    align: u32,
+    pfn: u32,
+    ready: bool,
+    desc: u64,
+    avail: u64,
+    used: u64,
+    last_avail: u16,
+    notified: bool,
+}
+
+/// a virtio-mmio block device with one request queue. requests are
+/// served when the queue is notified.
+pub struct VirtioBlk {
+    pub disk: Disk,
+    pub irq: IrqLine,
+    /// version 1 of the register interface, queues placed by page number
+    pub legacy: bool,
+    regs: Regs,
+}
+
+impl VirtioBlk {
+    pub const SIZE: u64 = 0x1000;
+
+    pub fn new(disk: Disk, legacy: bool) -> VirtioBlk {
+        VirtioBlk { disk, irq: IrqLine::new(), legacy, regs: Regs::default() }
+    }
+
+    /// size in sectors
+    pub fn capacity(&self) -> u64 {
+        self.disk.len() / SECTOR
+    }
+
+    pub fn features(&self) -> u64 {
+        if self.legacy { F_BLK_FLUSH } else { F_BLK_FLUSH | F_VERSION_1 }
+    }
+
+    pub fn driver_features(&self) -> u64 {
+        self.regs.driver_features
+    }
+
+    fn config(&self) -> [u8; 24] {
+        let mut config = [0; 24];
+        config[..8].copy_from_slice(&self.capacity().to_le_bytes());
+        config[20..].copy_from_slice(&(SECTOR as u32).to_le_bytes());
+        config
+    }
+
+    /// queue addresses of the legacy interface from its page number
+    fn place_legacy(&mut self) {
+        let r = &mut self.regs;
+        let align = (r.align as u64).max(1);
+        r.desc = r.pfn as u64 * r.page_size as u64;
+        r.avail = r.desc + 16 * r.num as u64;
+        r.used = (r.avail + 6 + 2 * r.num as u64).div_ceil(align) * align;
+        r.ready = r.pfn != 0;
+    }
+
+    /// the queue addresses come from the guest, any of their sums may wrap
+    fn process(&mut self, bus: &mut Bus) -> Option<()> {
+        let num = self.regs.num as u16;
+        let (avail, used) = (self.regs.avail, self.regs.used);
+        let avail_idx = read_u16(bus, avail.checked_add(2)?)?;
+        while self.regs.last_avail != avail_idx {
+            let slot = (self.regs.last_avail % num) as u64;
+            let head = read_u16(bus, avail.checked_add(4 + 2 * slot)?)?;
+            let len = self.request(bus, head)?;
+
+            let used_idx = read_u16(bus, used.checked_add(2)?)?;
+            let elem = used.checked_add(4 + 8 * (used_idx % num) as u64)?;
+            write_u32(bus, elem, head as u32)?;
+            write_u32(bus, elem.checked_add(4)?, len)?;
+            write_u16(bus, used.checked_add(2)?, used_idx.wrapping_add(1))?;
+            self.regs.last_avail = self.regs.last_avail.wrapping_add(1);
+            self.regs.interrupt_status |= 1;
+        }
+        Some(())
+    }
+
+    /// serve the chain at `head`, the number of bytes written back
+    fn request(&mut self, bus: &mut Bus, head: u16) -> Option<u32> {
+        let mut readable = vec![];
+        let mut writable = vec![];
+        let mut i = head;
+        for _ in 0..self.regs.num {
+            if i as u32 >= self.regs.num {
+                return None;
+            }
+            let desc = self.regs.desc.checked_add(16 * i as u64)?;
+            let addr = read_u64(bus, desc)?;
+            let len = read_u32(bus, desc.checked_add(8)?)?;
+            let flags = read_u16(bus, desc.checked_add(12)?)?;
+            if flags & DESC_WRITE != 0 {
+                writable.push((addr, len as u64));
+            } else {
+                // no request reads more than a header and the whole disk
+                if readable.len() as u64 + len as u64 > 16 + self.capacity() * SECTOR {
+                    return None;
+                }
+                readable.extend(bus.read_bytes(addr, len as usize)?);
+            }
+            if flags & DESC_NEXT == 0 {
+                break;
+            }
+            i = read_u16(bus, desc.checked_add(14)?)?;
+        }
+        if readable.len() < 16 {
+            return None;
+        }
+        let ty = u32::from_le_bytes(readable[0..4].try_into().unwrap());
+        let sector = u64::from_le_bytes(readable[8..16].try_into().unwrap());
+        // the status byte ends the last writable buffer
+        if writable.last()?.1 == 0 {
+            return None;
+        }
+        let data_len = writable.iter().map(|(_, len)| len).sum::<u64>() - 1;
+        let in_range = |len: u64| sector.checked_mul(SECTOR)
+            .and_then(|start| start.checked_add(len))
+            .is_some_and(|end| end <= self.capacity() * SECTOR);
+
+        let mut data = vec![];
+        let status = match ty {
+            BLK_T_IN if in_range(data_len) => {
+                data = vec![0; data_len as usize];
+                match self.disk.read_at(sector * SECTOR, &mut data) {
+                    Ok(()) => BLK_S_OK,
+                    Err(_) => BLK_S_IOERR,
+                }
+            }
+            BLK_T_OUT if in_range(readable.len() as u64 - 16) => {
+                match self.disk.write_at(sector * SECTOR, &readable[16..]) {
+                    Ok(()) => BLK_S_OK,
+                    Err(_) => BLK_S_IOERR,
+                }
+            }
+            BLK_T_IN | BLK_T_OUT => BLK_S_IOERR,
+            BLK_T_FLUSH => match self.disk.flush() {
+                Ok(()) => BLK_S_OK,
+                Err(_) => BLK_S_IOERR,
+            },
+            BLK_T_GET_ID => {
+                data = ID.iter().copied().take(data_len as usize).collect();
+                BLK_S_OK
+            }
+            _ => BLK_S_UNSUPP,
+        };
+        if status != BLK_S_OK {
+            data.clear();
+        }
+
+        // scatter the data, then the status into the last byte
+        let mut done = 0;
+        for &(addr, len) in &writable {
+            let n = (len as usize).min(data.len() - done);
+            if !bus.write_bytes(addr, &data[done..done + n]) {
+                return None;
+            }
+            done += n;
+        }
+        let (addr, len) = *writable.last()?;
+        bus.write_bytes(addr.checked_add(len - 1)?, &[status]).then_some(data.len() as u32 + 1)
+    }
+}
+
+impl Device for VirtioBlk {
+    fn read(&mut self, offset: u64, size: u8) -> Option<u64> {
+        if offset >= CONFIG {
+            let config = self.config();
+            let start = (offset - CONFIG) as usize;
+            let mut bytes = [0; 8];
+            bytes[..size as usize].copy_from_slice(config.get(start..start + size as usize)?);
+            return Some(u64::from_le_bytes(bytes));
+        }
+        if size != 4 {
+            return None;
+        }
+        let r = &self.regs;
+        let queue = r.queue_sel == 0;
+        let v = match offset {
+            MAGIC_VALUE => MAGIC,
+            VERSION => if self.legacy { 1 } else { 2 },
+            DEVICE_ID => DEVICE_BLK,
+            VENDOR_ID => VENDOR,
+            DEVICE_FEATURES => match r.device_features_sel {
+                0 => self.features() as u32,
+                1 => (self.features() >> 32) as u32,
+                _ => 0,
+            },
+            QUEUE_NUM_MAX if queue => QUEUE_MAX,
+            QUEUE_NUM_MAX => 0,
+            QUEUE_PFN if queue => r.pfn,
+            QUEUE_READY if queue => r.ready as u32,
+            QUEUE_PFN | QUEUE_READY => 0,
+            INTERRUPT_STATUS => r.interrupt_status,
+            STATUS => r.status,
+            CONFIG_GENERATION => 0,
+            _ => return None,
+        };
+        Some(v as u64)
+    }
+
+    fn write(&mut self, offset: u64, size: u8, value: u64) -> bool {
+        if size != 4 {
+            return false;
+        }
+        let v = value as u32;
+        let queue = self.regs.queue_sel == 0;
+        let r = &mut self.regs;
+        match offset {
+            DEVICE_FEATURES_SEL => r.device_features_sel = v,
+            DRIVER_FEATURES => match r.driver_features_sel {
+                0 => r.driver_features = set_low(r.driver_features, v),
+                1 => r.driver_features = set_high(r.driver_features, v),
+                _ => {}
+            },
+            DRIVER_FEATURES_SEL => r.driver_features_sel = v,
+            GUEST_PAGE_SIZE => r.page_size = v,
+            QUEUE_SEL => r.queue_sel = v,
+            // writes to queues that do not exist are ignored
+            QUEUE_NUM | QUEUE_ALIGN | QUEUE_PFN | QUEUE_READY | QUEUE_DESC_LOW | QUEUE_DESC_HIGH |
+            QUEUE_DRIVER_LOW | QUEUE_DRIVER_HIGH | QUEUE_DEVICE_LOW | QUEUE_DEVICE_HIGH if !queue => {}
+            QUEUE_NUM => r.num = v.min(QUEUE_MAX),
+            QUEUE_ALIGN => r.align = v,
+            QUEUE_PFN => {
+                r.pfn = v;
+                self.place_legacy();
+            }
+            QUEUE_READY => r.ready = v & 1 != 0,
+            QUEUE_DESC_LOW => r.desc = set_low(r.desc, v),
+            QUEUE_DESC_HIGH => r.desc = set_high(r.desc, v),
+            QUEUE_DRIVER_LOW => r.avail = set_low(r.avail, v),
+            QUEUE_DRIVER_HIGH => r.avail = set_high(r.avail, v),
+            QUEUE_DEVICE_LOW => r.used = set_low(r.used, v),
+            QUEUE_DEVICE_HIGH => r.used = set_high(r.used, v),
+            QUEUE_NOTIFY => r.notified = v == 0,
+            INTERRUPT_ACK => r.interrupt_status &= !v,
+            STATUS if v == 0 => self.regs = Regs::default(),
+            STATUS => r.status = v,
+            _ => return false,
+        }
+        self.irq.set(self.regs.interrupt_status != 0);
+        true
+    }
+
+    fn wants_dma(&self) -> bool {
+        self.regs.notified
+    }
+
+    fn dma(&mut self, bus: &mut Bus) {
+        self.regs.notified = false;
+        let r = &self.regs;
+        if r.status & STATUS_DRIVER_OK == 0 || !r.ready || r.num == 0 {
+            return;
+        }
+        if self.process(bus).is_none() {
+            self.regs.status |= STATUS_NEEDS_RESET;
+        }
+        self.irq.set(self.regs.interrupt_status != 0);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::emu::memory::Memory;
+    use crate::isa::riscv::bare::memory_layout::{KERNBASE, VIRTIO0};
+    use super::*;
+
+    const DESC: u64 = KERNBASE + 0x4000;
+    const HEADER: u64 = KERNBASE + 0x1000;
+    const BUF: u64 = KERNBASE + 0x2000;
+    const STATUS_BYTE: u64 = KERNBASE + 0x3000;
+
+    fn image() -> Vec<u8> {
+        (0..4 * SECTOR).map(|i| (i / SECTOR * 0x10 + i % 7) as u8).collect()
+    }
+
+    fn blk_bus(disk: Disk, legacy: bool) -> Bus {
+        let mut bus = Bus::new();
+        bus.add_ram("ram", KERNBASE, 0x10000);
+        assert!(bus.map("virtio0", VIRTIO0, VirtioBlk::SIZE, VirtioBlk::new(disk, legacy)));
+        bus
+    }
+
+    fn reg(bus: &mut Bus, r: u64) -> u64 {
+        bus.load(VIRTIO0 + r, 4).unwrap()
+    }
+
+    fn set(bus: &mut Bus, r: u64, v: u64) {
+        bus.store(VIRTIO0 + r, 4, v).unwrap()
+    }
+
+    /// status and feature negotiation, as xv6's virtio_disk_init
+    fn negotiate(bus: &mut Bus) {
+        assert_eq!(reg(bus, MAGIC_VALUE), MAGIC as u64);
+        assert_eq!(reg(bus, DEVICE_ID), DEVICE_BLK as u64);
+        assert_eq!(reg(bus, VENDOR_ID), VENDOR as u64);
+        set(bus, STATUS, 0);
+        let mut status = (STATUS_ACKNOWLEDGE | STATUS_DRIVER) as u64;
+        set(bus, STATUS, status);
+        let features = reg(bus, DEVICE_FEATURES);
+        set(bus, DRIVER_FEATURES, features);
+        status |= STATUS_FEATURES_OK as u64;
+        set(bus, STATUS, status);
+        assert_eq!(reg(bus, STATUS), status);
+        set(bus, QUEUE_SEL, 0);
+        assert!(reg(bus, QUEUE_NUM_MAX) >= 8);
+        set(bus, QUEUE_NUM, 8);
+    }
+
+    fn driver_ok(bus: &mut Bus) {
+        let status = reg(bus, STATUS) | STATUS_DRIVER_OK as u64;
+        set(bus, STATUS, status);
+    }
+
+    fn desc(bus: &mut Bus, i: u64, addr: u64, len: u64, flags: u16, next: u16) {
+        let d = DESC + 16 * i;
+        bus.store(d, 8, addr).unwrap();
+        bus.store(d + 8, 4, len).unwrap();
+        bus.store(d + 12, 2, flags as u64).unwrap();
+        bus.store(d + 14, 2, next as u64).unwrap();
+    }
+
+    /// a three descriptor request as built by virtio_disk_rw, submitted
+    /// through avail slot `idx`
+    fn submit(bus: &mut Bus, avail: u64, idx: u64, ty: u32, sector: u64, len: u64) {
+        bus.store(HEADER, 4, ty as u64).unwrap();
+        bus.store(HEADER + 8, 8, sector).unwrap();
+        bus.store(STATUS_BYTE, 1, 0xff).unwrap();
+        let write = if ty == BLK_T_IN || ty == BLK_T_GET_ID { DESC_WRITE } else { 0 };
+        desc(bus, 0, HEADER, 16, DESC_NEXT, 1);
+        desc(bus, 1, BUF, len, write | DESC_NEXT, 2);
+        desc(bus, 2, STATUS_BYTE, 1, DESC_WRITE, 0);
+        notify(bus, avail, idx);
+    }
+
+    /// publish the chain at descriptor 0 through avail slot `idx`
+    fn notify(bus: &mut Bus, avail: u64, idx: u64) {
+        bus.store(avail + 4 + 2 * (idx % 8), 2, 0).unwrap();
+        bus.store(avail + 2, 2, idx + 1).unwrap();
+        set(bus, QUEUE_NOTIFY, 0);
+    }
+
+    /// a modern device with its queue set up and running, (avail, used)
+    fn modern() -> (Bus, u64, u64) {
+        let mut bus = blk_bus(Disk::Memory(image()), false);
+        assert_eq!(reg(&mut bus, VERSION), 2);
+        negotiate(&mut bus);
+        set(&mut bus, DEVICE_FEATURES_SEL, 1);
+        assert_eq!(reg(&mut bus, DEVICE_FEATURES), 1);
+        let avail = KERNBASE + 0x5000;
+        let used = KERNBASE + 0x6000;
+        set(&mut bus, QUEUE_DESC_LOW, DESC & 0xffff_ffff);
+        set(&mut bus, QUEUE_DESC_HIGH, DESC >> 32);
+        set(&mut bus, QUEUE_DRIVER_LOW, avail);
+        set(&mut bus, QUEUE_DEVICE_LOW, used);
+        set(&mut bus, QUEUE_READY, 1);
+        driver_ok(&mut bus);
+        (bus, avail, used)
+    }
+
+    #[test]
+    fn test_modern() {
+        let (mut bus, avail, used) = modern();
+        assert_eq!(bus.load(VIRTIO0 + CONFIG, 8), Ok(4));
+
+        let irq = bus.device::<VirtioBlk>("virtio0").unwrap().irq.clone();
+        submit(&mut bus, avail, 0, BLK_T_IN, 1, SECTOR);
+        assert_eq!(bus.load(used + 2, 2), Ok(1));
+        assert_eq!(bus.load(used + 4, 4), Ok(0));
+        assert_eq!(bus.load(used + 8, 4), Ok(SECTOR + 1));
+        assert_eq!(bus.load(STATUS_BYTE, 1), Ok(BLK_S_OK as u64));
+        assert_eq!(bus.read_bytes(BUF, SECTOR as usize).unwrap(), image()[SECTOR as usize..2 * SECTOR as usize]);
+        assert!(irq.is_high());
+        assert_eq!(reg(&mut bus, INTERRUPT_STATUS), 1);
+        set(&mut bus, INTERRUPT_ACK, 1);
+        assert!(!irq.is_high());
+
+        bus.write_bytes(BUF, &[0xab; SECTOR as usize]);
+        submit(&mut bus, avail, 1, BLK_T_OUT, 3, SECTOR);
+        assert_eq!(bus.load(used + 2, 2), Ok(2));
+        assert_eq!(bus.load(used + 16, 4), Ok(1));
+        assert_eq!(bus.load(STATUS_BYTE, 1), Ok(BLK_S_OK as u64));
+        let Disk::Memory(disk) = &bus.device::<VirtioBlk>("virtio0").unwrap().disk else { unreachable!() };
+        assert!(disk[3 * SECTOR as usize..].iter().all(|&b| b == 0xab));
+
+        // past the end of the disk
+        submit(&mut bus, avail, 2, BLK_T_IN, 4, SECTOR);
+        assert_eq!(bus.load(STATUS_BYTE, 1), Ok(BLK_S_IOERR as u64));
+        submit(&mut bus, avail, 3, 0x99, 0, SECTOR);
+        assert_eq!(bus.load(STATUS_BYTE, 1), Ok(BLK_S_UNSUPP as u64));
+        submit(&mut bus, avail, 4, BLK_T_GET_ID, 0, 20);
+        assert_eq!(bus.read_bytes(BUF, ID.len()).unwrap(), ID);
+        assert_eq!(bus.load(used + 2, 2), Ok(5));
+    }
+
+    #[test]
+    fn test_bad_chain() {
+        // an empty status buffer
+        let (mut bus, avail, used) = modern();
+        bus.store(HEADER, 4, BLK_T_IN as u64).unwrap();
+        desc(&mut bus, 0, HEADER, 16, DESC_NEXT, 1);
+        desc(&mut bus, 1, BUF, SECTOR, DESC_WRITE | DESC_NEXT, 2);
+        desc(&mut bus, 2, STATUS_BYTE, 0, DESC_WRITE, 0);
+        notify(&mut bus, avail, 0);
+        assert_ne!(reg(&mut bus, STATUS) & STATUS_NEEDS_RESET as u64, 0);
+        assert_eq!(bus.load(used + 2, 2), Ok(0));
+
+        // a write larger than the disk is refused before it is read
+        let (mut bus, avail, used) = modern();
+        submit(&mut bus, avail, 0, BLK_T_OUT, 0, 0xffff_ffff);
+        assert_ne!(reg(&mut bus, STATUS) & STATUS_NEEDS_RESET as u64, 0);
+        assert_eq!(bus.load(used + 2, 2), Ok(0));
+
+        // a status buffer wrapping around the address space, the request
+        // type is unsupported so no data is scattered before the status
+        let (mut bus, avail, used) = modern();
+        bus.store(HEADER, 4, 0x7f).unwrap();
+        desc(&mut bus, 0, HEADER, 16, DESC_NEXT, 1);
+        desc(&mut bus, 1, u64::MAX - 3, 16, DESC_WRITE, 0);
+        notify(&mut bus, avail, 0);
+        assert_ne!(reg(&mut bus, STATUS) & STATUS_NEEDS_RESET as u64, 0);
+        assert_eq!(bus.load(used + 2, 2), Ok(0));
+    }
+
+    #[test]
+    fn test_legacy_file() {
+        let path = std::env::temp_dir().join(format!("lyuu-virtio-{}.img", std::process::id()));
+        std::fs::write(&path, image()).unwrap();
+        let mut bus = blk_bus(Disk::open(&path).unwrap(), true);
+        assert_eq!(reg(&mut bus, VERSION), 1);
+        negotiate(&mut bus);
+        set(&mut bus, GUEST_PAGE_SIZE, 4096);
+        set(&mut bus, QUEUE_ALIGN, 4096);
+        set(&mut bus, QUEUE_PFN, DESC >> 12);
+        driver_ok(&mut bus);
+
+        // avail follows the descriptors, used is on the next page
+        let avail = DESC + 16 * 8;
+        let used = DESC + 0x1000;
+        bus.write_bytes(BUF, &[0x5a; SECTOR as usize]);
+        submit(&mut bus, avail, 0, BLK_T_OUT, 0, SECTOR);
+        submit(&mut bus, avail, 1, BLK_T_FLUSH, 0, 0);
+        assert_eq!(bus.load(used + 2, 2), Ok(2));
+        assert_eq!(bus.load(used + 12, 4), Ok(0));
+        assert_eq!(bus.load(used + 16, 4), Ok(1));
+        assert_eq!(bus.load(STATUS_BYTE, 1), Ok(BLK_S_OK as u64));
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(data[..SECTOR as usize].iter().all(|&b| b == 0x5a));
+        assert_eq!(data[SECTOR as usize..], image()[SECTOR as usize..]);
+
+        // a reset forgets the queue
+        set(&mut bus, STATUS, 0);
+        assert_eq!(reg(&mut bus, QUEUE_PFN), 0);
+        assert_eq!(reg(&mut bus, STATUS), 0);
+    }
+}
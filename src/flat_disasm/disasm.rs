@@ -3,7 +3,7 @@ use super::{OpCode::*, FlatRiscV, *};
 
 
 //////////////////////////////
-/// impl
+// impl
 
 pub fn flat_disasm(src: &[u8], is_32bit: bool) -> Option<(FlatRiscV, usize)> {
   if is_32bit {
@@ -40,7 +40,7 @@ pub fn flat_disasm(src: &[u8], is_32bit: bool) -> Option<(FlatRiscV, usize)> {
     b"?????????????????000_?????_0100011", stype -> store.b;
     b"?????????????????001_?????_0100011", stype -> store.h;
     b"?????????????????010_?????_0100011", stype -> store.w;
-    b"?????????????????011_?????_0000011", itype -> store.d;
+    b"?????????????????011_?????_0100011", stype -> store.d;
     b"?????????????????000_?????_0010011", itype -> iop.add;
     b"?????????????????010_?????_0010011", itype -> iop.slt;
     b"?????????????????011_?????_0010011", itype -> iop.sltu;
@@ -70,6 +70,10 @@ pub fn flat_disasm(src: &[u8], is_32bit: bool) -> Option<(FlatRiscV, usize)> {
     b"00000000000000000001_00000_0001111", itype -> fence.i;
     b"00000000000000000000_00000_1110011", itype -> excep.call;
     b"00000000000100000000_00000_1110011", itype -> excep.ret;
+    b"00010000001000000000_00000_1110011", itype -> excep.sret;
+    b"00010000010100000000_00000_1110011", itype -> excep.wfi;
+    b"00110000001000000000_00000_1110011", itype -> excep.mret;
+    b"0001001??????????000_00000_1110011", rtype -> fence.vma;
     b"?????????????????001_?????_1110011", itype -> csr.rw;
     b"?????????????????010_?????_1110011", itype -> csr.rs;
     b"?????????????????011_?????_1110011", itype -> csr.rc;
@@ -77,16 +81,45 @@ pub fn flat_disasm(src: &[u8], is_32bit: bool) -> Option<(FlatRiscV, usize)> {
     b"?????????????????110_?????_1110011", itype -> csr.rsi;
     b"?????????????????111_?????_1110011", itype -> csr.rci;
   );
-  None
+  crate::disassembly::vendor::decode_flat(src, is_32bit)
 }
 
 
 mod test {
   #[test]
+  #[allow(clippy::unusual_byte_groupings)]
   fn test1() {
     use super::flat_disasm;
     let src = 0b11111111111111111111_00000_0110111_u32.to_le_bytes();
     let _r = flat_disasm(&src, false).unwrap();
     // println!("out: {:?}", r);
   }
+
+  #[test]
+  fn test_store_d() {
+    use super::{flat_disasm, OpCode};
+    use crate::isa::riscv::{Reg, RiscV, StoreType};
+    // sd ra, 8(sp) has the store opcode, not the load one
+    let (r, len) = flat_disasm(&0x00113423u32.to_le_bytes(), false).unwrap();
+    assert_eq!((r.opcode, len), (OpCode::store, 32));
+    assert_eq!(RiscV::try_from(r).ok(), Some(RiscV::Store(StoreType::Double, Reg(2), Reg(1), 8)));
+  }
+
+  #[test]
+  fn test_branch_imm() {
+    use super::flat_disasm;
+    // beq zero, zero, -4
+    assert_eq!(flat_disasm(&0xfe000ee3u32.to_le_bytes(), false).unwrap().0.imm, -4i32 as u32);
+    // beq zero, zero, 2048, bit 11 is not the sign
+    assert_eq!(flat_disasm(&0x000000e3u32.to_le_bytes(), false).unwrap().0.imm, 2048);
+  }
+
+  #[test]
+  fn test_jal_imm() {
+    use super::flat_disasm;
+    // jal zero, 0x80000, bit 19 is not the sign
+    assert_eq!(flat_disasm(&0x0008006fu32.to_le_bytes(), false).unwrap().0.imm, 0x80000);
+    // jal zero, -4
+    assert_eq!(flat_disasm(&0xffdff06fu32.to_le_bytes(), false).unwrap().0.imm, -4i32 as u32);
+  }
 }
\ No newline at end of file
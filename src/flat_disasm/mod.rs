@@ -7,7 +7,7 @@ pub mod disasm;
 
 
 ////////////////////////////////
-/// struct define
+// struct define
 
 
 #[allow(non_camel_case_types)]
@@ -27,6 +27,7 @@ pub enum OpCode {
   fence,
   excep,
   csr,
+  custom,
 }
 
 use std::fmt::Display;
@@ -67,7 +68,10 @@ impl FlatRiscV {
     }
     multi_match_frv!(self,
       op,
-      excep.ret => { self.imm = 0; }
+      excep.ret,
+      excep.sret,
+      excep.wfi,
+      excep.mret => { self.imm = 0; }
     );
   }
 }
@@ -75,6 +79,9 @@ impl FlatRiscV {
 
 impl Display for FlatRiscV {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if let Some(inst) = self.custom() {
+      return write!(f, "{}", inst);
+    }
     if self.imm == 0 {
       write!(f, "{:?}.{:b}  x{}, x{}, x{}", self.opcode, self.ext_op, self.rd, self.rs1, self.rs2)
     } else {
@@ -129,10 +136,15 @@ flag_gen!(and , 0b11100);
 
 // fence.i
 flag_gen!(i, 0b1);
+// sfence.vma
+flag_gen!(vma, 0b10);
 
 // ecall
 flag_gen!(call, 0b0);
 flag_gen!(ret, 0b1);  // break, break is rust keyword
+flag_gen!(sret, 0x102);
+flag_gen!(wfi , 0x105);
+flag_gen!(mret, 0x302);
 
 
 // csr
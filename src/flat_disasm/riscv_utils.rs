@@ -1,7 +1,7 @@
 
 
 /////////////////////////////
-/// part of inst
+// part of inst
 
 /*
 macro_rules! opcode {
@@ -83,7 +83,7 @@ macro_rules! jimm {
 
 
 /////////////////////////////
-/// synthesis instruction
+// synthesis instruction
 
 
 /*
@@ -114,7 +114,7 @@ macro_rules! inst_temp {
 
 
 //////////////////////////////////////////
-/// get and synthesis instruction
+// get and synthesis instruction
 
 #[macro_export]
 macro_rules! rtype {
@@ -160,7 +160,7 @@ macro_rules! btype {
   ($src:expr, $code:ident, $ext_op:expr) => {{
     let rs1: u8 = rs1!($src);
     let rs2: u8 = rs2!($src);
-    let imm: u32 = sext!(bimm!($src), 12, 32);
+    let imm: u32 = sext!(bimm!($src), 13, 32);
     inst_temp!($code, $ext_op, rs1, rs2, imm)
   }};
 }
@@ -169,7 +169,7 @@ macro_rules! btype {
 macro_rules! jtype {
   ($src:expr, $code:ident, $ext_op:expr) => {{
     let rd: u8 = rd!($src);
-    let imm: u32 = sext!(jimm!($src), 20, 32);
+    let imm: u32 = sext!(jimm!($src), 21, 32);
     inst_temp!($code, $ext_op, rd, imm)
   }};
 }
@@ -252,7 +252,7 @@ pub fn jtype(i: u32) -> (u8, u32) {
 
 
 ////////////////////////////
-/// match macro
+// match macro
 
 #[macro_export]
 macro_rules! match_ext {
@@ -11,7 +11,7 @@ macro_rules! bitmask {
 #[macro_export]
 macro_rules! bits {
   ($x:expr, $hi:expr, $lo:expr) => {{
-    debug_assert!($hi > $lo);
+    debug_assert!($hi >= $lo);
     ($x >> $lo) & bitmask!($hi - $lo + 1)
   }};
 }
@@ -77,6 +77,25 @@ pub fn bitpat(bitpat: &[u8], src: &[u8]) -> (bool, usize) {
   (true, bitpatlen)
 }
 
+/// compile a pattern of at most 32 bits into (mask, match, bits_length),
+/// `x & mask == match` agrees with `bitpat` on the low bits_length bits
+pub fn bitpat_mask(bitpat: &[u8]) -> (u32, u32, usize) {
+  let bits = bitpat.iter()
+    .filter(|x| **x != b'_' && **x != b' ' && **x != b'\t')
+    .collect::<Box<[_]>>();
+  assert!(bits.len() <= 32, "bitpat longer than 32 bits");
+  let (mut mask, mut value) = (0u32, 0u32);
+  for (offset, bit) in bits.iter().rev().enumerate() {
+    match **bit {
+      b'?' => {},
+      b'1' => { mask |= 1 << offset; value |= 1 << offset },
+      b'0' => mask |= 1 << offset,
+      _ => panic!("invalid bitpat char"),
+    }
+  }
+  (mask, value, bits.len())
+}
+
 
 /*
 /// bitpat(bitpat, sources) -> Result<(bool, bits_length), error string>
@@ -113,6 +132,13 @@ mod tests {
     assert_eq!(bits!(0b1001001, 4, 2), 0b010);
   }
 
+  #[test]
+  fn test_bits_single() {
+    // hi == lo selects one bit
+    assert_eq!(bits!(0b1000, 3, 3), 1);
+    assert_eq!(bits!(0b1000, 2, 2), 0);
+  }
+
   #[test]
   fn test_bitpat() {
     use super::bitpat;
@@ -120,6 +146,18 @@ mod tests {
     assert_eq!(bitpat(b"00001001", &0b0001001u8.to_le_bytes()), (true, 8));
   }
 
+  #[test]
+  fn test_bitpat_mask() {
+    use super::{bitpat, bitpat_mask};
+    let pat = b"0000000??????????000_?????_0101011";
+    let (mask, value, len) = bitpat_mask(pat);
+    assert_eq!((mask, value, len), (0xfe00707f, 0x0000002b, 32));
+    for code in [0x00c5852bu32, 0x00c5952b, 0x02c5852b] {
+      assert_eq!(code & mask == value, bitpat(pat, &code.to_le_bytes()).0);
+    }
+    assert_eq!(bitpat_mask(b"1?_01"), (0b1011, 0b1001, 4));
+  }
+
   #[test]
   fn test_sext() {
     assert_eq!(sext!(0b0111_11111111, 12, 16), 0b111_11111111);
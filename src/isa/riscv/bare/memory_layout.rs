@@ -1,6 +1,10 @@
 
 
 
+/// qemu's sifive test device, writes power off or reset the machine
+pub const VIRT_TEST: u64 = 0x100000;
+
+
 pub const UART0: u64 = 0x10000000;
 pub const UART0_IRQ: u64 = 10;
 
@@ -50,3 +54,23 @@ pub const fn plic_sclaim(hart: u64) -> u64 {
 pub const KERNBASE: u64 = 0x80000000;
 pub const PHYSTOP: u64 = KERNBASE + 128*1024*1024;
 
+
+
+/// `(name, base, size)` of the named spans above, most specific first
+pub const REGIONS: [(&str, u64, u64); 8] = [
+    ("CLINT_MTINE", CLINT_MTINE, 8),
+    ("CLINT", CLINT, 0x10000),
+    ("PLIC_PENDING", PLIC_PENDING, 0x80),
+    ("PLIC", PLIC, 0x4000000),
+    ("UART0", UART0, 0x100),
+    ("VIRTIO0", VIRTIO0, 0x1000),
+    ("VIRT_TEST", VIRT_TEST, 0x1000),
+    ("KERNBASE", KERNBASE, PHYSTOP - KERNBASE),
+];
+
+/// the span holding `addr` and the offset into it
+pub fn region_of(addr: u64) -> Option<(&'static str, u64)> {
+    REGIONS.iter()
+        .find(|(_, base, size)| *base <= addr && addr - base < *size)
+        .map(|(name, base, _)| (*name, addr - base))
+}
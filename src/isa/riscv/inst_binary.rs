@@ -4,6 +4,7 @@ use modular_bitfield::prelude::*;
 
 
 #[bitfield(bits = 32)]
+#[derive(Default)]
 pub struct RType {
     pub opcode: B7,
     pub rd: B5,
@@ -14,6 +15,19 @@ pub struct RType {
 }
 
 #[bitfield(bits = 32)]
+#[derive(Default)]
+pub struct R4Type {
+    pub opcode: B7,
+    pub rd: B5,
+    pub funct3: B3,
+    pub rs1: B5,
+    pub rs2: B5,
+    pub fmt: B2,
+    pub rs3: B5,
+}
+
+#[bitfield(bits = 32)]
+#[derive(Default)]
 pub struct IType {
     pub opcode: B7,
     pub rd: B5,
@@ -28,11 +42,11 @@ impl IType {
     }
 
     pub fn shamt32(&self) -> u8 {
-        (self.imm() | 0b11111) as u8
+        (self.imm() & 0b11111) as u8
     }
 
     pub fn shamt(&self) -> u8 {
-        (self.imm() | 0b111111) as u8
+        (self.imm() & 0b111111) as u8
     }
 
     pub fn sext_offset(&self) -> i16 {
@@ -57,6 +71,7 @@ impl IType {
 }
 
 #[bitfield(bits = 32)]
+#[derive(Default)]
 pub struct SType {
     pub opcode: B7,
     pub imm4_0: B5,
@@ -79,6 +94,7 @@ impl SType {
 }
 
 #[bitfield(bits = 32)]
+#[derive(Default)]
 pub struct BType {
     pub opcode: B7,
     pub imm11: B1,
@@ -107,6 +123,7 @@ impl BType {
 }
 
 #[bitfield(bits = 32)]
+#[derive(Default)]
 pub struct UType {
     pub opcode: B7,
     pub rd: B5,
@@ -114,6 +131,7 @@ pub struct UType {
 }
 
 #[bitfield(bits = 32)]
+#[derive(Default)]
 pub struct JType {
     pub opcode: B7,
     pub rd: B5,
@@ -144,6 +162,7 @@ impl JType {
 }
 
 #[test]
+#[allow(clippy::unusual_byte_groupings)]
 fn bit_field_test() {
     let inst: u32 = 0b00000000000000000000_00001_0110111;
     let inst0 = UType::from_bytes(inst.to_ne_bytes());
@@ -151,3 +170,10 @@ fn bit_field_test() {
     assert_eq!(inst0.opcode(), 0b0110111);
     assert_eq!(inst0.rd(), 0b00001);
 }
+
+#[test]
+fn shamt_test() {
+    // srai a0, a1, 3 and sraiw a0, a1, 3, funct6/funct7 sit above the shamt
+    assert_eq!(IType::from_bytes(0x4035d513u32.to_le_bytes()).shamt(), 3);
+    assert_eq!(IType::from_bytes(0x4035d51bu32.to_le_bytes()).shamt32(), 3);
+}
@@ -0,0 +1,732 @@
+use std::fmt::Display;
+use std::ops::{BitOr, BitOrAssign};
+
+use crate::disassembly::vendor::{self, Registry};
+use crate::flat_disasm::{self, FlatRiscV, OpCode};
+
+use super::*;
+
+
+/// encoding format. compressed base instructions are expanded by the
+/// decoder and report the format of their 32 bit equivalent, see
+/// `disassembly::riscv_c::compressed_format` for the raw 16 bit form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Format {
+    R,
+    R4,
+    I,
+    S,
+    B,
+    U,
+    J,
+    CR,
+    CI,
+    CSS,
+    CIW,
+    CL,
+    CS,
+    CA,
+    CB,
+    CJ,
+    // zcb
+    CLB,
+    CSB,
+    CLH,
+    CSH,
+    CU,
+    // zcmp, zcmt
+    CMPP,
+    CMMV,
+    CMJT,
+    Custom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Extension {
+    I,
+    M,
+    A,
+    F,
+    D,
+    Q,
+    Zfh,
+    Zfhmin,
+    Zicsr,
+    Zifencei,
+    Zba,
+    Zbb,
+    Zcmp,
+    Zcmt,
+    /// registry id of a vendor extension
+    Vendor(u16),
+}
+
+impl Display for Extension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Extension::Vendor(id) => match vendor::extension(*id) {
+                Some(e) => write!(f, "{}", e.name()),
+                None => write!(f, "vendor{}", id),
+            },
+            x => write!(f, "{}", format!("{:?}", x).to_lowercase()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RegClass {
+    Int,
+    Float,
+    Vector,
+    Csr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    Dest,
+    Src,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OperandKind {
+    Reg(RegClass, u16),
+    /// `value` is the operand as held by the decoded instruction, `bits` the
+    /// width of the encoded field
+    Imm { value: i64, bits: u8, signed: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Operand {
+    pub role: Role,
+    pub kind: OperandKind,
+}
+
+impl Operand {
+    fn dst(class: RegClass, num: u16) -> Operand {
+        Operand { role: Role::Dest, kind: OperandKind::Reg(class, num) }
+    }
+
+    fn src(class: RegClass, num: u16) -> Operand {
+        Operand { role: Role::Src, kind: OperandKind::Reg(class, num) }
+    }
+
+    fn imm(value: i64, bits: u8, signed: bool) -> Operand {
+        Operand { role: Role::Src, kind: OperandKind::Imm { value, bits, signed } }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Flags(pub u16);
+
+impl Flags {
+    pub const NONE: Flags       = Flags(0);
+    /// conditional branch
+    pub const BRANCH: Flags     = Flags(1 << 0);
+    /// unconditional control transfer
+    pub const JUMP: Flags       = Flags(1 << 1);
+    pub const CALL: Flags       = Flags(1 << 2);
+    pub const RETURN: Flags     = Flags(1 << 3);
+    /// target comes from a register or memory
+    pub const INDIRECT: Flags   = Flags(1 << 4);
+    pub const LOAD: Flags       = Flags(1 << 5);
+    pub const STORE: Flags      = Flags(1 << 6);
+    pub const ATOMIC: Flags     = Flags(1 << 7);
+    pub const CSR: Flags        = Flags(1 << 8);
+    pub const MAY_TRAP: Flags   = Flags(1 << 9);
+    pub const PRIVILEGED: Flags = Flags(1 << 10);
+    pub const FENCE: Flags      = Flags(1 << 11);
+
+    pub fn contains(&self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Flags;
+
+    fn bitor(self, rhs: Flags) -> Flags {
+        Flags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Flags {
+    fn bitor_assign(&mut self, rhs: Flags) {
+        self.0 |= rhs.0;
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstInfo {
+    pub mnemonic: String,
+    pub format: Format,
+    pub extension: Extension,
+    pub operands: Vec<Operand>,
+    pub flags: Flags,
+}
+
+/// x1 and x5 are the link registers of the psabi
+#[inline]
+pub fn is_link(reg: Reg) -> bool {
+    reg.0 == 1 || reg.0 == 5
+}
+
+impl Csr {
+    /// lowest privilege level allowed to access the csr, 0 = user
+    pub fn privilege(&self) -> u8 {
+        ((self.0 >> 8) & 0b11) as u8
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.0 >> 10 == 0b11
+    }
+}
+
+fn fp_extension(prec: FPrec) -> Extension {
+    match prec {
+        FPrec::S => Extension::F,
+        FPrec::D => Extension::D,
+        FPrec::H => Extension::Zfh,
+        FPrec::Q => Extension::Q,
+    }
+}
+
+fn int(reg: Reg) -> u16 {
+    reg.0 as u16
+}
+
+fn fp(reg: FReg) -> u16 {
+    reg.0 as u16
+}
+
+impl RiscV {
+    pub fn format(&self) -> Format {
+        match self {
+            RiscV::Lui(..) | RiscV::Auipc(..) => Format::U,
+            RiscV::Jal(..) => Format::J,
+            RiscV::Branch(..) => Format::B,
+            RiscV::Store(..) | RiscV::FStore(..) => Format::S,
+            RiscV::Jalr(..) |
+            RiscV::Load(..) |
+            RiscV::OpI(..) |
+            RiscV::OpIW(..) |
+            RiscV::Fence(..) |
+            RiscV::EOp(..) |
+            RiscV::CsrOp(..) |
+            RiscV::CsrOpI(..) |
+            RiscV::FLoad(..) |
+            RiscV::Ext(ExtType::SextB | ExtType::SextH, ..) => Format::I,
+            RiscV::Op(..) |
+            RiscV::OpW(..) |
+            RiscV::SFenceVma(..) |
+            RiscV::MulOp(..) |
+            RiscV::MulOpW(..) |
+            RiscV::Lr(..) |
+            RiscV::Sc(..) |
+            RiscV::Amo(..) |
+            RiscV::Ext(..) |
+            RiscV::FOp(..) |
+            RiscV::FSqrt(..) |
+            RiscV::FSgnj(..) |
+            RiscV::FMinMax(..) |
+            RiscV::FCmp(..) |
+            RiscV::FClass(..) |
+            RiscV::FCvt(..) |
+            RiscV::FCvtToInt(..) |
+            RiscV::FCvtFromInt(..) |
+            RiscV::FMvToInt(..) |
+            RiscV::FMvFromInt(..) => Format::R,
+            RiscV::FMa(..) => Format::R4,
+            RiscV::CmPush(..) | RiscV::CmPop(..) => Format::CMPP,
+            RiscV::CmMv(..) => Format::CMMV,
+            RiscV::CmJt(..) | RiscV::CmJalt(..) => Format::CMJT,
+            RiscV::Custom(..) => Format::Custom,
+        }
+    }
+
+    pub fn extension(&self) -> Extension {
+        match self {
+            RiscV::MulOp(..) | RiscV::MulOpW(..) => Extension::M,
+            RiscV::Lr(..) | RiscV::Sc(..) | RiscV::Amo(..) => Extension::A,
+            RiscV::Ext(ExtType::ZextW, ..) => Extension::Zba,
+            RiscV::Ext(..) => Extension::Zbb,
+            RiscV::Fence(IsFenceI(true), ..) => Extension::Zifencei,
+            RiscV::CsrOp(..) | RiscV::CsrOpI(..) => Extension::Zicsr,
+            RiscV::FLoad(FPrec::H, ..) |
+            RiscV::FStore(FPrec::H, ..) |
+            RiscV::FMvToInt(FPrec::H, ..) |
+            RiscV::FMvFromInt(FPrec::H, ..) |
+            RiscV::FCvt(FPrec::H, FPrec::S | FPrec::D, ..) |
+            RiscV::FCvt(FPrec::S | FPrec::D, FPrec::H, ..) => Extension::Zfhmin,
+            RiscV::FCvt(a, b, ..) => {
+                // the widest precision involved owns the conversion
+                let rank = |p: &FPrec| match p {
+                    FPrec::H => 0,
+                    FPrec::S => 1,
+                    FPrec::D => 2,
+                    FPrec::Q => 3,
+                };
+                fp_extension(if rank(a) >= rank(b) { *a } else { *b })
+            },
+            RiscV::FLoad(p, ..) |
+            RiscV::FStore(p, ..) |
+            RiscV::FMa(_, p, ..) |
+            RiscV::FOp(_, p, ..) |
+            RiscV::FSqrt(p, ..) |
+            RiscV::FSgnj(_, p, ..) |
+            RiscV::FMinMax(_, p, ..) |
+            RiscV::FCmp(_, p, ..) |
+            RiscV::FClass(p, ..) |
+            RiscV::FCvtToInt(_, p, ..) |
+            RiscV::FCvtFromInt(_, p, ..) |
+            RiscV::FMvToInt(p, ..) |
+            RiscV::FMvFromInt(p, ..) => fp_extension(*p),
+            RiscV::CmPush(..) | RiscV::CmPop(..) | RiscV::CmMv(..) => Extension::Zcmp,
+            RiscV::CmJt(..) | RiscV::CmJalt(..) => Extension::Zcmt,
+            RiscV::Custom(inst) => Extension::Vendor(inst.ext),
+            _ => Extension::I,
+        }
+    }
+
+    pub fn mnemonic(&self) -> String {
+        let r = self.to_string();
+        match r.split_once('\t') {
+            Some((mnemonic, _)) => mnemonic.to_string(),
+            None => r,
+        }
+    }
+
+    /// explicit operands in assembly order. memory operands are split into
+    /// the base register and the offset
+    pub fn operands(&self) -> Vec<Operand> {
+        use RegClass::*;
+        let shamt_bits = |ty: &OpType| matches!(ty, OpType::Sll | OpType::Srl | OpType::Sra);
+        match *self {
+            RiscV::Lui(rd, imm) | RiscV::Auipc(rd, imm) =>
+                vec![Operand::dst(Int, int(rd)), Operand::imm(imm as i32 as i64, 20, true)],
+            RiscV::Jal(rd, offset) =>
+                vec![Operand::dst(Int, int(rd)), Operand::imm(offset as i64, 21, true)],
+            RiscV::Jalr(rd, rs1, offset) =>
+                vec![Operand::dst(Int, int(rd)), Operand::src(Int, int(rs1)), Operand::imm(offset as i64, 12, true)],
+            RiscV::Branch(_, rs1, rs2, offset) =>
+                vec![Operand::src(Int, int(rs1)), Operand::src(Int, int(rs2)), Operand::imm(offset as i64, 13, true)],
+            RiscV::Load(_, rd, rs1, offset) =>
+                vec![Operand::dst(Int, int(rd)), Operand::src(Int, int(rs1)), Operand::imm(offset as i64, 12, true)],
+            RiscV::Store(_, rs1, rs2, offset) =>
+                vec![Operand::src(Int, int(rs2)), Operand::src(Int, int(rs1)), Operand::imm(offset as i64, 12, true)],
+            RiscV::OpI(ty, rd, rs1, imm) => {
+                let imm = if shamt_bits(&ty) {
+                    Operand::imm(imm as i64, 6, false)
+                } else {
+                    Operand::imm(imm as i64, 12, true)
+                };
+                vec![Operand::dst(Int, int(rd)), Operand::src(Int, int(rs1)), imm]
+            },
+            RiscV::OpIW(ty, rd, rs1, imm) => {
+                let imm = if shamt_bits(&ty) {
+                    Operand::imm(imm as i64, 5, false)
+                } else {
+                    Operand::imm(imm as i64, 12, true)
+                };
+                vec![Operand::dst(Int, int(rd)), Operand::src(Int, int(rs1)), imm]
+            },
+            RiscV::Op(_, rd, rs1, rs2) |
+            RiscV::OpW(_, rd, rs1, rs2) |
+            RiscV::MulOp(_, rd, rs1, rs2) |
+            RiscV::MulOpW(_, rd, rs1, rs2) =>
+                vec![Operand::dst(Int, int(rd)), Operand::src(Int, int(rs1)), Operand::src(Int, int(rs2))],
+            RiscV::Lr(_, _, rd, rs1) => vec![Operand::dst(Int, int(rd)), Operand::src(Int, int(rs1))],
+            RiscV::Sc(_, _, rd, rs1, rs2) | RiscV::Amo(_, _, _, rd, rs1, rs2) =>
+                vec![Operand::dst(Int, int(rd)), Operand::src(Int, int(rs2)), Operand::src(Int, int(rs1))],
+            RiscV::Ext(_, rd, rs1) => vec![Operand::dst(Int, int(rd)), Operand::src(Int, int(rs1))],
+            RiscV::Fence(IsFenceI(false), pred, succ) =>
+                vec![Operand::imm(pred.0 as i64, 4, false), Operand::imm(succ.0 as i64, 4, false)],
+            RiscV::Fence(..) | RiscV::EOp(..) => vec![],
+            RiscV::SFenceVma(rs1, rs2) => vec![Operand::src(Int, int(rs1)), Operand::src(Int, int(rs2))],
+            RiscV::CsrOp(_, rd, rs1, csr) =>
+                vec![Operand::dst(Int, int(rd)), Operand::src(Int, int(rs1)), Operand::src(Csr, csr.0)],
+            RiscV::CsrOpI(_, rd, zimm, csr) =>
+                vec![Operand::dst(Int, int(rd)), Operand::imm(zimm as i64, 5, false), Operand::src(Csr, csr.0)],
+            RiscV::FLoad(_, rd, rs1, offset) =>
+                vec![Operand::dst(Float, fp(rd)), Operand::src(Int, int(rs1)), Operand::imm(offset as i64, 12, true)],
+            RiscV::FStore(_, rs1, rs2, offset) =>
+                vec![Operand::src(Float, fp(rs2)), Operand::src(Int, int(rs1)), Operand::imm(offset as i64, 12, true)],
+            RiscV::FMa(_, _, rd, rs1, rs2, rs3, _) => vec![
+                Operand::dst(Float, fp(rd)),
+                Operand::src(Float, fp(rs1)),
+                Operand::src(Float, fp(rs2)),
+                Operand::src(Float, fp(rs3)),
+            ],
+            RiscV::FOp(_, _, rd, rs1, rs2, _) |
+            RiscV::FSgnj(_, _, rd, rs1, rs2) |
+            RiscV::FMinMax(_, _, rd, rs1, rs2) =>
+                vec![Operand::dst(Float, fp(rd)), Operand::src(Float, fp(rs1)), Operand::src(Float, fp(rs2))],
+            RiscV::FSqrt(_, rd, rs1, _) | RiscV::FCvt(_, _, rd, rs1, _) =>
+                vec![Operand::dst(Float, fp(rd)), Operand::src(Float, fp(rs1))],
+            RiscV::FCmp(_, _, rd, rs1, rs2) =>
+                vec![Operand::dst(Int, int(rd)), Operand::src(Float, fp(rs1)), Operand::src(Float, fp(rs2))],
+            RiscV::FClass(_, rd, rs1) | RiscV::FCvtToInt(_, _, rd, rs1, _) | RiscV::FMvToInt(_, rd, rs1) =>
+                vec![Operand::dst(Int, int(rd)), Operand::src(Float, fp(rs1))],
+            RiscV::FCvtFromInt(_, _, rd, rs1, _) | RiscV::FMvFromInt(_, rd, rs1) =>
+                vec![Operand::dst(Float, fp(rd)), Operand::src(Int, int(rs1))],
+            RiscV::CmPush(rlist, adj) => rlist.regs()
+                .map(|r| Operand::src(Int, int(r)))
+                .chain([Operand::imm(-(adj as i64), 9, true)])
+                .collect(),
+            RiscV::CmPop(_, rlist, adj) => rlist.regs()
+                .map(|r| Operand::dst(Int, int(r)))
+                .chain([Operand::imm(adj as i64, 9, false)])
+                .collect(),
+            RiscV::CmMv(CmMvType::Sa01, r1s, r2s) => vec![Operand::dst(Int, int(r1s)), Operand::dst(Int, int(r2s))],
+            RiscV::CmMv(CmMvType::A01s, r1s, r2s) => vec![Operand::src(Int, int(r1s)), Operand::src(Int, int(r2s))],
+            RiscV::CmJt(index) | RiscV::CmJalt(index) => vec![Operand::imm(index as i64, 8, false)],
+            RiscV::Custom(inst) => vendor::extension(inst.ext)
+                .map_or_else(Vec::new, |e| e.operands(&inst)),
+        }
+    }
+
+    pub fn flags(&self) -> Flags {
+        match *self {
+            RiscV::Branch(..) => Flags::BRANCH,
+            RiscV::Jal(rd, _) if is_link(rd) => Flags::JUMP | Flags::CALL,
+            RiscV::Jal(..) => Flags::JUMP,
+            RiscV::Jalr(rd, rs1, _) if rd.0 == 0 && is_link(rs1) => Flags::JUMP | Flags::RETURN | Flags::INDIRECT,
+            RiscV::Jalr(rd, _, _) if is_link(rd) => Flags::JUMP | Flags::CALL | Flags::INDIRECT,
+            RiscV::Jalr(..) => Flags::JUMP | Flags::INDIRECT,
+            RiscV::Load(..) | RiscV::FLoad(..) => Flags::LOAD | Flags::MAY_TRAP,
+            RiscV::Store(..) | RiscV::FStore(..) => Flags::STORE | Flags::MAY_TRAP,
+            RiscV::Lr(..) => Flags::LOAD | Flags::ATOMIC | Flags::MAY_TRAP,
+            RiscV::Sc(..) => Flags::STORE | Flags::ATOMIC | Flags::MAY_TRAP,
+            RiscV::Amo(..) => Flags::LOAD | Flags::STORE | Flags::ATOMIC | Flags::MAY_TRAP,
+            RiscV::Fence(..) => Flags::FENCE,
+            RiscV::EOp(EOpType::Mret | EOpType::Sret) =>
+                Flags::JUMP | Flags::RETURN | Flags::INDIRECT | Flags::MAY_TRAP | Flags::PRIVILEGED,
+            RiscV::EOp(EOpType::Wfi) => Flags::MAY_TRAP | Flags::PRIVILEGED,
+            RiscV::SFenceVma(..) => Flags::FENCE | Flags::MAY_TRAP | Flags::PRIVILEGED,
+            RiscV::EOp(_) => Flags::MAY_TRAP,
+            RiscV::CsrOp(_, _, _, csr) | RiscV::CsrOpI(_, _, _, csr) => {
+                let mut r = Flags::CSR | Flags::MAY_TRAP;
+                if csr.privilege() != 0 {
+                    r |= Flags::PRIVILEGED;
+                }
+                r
+            },
+            RiscV::CmPush(..) => Flags::STORE | Flags::MAY_TRAP,
+            RiscV::CmPop(CmPopType::Pop, ..) => Flags::LOAD | Flags::MAY_TRAP,
+            RiscV::CmPop(..) => Flags::LOAD | Flags::MAY_TRAP | Flags::JUMP | Flags::RETURN | Flags::INDIRECT,
+            RiscV::CmJt(..) => Flags::JUMP | Flags::INDIRECT | Flags::LOAD | Flags::MAY_TRAP,
+            RiscV::CmJalt(..) => Flags::JUMP | Flags::CALL | Flags::INDIRECT | Flags::LOAD | Flags::MAY_TRAP,
+            RiscV::Custom(inst) => vendor::extension(inst.ext)
+                .map_or(Flags::MAY_TRAP, |e| e.flags(&inst)),
+            _ => Flags::NONE,
+        }
+    }
+
+    pub fn is_branch(&self) -> bool {
+        self.flags().contains(Flags::BRANCH)
+    }
+
+    pub fn is_jump(&self) -> bool {
+        self.flags().contains(Flags::JUMP)
+    }
+
+    pub fn is_call(&self) -> bool {
+        self.flags().contains(Flags::CALL)
+    }
+
+    pub fn is_return(&self) -> bool {
+        self.flags().contains(Flags::RETURN)
+    }
+
+    pub fn is_load(&self) -> bool {
+        self.flags().contains(Flags::LOAD)
+    }
+
+    pub fn is_store(&self) -> bool {
+        self.flags().contains(Flags::STORE)
+    }
+
+    pub fn is_atomic(&self) -> bool {
+        self.flags().contains(Flags::ATOMIC)
+    }
+
+    pub fn is_csr(&self) -> bool {
+        self.flags().contains(Flags::CSR)
+    }
+
+    pub fn may_trap(&self) -> bool {
+        self.flags().contains(Flags::MAY_TRAP)
+    }
+
+    pub fn is_privileged(&self) -> bool {
+        self.flags().contains(Flags::PRIVILEGED)
+    }
+
+    pub fn info(&self) -> InstInfo {
+        InstInfo {
+            mnemonic: self.mnemonic(),
+            format: self.format(),
+            extension: self.extension(),
+            operands: self.operands(),
+            flags: self.flags(),
+        }
+    }
+
+    // the `_in` accessors look `RiscV::Custom` up in `registry` rather than
+    // the default one, for instructions from `disassembly_in`
+
+    pub fn mnemonic_in(&self, registry: &Registry) -> String {
+        match self {
+            RiscV::Custom(inst) => inst.mnemonic_in(registry),
+            _ => self.mnemonic(),
+        }
+    }
+
+    pub fn operands_in(&self, registry: &Registry) -> Vec<Operand> {
+        match self {
+            RiscV::Custom(inst) => registry.extension(inst.ext)
+                .map_or_else(Vec::new, |e| e.operands(inst)),
+            _ => self.operands(),
+        }
+    }
+
+    pub fn flags_in(&self, registry: &Registry) -> Flags {
+        match self {
+            RiscV::Custom(inst) => registry.extension(inst.ext)
+                .map_or(Flags::MAY_TRAP, |e| e.flags(inst)),
+            _ => self.flags(),
+        }
+    }
+
+    pub fn info_in(&self, registry: &Registry) -> InstInfo {
+        InstInfo {
+            mnemonic: self.mnemonic_in(registry),
+            format: self.format(),
+            extension: self.extension(),
+            operands: self.operands_in(registry),
+            flags: self.flags_in(registry),
+        }
+    }
+}
+
+
+impl TryFrom<FlatRiscV> for RiscV {
+    type Error = FlatRiscV;
+
+    fn try_from(inst: FlatRiscV) -> Result<Self, Self::Error> {
+        use flat_disasm as fl;
+        let rd = Reg(inst.rd);
+        let rs1 = Reg(inst.rs1);
+        let rs2 = Reg(inst.rs2);
+        let imm16 = inst.imm as i32 as Immi16;
+        let op_type = |ext_op: u16| -> Option<(OpType, bool)> {
+            let r = match ext_op {
+                fl::add => (OpType::Add, false),
+                fl::addw => (OpType::Add, true),
+                fl::sub => (OpType::Sub, false),
+                fl::subw => (OpType::Sub, true),
+                fl::sll => (OpType::Sll, false),
+                fl::sllw => (OpType::Sll, true),
+                fl::slt => (OpType::Slt, false),
+                fl::sltu => (OpType::Sltu, false),
+                fl::xor => (OpType::Xor, false),
+                fl::srl => (OpType::Srl, false),
+                fl::srlw => (OpType::Srl, true),
+                fl::sra => (OpType::Sra, false),
+                fl::sraw => (OpType::Sra, true),
+                fl::or => (OpType::Or, false),
+                fl::and => (OpType::And, false),
+                _ => return None,
+            };
+            Some(r)
+        };
+        let csr_type = |ext_op: u16| match ext_op & 0b11 {
+            0b01 => Some(CsrOpType::Rw),
+            0b10 => Some(CsrOpType::Rs),
+            0b11 => Some(CsrOpType::Rc),
+            _ => None,
+        };
+        let r = match inst.opcode {
+            OpCode::lui => RiscV::Lui(rd, inst.imm),
+            OpCode::auipc => RiscV::Auipc(rd, inst.imm),
+            OpCode::jal => RiscV::Jal(rd, inst.imm as Immi32),
+            OpCode::jalr => RiscV::Jalr(rd, rs1, imm16),
+            OpCode::br => {
+                let ty = match inst.ext_op {
+                    fl::eq => BrType::Eq,
+                    fl::ne => BrType::Ne,
+                    fl::lt => BrType::Lt,
+                    fl::ge => BrType::Ge,
+                    fl::ltu => BrType::Ltu,
+                    fl::geu => BrType::Geu,
+                    _ => return Err(inst),
+                };
+                RiscV::Branch(ty, rs1, rs2, imm16)
+            },
+            OpCode::load => {
+                let ty = match inst.ext_op {
+                    fl::b => LoadType::Byte,
+                    fl::h => LoadType::Half,
+                    fl::w => LoadType::Word,
+                    fl::d => LoadType::Double,
+                    fl::bu => LoadType::ByteU,
+                    fl::hu => LoadType::HalfU,
+                    fl::wu => LoadType::WordU,
+                    _ => return Err(inst),
+                };
+                RiscV::Load(ty, rd, rs1, imm16)
+            },
+            OpCode::store => {
+                let ty = match inst.ext_op {
+                    fl::b => StoreType::Byte,
+                    fl::h => StoreType::Half,
+                    fl::w => StoreType::Word,
+                    fl::d => StoreType::Double,
+                    _ => return Err(inst),
+                };
+                RiscV::Store(ty, rs1, rs2, imm16)
+            },
+            // there is no subi or subiw
+            OpCode::iop => match op_type(inst.ext_op).ok_or(inst)? {
+                (OpType::Sub, _) => return Err(inst),
+                (ty @ (OpType::Sll | OpType::Srl | OpType::Sra), false) =>
+                    RiscV::OpI(ty, rd, rs1, (inst.imm & 0b111111) as Immi16),
+                (ty @ (OpType::Sll | OpType::Srl | OpType::Sra), true) =>
+                    RiscV::OpIW(ty, rd, rs1, (inst.imm & 0b11111) as Immi16),
+                (ty, false) => RiscV::OpI(ty, rd, rs1, imm16),
+                (ty, true) => RiscV::OpIW(ty, rd, rs1, imm16),
+            },
+            OpCode::op => match op_type(inst.ext_op).ok_or(inst)? {
+                (ty, false) => RiscV::Op(ty, rd, rs1, rs2),
+                (ty, true) => RiscV::OpW(ty, rd, rs1, rs2),
+            },
+            OpCode::fence if inst.ext_op == fl::vma => RiscV::SFenceVma(rs1, rs2),
+            OpCode::fence => RiscV::Fence(
+                IsFenceI(inst.ext_op == fl::i),
+                Pred(((inst.imm >> 4) & 0b1111) as u8),
+                Succ((inst.imm & 0b1111) as u8)),
+            OpCode::excep => match inst.ext_op {
+                fl::call => RiscV::EOp(EOpType::Call),
+                fl::ret => RiscV::EOp(EOpType::Break),
+                fl::sret => RiscV::EOp(EOpType::Sret),
+                fl::wfi => RiscV::EOp(EOpType::Wfi),
+                fl::mret => RiscV::EOp(EOpType::Mret),
+                _ => return Err(inst),
+            },
+            OpCode::csr => {
+                let csr = Csr((inst.imm & 0xfff) as u16);
+                let ty = csr_type(inst.ext_op).ok_or(inst)?;
+                match inst.ext_op >> 2 {
+                    0 => RiscV::CsrOp(ty, rd, rs1, csr),
+                    1 => RiscV::CsrOpI(ty, rd, inst.rs1, csr),
+                    _ => return Err(inst),
+                }
+            },
+            OpCode::custom => RiscV::Custom(inst.custom().ok_or(inst)?),
+            OpCode::invalid => return Err(inst),
+        };
+        Ok(r)
+    }
+}
+
+impl FlatRiscV {
+    pub fn info(&self) -> Option<InstInfo> {
+        RiscV::try_from(*self).ok().map(|r| r.info())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::disassembly::riscv::disassembly;
+    use crate::flat_disasm::disasm::flat_disasm;
+    use super::*;
+
+    fn dis(code: u32) -> RiscV {
+        disassembly(code).unwrap().0
+    }
+
+    #[test]
+    fn test_info() {
+        // addi x10, x11, -4
+        let info = dis(0xffc58513).info();
+        assert_eq!(info.mnemonic, "addi");
+        assert_eq!(info.format, Format::I);
+        assert_eq!(info.extension, Extension::I);
+        assert_eq!(info.operands, vec![
+            Operand::dst(RegClass::Int, 10),
+            Operand::src(RegClass::Int, 11),
+            Operand::imm(-4, 12, true),
+        ]);
+        assert_eq!(info.flags, Flags::NONE);
+
+        // srai x10, x11, 3
+        assert_eq!(dis(0x4035d513).operands()[2], Operand::imm(3, 6, false));
+        // jalr x0, 0(x1)
+        assert_eq!(dis(0x00008067).to_string(), "jalr\tx0, 0(x1)");
+        assert!(dis(0x00008067).is_return());
+        // jal x1, 16
+        assert!(dis(0x010000ef).is_call());
+        // sd x1, 8(x2)
+        let sd = dis(0x00113423);
+        assert!(sd.is_store() && sd.may_trap());
+        assert_eq!(sd.operands()[0], Operand::src(RegClass::Int, 1));
+        // csrrw x0, mstatus, x10
+        let csrw = dis(0x30051073);
+        assert!(csrw.is_csr() && csrw.is_privileged());
+        assert_eq!(csrw.extension(), Extension::Zicsr);
+        // csrrs x10, fflags, x0
+        assert!(!dis(0x00102573).is_privileged());
+        // fence rw, rw
+        assert_eq!(dis(0x0330000f).to_string(), "fence\t3, 3");
+        // amoswap.w.aq x28, x7, (x9)
+        let amo = dis(0x0c74ae2f);
+        assert_eq!(amo.extension(), Extension::A);
+        assert_eq!(amo.flags(), Flags::LOAD | Flags::STORE | Flags::ATOMIC | Flags::MAY_TRAP);
+        assert_eq!(amo.operands()[1], Operand::src(RegClass::Int, 7));
+    }
+
+    #[test]
+    fn test_fp_extension() {
+        assert_eq!(dis(0x4020f0d3).extension(), Extension::Zfhmin);
+        assert_eq!(dis(0x042090d3).extension(), Extension::Zfh);
+        assert_eq!(dis(0x1a20f0c3).format(), Format::R4);
+        assert_eq!(dis(0x1a20f0c3).mnemonic(), "fmadd.d");
+        assert_eq!(Extension::Zfhmin.to_string(), "zfhmin");
+    }
+
+    #[test]
+    fn test_flat() {
+        for code in [0xffc58513_u32, 0x00113423, 0x30051073, 0x0330000f, 0x00008067, 0xfe0718e3] {
+            let (flat, _) = flat_disasm(&code.to_le_bytes(), false).unwrap();
+            assert_eq!(RiscV::try_from(flat), Ok(dis(code)));
+            assert_eq!(flat.info(), Some(dis(code).info()));
+        }
+    }
+
+    #[test]
+    fn test_flat_invalid() {
+        use crate::flat_disasm as fl;
+        let flat = |opcode, ext_op| FlatRiscV { opcode, ext_op, ..fl::DEF };
+        // sub has no immediate form, the w forms only exist for add, sub
+        // and shifts
+        for ext_op in [fl::sub, fl::subw] {
+            assert_eq!(RiscV::try_from(flat(OpCode::iop, ext_op)), Err(flat(OpCode::iop, ext_op)));
+        }
+        assert!(RiscV::try_from(flat(OpCode::iop, fl::sra)).is_ok());
+        assert!(RiscV::try_from(flat(OpCode::iop, fl::addw)).is_ok());
+        assert!(RiscV::try_from(flat(OpCode::op, fl::subw)).is_ok());
+        for ext_op in [fl::slt | 1, fl::sltu | 1, fl::xor | 1, fl::or | 1, fl::and | 1] {
+            assert!(RiscV::try_from(flat(OpCode::iop, ext_op)).is_err());
+            assert!(RiscV::try_from(flat(OpCode::op, ext_op)).is_err());
+        }
+        // csr ops need one of rw, rs, rc
+        for ext_op in [0b000, 0b100, 0b1001] {
+            assert!(RiscV::try_from(flat(OpCode::csr, ext_op)).is_err());
+        }
+        assert!(RiscV::try_from(flat(OpCode::csr, fl::rci)).is_ok());
+        assert!(RiscV::try_from(flat(OpCode::excep, 0x7)).is_err());
+        assert_eq!(RiscV::try_from(flat(OpCode::excep, fl::ret)), Ok(RiscV::EOp(EOpType::Break)));
+    }
+}
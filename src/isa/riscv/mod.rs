@@ -1,6 +1,8 @@
 pub mod inst_binary;
 pub mod bare;
 pub mod reg;
+pub mod meta;
+pub mod regset;
 
 
 use std::fmt::Display;
@@ -29,9 +31,37 @@ pub type Rd = Reg;
 pub type Rs1 = Reg;
 pub type Rs2 = Reg;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FReg(pub u8);
+
+impl FReg {
+    pub fn new(value: u8) -> FReg {
+        assert!(value <= 0b11111);
+        FReg(value)
+    }
+}
+
+impl Display for FReg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "f{}", self.0)
+    }
+}
+
+pub type FRd = FReg;
+pub type FRs1 = FReg;
+pub type FRs2 = FReg;
+pub type FRs3 = FReg;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Csr(pub u16);
 
+impl Csr {
+    /// look a csr up by its name in `CSR_MAP`
+    pub fn from_name(name: &str) -> Option<Csr> {
+        CSR_MAP.iter().find(|(_, n)| **n == name).map(|(k, _)| Csr(*k as u16))
+    }
+}
+
 impl Display for Csr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(r) = CSR_MAP.get(&self.0.into()).cloned() {
@@ -153,14 +183,108 @@ pub enum OpWType {
 }
 // */
 
+#[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct IsFenceI(pub bool);
+pub enum MulOpType {
+    Mul     = 0b000,
+    Mulh    = 0b001,
+    Mulhsu  = 0b010,
+    Mulhu   = 0b011,
+    Div     = 0b100,
+    Divu    = 0b101,
+    Rem     = 0b110,
+    Remu    = 0b111,
+}
 
+/// read-modify-write ops of the a extension, by their funct5 field
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AmoOpType {
+    Add     = 0b00000,
+    Swap    = 0b00001,
+    Xor     = 0b00100,
+    Or      = 0b01000,
+    And     = 0b01100,
+    Min     = 0b10000,
+    Max     = 0b10100,
+    Minu    = 0b11000,
+    Maxu    = 0b11100,
+}
+
+/// width of lr, sc and the amos, the funct3 field
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AmoWidth {
+    W = 0b010,
+    D = 0b011,
+}
+
+impl AmoWidth {
+    pub fn bytes(&self) -> u8 {
+        match self {
+            AmoWidth::W => 4,
+            AmoWidth::D => 8,
+        }
+    }
+}
+
+impl Display for AmoWidth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AmoWidth::W => write!(f, "w"),
+            AmoWidth::D => write!(f, "d"),
+        }
+    }
+}
+
+/// the `aq` and `rl` ordering bits, funct7[1:0]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AqRl(pub u8);
+
+impl AqRl {
+    pub fn aq(&self) -> bool {
+        self.0 & 0b10 != 0
+    }
+
+    pub fn rl(&self) -> bool {
+        self.0 & 0b01 != 0
+    }
+}
+
+/// mnemonic suffix, empty when neither bit is set
+impl Display for AqRl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 & 0b11 {
+            0b00 => Ok(()),
+            0b01 => write!(f, ".rl"),
+            0b10 => write!(f, ".aq"),
+            _ => write!(f, ".aqrl"),
+        }
+    }
+}
+
+/// sign/zero extension ops of zbb/zba, also reachable through zcb
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExtType {
+    SextB,
+    SextH,
+    ZextH,
+    /// add.uw rd, rs1, x0
+    ZextW,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IsFenceI(pub bool);
+
+/// `SYSTEM` instructions without operands, by their `imm` field
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum EOpType {
-    Call    = 0b0,
-    Break   = 0b1
+    Call    = 0x000,
+    Break   = 0x001,
+    Sret    = 0x102,
+    Wfi     = 0x105,
+    Mret    = 0x302,
 }
 
 #[repr(u8)]
@@ -171,6 +295,265 @@ pub enum CsrOpType {
     Rc = 0b011,
 }
 
+/// floating point precision, encoded as the `fmt` field (funct7[1:0])
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FPrec {
+    S = 0b00,
+    D = 0b01,
+    H = 0b10,
+    Q = 0b11,
+}
+
+impl FPrec {
+    pub fn from_fmt(fmt: u8) -> FPrec {
+        match fmt & 0b11 {
+            0b00 => FPrec::S,
+            0b01 => FPrec::D,
+            0b10 => FPrec::H,
+            _ => FPrec::Q,
+        }
+    }
+
+    /// width field (funct3) of LOAD-FP/STORE-FP
+    pub fn from_width(width: u8) -> Option<FPrec> {
+        match width {
+            0b001 => Some(FPrec::H),
+            0b010 => Some(FPrec::S),
+            0b011 => Some(FPrec::D),
+            0b100 => Some(FPrec::Q),
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        match self {
+            FPrec::H => 16,
+            FPrec::S => 32,
+            FPrec::D => 64,
+            FPrec::Q => 128,
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            FPrec::S => "s",
+            FPrec::D => "d",
+            FPrec::H => "h",
+            FPrec::Q => "q",
+        }
+    }
+
+    /// suffix used by `fmv.x.*`/`fmv.*.x`, single precision is spelled `w`
+    pub fn mv_suffix(&self) -> &'static str {
+        match self {
+            FPrec::S => "w",
+            _ => self.suffix(),
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RoundMode {
+    Rne = 0b000,
+    Rtz = 0b001,
+    Rdn = 0b010,
+    Rup = 0b011,
+    Rmm = 0b100,
+    Dyn = 0b111,
+}
+
+impl RoundMode {
+    pub fn from_bits(rm: u8) -> Option<RoundMode> {
+        match rm {
+            0b000 => Some(RoundMode::Rne),
+            0b001 => Some(RoundMode::Rtz),
+            0b010 => Some(RoundMode::Rdn),
+            0b011 => Some(RoundMode::Rup),
+            0b100 => Some(RoundMode::Rmm),
+            0b111 => Some(RoundMode::Dyn),
+            _ => None,
+        }
+    }
+}
+
+impl Display for RoundMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let r = match self {
+            RoundMode::Rne => "rne",
+            RoundMode::Rtz => "rtz",
+            RoundMode::Rdn => "rdn",
+            RoundMode::Rup => "rup",
+            RoundMode::Rmm => "rmm",
+            RoundMode::Dyn => "dyn",
+        };
+        write!(f, "{}", r)
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FMaType {
+    Madd    = 0b00,
+    Msub    = 0b01,
+    Nmsub   = 0b10,
+    Nmadd   = 0b11,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FOpType {
+    Add = 0b00000,
+    Sub = 0b00001,
+    Mul = 0b00010,
+    Div = 0b00011,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FSgnjType {
+    J   = 0b000,
+    Jn  = 0b001,
+    Jx  = 0b010,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FMinMaxType {
+    Min = 0b000,
+    Max = 0b001,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FCmpType {
+    Le = 0b000,
+    Lt = 0b001,
+    Eq = 0b010,
+}
+
+/// integer side of `fcvt`, encoded in the rs2 field
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FIntType {
+    W   = 0b00,
+    Wu  = 0b01,
+    L   = 0b10,
+    Lu  = 0b11,
+}
+
+impl FIntType {
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            FIntType::W => "w",
+            FIntType::Wu => "wu",
+            FIntType::L => "l",
+            FIntType::Lu => "lu",
+        }
+    }
+}
+
+/// zcmp register list: `{ra}`, `{ra, s0}`, ..., `{ra, s0-s11}`, encoded as 4..=15
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RList(pub u8);
+
+impl RList {
+    /// number of saved registers
+    pub fn len(&self) -> usize {
+        match self.0 {
+            15 => 13,
+            x => x as usize - 3,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// ra, then s0, s1, s2..s11 in push order
+    pub fn regs(&self) -> impl Iterator<Item = Reg> {
+        const ORDER: [u8; 13] = [1, 8, 9, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27];
+        ORDER.into_iter().take(self.len()).map(Reg)
+    }
+
+    /// stack adjustment before applying the `spimm` field
+    pub fn stack_adj_base(&self, is_32bit: bool) -> u16 {
+        let bytes = self.len() as u16 * if is_32bit { 4 } else { 8 };
+        (bytes + 15) & !15
+    }
+}
+
+impl Display for RList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.len() {
+            1 => write!(f, "{{ra}}"),
+            2 => write!(f, "{{ra, s0}}"),
+            n => write!(f, "{{ra, s0-s{}}}", n - 2),
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CmPopType {
+    Pop     = 0b11010,
+    PopRetZ = 0b11100,
+    PopRet  = 0b11110,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CmMvType {
+    /// cm.mvsa01 r1s, r2s: r1s = a0, r2s = a1
+    Sa01,
+    /// cm.mva01s r1s, r2s: a0 = r1s, a1 = r2s
+    A01s,
+}
+
+/// value of the zcmt `jvt` csr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Jvt(pub u64);
+
+impl Jvt {
+    pub fn base(&self) -> u64 {
+        self.0 & !0b111111
+    }
+
+    pub fn mode(&self) -> u8 {
+        (self.0 & 0b111111) as u8
+    }
+
+    /// address of the table entry used by `cm.jt`/`cm.jalt` with `index`
+    pub fn entry_addr(&self, index: Imm8, is_32bit: bool) -> u64 {
+        let xlen_bytes = if is_32bit { 4 } else { 8 };
+        self.base().wrapping_add(index as u64 * xlen_bytes)
+    }
+
+    /// resolve the jump target from a memory region mapped at `mem_base`
+    pub fn target(&self, index: Imm8, is_32bit: bool, mem: &[u8], mem_base: u64) -> Option<u64> {
+        if self.mode() != 0 {
+            return None;
+        }
+        let offset = self.entry_addr(index, is_32bit).checked_sub(mem_base)? as usize;
+        let entry = if is_32bit {
+            u32::from_le_bytes(mem.get(offset..offset + 4)?.try_into().ok()?) as u64
+        } else {
+            u64::from_le_bytes(mem.get(offset..offset + 8)?.try_into().ok()?)
+        };
+        Some(entry & !1)
+    }
+}
+
+/// instruction of a registered vendor extension, see `disassembly::vendor`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CustomInst {
+    /// registry id of the extension
+    pub ext: u16,
+    /// extension local opcode
+    pub op: u16,
+    pub raw: u32,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum RiscV {
     // rvi
@@ -185,10 +568,86 @@ pub enum RiscV {
     OpIW(OpType, Rd, Rs1, Immi16),
     Op(OpType, Rd, Rs1, Rs2),
     OpW(OpType, Rd, Rs1, Rs2),
+    // rvm
+    MulOp(MulOpType, Rd, Rs1, Rs2),
+    MulOpW(MulOpType, Rd, Rs1, Rs2),
+    // rva
+    Lr(AmoWidth, AqRl, Rd, Rs1),
+    Sc(AmoWidth, AqRl, Rd, Rs1, Rs2),
+    Amo(AmoOpType, AmoWidth, AqRl, Rd, Rs1, Rs2),
+    // zbb, zba
+    Ext(ExtType, Rd, Rs1),
     Fence(IsFenceI, Pred, Succ),
     EOp(EOpType),
+    /// sfence.vma, the virtual address and the asid
+    SFenceVma(Rs1, Rs2),
     CsrOp(CsrOpType, Rd, Rs1, Csr),
     CsrOpI(CsrOpType, Rd, Zimm, Csr),
+    // rvf, rvd, zfh, zfhmin, rvq
+    FLoad(FPrec, FRd, Rs1, Immi16),
+    FStore(FPrec, Rs1, FRs2, Immi16),
+    FMa(FMaType, FPrec, FRd, FRs1, FRs2, FRs3, RoundMode),
+    FOp(FOpType, FPrec, FRd, FRs1, FRs2, RoundMode),
+    FSqrt(FPrec, FRd, FRs1, RoundMode),
+    FSgnj(FSgnjType, FPrec, FRd, FRs1, FRs2),
+    FMinMax(FMinMaxType, FPrec, FRd, FRs1, FRs2),
+    FCmp(FCmpType, FPrec, Rd, FRs1, FRs2),
+    FClass(FPrec, Rd, FRs1),
+    /// fcvt.<dst>.<src>
+    FCvt(FPrec, FPrec, FRd, FRs1, RoundMode),
+    FCvtToInt(FIntType, FPrec, Rd, FRs1, RoundMode),
+    FCvtFromInt(FIntType, FPrec, FRd, Rs1, RoundMode),
+    FMvToInt(FPrec, Rd, FRs1),
+    FMvFromInt(FPrec, FRd, Rs1),
+    // zcmp, stack adjustment in bytes
+    CmPush(RList, Imm32),
+    CmPop(CmPopType, RList, Imm32),
+    CmMv(CmMvType, Rs1, Rs2),
+    // zcmt, table index
+    CmJt(Imm8),
+    CmJalt(Imm8),
+    // vendor extensions
+    Custom(CustomInst),
+}
+
+fn mul_name(ty: MulOpType) -> &'static str {
+    match ty {
+        MulOpType::Mul => "mul",
+        MulOpType::Mulh => "mulh",
+        MulOpType::Mulhsu => "mulhsu",
+        MulOpType::Mulhu => "mulhu",
+        MulOpType::Div => "div",
+        MulOpType::Divu => "divu",
+        MulOpType::Rem => "rem",
+        MulOpType::Remu => "remu",
+    }
+}
+
+fn amo_name(ty: AmoOpType) -> &'static str {
+    match ty {
+        AmoOpType::Add => "amoadd",
+        AmoOpType::Swap => "amoswap",
+        AmoOpType::Xor => "amoxor",
+        AmoOpType::Or => "amoor",
+        AmoOpType::And => "amoand",
+        AmoOpType::Min => "amomin",
+        AmoOpType::Max => "amomax",
+        AmoOpType::Minu => "amominu",
+        AmoOpType::Maxu => "amomaxu",
+    }
+}
+
+/// `, rm` operand suffix, omitted for the dynamic rounding mode
+struct Rm(RoundMode);
+
+impl Display for Rm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0 == RoundMode::Dyn {
+            Ok(())
+        } else {
+            write!(f, ", {}", self.0)
+        }
+    }
 }
 
 impl Display for RiscV {
@@ -214,10 +673,10 @@ impl Display for RiscV {
             RiscV::Load(LoadType::HalfU, rd, rs1, offset) => write!(f, "lhu\t{}, {}({})", rd, offset, rs1),
             RiscV::Load(LoadType::WordU, rd, rs1, offset) => write!(f, "lwu\t{}, {}({})", rd, offset, rs1),
 
-            RiscV::Store(StoreType::Byte, rs1, rs2, imm) => write!(f, "sb\t{}, {}({})", rs1, imm, rs2),
-            RiscV::Store(StoreType::Half, rs1, rs2, imm) => write!(f, "sh\t{}, {}({})", rs1, imm, rs2),
-            RiscV::Store(StoreType::Word, rs1, rs2, imm) => write!(f, "sw\t{}, {}({})", rs1, imm, rs2),
-            RiscV::Store(StoreType::Double, rs1, rs2, imm) => write!(f, "sd\t{}, {}({})", rs1, imm, rs2),
+            RiscV::Store(StoreType::Byte, rs1, rs2, imm) => write!(f, "sb\t{}, {}({})", rs2, imm, rs1),
+            RiscV::Store(StoreType::Half, rs1, rs2, imm) => write!(f, "sh\t{}, {}({})", rs2, imm, rs1),
+            RiscV::Store(StoreType::Word, rs1, rs2, imm) => write!(f, "sw\t{}, {}({})", rs2, imm, rs1),
+            RiscV::Store(StoreType::Double, rs1, rs2, imm) => write!(f, "sd\t{}, {}({})", rs2, imm, rs1),
 
             RiscV::OpI(OpType::Add, rd, rs1, imm) => write!(f, "addi\t{}, {}, {}", rd, rs1, imm),
             RiscV::OpI(OpType::Slt, rd, rs1, imm) => write!(f, "slti\t{}, {}, {}", rd, rs1, imm),
@@ -251,11 +710,28 @@ impl Display for RiscV {
             RiscV::OpW(OpType::Srl, rd, rs1, rs2) => write!(f, "srlw\t{}, {}, {}", rd, rs1, rs2),
             RiscV::OpW(OpType::Sra, rd, rs1, rs2) => write!(f, "sraw\t{}, {}, {}", rd, rs1, rs2),
 
-            RiscV::Fence(IsFenceI(false), pred, succ) => write!(f, "fence.i\t{}, {}", pred, succ),
-            RiscV::Fence(IsFenceI(true), _, _) => write!(f, "fence"),
+            RiscV::MulOp(ty, rd, rs1, rs2) => write!(f, "{}\t{}, {}, {}", mul_name(*ty), rd, rs1, rs2),
+            RiscV::MulOpW(ty, rd, rs1, rs2) => write!(f, "{}w\t{}, {}, {}", mul_name(*ty), rd, rs1, rs2),
+
+            RiscV::Lr(w, ord, rd, rs1) => write!(f, "lr.{}{}\t{}, ({})", w, ord, rd, rs1),
+            RiscV::Sc(w, ord, rd, rs1, rs2) => write!(f, "sc.{}{}\t{}, {}, ({})", w, ord, rd, rs2, rs1),
+            RiscV::Amo(ty, w, ord, rd, rs1, rs2) =>
+                write!(f, "{}.{}{}\t{}, {}, ({})", amo_name(*ty), w, ord, rd, rs2, rs1),
+
+            RiscV::Ext(ExtType::SextB, rd, rs1) => write!(f, "sext.b\t{}, {}", rd, rs1),
+            RiscV::Ext(ExtType::SextH, rd, rs1) => write!(f, "sext.h\t{}, {}", rd, rs1),
+            RiscV::Ext(ExtType::ZextH, rd, rs1) => write!(f, "zext.h\t{}, {}", rd, rs1),
+            RiscV::Ext(ExtType::ZextW, rd, rs1) => write!(f, "zext.w\t{}, {}", rd, rs1),
+
+            RiscV::Fence(IsFenceI(false), pred, succ) => write!(f, "fence\t{}, {}", pred, succ),
+            RiscV::Fence(IsFenceI(true), _, _) => write!(f, "fence.i"),
 
             RiscV::EOp(EOpType::Call) => write!(f, "ecall"),
             RiscV::EOp(EOpType::Break) => write!(f, "ebreak"),
+            RiscV::EOp(EOpType::Sret) => write!(f, "sret"),
+            RiscV::EOp(EOpType::Wfi) => write!(f, "wfi"),
+            RiscV::EOp(EOpType::Mret) => write!(f, "mret"),
+            RiscV::SFenceVma(rs1, rs2) => write!(f, "sfence.vma\t{}, {}", rs1, rs2),
 
             RiscV::CsrOpI(CsrOpType::Rw, rd, rs1, csr) => write!(f, "csrrwi\t{}, {}, {}", rd, rs1, csr),
             RiscV::CsrOpI(CsrOpType::Rs, rd, rs1, csr) => write!(f, "csrrsi\t{}, {}, {}", rd, rs1, csr),
@@ -265,6 +741,57 @@ impl Display for RiscV {
             RiscV::CsrOp(CsrOpType::Rs, rd, rs1, csr) => write!(f, "csrrs\t{}, {}, {}", rd, rs1, csr),
             RiscV::CsrOp(CsrOpType::Rc, rd, rs1, csr) => write!(f, "csrrc\t{}, {}, {}", rd, rs1, csr),
 
+            RiscV::FLoad(p, rd, rs1, offset) => write!(f, "fl{}\t{}, {}({})", p.mv_suffix(), rd, offset, rs1),
+            RiscV::FStore(p, rs1, rs2, offset) => write!(f, "fs{}\t{}, {}({})", p.mv_suffix(), rs2, offset, rs1),
+
+            RiscV::FMa(ty, p, rd, rs1, rs2, rs3, rm) => {
+                let name = match ty {
+                    FMaType::Madd => "fmadd",
+                    FMaType::Msub => "fmsub",
+                    FMaType::Nmsub => "fnmsub",
+                    FMaType::Nmadd => "fnmadd",
+                };
+                write!(f, "{}.{}\t{}, {}, {}, {}{}", name, p.suffix(), rd, rs1, rs2, rs3, Rm(*rm))
+            },
+            RiscV::FOp(ty, p, rd, rs1, rs2, rm) => {
+                let name = match ty {
+                    FOpType::Add => "fadd",
+                    FOpType::Sub => "fsub",
+                    FOpType::Mul => "fmul",
+                    FOpType::Div => "fdiv",
+                };
+                write!(f, "{}.{}\t{}, {}, {}{}", name, p.suffix(), rd, rs1, rs2, Rm(*rm))
+            },
+            RiscV::FSqrt(p, rd, rs1, rm) => write!(f, "fsqrt.{}\t{}, {}{}", p.suffix(), rd, rs1, Rm(*rm)),
+            RiscV::FSgnj(FSgnjType::J, p, rd, rs1, rs2) => write!(f, "fsgnj.{}\t{}, {}, {}", p.suffix(), rd, rs1, rs2),
+            RiscV::FSgnj(FSgnjType::Jn, p, rd, rs1, rs2) => write!(f, "fsgnjn.{}\t{}, {}, {}", p.suffix(), rd, rs1, rs2),
+            RiscV::FSgnj(FSgnjType::Jx, p, rd, rs1, rs2) => write!(f, "fsgnjx.{}\t{}, {}, {}", p.suffix(), rd, rs1, rs2),
+            RiscV::FMinMax(FMinMaxType::Min, p, rd, rs1, rs2) => write!(f, "fmin.{}\t{}, {}, {}", p.suffix(), rd, rs1, rs2),
+            RiscV::FMinMax(FMinMaxType::Max, p, rd, rs1, rs2) => write!(f, "fmax.{}\t{}, {}, {}", p.suffix(), rd, rs1, rs2),
+            RiscV::FCmp(FCmpType::Eq, p, rd, rs1, rs2) => write!(f, "feq.{}\t{}, {}, {}", p.suffix(), rd, rs1, rs2),
+            RiscV::FCmp(FCmpType::Lt, p, rd, rs1, rs2) => write!(f, "flt.{}\t{}, {}, {}", p.suffix(), rd, rs1, rs2),
+            RiscV::FCmp(FCmpType::Le, p, rd, rs1, rs2) => write!(f, "fle.{}\t{}, {}, {}", p.suffix(), rd, rs1, rs2),
+            RiscV::FClass(p, rd, rs1) => write!(f, "fclass.{}\t{}, {}", p.suffix(), rd, rs1),
+            RiscV::FCvt(dst, src, rd, rs1, rm) =>
+                write!(f, "fcvt.{}.{}\t{}, {}{}", dst.suffix(), src.suffix(), rd, rs1, Rm(*rm)),
+            RiscV::FCvtToInt(ity, p, rd, rs1, rm) =>
+                write!(f, "fcvt.{}.{}\t{}, {}{}", ity.suffix(), p.suffix(), rd, rs1, Rm(*rm)),
+            RiscV::FCvtFromInt(ity, p, rd, rs1, rm) =>
+                write!(f, "fcvt.{}.{}\t{}, {}{}", p.suffix(), ity.suffix(), rd, rs1, Rm(*rm)),
+            RiscV::FMvToInt(p, rd, rs1) => write!(f, "fmv.x.{}\t{}, {}", p.mv_suffix(), rd, rs1),
+            RiscV::FMvFromInt(p, rd, rs1) => write!(f, "fmv.{}.x\t{}, {}", p.mv_suffix(), rd, rs1),
+
+            RiscV::CmPush(rlist, adj) => write!(f, "cm.push\t{}, -{}", rlist, adj),
+            RiscV::CmPop(CmPopType::Pop, rlist, adj) => write!(f, "cm.pop\t{}, {}", rlist, adj),
+            RiscV::CmPop(CmPopType::PopRet, rlist, adj) => write!(f, "cm.popret\t{}, {}", rlist, adj),
+            RiscV::CmPop(CmPopType::PopRetZ, rlist, adj) => write!(f, "cm.popretz\t{}, {}", rlist, adj),
+            RiscV::CmMv(CmMvType::Sa01, r1s, r2s) => write!(f, "cm.mvsa01\t{}, {}", r1s, r2s),
+            RiscV::CmMv(CmMvType::A01s, r1s, r2s) => write!(f, "cm.mva01s\t{}, {}", r1s, r2s),
+            RiscV::CmJt(index) => write!(f, "cm.jt\t{}", index),
+            RiscV::CmJalt(index) => write!(f, "cm.jalt\t{}", index),
+
+            RiscV::Custom(inst) => write!(f, "{}", inst),
+
             _ => panic!("is not supported"),
         }
     }
@@ -0,0 +1,399 @@
+use std::fmt::Display;
+use std::ops::{BitOr, BitOrAssign};
+
+use crate::disassembly::vendor::{self, Registry};
+use crate::flat_disasm::FlatRiscV;
+
+use super::*;
+
+
+const CSR_INLINE: usize = 6;
+
+/// registers read or written by an instruction. x0 is a sink: it never
+/// shows up in a set. csrs are kept inline, a set holding more of them than
+/// fit degrades into "any csr".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct RegSet {
+    pub x: u32,
+    pub f: u32,
+    pub v: u32,
+    csr: [u16; CSR_INLINE],
+    ncsr: u8,
+    any_csr: bool,
+}
+
+impl RegSet {
+    pub const EMPTY: RegSet = RegSet {
+        x: 0,
+        f: 0,
+        v: 0,
+        csr: [0; CSR_INLINE],
+        ncsr: 0,
+        any_csr: false,
+    };
+
+    pub fn with_x(mut self, reg: Reg) -> RegSet {
+        self.insert_x(reg);
+        self
+    }
+
+    pub fn with_f(mut self, reg: FReg) -> RegSet {
+        self.insert_f(reg);
+        self
+    }
+
+    pub fn with_csr(mut self, csr: Csr) -> RegSet {
+        self.insert_csr(csr);
+        self
+    }
+
+    pub fn insert_x(&mut self, reg: Reg) {
+        if reg.0 != 0 {
+            self.x |= 1 << reg.0;
+        }
+    }
+
+    pub fn insert_f(&mut self, reg: FReg) {
+        self.f |= 1 << reg.0;
+    }
+
+    pub fn insert_v(&mut self, reg: u8) {
+        self.v |= 1 << reg;
+    }
+
+    pub fn insert_csr(&mut self, csr: Csr) {
+        if self.any_csr || self.contains_csr(csr) {
+            return;
+        }
+        if self.ncsr as usize == CSR_INLINE {
+            self.any_csr = true;
+            return;
+        }
+        let len = self.ncsr as usize;
+        let pos = self.csr[..len].partition_point(|x| *x < csr.0);
+        self.csr.copy_within(pos..len, pos + 1);
+        self.csr[pos] = csr.0;
+        self.ncsr += 1;
+    }
+
+    pub fn remove_x(&mut self, reg: Reg) {
+        self.x &= !(1 << reg.0);
+    }
+
+    pub fn contains_x(&self, reg: Reg) -> bool {
+        self.x & (1 << reg.0) != 0
+    }
+
+    pub fn contains_f(&self, reg: FReg) -> bool {
+        self.f & (1 << reg.0) != 0
+    }
+
+    pub fn contains_csr(&self, csr: Csr) -> bool {
+        self.any_csr || self.csrs().any(|x| x == csr)
+    }
+
+    /// the set lost track of its csrs and must be treated as touching all of them
+    pub fn is_any_csr(&self) -> bool {
+        self.any_csr
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x == 0 && self.f == 0 && self.v == 0 && self.ncsr == 0 && !self.any_csr
+    }
+
+    pub fn intersects(&self, other: &RegSet) -> bool {
+        self.x & other.x != 0 ||
+        self.f & other.f != 0 ||
+        self.v & other.v != 0 ||
+        (self.any_csr && (other.ncsr != 0 || other.any_csr)) ||
+        (other.any_csr && self.ncsr != 0) ||
+        self.csrs().any(|c| other.contains_csr(c))
+    }
+
+    pub fn x_regs(&self) -> impl Iterator<Item = Reg> + '_ {
+        (0..32).filter(|i| self.x & (1 << i) != 0).map(Reg)
+    }
+
+    pub fn f_regs(&self) -> impl Iterator<Item = FReg> + '_ {
+        (0..32).filter(|i| self.f & (1 << i) != 0).map(FReg)
+    }
+
+    pub fn csrs(&self) -> impl Iterator<Item = Csr> + '_ {
+        self.csr[..self.ncsr as usize].iter().map(|x| Csr(*x))
+    }
+}
+
+impl BitOr for RegSet {
+    type Output = RegSet;
+
+    fn bitor(mut self, rhs: RegSet) -> RegSet {
+        self |= rhs;
+        self
+    }
+}
+
+impl BitOrAssign for RegSet {
+    fn bitor_assign(&mut self, rhs: RegSet) {
+        self.x |= rhs.x;
+        self.f |= rhs.f;
+        self.v |= rhs.v;
+        self.any_csr |= rhs.any_csr;
+        for csr in rhs.csrs() {
+            self.insert_csr(csr);
+        }
+    }
+}
+
+impl Display for RegSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names = self.x_regs().map(|r| r.to_string())
+            .chain(self.f_regs().map(|r| r.to_string()))
+            .chain((0..32).filter(|i| self.v & (1 << i) != 0).map(|i| format!("v{}", i)))
+            .chain(self.csrs().map(|c| c.to_string()))
+            .collect::<Vec<_>>();
+        if self.any_csr {
+            names.push("csr*".to_string());
+        }
+        write!(f, "{{{}}}", names.join(", "))
+    }
+}
+
+
+pub const FFLAGS: Csr = Csr(0x001);
+pub const FRM: Csr = Csr(0x002);
+pub const JVT: Csr = Csr(0x017);
+
+const SP: Reg = Reg(2);
+const RA: Reg = Reg(1);
+
+/// frm is only consulted for the dynamic rounding mode
+fn rm_reads(rm: RoundMode) -> RegSet {
+    if rm == RoundMode::Dyn {
+        RegSet::EMPTY.with_csr(FRM)
+    } else {
+        RegSet::EMPTY
+    }
+}
+
+impl RiscV {
+    pub fn reads(&self) -> RegSet {
+        let r = RegSet::EMPTY;
+        match *self {
+            RiscV::Lui(..) | RiscV::Auipc(..) | RiscV::Jal(..) => r,
+            RiscV::Jalr(_, rs1, _) |
+            RiscV::Load(_, _, rs1, _) |
+            RiscV::OpI(_, _, rs1, _) |
+            RiscV::OpIW(_, _, rs1, _) |
+            RiscV::Ext(_, _, rs1) |
+            RiscV::Lr(_, _, _, rs1) |
+            RiscV::FLoad(_, _, rs1, _) |
+            RiscV::FMvFromInt(_, _, rs1) => r.with_x(rs1),
+            RiscV::Branch(_, rs1, rs2, _) |
+            RiscV::Store(_, rs1, rs2, _) |
+            RiscV::Op(_, _, rs1, rs2) |
+            RiscV::OpW(_, _, rs1, rs2) |
+            RiscV::MulOp(_, _, rs1, rs2) |
+            RiscV::MulOpW(_, _, rs1, rs2) |
+            RiscV::Sc(_, _, _, rs1, rs2) |
+            RiscV::Amo(_, _, _, _, rs1, rs2) |
+            RiscV::SFenceVma(rs1, rs2) => r.with_x(rs1).with_x(rs2),
+            // xret restores from xstatus and jumps to xepc
+            RiscV::EOp(EOpType::Mret) => r.with_csr(Csr(0x300)).with_csr(Csr(0x341)),
+            RiscV::EOp(EOpType::Sret) => r.with_csr(Csr(0x100)).with_csr(Csr(0x141)),
+            RiscV::Fence(..) | RiscV::EOp(..) => r,
+            RiscV::CsrOp(ty, rd, rs1, csr) => {
+                let r = r.with_x(rs1);
+                // csrrw with rd = x0 does not read the csr
+                if ty == CsrOpType::Rw && rd.0 == 0 { r } else { r.with_csr(csr) }
+            },
+            RiscV::CsrOpI(ty, rd, _, csr) => {
+                if ty == CsrOpType::Rw && rd.0 == 0 { r } else { r.with_csr(csr) }
+            },
+            RiscV::FStore(_, rs1, rs2, _) => r.with_x(rs1).with_f(rs2),
+            RiscV::FMa(_, _, _, rs1, rs2, rs3, rm) => rm_reads(rm).with_f(rs1).with_f(rs2).with_f(rs3),
+            RiscV::FOp(_, _, _, rs1, rs2, rm) => rm_reads(rm).with_f(rs1).with_f(rs2),
+            RiscV::FSqrt(_, _, rs1, rm) |
+            RiscV::FCvt(_, _, _, rs1, rm) |
+            RiscV::FCvtToInt(_, _, _, rs1, rm) => rm_reads(rm).with_f(rs1),
+            RiscV::FCvtFromInt(_, _, _, rs1, rm) => rm_reads(rm).with_x(rs1),
+            RiscV::FSgnj(_, _, _, rs1, rs2) |
+            RiscV::FMinMax(_, _, _, rs1, rs2) |
+            RiscV::FCmp(_, _, _, rs1, rs2) => r.with_f(rs1).with_f(rs2),
+            RiscV::FClass(_, _, rs1) | RiscV::FMvToInt(_, _, rs1) => r.with_f(rs1),
+            RiscV::CmPush(rlist, _) => rlist.regs().fold(r.with_x(SP), |r, x| r.with_x(x)),
+            RiscV::CmPop(..) => r.with_x(SP),
+            RiscV::CmMv(CmMvType::Sa01, ..) => r.with_x(Reg(10)).with_x(Reg(11)),
+            RiscV::CmMv(CmMvType::A01s, r1s, r2s) => r.with_x(r1s).with_x(r2s),
+            RiscV::CmJt(_) | RiscV::CmJalt(_) => r.with_csr(JVT),
+            RiscV::Custom(inst) => vendor::extension(inst.ext)
+                .map_or(r, |e| e.reads(&inst)),
+        }
+    }
+
+    pub fn writes(&self) -> RegSet {
+        let r = RegSet::EMPTY;
+        match *self {
+            RiscV::Lui(rd, _) |
+            RiscV::Auipc(rd, _) |
+            RiscV::Jal(rd, _) |
+            RiscV::Jalr(rd, _, _) |
+            RiscV::Load(_, rd, _, _) |
+            RiscV::OpI(_, rd, _, _) |
+            RiscV::OpIW(_, rd, _, _) |
+            RiscV::Op(_, rd, _, _) |
+            RiscV::OpW(_, rd, _, _) |
+            RiscV::MulOp(_, rd, _, _) |
+            RiscV::MulOpW(_, rd, _, _) |
+            RiscV::Lr(_, _, rd, _) |
+            RiscV::Sc(_, _, rd, _, _) |
+            RiscV::Amo(_, _, _, rd, _, _) |
+            RiscV::Ext(_, rd, _) |
+            RiscV::FClass(_, rd, _) |
+            RiscV::FMvToInt(_, rd, _) => r.with_x(rd),
+            RiscV::EOp(EOpType::Mret) => r.with_csr(Csr(0x300)),
+            RiscV::EOp(EOpType::Sret) => r.with_csr(Csr(0x100)),
+            RiscV::Branch(..) | RiscV::Store(..) | RiscV::FStore(..) | RiscV::Fence(..) | RiscV::EOp(..) |
+            RiscV::SFenceVma(..) => r,
+            RiscV::CsrOp(ty, rd, rs1, csr) => {
+                let r = r.with_x(rd);
+                // csrrs/csrrc with rs1 = x0 do not write the csr
+                if ty != CsrOpType::Rw && rs1.0 == 0 { r } else { r.with_csr(csr) }
+            },
+            RiscV::CsrOpI(ty, rd, zimm, csr) => {
+                let r = r.with_x(rd);
+                if ty != CsrOpType::Rw && zimm == 0 { r } else { r.with_csr(csr) }
+            },
+            RiscV::FLoad(_, rd, _, _) |
+            RiscV::FSgnj(_, _, rd, _, _) |
+            RiscV::FMvFromInt(_, rd, _) => r.with_f(rd),
+            RiscV::FMa(_, _, rd, ..) |
+            RiscV::FOp(_, _, rd, ..) |
+            RiscV::FSqrt(_, rd, ..) |
+            RiscV::FMinMax(_, _, rd, ..) |
+            RiscV::FCvt(_, _, rd, ..) |
+            RiscV::FCvtFromInt(_, _, rd, ..) => r.with_f(rd).with_csr(FFLAGS),
+            RiscV::FCmp(_, _, rd, ..) |
+            RiscV::FCvtToInt(_, _, rd, ..) => r.with_x(rd).with_csr(FFLAGS),
+            RiscV::CmPush(..) => r.with_x(SP),
+            RiscV::CmPop(ty, rlist, _) => {
+                let r = rlist.regs().fold(r.with_x(SP), |r, x| r.with_x(x));
+                if ty == CmPopType::PopRetZ { r.with_x(Reg(10)) } else { r }
+            },
+            RiscV::CmMv(CmMvType::Sa01, r1s, r2s) => r.with_x(r1s).with_x(r2s),
+            RiscV::CmMv(CmMvType::A01s, ..) => r.with_x(Reg(10)).with_x(Reg(11)),
+            RiscV::CmJt(_) => r,
+            RiscV::CmJalt(_) => r.with_x(RA),
+            RiscV::Custom(inst) => vendor::extension(inst.ext)
+                .map_or(r, |e| e.writes(&inst)),
+        }
+    }
+
+    /// `reads`, looking `RiscV::Custom` up in `registry`
+    pub fn reads_in(&self, registry: &Registry) -> RegSet {
+        match self {
+            RiscV::Custom(inst) => registry.extension(inst.ext)
+                .map_or(RegSet::EMPTY, |e| e.reads(inst)),
+            _ => self.reads(),
+        }
+    }
+
+    /// `writes`, looking `RiscV::Custom` up in `registry`
+    pub fn writes_in(&self, registry: &Registry) -> RegSet {
+        match self {
+            RiscV::Custom(inst) => registry.extension(inst.ext)
+                .map_or(RegSet::EMPTY, |e| e.writes(inst)),
+            _ => self.writes(),
+        }
+    }
+}
+
+impl FlatRiscV {
+    pub fn reads(&self) -> RegSet {
+        RiscV::try_from(*self).map_or(RegSet::EMPTY, |r| r.reads())
+    }
+
+    pub fn writes(&self) -> RegSet {
+        RiscV::try_from(*self).map_or(RegSet::EMPTY, |r| r.writes())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::disassembly::riscv::disassembly;
+    use crate::flat_disasm::disasm::flat_disasm;
+    use super::*;
+
+    fn dis(code: u32) -> RiscV {
+        disassembly(code).unwrap().0
+    }
+
+    #[test]
+    fn test_int() {
+        // add x10, x11, x12
+        let add = dis(0x00c58533);
+        assert_eq!(add.reads(), RegSet::EMPTY.with_x(Reg(11)).with_x(Reg(12)));
+        assert_eq!(add.writes(), RegSet::EMPTY.with_x(Reg(10)));
+        // jal x1, 16
+        assert_eq!(dis(0x010000ef).writes(), RegSet::EMPTY.with_x(RA));
+        // jal x0, 16 writes nothing
+        assert!(dis(0x0100006f).writes().is_empty());
+        // addi x10, x0, 1 reads nothing
+        assert!(dis(0x00100513).reads().is_empty());
+        // sd x1, 8(x2)
+        assert_eq!(dis(0x00113423).reads().to_string(), "{x1, x2}");
+    }
+
+    #[test]
+    fn test_csr() {
+        // csrrw x0, mstatus, x10: write only
+        let csrw = dis(0x30051073);
+        assert_eq!(csrw.reads(), RegSet::EMPTY.with_x(Reg(10)));
+        assert_eq!(csrw.writes(), RegSet::EMPTY.with_csr(Csr(0x300)));
+        // csrrs x10, mstatus, x0: read only
+        let csrr = dis(0x30002573);
+        assert_eq!(csrr.reads(), RegSet::EMPTY.with_csr(Csr(0x300)));
+        assert_eq!(csrr.writes(), RegSet::EMPTY.with_x(Reg(10)));
+        // csrrci x10, mstatus, 8
+        let csrci = dis(0x30047573);
+        assert!(csrci.reads().contains_csr(Csr(0x300)) && csrci.writes().contains_csr(Csr(0x300)));
+    }
+
+    #[test]
+    fn test_fp() {
+        // fadd.s f1, f1, f2 (dyn)
+        let fadd = dis(0x0020f0d3);
+        assert_eq!(fadd.reads(), RegSet::EMPTY.with_f(FReg(1)).with_f(FReg(2)).with_csr(FRM));
+        assert_eq!(fadd.writes(), RegSet::EMPTY.with_f(FReg(1)).with_csr(FFLAGS));
+        // fadd.h f1, f1, f2, rtz
+        assert!(!dis(0x042090d3).reads().contains_csr(FRM));
+    }
+
+    #[test]
+    fn test_zcmp() {
+        let push = RiscV::CmPush(RList(5), 16);
+        assert_eq!(push.reads().to_string(), "{x1, x2, x8}");
+        assert_eq!(push.writes().to_string(), "{x2}");
+        let popretz = RiscV::CmPop(CmPopType::PopRetZ, RList(4), 16);
+        assert_eq!(popretz.writes().to_string(), "{x1, x2, x10}");
+    }
+
+    #[test]
+    fn test_csr_overflow() {
+        let mut set = RegSet::EMPTY;
+        for i in 0..CSR_INLINE as u16 {
+            set.insert_csr(Csr(0x300 + i));
+        }
+        assert!(!set.is_any_csr());
+        assert_eq!(set.csrs().next(), Some(Csr(0x300)));
+        set.insert_csr(Csr(0x341));
+        assert!(set.is_any_csr() && set.contains_csr(Csr(0x7ff)));
+    }
+
+    #[test]
+    fn test_flat() {
+        let (flat, _) = flat_disasm(&0x00c58533_u32.to_le_bytes(), false).unwrap();
+        assert_eq!(flat.reads(), dis(0x00c58533).reads());
+        assert_eq!(flat.writes(), dis(0x00c58533).writes());
+    }
+}
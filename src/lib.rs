@@ -5,6 +5,9 @@ pub mod utils;
 pub mod isa;
 pub mod disassembly;
 pub mod flat_disasm;
+pub mod elf;
+pub mod analysis;
+pub mod emu;
 
 
 #[test]